@@ -0,0 +1,141 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// # Auth Result
+///
+/// This enum represents the outcome of a SPF, DKIM or DMARC check, the results shared by the three (RFC 8601 2.7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AuthResult {
+    /// The check passed
+    Pass,
+    /// The check failed
+    Fail,
+    /// The check failed weakly (SPF `~all`)
+    SoftFail,
+    /// The domain doesn't state a policy for the sender (SPF `?all`)
+    Neutral,
+    /// There was nothing to check (no record or no signature)
+    None,
+    /// The check couldn't be done because of a temporary error, usually DNS
+    TempError,
+    /// The check couldn't be done because of a permanent error, like a malformed record
+    PermError,
+}
+
+impl AuthResult {
+    /// # All
+    ///
+    /// Every result, in the order of the counters.
+    pub const ALL: [AuthResult; 7] = [
+        AuthResult::Pass,
+        AuthResult::Fail,
+        AuthResult::SoftFail,
+        AuthResult::Neutral,
+        AuthResult::None,
+        AuthResult::TempError,
+        AuthResult::PermError,
+    ];
+
+    /// # Index
+    ///
+    /// The position of the result in `ALL`.
+    fn index(self) -> usize {
+        match self {
+            AuthResult::Pass => 0,
+            AuthResult::Fail => 1,
+            AuthResult::SoftFail => 2,
+            AuthResult::Neutral => 3,
+            AuthResult::None => 4,
+            AuthResult::TempError => 5,
+            AuthResult::PermError => 6,
+        }
+    }
+}
+
+impl fmt::Display for AuthResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let result = match self {
+            AuthResult::Pass => "pass",
+            AuthResult::Fail => "fail",
+            AuthResult::SoftFail => "softfail",
+            AuthResult::Neutral => "neutral",
+            AuthResult::None => "none",
+            AuthResult::TempError => "temperror",
+            AuthResult::PermError => "permerror",
+        };
+        write!(f, "{}", result)
+    }
+}
+
+/// # Auth Counters
+///
+/// This struct counts the outcomes of one kind of check by result, it can be read while the server runs.
+#[derive(Debug, Default)]
+pub struct AuthCounters {
+    counts: [AtomicU64; 7],
+}
+
+impl AuthCounters {
+    /// # Record
+    ///
+    /// This function counts a check with the given result.
+    pub fn record(&self, result: AuthResult) {
+        self.counts[result.index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// # Get
+    ///
+    /// This function returns how many checks had the given result.
+    pub fn get(&self, result: AuthResult) -> u64 {
+        self.counts[result.index()].load(Ordering::Relaxed)
+    }
+
+    /// # Total
+    ///
+    /// This function returns how many checks were counted.
+    pub fn total(&self) -> u64 {
+        AuthResult::ALL.iter().map(|result| self.get(*result)).sum()
+    }
+}
+
+/// # Auth Stats
+///
+/// This struct aggregates the outcomes of the SPF, DKIM and DMARC checks of all the connections of a server,
+/// available from `ServerHandle::auth_stats`. The checks done with a connection (`check_spf`, `dkim::verify`...) are counted,
+/// the crate doesn't evaluate DMARC policies so the DMARC verdicts are recorded by the application.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::auth_stats::AuthResult;
+/// use neo_email::server::SMTPServer;
+///
+/// let server = SMTPServer::<()>::new();
+/// let stats = server.handle().auth_stats();
+///
+/// // e.g. after evaluating the DMARC policy in the on_email controller, with `conn.auth_stats`
+/// stats.dmarc.record(AuthResult::Pass);
+/// stats.dmarc.record(AuthResult::Fail);
+/// stats.dmarc.record(AuthResult::Pass);
+///
+/// assert_eq!(stats.dmarc.get(AuthResult::Pass), 2);
+/// assert_eq!(stats.dmarc.get(AuthResult::Fail), 1);
+/// assert_eq!(stats.dmarc.total(), 3);
+/// assert_eq!(stats.spf.total(), 0);
+/// assert_eq!(AuthResult::TempError.to_string(), "temperror");
+/// ```
+#[derive(Debug, Default)]
+pub struct AuthStats {
+    /// # SPF
+    ///
+    /// The outcomes of the SPF checks.
+    pub spf: AuthCounters,
+    /// # DKIM
+    ///
+    /// The outcomes of the DKIM verifications, `None` for the unsigned mails.
+    pub dkim: AuthCounters,
+    /// # DMARC
+    ///
+    /// The outcomes of the DMARC evaluations.
+    pub dmarc: AuthCounters,
+}
@@ -0,0 +1,955 @@
+use std::{
+    collections::hash_map::RandomState,
+    future::Future,
+    hash::{BuildHasher, Hasher},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufStream},
+    net::{TcpStream, ToSocketAddrs},
+    sync::Mutex,
+};
+use tokio_native_tls::{TlsConnector, TlsStream};
+use trust_dns_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    proto::op::ResponseCode,
+    TokioAsyncResolver,
+};
+
+use crate::{
+    errors::{Error, SMTPError},
+    mail::{EmailAddress, Mail},
+    message::{self, Message, MAX_REPLY_LINE},
+    status_code::StatusCodes,
+};
+
+/// # SMTP Port
+///
+/// The port of the mail servers of a domain, where `deliver` connects to.
+pub const SMTP_PORT: u16 = 25;
+
+/// # Default Timeout
+///
+/// The time the client waits to connect, for each reply and for each write, the 5 minutes of RFC 5321 4.5.3.2.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// # SMTP Client
+///
+/// This struct represents a connection to another SMTP server, to relay the accepted mail.
+/// Every command returns the last line of the reply, and a reply with a status other than the expected is an `Error::UnexpectedReply`.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::client::SMTPClient;
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_email::OnEmailController;
+/// use neo_email::errors::Error;
+/// use neo_email::headers::EmailHeaders;
+/// use neo_email::mail::{EmailAddress, Mail};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::sync::{mpsc, Mutex};
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let (received, mut receiver) = mpsc::unbounded_channel::<Mail<Vec<u8>>>();
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+///         .workers(2)
+///         .on_email(OnEmailController::new(move |_conn: Arc<Mutex<SMTPConnection<()>>>, mail: Mail<Vec<u8>>| {
+///             let received = received.clone();
+///             async move {
+///                 received.send(mail).unwrap();
+///                 Message::new(StatusCodes::OK, "Queued".to_string())
+///             }
+///         }))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let mut client = SMTPClient::connect(addr).await.unwrap();
+///     assert_eq!(client.greeting.last().unwrap().status, StatusCodes::SMTPServiceReady);
+///     client.ehlo("relay.nervio.us").await.unwrap();
+///     assert!(client.supports("PIPELINING"));
+///
+///     let sender = EmailAddress::from_string("jean@nervio.us").unwrap();
+///     let recipient = EmailAddress::from_string("admin@nervio.us").unwrap();
+///     client.mail_from(&sender).await.unwrap();
+///     client.rcpt_to(&recipient).await.unwrap();
+///     let reply = client.data(b"Subject: Relayed\n\nHello\n.\n..signed\r\n").await.unwrap();
+///     assert_eq!(reply.message, "Queued");
+///
+///     // The server got the message as sent, with CRLF line breaks
+///     let mail = receiver.recv().await.unwrap();
+///     assert_eq!(mail.headers.get(&EmailHeaders::Subject).unwrap(), "Relayed");
+///     assert_eq!(mail.body, b"Hello\r\n.\r\n..signed\r\n".to_vec());
+///
+///     // A command out of sequence is an unexpected reply
+///     let err = client.data(b"Hello\r\n").await.unwrap_err();
+///     assert!(matches!(err, Error::UnexpectedReply(reply) if reply.status == StatusCodes::BadSequenceOfCommands));
+///
+///     let reply = client.quit().await.unwrap();
+///     assert_eq!(reply.status, StatusCodes::ServiceClosingTransmissionChannel);
+/// }
+/// ```
+pub struct SMTPClient {
+    stream: Option<ClientStream>,
+    /// # Greeting
+    ///
+    /// The lines of the greeting of the server.
+    pub greeting: Vec<Message>,
+    /// # Extensions
+    ///
+    /// The extensions advertised in the last EHLO, like `SIZE 10485760`, cleared by STARTTLS.
+    pub extensions: Vec<String>,
+    timeout: Duration,
+}
+
+/// # Client Stream
+///
+/// The socket of the client, replaced by its TLS stream after STARTTLS.
+enum ClientStream {
+    Plain(BufStream<TcpStream>),
+    Tls(Box<BufStream<TlsStream<TcpStream>>>),
+}
+
+impl SMTPClient {
+    /// # Connect
+    ///
+    /// This function connects to the server and reads its greeting, which must be a 220, with the default timeout.
+    pub async fn connect<A: ToSocketAddrs>(addr: A) -> Result<Self, SMTPError> {
+        Self::connect_with_timeout(addr, DEFAULT_TIMEOUT).await
+    }
+
+    /// # Connect With Timeout
+    ///
+    /// This function connects to the server and reads its greeting like `connect`, the timeout applies to the connection,
+    /// to each reply and to each write of the session. A timeout is an `Error::IoError` of kind `TimedOut`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::client::SMTPClient;
+    /// use neo_email::errors::Error;
+    /// use std::time::Duration;
+    /// use tokio::net::TcpListener;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     // A server that accepts the connection and never greets
+    ///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    ///     let addr = listener.local_addr().unwrap();
+    ///     tokio::spawn(async move {
+    ///         let (_socket, _) = listener.accept().await.unwrap();
+    ///         std::future::pending::<()>().await;
+    ///     });
+    ///
+    ///     let err = SMTPClient::connect_with_timeout(addr, Duration::from_millis(100)).await.err().unwrap();
+    ///     assert!(matches!(err, Error::IoError(err) if err.kind() == std::io::ErrorKind::TimedOut));
+    /// }
+    /// ```
+    pub async fn connect_with_timeout<A: ToSocketAddrs>(addr: A, timeout: Duration) -> Result<Self, SMTPError> {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| timed_out("connecting"))??;
+        let mut client = SMTPClient {
+            stream: Some(ClientStream::Plain(BufStream::new(stream))),
+            greeting: Vec::new(),
+            extensions: Vec::new(),
+            timeout,
+        };
+
+        let greeting = client.read_reply().await?;
+        expect_reply(&greeting, |status| *status == StatusCodes::SMTPServiceReady)?;
+        client.greeting = greeting;
+        Ok(client)
+    }
+
+    /// # EHLO
+    ///
+    /// This function greets the server with the domain of the client, keeping the advertised extensions.
+    pub async fn ehlo(&mut self, domain: &str) -> Result<Message, SMTPError> {
+        let reply = self.command(&format!("EHLO {}", domain)).await?;
+        let last = expect_reply(&reply, StatusCodes::is_positive_completion)?;
+        // The first line is the greeting, the others are the extensions
+        self.extensions = reply.iter().skip(1).map(|line| line.message.clone()).collect();
+        Ok(last)
+    }
+
+    /// # Supports
+    ///
+    /// This function returns if the server advertised the extension in the last EHLO, ignoring its parameters and case.
+    pub fn supports(&self, extension: &str) -> bool {
+        self.extensions.iter().any(|advertised| {
+            advertised
+                .split_whitespace()
+                .next()
+                .is_some_and(|keyword| keyword.eq_ignore_ascii_case(extension))
+        })
+    }
+
+    /// # STARTTLS
+    ///
+    /// This function upgrades the connection to TLS, `domain` is the name the certificate of the server is verified against.
+    /// The extensions are cleared, as the client must send EHLO again (RFC 3207 4.2).
+    pub async fn starttls(&mut self, connector: &TlsConnector, domain: &str) -> Result<Message, SMTPError> {
+        let reply = self.command("STARTTLS").await?;
+        let last = expect_reply(&reply, |status| *status == StatusCodes::SMTPServiceReady)?;
+
+        let stream = match self.stream.take() {
+            Some(ClientStream::Plain(stream)) => stream.into_inner(),
+            Some(tls) => {
+                self.stream = Some(tls);
+                return Err(Error::CustomError("The connection already uses TLS".to_string()));
+            }
+            None => return Err(closed()),
+        };
+        let tls_stream = tokio::time::timeout(self.timeout, connector.connect(domain, stream))
+            .await
+            .map_err(|_| timed_out("the TLS handshake"))?
+            .map_err(|err| Error::CustomError(format!("TLS handshake failed: {}", err)))?;
+        self.stream = Some(ClientStream::Tls(Box::new(BufStream::new(tls_stream))));
+        self.extensions.clear();
+        Ok(last)
+    }
+
+    /// # MAIL FROM
+    ///
+    /// This function starts a transaction with the sender of the message.
+    pub async fn mail_from(&mut self, sender: &EmailAddress) -> Result<Message, SMTPError> {
+        let reply = self.command(&format!("MAIL FROM:<{}>", sender)).await?;
+        expect_reply(&reply, StatusCodes::is_positive_completion)
+    }
+
+    /// # RCPT TO
+    ///
+    /// This function adds a recipient to the transaction.
+    pub async fn rcpt_to(&mut self, recipient: &EmailAddress) -> Result<Message, SMTPError> {
+        let reply = self.command(&format!("RCPT TO:<{}>", recipient)).await?;
+        expect_reply(&reply, StatusCodes::is_positive_completion)
+    }
+
+    /// # DATA
+    ///
+    /// This function sends the message, the lines starting with a dot are escaped (RFC 5321 4.5.2) and the terminator is added.
+    /// The bare LF line breaks are sent as CRLF, the only line break of SMTP (RFC 5321 2.3.8).
+    pub async fn data(&mut self, data: &[u8]) -> Result<Message, SMTPError> {
+        let reply = self.command("DATA").await?;
+        expect_reply(&reply, |status| *status == StatusCodes::StartMailInput)?;
+
+        let mut payload = Vec::with_capacity(data.len() + 8);
+        for line in data.split_inclusive(|byte| *byte == b'\n') {
+            if line.first() == Some(&b'.') {
+                payload.push(b'.');
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            payload.extend_from_slice(line);
+            payload.extend_from_slice(b"\r\n");
+        }
+        payload.extend_from_slice(b".\r\n");
+        self.write(&payload).await?;
+
+        let reply = self.read_reply().await?;
+        expect_reply(&reply, StatusCodes::is_positive_completion)
+    }
+
+    /// # QUIT
+    ///
+    /// This function ends the session, the server closes the connection after its 221.
+    pub async fn quit(&mut self) -> Result<Message, SMTPError> {
+        let reply = self.command("QUIT").await?;
+        let last = expect_reply(&reply, |status| *status == StatusCodes::ServiceClosingTransmissionChannel)?;
+        self.stream = None;
+        Ok(last)
+    }
+
+    /// # Command
+    ///
+    /// This function sends a command line (without CRLF) and returns all the lines of the reply, whatever their status.
+    pub async fn command(&mut self, command: &str) -> Result<Vec<Message>, SMTPError> {
+        log::trace!("[📤] Sending {}", command);
+        self.write(format!("{}\r\n", command).as_bytes()).await?;
+        self.read_reply().await
+    }
+
+    /// # Read Reply
+    ///
+    /// This function reads a reply of the server, the lines of a multiline reply (`250-` until `250 `) in order.
+    pub async fn read_reply(&mut self) -> Result<Vec<Message>, SMTPError> {
+        let mut reply = Vec::new();
+        loop {
+            let line = self.read_line().await?;
+            let (message, is_last) = parse_reply_line(&line)?;
+            reply.push(message);
+            if is_last {
+                return Ok(reply);
+            }
+        }
+    }
+
+    /// # Write
+    ///
+    /// This function writes and flushes the bytes to the server.
+    async fn write(&mut self, bytes: &[u8]) -> Result<(), SMTPError> {
+        let timeout = self.timeout;
+        let write = async {
+            match self.stream.as_mut().ok_or_else(closed)? {
+                ClientStream::Plain(stream) => {
+                    stream.write_all(bytes).await?;
+                    stream.flush().await?;
+                }
+                ClientStream::Tls(stream) => {
+                    stream.write_all(bytes).await?;
+                    stream.flush().await?;
+                }
+            }
+            Ok(())
+        };
+        tokio::time::timeout(timeout, write).await.map_err(|_| timed_out("writing"))?
+    }
+
+    /// # Read Line
+    ///
+    /// This function reads a reply line, tolerating lines longer than the RFC limit up to a point.
+    async fn read_line(&mut self) -> Result<String, SMTPError> {
+        let limit = (MAX_REPLY_LINE * 4) as u64;
+        let timeout = self.timeout;
+        let mut line = Vec::new();
+        let read = async {
+            match self.stream.as_mut().ok_or_else(closed)? {
+                ClientStream::Plain(stream) => (&mut *stream).take(limit).read_until(b'\n', &mut line).await?,
+                ClientStream::Tls(stream) => (&mut **stream).take(limit).read_until(b'\n', &mut line).await?,
+            };
+            Ok::<(), SMTPError>(())
+        };
+        tokio::time::timeout(timeout, read).await.map_err(|_| timed_out("waiting for a reply"))??;
+
+        if line.is_empty() {
+            return Err(Error::IoError(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "The server closed the connection",
+            )));
+        }
+        if !line.ends_with(b"\n") {
+            return Err(Error::ParseError("Reply line too long".to_string()));
+        }
+        Ok(String::from_utf8_lossy(&line).trim_end_matches(['\r', '\n']).to_string())
+    }
+}
+
+/// # Parse Reply Line
+///
+/// This function parses a reply line into its message, and if it's the last line of the reply.
+fn parse_reply_line(line: &str) -> Result<(Message, bool), SMTPError> {
+    let (code, is_last, text) = message::parse_reply_line(line)
+        .ok_or_else(|| Error::ParseError(format!("Malformed reply line: {}", line)))?;
+    let status = StatusCodes::from_code(code)
+        .ok_or_else(|| Error::ParseError(format!("Unknown status code in reply: {}", line)))?;
+
+    Ok((Message::new(status, text.to_string()), is_last))
+}
+
+/// # Expect Reply
+///
+/// This function returns the last line of the reply if its status is the expected, otherwise an unexpected reply error.
+fn expect_reply(reply: &[Message], expected: impl Fn(&StatusCodes) -> bool) -> Result<Message, SMTPError> {
+    let last = reply
+        .last()
+        .cloned()
+        .ok_or(Error::ParseError("Empty reply".to_string()))?;
+    if expected(&last.status) {
+        Ok(last)
+    } else {
+        Err(Error::UnexpectedReply(last))
+    }
+}
+
+/// # Timed Out
+///
+/// The error of a step of the session that took longer than the timeout.
+fn timed_out(step: &str) -> SMTPError {
+    Error::IoError(std::io::Error::new(
+        std::io::ErrorKind::TimedOut,
+        format!("Timed out {}", step),
+    ))
+}
+
+/// # Closed
+///
+/// The error of a command sent after QUIT or a failed STARTTLS.
+fn closed() -> SMTPError {
+    Error::IoError(std::io::Error::new(
+        std::io::ErrorKind::NotConnected,
+        "The connection is closed",
+    ))
+}
+
+/// # Resolve MX
+///
+/// This function returns the mail servers of the domain as `(preference, host)` in the order they must be tried (RFC 5321 5.1),
+/// the lowest preference first and the hosts with the same preference shuffled to spread the load.
+///
+/// A domain without MX records is its own mail server (implicit MX) if it has an address, and a domain
+/// with a null MX (`MX 0 .`, RFC 7505) doesn't accept mail, which is an error.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::client::resolve_mx;
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+/// use trust_dns_resolver::proto::rr::rdata::{A, MX};
+/// use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// fn answer(name: &str, record_type: RecordType) -> Result<Vec<RData>, ResponseCode> {
+///     let mx = |preference: u16, host: &str| RData::MX(MX::new(preference, Name::from_ascii(host).unwrap()));
+///     match (name, record_type) {
+///         ("nervio.us.", RecordType::MX) => Ok(vec![
+///             mx(20, "backup.nervio.us."),
+///             mx(10, "mx1.nervio.us."),
+///             mx(10, "mx2.nervio.us."),
+///         ]),
+///         ("implicit.nervio.us.", RecordType::A) => Ok(vec![RData::A(A::new(192, 0, 2, 1))]),
+///         ("null.nervio.us.", RecordType::MX) => Ok(vec![mx(0, ".")]),
+///         ("missing.nervio.us.", _) => Err(ResponseCode::NXDomain),
+///         _ => Ok(Vec::new()),
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering the records above
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let query = request.queries()[0].clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .add_queries(request.queries().to_vec());
+///             match answer(&query.name().to_string(), query.query_type()) {
+///                 Ok(answers) => {
+///                     for rdata in answers {
+///                         response.add_answer(Record::from_rdata(query.name().clone(), 300, rdata));
+///                     }
+///                 }
+///                 Err(code) => {
+///                     response.set_response_code(code);
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     // Ordered by preference, the equal ones in any order
+///     let hosts = resolve_mx(resolver.clone(), "nervio.us").await.unwrap();
+///     let mut first = vec![hosts[0].1.clone(), hosts[1].1.clone()];
+///     first.sort();
+///     assert_eq!(first, vec!["mx1.nervio.us", "mx2.nervio.us"]);
+///     assert_eq!(hosts[2], (20, "backup.nervio.us".to_string()));
+///
+///     // Implicit MX
+///     let hosts = resolve_mx(resolver.clone(), "implicit.nervio.us").await.unwrap();
+///     assert_eq!(hosts, vec![(0, "implicit.nervio.us".to_string())]);
+///
+///     // Null MX, and a domain that doesn't exist
+///     assert!(resolve_mx(resolver.clone(), "null.nervio.us").await.is_err());
+///     assert!(resolve_mx(resolver.clone(), "missing.nervio.us").await.is_err());
+/// }
+/// ```
+pub async fn resolve_mx(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    domain: &str,
+) -> Result<Vec<(u16, String)>, SMTPError> {
+    let domain = domain.trim_end_matches('.');
+    let dns_resolver = dns_resolver.lock().await;
+
+    let records = match dns_resolver.mx_lookup(format!("{}.", domain).as_str()).await {
+        Ok(lookup) => lookup
+            .iter()
+            .map(|mx| (mx.preference(), mx.exchange().clone()))
+            .collect::<Vec<_>>(),
+        Err(err) if has_no_records(&err) => Vec::new(),
+        Err(err) => {
+            return Err(Error::DNSError(
+                format!("Failed to get MX records of {}", domain),
+                Some(Box::new(err)),
+            ))
+        }
+    };
+
+    if records.iter().any(|(_, exchange)| exchange.is_root()) {
+        return Err(Error::DeliveryError(
+            format!("{} doesn't accept mail (null MX)", domain),
+            Vec::new(),
+        ));
+    }
+
+    // Without MX records the domain itself receives the mail
+    if records.is_empty() {
+        return match dns_resolver.lookup_ip(format!("{}.", domain).as_str()).await {
+            Ok(lookup) if lookup.iter().next().is_some() => Ok(vec![(0, domain.to_string())]),
+            Ok(_) => Err(Error::DNSError(format!("{} has no MX or address records", domain), None)),
+            Err(err) => Err(Error::DNSError(
+                format!("{} has no MX or address records", domain),
+                Some(Box::new(err)),
+            )),
+        };
+    }
+
+    let mut hosts = records
+        .into_iter()
+        .map(|(preference, exchange)| {
+            let host = exchange.to_string().trim_end_matches('.').to_string();
+            (preference, random_key(), host)
+        })
+        .collect::<Vec<_>>();
+    hosts.sort_by_key(|(preference, key, _)| (*preference, *key));
+
+    Ok(hosts
+        .into_iter()
+        .map(|(preference, _, host)| (preference, host))
+        .collect())
+}
+
+/// # Try MX Hosts
+///
+/// This function tries to deliver to the mail servers of the domain in the order of `resolve_mx`, calling `attempt`
+/// with each host until one succeeds. If all fail the error has the failure of each host in the order they were tried.
+/// A 5xx reply (`Error::UnexpectedReply`) stops the walk, as the domain rejected the mail permanently.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::client::try_mx_hosts;
+/// use neo_email::errors::Error;
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType};
+/// use trust_dns_resolver::proto::rr::rdata::MX;
+/// use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering three MX records for any domain
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let query = request.queries()[0].clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .add_queries(request.queries().to_vec());
+///             if query.query_type() == RecordType::MX {
+///                 for (preference, host) in [(30, "mx3.nervio.us."), (10, "mx1.nervio.us."), (20, "mx2.nervio.us.")] {
+///                     let mx = RData::MX(MX::new(preference, Name::from_ascii(host).unwrap()));
+///                     response.add_answer(Record::from_rdata(query.name().clone(), 300, mx));
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     // The first host is down, the second accepts
+///     let accepted = try_mx_hosts(resolver.clone(), "nervio.us", |host| async move {
+///         match host.as_str() {
+///             "mx1.nervio.us" => Err(Error::CustomError("Connection refused".to_string())),
+///             _ => Ok(host),
+///         }
+///     })
+///     .await
+///     .unwrap();
+///     assert_eq!(accepted, "mx2.nervio.us");
+///
+///     // All the hosts fail
+///     let err = try_mx_hosts(resolver.clone(), "nervio.us", |host| async move {
+///         Err::<(), _>(Error::CustomError(format!("{} is down", host)))
+///     })
+///     .await
+///     .unwrap_err();
+///     match err {
+///         Error::DeliveryError(_, attempts) => {
+///             let hosts = attempts.iter().map(|(host, _)| host.as_str()).collect::<Vec<_>>();
+///             assert_eq!(hosts, vec!["mx1.nervio.us", "mx2.nervio.us", "mx3.nervio.us"]);
+///         }
+///         err => panic!("Unexpected error {}", err),
+///     }
+/// }
+/// ```
+pub async fn try_mx_hosts<T, F, Fut>(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    domain: &str,
+    mut attempt: F,
+) -> Result<T, SMTPError>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = Result<T, SMTPError>>,
+{
+    let hosts = resolve_mx(dns_resolver, domain).await?;
+
+    let mut attempts = Vec::new();
+    for (preference, host) in hosts {
+        log::trace!("[📤] Trying {} (preference {}) for {}", host, preference, domain);
+        match attempt(host.clone()).await {
+            Ok(delivered) => return Ok(delivered),
+            Err(err) => {
+                log::debug!("[📤] Delivery to {} failed: {}", host, err);
+                let permanent = matches!(&err, Error::UnexpectedReply(reply) if reply.is_permanent_negative());
+                attempts.push((host, err));
+                if permanent {
+                    break;
+                }
+            }
+        }
+    }
+
+    Err(Error::DeliveryError(
+        format!("No mail server of {} accepted the mail", domain),
+        attempts,
+    ))
+}
+
+/// # Deliver
+///
+/// This function delivers the mail to the recipients of a domain through its mail servers, trying each host in the order
+/// of `resolve_mx` until one accepts it. The hosts are resolved with the given resolver and the mail is sent in plaintext,
+/// use `SMTPClient` directly for STARTTLS. `helo_name` is the name of this host sent in EHLO, and `sender` the reverse-path
+/// of MAIL FROM where the bounces go, which can differ from the `From` header of the mail.
+///
+/// The recipients must be of the same domain, `Envelope::recipients_by_domain` groups them. A rejected recipient doesn't
+/// abort the transaction, the mail is sent to the accepted ones and the report has the reply to each RCPT. When no host accepts
+/// the mail the error is an `Error::DeliveryError` with the failure of each host, a rejection is an `Error::UnexpectedReply`
+/// with the reply of the server to tell a temporary (4xx) from a permanent (5xx) failure. When every recipient is rejected
+/// the reply is the first temporary rejection if any, so the next host is tried.
+///
+/// The mail is sent with `Mail::to_bytes`, the parsed header fields byte for byte, so the `Received` trace headers and the
+/// DKIM signatures reach the next host unchanged. Each host has `DEFAULT_TIMEOUT` to connect and for every reply,
+/// a host that times out is a temporary failure and the next one is tried.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::client::deliver_on_port;
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_email::OnEmailController;
+/// use neo_email::controllers::on_rcpt::OnRCPTCommandController;
+/// use neo_email::errors::Error;
+/// use neo_email::headers::EmailHeaders;
+/// use neo_email::mail::{EmailAddress, Mail};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message as DNSMessage, MessageType};
+/// use trust_dns_resolver::proto::rr::rdata::{A, MX};
+/// use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// // mx1 has no server listening, mx2 is the server of the example
+/// fn answer(name: &str, record_type: RecordType) -> Vec<RData> {
+///     let mx = |preference: u16, host: &str| RData::MX(MX::new(preference, Name::from_ascii(host).unwrap()));
+///     match (name, record_type) {
+///         ("nervio.us.", RecordType::MX) => vec![mx(10, "mx1.nervio.us."), mx(20, "mx2.nervio.us.")],
+///         ("strict.nervio.us.", RecordType::MX) => vec![mx(10, "mx2.nervio.us."), mx(20, "mx1.nervio.us.")],
+///         ("mx1.nervio.us.", RecordType::A) => vec![RData::A(A::new(127, 0, 0, 2))],
+///         ("mx2.nervio.us.", RecordType::A) => vec![RData::A(A::new(127, 0, 0, 1))],
+///         _ => Vec::new(),
+///     }
+/// }
+///
+/// async fn on_rcpt(_conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<Message, Message> {
+///     Ok(match data.as_str() {
+///         "TO:<later@nervio.us>" => Message::new(StatusCodes::RequestedMailActionNotTakenMailboxUnavailable, "Try later".to_string()),
+///         "TO:<nobody@nervio.us>" | "TO:<nobody@strict.nervio.us>" => {
+///             Message::new(StatusCodes::RequestedActionNotTakenMailboxUnavailable, "No such user".to_string())
+///         }
+///         _ => Message::new(StatusCodes::OK, "Ok".to_string()),
+///     })
+/// }
+///
+/// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, mail: Mail<Vec<u8>>) -> Message {
+///     let conn = conn.lock().await;
+///     let subject = mail.headers.get(&EmailHeaders::Subject).cloned().unwrap_or_default();
+///     let sender = conn.envelope.as_ref().and_then(|envelope| envelope.sender.clone()).unwrap();
+///     let helo = conn.helo.clone().unwrap_or_default();
+///     Message::new(StatusCodes::OK, format!("Queued {} from {} via {}", subject, sender, helo))
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = DNSMessage::from_bytes(&buf[..n]).unwrap();
+///             let query = request.queries()[0].clone();
+///             let mut response = DNSMessage::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .add_queries(request.queries().to_vec());
+///             for rdata in answer(&query.name().to_string(), query.query_type()) {
+///                 response.add_answer(Record::from_rdata(query.name().clone(), 300, rdata));
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .workers(2)
+///         .on_rcpt_cmd(OnRCPTCommandController::new(on_rcpt))
+///         .on_email(OnEmailController::new(on_email))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let port = server.local_addr().unwrap().port();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let mail = Mail::builder()
+///         .header(EmailHeaders::Subject, "Hello")
+///         .default_from("Jean <jean@nervio.us>")
+///         .body(b"Hello, World!\r\n".to_vec())
+///         .build();
+///     let sender = EmailAddress::from_string("bounces@nervio.us").unwrap();
+///     let to = |address: &str| vec![EmailAddress::from_string(address).unwrap()];
+///
+///     // mx1 refuses the connection, mx2 accepts the mail
+///     let report = deliver_on_port(resolver.clone(), "relay.nervio.us", &sender, &mail, &to("admin@nervio.us"), port).await.unwrap();
+///     assert_eq!(report.reply.message, "Queued Hello from bounces@nervio.us via relay.nervio.us");
+///
+///     // A rejected recipient doesn't stop the delivery to the others
+///     let recipients = vec![
+///         EmailAddress::from_string("nobody@nervio.us").unwrap(),
+///         EmailAddress::from_string("admin@nervio.us").unwrap(),
+///         EmailAddress::from_string("later@nervio.us").unwrap(),
+///     ];
+///     let report = deliver_on_port(resolver.clone(), "relay.nervio.us", &sender, &mail, &recipients, port).await.unwrap();
+///     assert!(report.reply.is_positive_completion());
+///     assert_eq!(report.accepted().map(|recipient| recipient.to_string()).collect::<Vec<_>>(), vec!["admin@nervio.us"]);
+///     let rejected = report.rejected().map(|(recipient, reply)| (recipient.to_string(), reply.message.as_str())).collect::<Vec<_>>();
+///     assert_eq!(
+///         rejected,
+///         vec![
+///             ("nobody@nervio.us".to_string(), "No such user"),
+///             ("later@nervio.us".to_string(), "Try later"),
+///         ]
+///     );
+///
+///     // A temporary failure tries the next host
+///     let Error::DeliveryError(_, attempts) = deliver_on_port(resolver.clone(), "relay.nervio.us", &sender, &mail, &to("later@nervio.us"), port).await.unwrap_err() else {
+///         panic!("Expected a delivery error");
+///     };
+///     assert_eq!(attempts.len(), 2);
+///     assert!(matches!(&attempts[0], (host, Error::IoError(_)) if host == "mx1.nervio.us"));
+///     assert!(matches!(&attempts[1], (host, Error::UnexpectedReply(reply)) if host == "mx2.nervio.us" && reply.is_transient_negative()));
+///
+///     // A permanent failure of every recipient stops at the first host
+///     let Error::DeliveryError(_, attempts) = deliver_on_port(resolver.clone(), "relay.nervio.us", &sender, &mail, &to("nobody@strict.nervio.us"), port).await.unwrap_err() else {
+///         panic!("Expected a delivery error");
+///     };
+///     assert_eq!(attempts.len(), 1);
+///     assert!(matches!(&attempts[0].1, Error::UnexpectedReply(reply) if reply.status == StatusCodes::RequestedActionNotTakenMailboxUnavailable));
+/// }
+/// ```
+pub async fn deliver(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    helo_name: &str,
+    sender: &EmailAddress,
+    mail: &Mail<Vec<u8>>,
+    recipients: &[EmailAddress],
+) -> Result<DeliveryReport, SMTPError> {
+    deliver_on_port(dns_resolver, helo_name, sender, mail, recipients, SMTP_PORT).await
+}
+
+/// # Deliver On Port
+///
+/// This function is `deliver` connecting to the given port of the hosts instead of 25, for tests or private relays.
+pub async fn deliver_on_port(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    helo_name: &str,
+    sender: &EmailAddress,
+    mail: &Mail<Vec<u8>>,
+    recipients: &[EmailAddress],
+    port: u16,
+) -> Result<DeliveryReport, SMTPError> {
+    let domain = recipients
+        .first()
+        .map(|recipient| recipient.normalized().domain)
+        .ok_or(Error::CustomError("No recipients to deliver to".to_string()))?;
+    if recipients.iter().any(|recipient| recipient.normalized().domain != domain) {
+        return Err(Error::CustomError(
+            "The recipients must be of the same domain".to_string(),
+        ));
+    }
+    let data = mail.to_bytes();
+
+    try_mx_hosts(dns_resolver.clone(), &domain, |host| {
+        let dns_resolver = dns_resolver.clone();
+        let data = data.clone();
+        async move { deliver_to_host(dns_resolver, &host, port, helo_name, sender, recipients, &data).await }
+    })
+    .await
+}
+
+/// # Deliver To Host
+///
+/// This function runs a transaction with a host, sending the data if at least one recipient was accepted.
+async fn deliver_to_host(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    host: &str,
+    port: u16,
+    helo_name: &str,
+    sender: &EmailAddress,
+    recipients: &[EmailAddress],
+    data: &[u8],
+) -> Result<DeliveryReport, SMTPError> {
+    let addresses = dns_resolver
+        .lock()
+        .await
+        .lookup_ip(format!("{}.", host).as_str())
+        .await
+        .map_err(|err| Error::DNSError(format!("Failed to resolve {}", host), Some(Box::new(err))))?
+        .iter()
+        .map(|ip| SocketAddr::new(ip, port))
+        .collect::<Vec<_>>();
+
+    let mut client = SMTPClient::connect(&addresses[..]).await?;
+    client.ehlo(helo_name).await?;
+    client.mail_from(sender).await?;
+    let mut replies = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let reply = match client.rcpt_to(recipient).await {
+            Ok(reply) => reply,
+            Err(Error::UnexpectedReply(reply)) => reply,
+            Err(err) => return Err(err),
+        };
+        replies.push((recipient.clone(), reply));
+    }
+
+    if !replies.iter().any(|(_, reply)| reply.is_positive_completion()) {
+        // A temporary rejection may be accepted by another host
+        let rejection = replies
+            .iter()
+            .find(|(_, reply)| reply.is_transient_negative())
+            .or(replies.first())
+            .map(|(_, reply)| reply.clone())
+            .ok_or(Error::CustomError("No recipients to deliver to".to_string()))?;
+        return Err(Error::UnexpectedReply(rejection));
+    }
+    let reply = client.data(data).await?;
+
+    // The mail was accepted, a failed QUIT doesn't change it
+    if let Err(err) = client.quit().await {
+        log::debug!("[📤] QUIT to {} failed: {}", host, err);
+    }
+    Ok(DeliveryReport {
+        recipients: replies,
+        reply,
+    })
+}
+
+/// # Delivery Report
+///
+/// This struct represents the outcome of `deliver`, the mail was sent to the accepted recipients.
+/// The rejected ones must be retried later (4xx) or bounced (5xx) by the caller.
+#[derive(Debug, Clone)]
+pub struct DeliveryReport {
+    /// # Recipients
+    ///
+    /// The reply to the RCPT of each recipient, in order.
+    pub recipients: Vec<(EmailAddress, Message)>,
+    /// # Reply
+    ///
+    /// The reply to the data, which applies to every accepted recipient.
+    pub reply: Message,
+}
+
+impl DeliveryReport {
+    /// # Accepted
+    ///
+    /// This function returns the recipients the mail was delivered to.
+    pub fn accepted(&self) -> impl Iterator<Item = &EmailAddress> {
+        self.recipients
+            .iter()
+            .filter(|(_, reply)| reply.is_positive_completion())
+            .map(|(recipient, _)| recipient)
+    }
+
+    /// # Rejected
+    ///
+    /// This function returns the recipients the server rejected, with its reply.
+    pub fn rejected(&self) -> impl Iterator<Item = &(EmailAddress, Message)> {
+        self.recipients
+            .iter()
+            .filter(|(_, reply)| !reply.is_positive_completion())
+    }
+}
+
+/// # Has No Records
+///
+/// This function returns if the DNS answered that there are no records, other negative answers are failures.
+fn has_no_records(err: &ResolveError) -> bool {
+    matches!(
+        err.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NoError | ResponseCode::NXDomain,
+            ..
+        }
+    )
+}
+
+/// # Random Key
+///
+/// This function returns a random number to shuffle the hosts with the same preference, from the randomly keyed std hasher.
+fn random_key() -> u64 {
+    RandomState::new().build_hasher().finish()
+}
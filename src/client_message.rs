@@ -10,7 +10,7 @@ use super::{command::Commands, errors::Error};
 /// It contains the command and the data.
 /// Usually they are like this:
 ///
-/// ```
+/// ```text
 /// HELO example.com
 /// MAIL FROM: <...>
 /// RCPT TO: <...>
@@ -32,7 +32,7 @@ impl<T> ClientMessage<T> {
     /// # From Bytes
     ///
     /// This function converts a byte array to a ClientMessage struct.
-    pub fn from_bytes<'a>(bytes: Vec<u8>) -> Result<ClientMessage<T>, Error>
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<ClientMessage<T>, Error>
     where
         // The data must be able to be converted from a Vec<u8>
         T: std::convert::From<std::string::String> + Debug,
@@ -1,13 +1,17 @@
 use std::sync::Arc;
 
+use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use tokio::sync::{mpsc::Receiver, Mutex};
 
 use crate::{
     client_message::ClientMessage,
-    connection::{SMTPConnection, SMTPConnectionStatus},
-    errors::Error,
-    mail::EmailAddress,
+    controllers::on_mail_cmd::MailCommand,
+    connection::{PendingAuth, SMTPConnection, SMTPConnectionStatus, MAX_LOCAL_PART_LENGTH},
+    envelope::BodyType,
+    errors::{Error, SMTPError},
+    helo::{address_literal, is_fqdn, HeloIdentity},
+    mail::{decode_base64, EmailAddress},
     message::Message,
     server::Controllers,
     status_code::StatusCodes,
@@ -106,42 +110,384 @@ impl Commands {
 
     /// # Parse MAIL Command Data
     /// 
-    /// This function parses the data from the MAIL command.
+    /// This function parses the data from the MAIL command, the source route of the path is ignored (see `path_mailbox`).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// let sender = Commands::parse_mail_command_data("FROM:<@hosta.nervio.us,@hostb.nervio.us:jean@hostc.nervio.us>".to_string()).unwrap();
+    /// assert_eq!(sender.to_string(), "jean@hostc.nervio.us");
+    /// let sender = Commands::parse_mail_command_data("FROM:<jean@nervio.us> SIZE=1024".to_string()).unwrap();
+    /// assert_eq!(sender.to_string(), "jean@nervio.us");
+    ///
+    /// let recipient = Commands::parse_rcpt_command_data("TO:<@relay.nervio.us:admin@nervio.us>".to_string()).unwrap();
+    /// assert_eq!(recipient.to_string(), "admin@nervio.us");
+    /// let recipient = Commands::parse_rcpt_command_data("TO:<admin@nervio.us>".to_string()).unwrap();
+    /// assert_eq!(recipient.to_string(), "admin@nervio.us");
+    /// ```
     pub fn parse_mail_command_data(data: String) -> Result<EmailAddress, Error> {
+        let email_address = Commands::path_mailbox(&data)?;
+        EmailAddress::from_string(email_address)
+            .map_err(|_| Error::ParseError("Invalid email address".to_string()))
+    }
+
+    /// # Path Mailbox
+    ///
+    /// This function extracts the mailbox of the path in the MAIL or RCPT command data, between `<` and `>`.
+    /// The deprecated source route (`<@hosta,@hostb:user@hostc>`) is accepted and ignored (RFC 5321 4.1.2 and C),
+    /// only the final mailbox is returned.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// assert_eq!(Commands::path_mailbox("FROM:<@hosta,@hostb:user@hostc>").unwrap(), "user@hostc");
+    /// assert_eq!(Commands::path_mailbox(" TO:<user@hostc> NOTIFY=NEVER").unwrap(), "user@hostc");
+    /// assert_eq!(Commands::path_mailbox("FROM:<>").unwrap(), "");
+    /// assert!(Commands::path_mailbox("FROM:<@hosta,@hostb>").is_err());
+    /// assert!(Commands::path_mailbox("FROM:user@hostc").is_err());
+    /// ```
+    pub fn path_mailbox(data: &str) -> Result<&str, Error> {
+        let invalid = || Error::ParseError("Invalid email address".to_string());
+
         // Trim any leading or trailing whitespace
         let data = data.trim();
 
         // Extract the part between '<' and '>'
-        let start = data
-            .find('<')
-            .ok_or(Error::ParseError("Invalid email address".to_string()))?;
-        let end = data
-            .find('>')
-            .ok_or(Error::ParseError("Invalid email address".to_string()))?;
-
-        // Extract and trim the email address part
-        let email_address = &data[start + 1..end];
-        EmailAddress::from_string(email_address)
-            .map_err(|_| Error::ParseError("Invalid email address".to_string()))
+        let start = data.find('<').ok_or_else(invalid)?;
+        let end = data.find('>').ok_or_else(invalid)?;
+        let path = data.get(start + 1..end).ok_or_else(invalid)?;
+
+        // The source route ends at the first ':', the mailbox can't contain it unquoted
+        match path.starts_with('@') {
+            true => path.split_once(':').map(|(_, mailbox)| mailbox).ok_or_else(invalid),
+            false => Ok(path),
+        }
+    }
+
+    /// # Path Within Limits
+    ///
+    /// This function returns if the path of the MAIL or RCPT command data, from `<` to `>`, is at most `max_path_length` octets
+    /// and its local part at most 64 (RFC 5321 4.5.3.1). It's checked before parsing the address, data without a path is left to the parser.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    /// use neo_email::connection::DEFAULT_MAX_PATH_LENGTH;
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let long_local_part = format!("FROM:<{}@nervio.us>", "a".repeat(65));
+    ///     let long_path = format!("TO:<jean@{}nervio.us>", "a.".repeat(125));
+    ///     assert!(Commands::path_within_limits("FROM:<jean@nervio.us> SIZE=1024", DEFAULT_MAX_PATH_LENGTH));
+    ///     assert!(Commands::path_within_limits("FROM:<>", DEFAULT_MAX_PATH_LENGTH));
+    ///     assert!(Commands::path_within_limits(&format!("FROM:<{}@nervio.us>", "a".repeat(64)), DEFAULT_MAX_PATH_LENGTH));
+    ///     assert!(!Commands::path_within_limits(&long_local_part, DEFAULT_MAX_PATH_LENGTH));
+    ///     assert!(!Commands::path_within_limits(&long_path, DEFAULT_MAX_PATH_LENGTH));
+    ///     // The parameters don't count
+    ///     assert!(Commands::path_within_limits(&format!("FROM:<jean@nervio.us> X={}", "a".repeat(300)), DEFAULT_MAX_PATH_LENGTH));
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.workers(2).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(format!("MAIL {}\r\n", long_local_part).as_bytes()).await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "501 Syntax error, path too long");
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///     writer.write_all(format!("RCPT {}\r\n", long_path).as_bytes()).await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "501 Syntax error, path too long");
+    /// }
+    /// ```
+    pub fn path_within_limits(data: &str, max_path_length: usize) -> bool {
+        let Some(start) = data.find('<') else {
+            return true;
+        };
+        let path = match data[start..].find('>') {
+            Some(end) => &data[start..=start + end],
+            // Unterminated, everything up to the parameters
+            None => data[start..].split_whitespace().next().unwrap_or_default(),
+        };
+        if path.len() > max_path_length {
+            return false;
+        }
+
+        // The local part of the mailbox, after the source route if any (`<@relay.nervio.us:jean@nervio.us>`)
+        let mailbox = path.trim_start_matches('<').trim_end_matches('>');
+        let mailbox = match mailbox.starts_with('@') {
+            true => mailbox.split_once(':').map(|(_, mailbox)| mailbox).unwrap_or_default(),
+            false => mailbox,
+        };
+        let local_part = mailbox.rsplit_once('@').map(|(local_part, _)| local_part).unwrap_or(mailbox);
+        local_part.len() <= MAX_LOCAL_PART_LENGTH
+    }
+
+    /// # Has MAIL Parameter
+    ///
+    /// This function returns if the MAIL command data has the given ESMTP parameter after the address, for example `REQUIRETLS` or `BODY=8BITMIME`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// assert!(Commands::has_mail_parameter("FROM:<jean@nervio.us> requiretls", "REQUIRETLS"));
+    /// assert!(Commands::has_mail_parameter("FROM:<jean@nervio.us> BODY=8BITMIME", "BODY"));
+    /// assert!(!Commands::has_mail_parameter("FROM:<requiretls@nervio.us>", "REQUIRETLS"));
+    /// ```
+    pub fn has_mail_parameter(data: &str, parameter: &str) -> bool {
+        Commands::parse_mail_parameters(data).contains_key(&parameter.to_ascii_uppercase())
+    }
+
+    /// # Parse MAIL Parameters
+    ///
+    /// This function parses the ESMTP parameters after the address of the MAIL command data (RFC 5321 4.1.2),
+    /// the keywords are uppercased as they are case-insensitive, and the values are kept as given (None for `SMTPUTF8`).
+    /// If a keyword is repeated only the first one is kept.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// let parameters = Commands::parse_mail_parameters("FROM:<jean@nervio.us> size=1000 SMTPUTF8 ENVID=QQ314159");
+    /// assert_eq!(parameters.len(), 3);
+    /// assert_eq!(parameters["SIZE"], Some("1000".to_string()));
+    /// assert_eq!(parameters["SMTPUTF8"], None);
+    /// assert_eq!(parameters["ENVID"], Some("QQ314159".to_string()));
+    ///
+    /// assert!(Commands::parse_mail_parameters("FROM:<size=1@nervio.us>").is_empty());
+    /// assert!(Commands::parse_mail_parameters("FROM:jean@nervio.us SIZE=1").is_empty());
+    /// ```
+    pub fn parse_mail_parameters(data: &str) -> HashMap<String, Option<String>> {
+        let mut parameters = HashMap::new();
+        let Some(end) = data.find('>') else {
+            return parameters;
+        };
+
+        for parameter in data[end + 1..].split_whitespace() {
+            let (keyword, value) = match parameter.split_once('=') {
+                Some((keyword, value)) => (keyword, Some(value.to_string())),
+                None => (parameter, None),
+            };
+            parameters.entry(keyword.to_ascii_uppercase()).or_insert(value);
+        }
+
+        parameters
+    }
+
+    /// # Parse MAIL Command
+    ///
+    /// This function parses the sender and the ESMTP parameters of the MAIL command data, see `parse_mail_command_data`
+    /// and `parse_mail_parameters`. It fails without a valid address, including the null sender `<>`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// let (sender, parameters) = Commands::parse_mail_command("FROM:<a@b.com> SIZE=1000 BODY=7BIT").unwrap();
+    /// assert_eq!(sender.to_string(), "a@b.com");
+    /// assert_eq!(parameters.len(), 2);
+    /// assert_eq!(parameters.get("SIZE"), Some(&Some("1000".to_string())));
+    /// assert_eq!(parameters.get("BODY"), Some(&Some("7BIT".to_string())));
+    ///
+    /// let (_, parameters) = Commands::parse_mail_command("FROM:<a@b.com> Body=8BITMIME smtputf8").unwrap();
+    /// assert_eq!(parameters.get("BODY"), Some(&Some("8BITMIME".to_string())));
+    /// assert_eq!(parameters.get("SMTPUTF8"), Some(&None));
+    ///
+    /// assert!(Commands::parse_mail_command("FROM: SIZE=1000").is_err());
+    /// assert!(Commands::parse_mail_command("FROM:<> SIZE=1000").is_err());
+    /// ```
+    pub fn parse_mail_command(data: &str) -> Result<(EmailAddress, HashMap<String, Option<String>>), Error> {
+        let sender = Commands::parse_mail_command_data(data.to_string())?;
+        Ok((sender, Commands::parse_mail_parameters(data)))
+    }
+
+    /// # Parse BODY Parameter
+    ///
+    /// This function parses the MAIL command `BODY` parameter (RFC 6152), None if it isn't given.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    /// use neo_email::envelope::BodyType;
+    ///
+    /// let body = Commands::parse_body_parameter("FROM:<jean@nervio.us> BODY=8BITMIME").unwrap();
+    /// assert_eq!(body, Some(BodyType::EightBitMime));
+    /// let body = Commands::parse_body_parameter("FROM:<jean@nervio.us> SIZE=1024 body=7bit").unwrap();
+    /// assert_eq!(body, Some(BodyType::SevenBit));
+    /// assert_eq!(Commands::parse_body_parameter("FROM:<body=8bitmime@nervio.us>").unwrap(), None);
+    ///
+    /// assert!(Commands::parse_body_parameter("FROM:<jean@nervio.us> BODY=8BIT").is_err());
+    /// assert!(Commands::parse_body_parameter("FROM:<jean@nervio.us> BODY").is_err());
+    /// ```
+    pub fn parse_body_parameter(data: &str) -> Result<Option<BodyType>, Error> {
+        match Commands::parse_mail_parameters(data).remove("BODY") {
+            Some(value) => value
+                .and_then(|value| BodyType::from_parameter(&value))
+                .map(Some)
+                .ok_or(Error::ParseError("Invalid BODY parameter".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// # Parse SIZE Parameter
+    ///
+    /// This function parses the MAIL command `SIZE` parameter (RFC 1870), the size of the message declared by the client,
+    /// None if it isn't given.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    ///
+    /// assert_eq!(Commands::parse_size_parameter("FROM:<jean@nervio.us> SIZE=20480").unwrap(), Some(20480));
+    /// assert_eq!(Commands::parse_size_parameter("FROM:<jean@nervio.us> BODY=7BIT").unwrap(), None);
+    ///
+    /// assert!(Commands::parse_size_parameter("FROM:<jean@nervio.us> SIZE=big").is_err());
+    /// assert!(Commands::parse_size_parameter("FROM:<jean@nervio.us> SIZE").is_err());
+    /// ```
+    pub fn parse_size_parameter(data: &str) -> Result<Option<usize>, Error> {
+        match Commands::parse_mail_parameters(data).remove("SIZE") {
+            Some(value) => value
+                .and_then(|value| value.parse::<usize>().ok())
+                .map(Some)
+                .ok_or(Error::ParseError("Invalid SIZE parameter".to_string())),
+            None => Ok(None),
+        }
+    }
+
+    /// # Parse AUTH Parameter
+    ///
+    /// This function parses the MAIL command `AUTH` parameter (RFC 4954 5) decoding its xtext, None if it isn't given.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::{parse_auth_plain, Commands};
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_auth::{AuthOutcome, OnAuthController};
+    /// use neo_email::controllers::on_email::OnEmailController;
+    /// use neo_email::mail::Mail;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_auth(_conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<AuthOutcome, Message> {
+    ///     let (_, user, _) = parse_auth_plain(&data).map_err(|_| Message::new(StatusCodes::SyntaxError, "Invalid".to_string()))?;
+    ///     Ok(AuthOutcome::new(Message::new(StatusCodes::AuthenticationSuccessful, "Authenticated".to_string()), user))
+    /// }
+    ///
+    /// // Replies with the AUTH of the envelope and if the connection is still authenticated
+    /// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+    ///     let conn = conn.lock().await;
+    ///     let auth = conn.envelope.as_ref().and_then(|envelope| envelope.auth.clone());
+    ///     Message::new(StatusCodes::OK, format!("auth={:?} authenticated={}", auth, conn.authenticated))
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     assert_eq!(Commands::parse_auth_parameter("FROM:<jean@nervio.us> AUTH=a@b").unwrap(), Some("a@b".to_string()));
+    ///     assert_eq!(Commands::parse_auth_parameter("FROM:<jean@nervio.us> auth=<>").unwrap(), Some("<>".to_string()));
+    ///     assert_eq!(Commands::parse_auth_parameter("FROM:<jean@nervio.us> AUTH=a+2Bb@c").unwrap(), Some("a+b@c".to_string()));
+    ///     assert_eq!(Commands::parse_auth_parameter("FROM:<auth=a@b>").unwrap(), None);
+    ///     assert!(Commands::parse_auth_parameter("FROM:<jean@nervio.us> AUTH=a+2@b").is_err());
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .on_auth(OnAuthController::new(on_auth))
+    ///         .on_email(OnEmailController::new(on_email))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     // Ignored before the authentication
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us> AUTH=a@b\r\nRSET\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     // \0jean\0password
+    ///     writer.write_all(b"AUTH PLAIN AGplYW4AcGFzc3dvcmQ=\r\n").await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "235 Authenticated");
+    ///
+    ///     let messages = [
+    ///         ("MAIL FROM:<jean@nervio.us> AUTH=a@b", "250 auth=Some(\"a@b\") authenticated=true"),
+    ///         ("MAIL FROM:<jean@nervio.us> AUTH=c@d", "250 auth=Some(\"c@d\") authenticated=true"),
+    ///         ("MAIL FROM:<jean@nervio.us>", "250 auth=None authenticated=true"),
+    ///     ];
+    ///     for (mail, reply) in messages {
+    ///         writer.write_all(format!("{}\r\n", mail).as_bytes()).await.unwrap();
+    ///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///         writer.write_all(b"RCPT TO:<admin@nervio.us>\r\n").await.unwrap();
+    ///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///         writer.write_all(b"DATA\r\n").await.unwrap();
+    ///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("354 "));
+    ///         writer.write_all(b"Subject: Test\r\n\r\nHello\r\n.\r\n").await.unwrap();
+    ///         assert_eq!(lines.next_line().await.unwrap().unwrap(), reply);
+    ///     }
+    /// }
+    /// ```
+    pub fn parse_auth_parameter(data: &str) -> Result<Option<String>, Error> {
+        let Some(value) = Commands::parse_mail_parameters(data).remove("AUTH") else {
+            return Ok(None);
+        };
+        let invalid = || Error::ParseError("Invalid AUTH parameter".to_string());
+        let value = value.ok_or_else(invalid)?;
+        if value == "<>" {
+            return Ok(Some(value));
+        }
+
+        // xtext, `+` followed by two uppercase hex digits encodes a byte (RFC 3461 4)
+        let mut decoded = Vec::with_capacity(value.len());
+        let mut bytes = value.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'+' {
+                decoded.push(byte);
+                continue;
+            }
+            let hex = [bytes.next().ok_or_else(invalid)?, bytes.next().ok_or_else(invalid)?];
+            let hex = std::str::from_utf8(&hex).map_err(|_| invalid())?;
+            decoded.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+        }
+        let decoded = String::from_utf8(decoded).map_err(|_| invalid())?;
+        if decoded.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Some(decoded))
     }
 
     /// # Parse RCPT Command Data
     /// 
-    /// This function parses the data from the RCPT command.
+    /// This function parses the data from the RCPT command, the source route of the path is ignored like in MAIL.
     pub fn parse_rcpt_command_data(data: String) -> Result<EmailAddress, Error> {
-        // Trim any leading or trailing whitespace
-        let data = data.trim();
-
-        // Extract the part between '<' and '>'
-        let start = data
-            .find('<')
-            .ok_or(Error::ParseError("Invalid email address".to_string()))?;
-        let end = data
-            .find('>')
-            .ok_or(Error::ParseError("Invalid email address".to_string()))?;
-
-        // Extract and trim the email address part
-        let email_address = &data[start + 1..end];
+        let email_address = Commands::path_mailbox(&data)?;
         EmailAddress::from_string(email_address)
             .map_err(|_| Error::ParseError("Invalid email address".to_string()))
     }
@@ -171,80 +517,178 @@ where
         return Err(Error::UnknownCommand(client_message.command.clone()));
     }
 
-    let result = match client_message.command {
-        Commands::HELO => (
-            vec![Message::builder()
-                .status(StatusCodes::OK)
-                .message(format!("Hello {}", "unknown"))
-                .build()],
-            SMTPConnectionStatus::WaitingCommand,
-        ),
-        Commands::EHLO => {
-            let mut ehlo_messages = vec![
-                Message::builder()
-                    .status(StatusCodes::OK)
-                    .message("Hello".to_string())
-                    .build(),
-                Message::builder()
-                    .status(StatusCodes::OK)
-                    .message(format!("SIZE {}", max_size))
-                    .build(),
-                Message::builder()
-                    .status(StatusCodes::OK)
-                    .message("8BITMIME".to_string())
-                    .build(),
-                Message::builder()
-                    .status(StatusCodes::OK)
-                    .message("PIPELINING".to_string())
-                    .build(),
-                Message::builder()
-                    .status(StatusCodes::OK)
-                    .message("HELP".to_string())
-                    .build(),
-            ];
+    // Validate the HELO/EHLO identity (if there is a HELO policy)
+    if client_message.command == Commands::HELO || client_message.command == Commands::EHLO {
+        let guarded_conn = conn.lock().await;
+        let helo_policy = guarded_conn.helo_policy;
+        let dns_resolver = guarded_conn.dns_resolver.clone();
+        let peer_addr = guarded_conn.get_peer_addr().await;
+        drop(guarded_conn);
 
-            let conn = conn.lock().await;
-            if !conn.use_tls {
-                ehlo_messages.push(
-                    Message::builder()
+        if let Some(helo_policy) = helo_policy {
+            let valid = match peer_addr {
+                Ok(peer_addr) => {
+                    helo_policy
+                        .validate(&client_message.data, peer_addr.ip(), dns_resolver)
+                        .await
+                }
+                Err(_) => false,
+            };
+
+            if !valid {
+                return Ok((
+                    vec![Message::builder()
+                        .status(StatusCodes::RequestedActionNotTakenMailboxUnavailable)
+                        .message("HELO/EHLO identity rejected".to_string())
+                        .build()],
+                    SMTPConnectionStatus::WaitingCommand,
+                ));
+            }
+        }
+    }
+
+    let mut result = match client_message.command {
+        Commands::HELO => {
+            if conn.lock().await.require_ehlo {
+                (
+                    vec![Message::builder()
+                        .status(StatusCodes::CommandNotImplemented)
+                        .message("HELO not accepted, use EHLO".to_string())
+                        .build()],
+                    SMTPConnectionStatus::WaitingCommand,
+                )
+            } else {
+                (
+                    vec![Message::builder()
                         .status(StatusCodes::OK)
-                        .message("STARTTLS".to_string())
-                        .build(),
+                        .message(format!("Hello {}", "unknown"))
+                        .build()],
+                    SMTPConnectionStatus::WaitingCommand,
                 )
             }
+        }
+        Commands::EHLO => {
+            let conn = conn.lock().await;
+            let mut keywords = vec!["Hello".to_string(), format!("SIZE {}", max_size)];
+
+            if conn.eight_bit_mime {
+                keywords.push("8BITMIME".to_string());
+            }
+
+            if conn.pipelining {
+                keywords.push("PIPELINING".to_string());
+            }
+
+            if conn.smtputf8_enabled {
+                keywords.push("SMTPUTF8".to_string());
+            }
+
+            keywords.push("HELP".to_string());
+
+            if conn.tls_available && !conn.use_tls {
+                keywords.push("STARTTLS".to_string());
+            }
+
+            // REQUIRETLS is only offered once the session is protected (RFC 8689 2)
+            if conn.use_tls && conn.requiretls_enabled {
+                keywords.push("REQUIRETLS".to_string());
+            }
 
             if controllers.on_auth.is_some() {
-                ehlo_messages.push(
-                    Message::builder()
-                        .status(StatusCodes::OK)
-                        .message(
-                            "AUTH PLAIN LOGIN CRAM-MD5 DIGEST-MD5 GSSAPI NTLM XOAUTH2".to_string(),
-                        )
-                        .build(),
-                );
+                keywords.push("AUTH PLAIN LOGIN CRAM-MD5 DIGEST-MD5 GSSAPI NTLM XOAUTH2".to_string());
             }
 
             drop(conn);
 
-            (ehlo_messages, SMTPConnectionStatus::WaitingCommand)
+            (Message::multiline(StatusCodes::OK, keywords), SMTPConnectionStatus::WaitingCommand)
+        }
+        Commands::MAIL | Commands::AUTH if tls_required(&conn).await => (
+            vec![Message::builder()
+                .status(StatusCodes::MustIssueStartTLSFirst)
+                .message("Must issue a STARTTLS command first".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
+        // A transaction is in progress, it ends with DATA or RSET (RFC 5321 4.1.4)
+        Commands::MAIL if conn.lock().await.envelope.is_some() => (
+            vec![Message::builder()
+                .status(StatusCodes::BadSequenceOfCommands)
+                .message("Bad sequence of commands".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
+        Commands::MAIL | Commands::RCPT
+            if !Commands::path_within_limits(&client_message.data, conn.lock().await.max_path_length) =>
+        {
+            (
+                vec![Message::builder()
+                    .status(StatusCodes::SyntaxErrorInParametersOrArguments)
+                    .message("Syntax error, path too long".to_string())
+                    .build()],
+                SMTPConnectionStatus::WaitingCommand,
+            )
         }
+        Commands::MAIL if requires_tls_over_plaintext(&conn, &client_message.data).await => (
+            vec![Message::builder()
+                .status(StatusCodes::MustIssueStartTLSFirst)
+                .message("REQUIRETLS needs a TLS connection, issue STARTTLS first".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
+        Commands::MAIL if !body_type_accepted(&conn, &client_message.data).await => (
+            vec![Message::builder()
+                .status(StatusCodes::ParametersNotRecognized)
+                .message("BODY parameter not supported".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
+        Commands::MAIL
+            if Commands::has_mail_parameter(&client_message.data, "SMTPUTF8") && !conn.lock().await.smtputf8_enabled =>
+        {
+            (
+                vec![Message::builder()
+                    .status(StatusCodes::ParametersNotRecognized)
+                    .message("SMTPUTF8 parameter not supported".to_string())
+                    .build()],
+                SMTPConnectionStatus::WaitingCommand,
+            )
+        }
+        Commands::MAIL | Commands::RCPT if !address_encoding_accepted(&conn, client_message).await => (
+            vec![Message::builder()
+                .status(StatusCodes::MailboxNameNotAllowed)
+                .enhanced(5, 6, 7)
+                .message("Non-ASCII address requires SMTPUTF8".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
+        Commands::MAIL if declared_size_exceeds(&client_message.data, max_size) => (
+            vec![Message::builder()
+                .status(StatusCodes::ExceededStorageAllocation)
+                .enhanced(5, 3, 4)
+                .message("Message size exceeds fixed maximum message size".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        ),
         Commands::MAIL => {
             if let Some(on_mail_cmd) = &controllers.on_mail_cmd {
                 let on_mail_cmd = on_mail_cmd.0.clone();
-                match on_mail_cmd(conn.clone(), client_message.data.clone()).await {
-                    Ok(response) => {
-                        return Ok((vec![response], SMTPConnectionStatus::WaitingCommand))
-                    }
+                let mail_command = MailCommand::new(client_message.data.clone());
+                match on_mail_cmd(conn.clone(), mail_command).await {
+                    Ok(response) => (vec![response], SMTPConnectionStatus::WaitingCommand),
                     Err(response) => return Ok((vec![response], SMTPConnectionStatus::Closed)),
                 }
             } else {
-                (
-                    vec![Message::builder()
+                let echo_addresses = conn.lock().await.echo_addresses;
+                let sender = Commands::parse_mail_command_data(client_message.data.clone());
+                let response = match (echo_addresses, sender) {
+                    (true, Ok(sender)) => Message::sender_ok(Some(&sender)),
+                    (true, Err(_)) if client_message.data.contains("<>") => Message::sender_ok(None),
+                    _ => Message::builder()
                         .status(StatusCodes::OK)
                         .message("Ok".to_string())
-                        .build()],
-                    SMTPConnectionStatus::WaitingCommand,
-                )
+                        .build(),
+                };
+                (vec![response], SMTPConnectionStatus::WaitingCommand)
             }
         }
         Commands::RCPT => {
@@ -255,11 +699,8 @@ where
                     Err(response) => (vec![response], SMTPConnectionStatus::Closed),
                 }
             } else {
-                let last_command = conn.lock().await;
-                let last_command = last_command
-                    .tracing_commands
-                    .last()
-                    .unwrap_or(&Commands::HELO);
+                let guarded_conn = conn.lock().await;
+                let last_command = guarded_conn.last_command().unwrap_or(&Commands::HELO);
 
                 if last_command != &Commands::MAIL && last_command != &Commands::RCPT {
                     (
@@ -270,23 +711,41 @@ where
                         SMTPConnectionStatus::WaitingCommand,
                     )
                 } else {
+                    // Without controller no recipient is routed, they get the default response (`550 Relay access denied`)
                     (
-                        vec![Message::builder()
-                            .status(StatusCodes::OK)
-                            .message("Ok".to_string())
-                            .build()],
+                        vec![guarded_conn.default_rcpt_response.clone()],
                         SMTPConnectionStatus::WaitingCommand,
                     )
                 }
             }
         }
-        Commands::DATA => (
-            vec![Message::builder()
-                .status(StatusCodes::StartMailInput)
-                .message("Start mail input; end with <CRLF>.<CRLF>".to_string())
-                .build()],
-            SMTPConnectionStatus::WaitingData,
-        ),
+        Commands::DATA => {
+            // DATA requires a transaction (MAIL FROM) with at least one recipient
+            let has_recipients = conn
+                .lock()
+                .await
+                .envelope
+                .as_ref()
+                .is_some_and(|envelope| !envelope.recipients.is_empty());
+
+            if has_recipients {
+                (
+                    vec![Message::builder()
+                        .status(StatusCodes::StartMailInput)
+                        .message("Start mail input; end with <CRLF>.<CRLF>".to_string())
+                        .build()],
+                    SMTPConnectionStatus::WaitingData,
+                )
+            } else {
+                (
+                    vec![Message::builder()
+                        .status(StatusCodes::BadSequenceOfCommands)
+                        .message("Bad sequence of commands, MAIL and RCPT required".to_string())
+                        .build()],
+                    SMTPConnectionStatus::WaitingCommand,
+                )
+            }
+        }
         Commands::RSET => (
             vec![Message::builder()
                 .status(StatusCodes::OK)
@@ -303,15 +762,32 @@ where
                 .build()],
             SMTPConnectionStatus::WaitingCommand,
         ),
-        Commands::EXPN => (
-            vec![Message::builder()
-                .status(StatusCodes::CommandNotImplemented)
-                .message(
-                    "Cannot EXPN user, but will accept message and attempt delivery".to_string(),
+        Commands::EXPN => {
+            if let Some(on_expn_cmd) = &controllers.on_expn_cmd {
+                let on_expn_cmd = on_expn_cmd.0.clone();
+                match on_expn_cmd(conn.clone(), client_message.data.clone()).await {
+                    Ok(members) => match stream_expn_members(conn.clone(), members).await {
+                        // The last member is the final line of the reply
+                        Ok(last_member) => (vec![last_member], SMTPConnectionStatus::WaitingCommand),
+                        Err(err) => {
+                            log::error!("[📋] Error streaming the mailing list members: {}", err);
+                            (vec![], SMTPConnectionStatus::Closed)
+                        }
+                    },
+                    Err(response) => (vec![response], SMTPConnectionStatus::WaitingCommand),
+                }
+            } else {
+                (
+                    vec![Message::builder()
+                        .status(StatusCodes::CommandNotImplemented)
+                        .message(
+                            "Cannot EXPN user, but will accept message and attempt delivery".to_string(),
+                        )
+                        .build()],
+                    SMTPConnectionStatus::WaitingCommand,
                 )
-                .build()],
-            SMTPConnectionStatus::WaitingCommand,
-        ),
+            }
+        }
         Commands::HELP => (
             vec![Message::builder()
                 .status(StatusCodes::HelpMessage)
@@ -334,34 +810,68 @@ where
             SMTPConnectionStatus::Closed,
         ),
         Commands::AUTH => {
-            if let Some(on_auth) = &controllers.on_auth {
-                let on_auth = on_auth.0.clone();
-                match on_auth(conn.clone(), client_message.data.clone()).await {
-                    Ok(response) => (vec![response], SMTPConnectionStatus::WaitingCommand),
-                    Err(response) => return Ok((vec![response], SMTPConnectionStatus::Closed)),
-                }
-            } else {
+            let already_authenticated = {
+                let conn = conn.lock().await;
+                conn.reject_repeated_auth && conn.authenticated
+            };
+
+            if already_authenticated {
+                log::trace!("[🔑🚫] AUTH received on an already authenticated connection");
                 (
                     vec![Message::builder()
-                        .status(StatusCodes::CommandNotImplemented)
-                        .message("Command not recognized".to_string())
+                        .status(StatusCodes::BadSequenceOfCommands)
+                        .message("Already authenticated".to_string())
                         .build()],
                     SMTPConnectionStatus::WaitingCommand,
                 )
+            } else if let Some(pending_auth) = controllers
+                .on_auth
+                .as_ref()
+                .and_then(|_| pending_auth_for(&client_message.data))
+            {
+                // Without initial response the credentials come in the next lines (RFC 4954 4)
+                let challenge = auth_challenge(&pending_auth);
+                conn.lock().await.pending_auth = Some(pending_auth);
+                (vec![challenge], SMTPConnectionStatus::WaitingCommand)
+            } else {
+                dispatch_auth(conn.clone(), &controllers, client_message.data.clone()).await
             }
         }
         Commands::STARTTLS => {
-            let conn = conn.lock().await;
+            let mut conn = conn.lock().await;
+
+            let limit_reached = conn
+                .max_starttls_attempts
+                .is_some_and(|max_attempts| conn.starttls_attempts >= max_attempts);
 
-            if conn.use_tls {
+            // STARTTLS is only advertised without TLS and with an acceptor, otherwise it's not implemented for the client,
+            // once advertised a failure to start the handshake is temporary (454)
+            if !conn.tls_available || conn.use_tls {
+                let reason = if conn.use_tls { "Already using TLS" } else { "TLS not supported" };
                 (
                     vec![Message::builder()
-                        .status(StatusCodes::TransactionFailed)
-                        .message("Already using TLS".to_string())
+                        .status(StatusCodes::CommandNotImplemented)
+                        .message(reason.to_string())
                         .build()],
                     SMTPConnectionStatus::WaitingCommand,
                 )
+            } else if limit_reached {
+                log::trace!("[🌐🔒🚫] Max STARTTLS attempts reached");
+                let status = if conn.close_on_starttls_limit {
+                    SMTPConnectionStatus::Closed
+                } else {
+                    SMTPConnectionStatus::WaitingCommand
+                };
+
+                (
+                    vec![Message::builder()
+                        .status(StatusCodes::TLSNotAvailable)
+                        .message("Too many STARTTLS attempts, TLS not available".to_string())
+                        .build()],
+                    status,
+                )
             } else {
+                conn.starttls_attempts += 1;
                 (
                     vec![Message::builder()
                         .status(StatusCodes::SMTPServiceReady)
@@ -391,10 +901,479 @@ where
     };
 
     let mut guarded_conn = conn.lock().await;
-    guarded_conn
-        .tracing_commands
-        .push(client_message.command.clone());
+    // A recipient size limit only applies to the RCPT that set it
+    let recipient_max_size = guarded_conn.recipient_max_size.take();
+    // Only accepted commands change the transaction
+    let accepted = matches!(
+        result.0.last().map(|message| &message.status),
+        Some(StatusCodes::OK | StatusCodes::UserNotLocalWillForward)
+    );
+    if accepted && result.1 != SMTPConnectionStatus::Closed {
+        match client_message.command {
+            // A new HELO/EHLO clears the transaction, keeping the TLS and authentication state (RFC 5321 4.1.4)
+            Commands::HELO | Commands::EHLO => {
+                guarded_conn.reset_transaction();
+                guarded_conn.helo = Some(client_message.data.trim().to_string());
+            }
+            // MAIL FROM starts a new transaction
+            Commands::MAIL => {
+                let sender = Commands::parse_mail_command_data(client_message.data.clone()).ok();
+                let require_tls = guarded_conn.requiretls_enabled
+                    && Commands::has_mail_parameter(&client_message.data, "REQUIRETLS");
+                let body_type = Commands::parse_body_parameter(&client_message.data).ok().flatten();
+                let declared_size = Commands::parse_size_parameter(&client_message.data).ok().flatten();
+                let smtputf8 = guarded_conn.smtputf8_enabled
+                    && Commands::has_mail_parameter(&client_message.data, "SMTPUTF8");
+                // The AUTH parameter of unauthenticated clients is ignored (RFC 4954 5)
+                let auth = match guarded_conn.authenticated {
+                    true => Commands::parse_auth_parameter(&client_message.data).ok().flatten(),
+                    false => None,
+                };
+                guarded_conn.start_transaction(sender);
+                if let Some(envelope) = guarded_conn.envelope.as_mut() {
+                    envelope.require_tls = require_tls;
+                    envelope.body_type = body_type;
+                    envelope.declared_size = declared_size;
+                    envelope.auth = auth;
+                    envelope.smtputf8 = smtputf8;
+                }
+            }
+            // RCPT TO adds a recipient to the transaction
+            Commands::RCPT => {
+                let recipient = Commands::parse_rcpt_command_data(client_message.data.clone());
+                let dedupe_recipients = guarded_conn.dedupe_recipients;
+                if let (Some(envelope), Ok(recipient)) = (guarded_conn.envelope.as_mut(), recipient)
+                {
+                    if dedupe_recipients && envelope.has_recipient(&recipient) {
+                        log::trace!("[📧] Duplicate recipient {} ignored", recipient);
+                        result.0 = vec![Message::builder()
+                            .status(StatusCodes::OK)
+                            .message("Duplicate recipient ignored".to_string())
+                            .build()];
+                    } else {
+                        envelope.recipients.push(recipient);
+                    }
+
+                    if let Some(recipient_max_size) = recipient_max_size {
+                        envelope.max_size = Some(
+                            envelope.max_size.map_or(recipient_max_size, |max_size| max_size.min(recipient_max_size)),
+                        );
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The mail buffer is sized once for the declared message when the data starts instead of growing while it's received,
+    // never above max_size as the client may lie
+    if client_message.command == Commands::DATA && result.1 == SMTPConnectionStatus::WaitingData {
+        let declared_size = guarded_conn.envelope.as_ref().and_then(|envelope| envelope.declared_size);
+        if let Some(declared_size) = declared_size {
+            guarded_conn.mail_buffer.reserve(declared_size.min(max_size));
+        }
+    }
+    guarded_conn.record_command(client_message.command.clone());
     drop(guarded_conn);
 
     Ok(result)
 }
+
+/// # Requires TLS Over Plaintext
+///
+/// This function returns if the MAIL command asks for REQUIRETLS on a connection without TLS, that must be rejected with 530.
+async fn requires_tls_over_plaintext<B>(conn: &Arc<Mutex<SMTPConnection<B>>>, data: &str) -> bool {
+    let conn = conn.lock().await;
+    conn.requiretls_enabled && !conn.use_tls && Commands::has_mail_parameter(data, "REQUIRETLS")
+}
+
+/// # TLS Required
+///
+/// This function returns if the connection is still in plaintext while the client must upgrade it (see `SMTPServer::require_tls_except`).
+async fn tls_required<B>(conn: &Arc<Mutex<SMTPConnection<B>>>) -> bool {
+    let conn = conn.lock().await;
+    conn.require_tls && !conn.use_tls
+}
+
+/// # Body Type Accepted
+///
+/// This function returns if the MAIL command `BODY` parameter is valid and supported, 8BITMIME only when enabled
+/// and never BINARYMIME as BDAT isn't implemented.
+async fn body_type_accepted<B>(conn: &Arc<Mutex<SMTPConnection<B>>>, data: &str) -> bool {
+    match Commands::parse_body_parameter(data) {
+        Ok(None | Some(BodyType::SevenBit)) => true,
+        Ok(Some(BodyType::EightBitMime)) => conn.lock().await.eight_bit_mime,
+        Ok(Some(BodyType::BinaryMime)) | Err(_) => false,
+    }
+}
+
+/// # Address Encoding Accepted
+///
+/// This function returns if the MAIL or RCPT address is ASCII, or the transaction is SMTPUTF8: the MAIL command
+/// with the `SMTPUTF8` parameter, or the RCPT of a transaction started by it (RFC 6531 3.5).
+async fn address_encoding_accepted<B>(conn: &Arc<Mutex<SMTPConnection<B>>>, client_message: &ClientMessage<String>) -> bool {
+    let address = match client_message.command {
+        Commands::MAIL => Commands::parse_mail_command_data(client_message.data.clone()),
+        _ => Commands::parse_rcpt_command_data(client_message.data.clone()),
+    };
+    // The invalid addresses are left to the usual handling
+    if address.map_or(true, |address| address.is_ascii()) {
+        return true;
+    }
+
+    let conn = conn.lock().await;
+    match client_message.command {
+        Commands::MAIL => conn.smtputf8_enabled && Commands::has_mail_parameter(&client_message.data, "SMTPUTF8"),
+        _ => conn.envelope.as_ref().is_some_and(|envelope| envelope.smtputf8),
+    }
+}
+
+/// # Declared Size Exceeds
+///
+/// This function returns if the MAIL command `SIZE` parameter declares a message bigger than the max size,
+/// the size received in DATA is still checked for the clients that lie.
+fn declared_size_exceeds(data: &str, max_size: usize) -> bool {
+    Commands::parse_size_parameter(data).is_ok_and(|declared_size| declared_size.is_some_and(|size| size > max_size))
+}
+
+/// # Dispatch AUTH
+///
+/// This function calls the AUTH controller with the mechanism and the credentials, recording who authenticated on success.
+async fn dispatch_auth<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    controllers: &Controllers<B>,
+    data: String,
+) -> (Vec<Message>, SMTPConnectionStatus)
+where
+    B: 'static + Default + Send + Sync + Clone,
+{
+    let Some(on_auth) = &controllers.on_auth else {
+        return (
+            vec![Message::builder()
+                .status(StatusCodes::CommandNotImplemented)
+                .message("Command not recognized".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        );
+    };
+
+    let on_auth = on_auth.0.clone();
+    match on_auth(conn.clone(), data).await {
+        Ok(outcome) => {
+            if outcome.message.status == StatusCodes::AuthenticationSuccessful {
+                let mut conn = conn.lock().await;
+                conn.authenticated = true;
+                conn.auth_identity = outcome.identity;
+                conn.auth_authzid = outcome.authzid;
+            }
+            (vec![outcome.message], SMTPConnectionStatus::WaitingCommand)
+        }
+        Err(response) => (vec![response], SMTPConnectionStatus::Closed),
+    }
+}
+
+/// # Pending AUTH For
+///
+/// This function returns the exchange to start for the data of an AUTH command that needs more lines from the client,
+/// None when the controller can be called with the data as is.
+fn pending_auth_for(data: &str) -> Option<PendingAuth> {
+    let mut fields = data.split_whitespace();
+    let mechanism = fields.next()?.to_uppercase();
+    match (mechanism.as_str(), fields.next()) {
+        ("PLAIN", None) => Some(PendingAuth::Plain),
+        ("LOGIN", None) => Some(PendingAuth::LoginUsername),
+        // The username as initial response, only the password is asked
+        ("LOGIN", Some(username)) => Some(PendingAuth::LoginPassword(username.to_string())),
+        _ => None,
+    }
+}
+
+/// # AUTH Challenge
+///
+/// This function returns the 334 challenge asking the client for the next line of the exchange.
+fn auth_challenge(pending_auth: &PendingAuth) -> Message {
+    let challenge = match pending_auth {
+        PendingAuth::Plain => "",
+        // "Username:" and "Password:" in base64
+        PendingAuth::LoginUsername => "VXNlcm5hbWU6",
+        PendingAuth::LoginPassword(_) => "UGFzc3dvcmQ6",
+    };
+    Message::builder()
+        .status(StatusCodes::ServerChallenge)
+        .message(challenge.to_string())
+        .build()
+}
+
+/// # Handle AUTH Response
+///
+/// This function handles the line sent by the client after a 334 challenge, `*` cancels the authentication.
+/// The AUTH controller is called once the exchange is complete, with `PLAIN <base64>` as if the credentials were sent
+/// with the AUTH command, or `LOGIN <base64 username> <base64 password>` (see `parse_auth_login`).
+pub(crate) async fn handle_auth_response<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    controllers: &Controllers<B>,
+    pending_auth: PendingAuth,
+    response: String,
+) -> (Vec<Message>, SMTPConnectionStatus)
+where
+    B: 'static + Default + Send + Sync + Clone,
+{
+    let response = response.trim();
+    if response == "*" {
+        log::trace!("[🔑] AUTH {:?} cancelled by the client", pending_auth);
+        return (
+            vec![Message::builder()
+                .status(StatusCodes::SyntaxErrorInParametersOrArguments)
+                .message("Authentication cancelled".to_string())
+                .build()],
+            SMTPConnectionStatus::WaitingCommand,
+        );
+    }
+
+    match pending_auth {
+        PendingAuth::Plain => dispatch_auth(conn, controllers, format!("PLAIN {}", response)).await,
+        PendingAuth::LoginUsername => {
+            let next = PendingAuth::LoginPassword(response.to_string());
+            let challenge = auth_challenge(&next);
+            conn.lock().await.pending_auth = Some(next);
+            (vec![challenge], SMTPConnectionStatus::WaitingCommand)
+        }
+        PendingAuth::LoginPassword(username) => {
+            dispatch_auth(conn, controllers, format!("LOGIN {} {}", username, response)).await
+        }
+    }
+}
+
+/// # Parse AUTH LOGIN
+///
+/// This function decodes the username and the password of a completed AUTH LOGIN exchange,
+/// the AUTH controller receives them as `LOGIN <base64 username> <base64 password>`.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::command::parse_auth_login;
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_auth::{AuthOutcome, OnAuthController};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_auth(_conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<AuthOutcome, Message> {
+///     match parse_auth_login(&data) {
+///         Ok((user, password)) if user == "jean" && password == "pass word" => Ok(AuthOutcome::new(
+///             Message::new(StatusCodes::AuthenticationSuccessful, "Authentication successful".to_string()),
+///             user,
+///         )),
+///         _ => Ok(Message::new(StatusCodes::AuthenticationCredetialsInvalid, "Invalid credentials".to_string()).into()),
+///     }
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     assert_eq!(
+///         parse_auth_login("LOGIN amVhbg== cGFzcyB3b3Jk").unwrap(),
+///         ("jean".to_string(), "pass word".to_string())
+///     );
+///     assert!(parse_auth_login("LOGIN amVhbg==").is_err());
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_auth(OnAuthController::new(on_auth))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     // AUTH LOGIN, the username and the password, a wrong password first
+///     for (password, reply) in [("d3Jvbmc=", "535 Invalid credentials"), ("cGFzcyB3b3Jk", "235 Authentication successful")] {
+///         writer.write_all(b"AUTH LOGIN\r\n").await.unwrap();
+///         assert_eq!(lines.next_line().await.unwrap().unwrap(), "334 VXNlcm5hbWU6");
+///         writer.write_all(b"amVhbg==\r\n").await.unwrap();
+///         assert_eq!(lines.next_line().await.unwrap().unwrap(), "334 UGFzc3dvcmQ6");
+///         writer.write_all(format!("{}\r\n", password).as_bytes()).await.unwrap();
+///         assert_eq!(lines.next_line().await.unwrap().unwrap(), reply);
+///     }
+/// }
+/// ```
+pub fn parse_auth_login(data: &str) -> Result<(String, String), SMTPError> {
+    let mut fields = data.split_whitespace();
+    let (Some(mechanism), Some(username), Some(password), None) = (fields.next(), fields.next(), fields.next(), fields.next()) else {
+        return Err(Error::ParseError("Missing AUTH LOGIN credentials".to_string()));
+    };
+    if !mechanism.eq_ignore_ascii_case("LOGIN") {
+        return Err(Error::ParseError("Not an AUTH LOGIN".to_string()));
+    }
+
+    let decode = |field: &str| {
+        decode_base64(field)
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .ok_or(Error::ParseError("Invalid base64 credentials".to_string()))
+    };
+    Ok((decode(username)?, decode(password)?))
+}
+
+/// # Parse HELO Identity
+///
+/// This function parses the identity given after HELO/EHLO, a FQDN or a bracketed address literal (RFC 5321 4.1.3),
+/// this is the parser used by the `validate_helo` policies.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::command::parse_helo_identity;
+/// use neo_email::helo::HeloIdentity;
+/// use std::net::IpAddr;
+///
+/// assert_eq!(parse_helo_identity("Mail.Nervio.us."), HeloIdentity::Domain("mail.nervio.us".to_string()));
+/// assert_eq!(parse_helo_identity("[192.0.2.1]"), HeloIdentity::AddressLiteral("192.0.2.1".parse::<IpAddr>().unwrap()));
+/// assert_eq!(
+///     parse_helo_identity(" [IPv6:2001:db8::1] "),
+///     HeloIdentity::AddressLiteral("2001:db8::1".parse::<IpAddr>().unwrap())
+/// );
+///
+/// for garbage in ["", "localhost", "-bad-.nervio.us", "192.0.2.1", "[2001:db8::1]", "[IPv6:192.0.2.1]", "[999.0.2.1]", "mail_server.nervio.us"] {
+///     assert_eq!(parse_helo_identity(garbage), HeloIdentity::Invalid, "{}", garbage);
+/// }
+/// ```
+pub fn parse_helo_identity(identity: &str) -> HeloIdentity {
+    let identity = identity.trim();
+    if let Some(ip) = address_literal(identity) {
+        return HeloIdentity::AddressLiteral(ip);
+    }
+
+    let domain = identity.trim_end_matches('.').to_lowercase();
+    if is_fqdn(&domain) {
+        HeloIdentity::Domain(domain)
+    } else {
+        HeloIdentity::Invalid
+    }
+}
+
+/// # Parse AUTH PLAIN
+///
+/// This function decodes the credentials of AUTH PLAIN (RFC 4616), returning the authorization identity (None if empty),
+/// the authentication identity and the password. It takes the data of the AUTH command (`PLAIN <base64>`),
+/// or only the base64 when it's sent on its own line after the 334 challenge.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::command::parse_auth_plain;
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_auth::{AuthOutcome, OnAuthController};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_auth(_conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<AuthOutcome, Message> {
+///     match parse_auth_plain(&data) {
+///         Ok((_, user, password)) if user == "jean" && password == "password" => Ok(AuthOutcome::new(
+///             Message::new(StatusCodes::AuthenticationSuccessful, "Authentication successful".to_string()),
+///             user,
+///         )),
+///         _ => Ok(Message::new(StatusCodes::AuthenticationCredetialsInvalid, "Invalid credentials".to_string()).into()),
+///     }
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     // \0jean\0password
+///     let credentials = parse_auth_plain("PLAIN AGplYW4AcGFzc3dvcmQ=").unwrap();
+///     assert_eq!(credentials, (None, "jean".to_string(), "password".to_string()));
+///
+///     // admin\0jean\0password, sent after a bare `AUTH PLAIN`
+///     let credentials = parse_auth_plain("YWRtaW4AamVhbgBwYXNzd29yZA==").unwrap();
+///     assert_eq!(credentials, (Some("admin".to_string()), "jean".to_string(), "password".to_string()));
+///
+///     assert!(parse_auth_plain("PLAIN").is_err());
+///     assert!(parse_auth_plain("PLAIN not-base64!").is_err());
+///     // jean\0password, without the authzid field
+///     assert!(parse_auth_plain("PLAIN amVhbgBwYXNzd29yZA==").is_err());
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_auth(OnAuthController::new(on_auth))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     // Cancelled after the challenge
+///     writer.write_all(b"AUTH PLAIN\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "334 ");
+///     writer.write_all(b"*\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "501 Authentication cancelled");
+///
+///     // Initial response absent, the credentials come in the next line
+///     writer.write_all(b"AUTH PLAIN\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "334 ");
+///     writer.write_all(b"AGplYW4AcGFzc3dvcmQ=\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "235 Authentication successful");
+/// }
+/// ```
+pub fn parse_auth_plain(data: &str) -> Result<(Option<String>, String, String), SMTPError> {
+    let mut fields = data.split_whitespace();
+    let blob = match (fields.next(), fields.next()) {
+        (Some(mechanism), Some(blob)) if mechanism.eq_ignore_ascii_case("PLAIN") => blob,
+        (Some(blob), None) if !blob.eq_ignore_ascii_case("PLAIN") => blob,
+        _ => return Err(Error::ParseError("Missing AUTH PLAIN credentials".to_string())),
+    };
+
+    let decoded = decode_base64(blob).ok_or(Error::ParseError("Invalid base64 credentials".to_string()))?;
+    let decoded = String::from_utf8(decoded).map_err(|_| Error::ParseError("Invalid UTF-8 credentials".to_string()))?;
+
+    let mut parts = decoded.split('\0');
+    match (parts.next(), parts.next(), parts.next(), parts.next()) {
+        (Some(authzid), Some(authcid), Some(passwd), None) if !authcid.is_empty() => {
+            let authzid = (!authzid.is_empty()).then(|| authzid.to_string());
+            Ok((authzid, authcid.to_string(), passwd.to_string()))
+        }
+        _ => Err(Error::ParseError("Invalid AUTH PLAIN credentials".to_string())),
+    }
+}
+
+/// # Stream EXPN Members
+///
+/// This function writes every member of the mailing list as a `250-` line as soon as the next one is received,
+/// and returns the last one, to be sent as the final `250 ` line of the reply.
+async fn stream_expn_members<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    mut members: Receiver<String>,
+) -> std::io::Result<Message> {
+    let mut previous = None;
+    while let Some(member) = members.recv().await {
+        if let Some(previous) = previous.replace(member) {
+            // The connection is only locked to write, the controller may need it to produce the members
+            conn.lock()
+                .await
+                .write_message(&Message::new(StatusCodes::OK, previous), false)
+                .await?;
+        }
+    }
+
+    Ok(match previous {
+        Some(last_member) => Message::new(StatusCodes::OK, last_member),
+        None => Message::new(StatusCodes::OK, "Mailing list is empty".to_string()),
+    })
+}
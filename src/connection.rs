@@ -1,16 +1,26 @@
 use std::net::SocketAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use hashbrown::HashMap;
 use serde::{Deserialize, Serialize};
 use tokio::io::AsyncReadExt;
 use tokio::io::AsyncWriteExt;
 use tokio::time::timeout;
-use tokio::{io::BufStream, net::TcpStream, sync::Mutex};
+use tokio::{io::BufStream, net::TcpStream, sync::Mutex, sync::Semaphore};
 use tokio_native_tls::TlsStream;
 use trust_dns_resolver::TokioAsyncResolver;
 
+use crate::auth_stats::AuthStats;
 use crate::command::Commands;
+use crate::envelope::Envelope;
+use crate::mail::EmailAddress;
+use crate::helo::HeloPolicy;
+use crate::log_throttle::TLS_FAILURES;
+use crate::message::{LineEnding, Message};
+use crate::status_code::StatusCodes;
+#[cfg(feature = "spf-experimental")]
+use crate::utilities::spf::SPFLimits;
 
 /// # Connection Status
 /// 
@@ -35,6 +45,45 @@ pub enum SMTPConnectionStatus {
     Closed,
 }
 
+/// # Pending AUTH
+///
+/// This enum represents an AUTH exchange waiting for the client to answer a 334 challenge.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PendingAuth {
+    /// # Plain
+    ///
+    /// AUTH PLAIN without initial response, waiting for the base64 credentials.
+    Plain,
+    /// # Login Username
+    ///
+    /// AUTH LOGIN, waiting for the base64 username.
+    LoginUsername,
+    /// # Login Password
+    ///
+    /// AUTH LOGIN, waiting for the base64 password, with the base64 username already received.
+    LoginPassword(String),
+}
+
+/// # Max Tracing Commands
+///
+/// The number of last commands kept in `SMTPConnection::tracing_commands`, older commands are only counted.
+pub const MAX_TRACING_COMMANDS: usize = 32;
+
+/// # Default Max Command Size
+///
+/// The default max length of a command line, RFC 5321 allows 512 octets but AUTH blobs and ESMTP parameters are often longer.
+pub const DEFAULT_MAX_COMMAND_SIZE: usize = 2048;
+
+/// # Default Max Path Length
+///
+/// The default max length of the path of MAIL and RCPT including the angle brackets, the RFC 5321 limit (4.5.3.1.3).
+pub const DEFAULT_MAX_PATH_LENGTH: usize = 256;
+
+/// # Max Local Part Length
+///
+/// The max length of the local part of the MAIL and RCPT addresses (RFC 5321 4.5.3.1.1).
+pub const MAX_LOCAL_PART_LENGTH: usize = 64;
+
 /// # SMTP Connection
 ///
 /// This struct represents a connection to the SMTP server with the necessary information.
@@ -44,6 +93,14 @@ pub struct SMTPConnection<T> {
     /// 
     /// This field represents if the connection is using TLS.
     pub use_tls: bool,
+    /// # Peer Address
+    /// 
+    /// This field represents the address of the client, None if unknown (e.g. a connection without socket).
+    pub peer_addr: Option<SocketAddr>,
+    /// # Auth Stats
+    /// 
+    /// This field represents the counters of the SPF, DKIM and DMARC outcomes, shared by all the connections of the server.
+    pub auth_stats: Arc<AuthStats>,
     /// # TLS Buffer
     /// 
     /// This field represents the TLS Buffer.
@@ -60,6 +117,22 @@ pub struct SMTPConnection<T> {
     /// 
     /// This field represents the Mail Buffer, usually intended for emails data, actioned by DATA command.
     pub mail_buffer: Vec<u8>,
+    /// # Max Command Size
+    /// 
+    /// This field represents the max length of a command line in octets including the CRLF, longer lines are replied with 500.
+    pub max_command_size: usize,
+    /// # Pipelining
+    /// 
+    /// This field represents if the client can pipeline commands (RFC 2920), if disabled PIPELINING isn't advertised and a pipelined command closes the connection.
+    pub pipelining: bool,
+    /// # Discarding Command
+    /// 
+    /// This field represents if the command line being received exceeded the max command size, it's read until the CRLF without being stored.
+    pub discarding_command: bool,
+    /// # Discarding Data
+    /// 
+    /// This field represents if the mail being received exceeded the max size, its data is read until the terminator without being stored.
+    pub discarding_data: bool,
     /// # Connection Status
     /// 
     /// This field represents the connection status.
@@ -74,14 +147,322 @@ pub struct SMTPConnection<T> {
     pub state: Arc<Mutex<T>>,
     /// # Tracing Commands
     /// 
-    /// This field represents the traced commands.
+    /// This field represents the last traced commands, capped to `MAX_TRACING_COMMANDS`.
     pub tracing_commands: Vec<Commands>,
+    /// # Command Counts
+    /// 
+    /// This field represents how many times each command was received in this session, unknown commands are counted together.
+    pub command_counts: HashMap<Commands, usize>,
+    /// # Session Bytes
+    /// 
+    /// This field represents the total bytes read from the client in this session (commands and data).
+    pub session_bytes: usize,
+    /// # Command Bytes
+    /// 
+    /// This field represents the bytes read from the client while waiting commands in this session.
+    pub command_bytes: usize,
+    /// # Data Bytes
+    /// 
+    /// This field represents the bytes read from the client during DATA in this session, terminators included.
+    pub data_bytes: usize,
+    /// # Max Session Bytes
+    /// 
+    /// This field represents the max bytes that can be read from the client in this session, unlimited if None.
+    pub max_session_bytes: Option<usize>,
+    /// # HELO Policy
+    /// 
+    /// This field represents the policy applied to the HELO/EHLO identity, no validation if None.
+    pub helo_policy: Option<HeloPolicy>,
+    /// # Line Ending
+    /// 
+    /// This field represents the line ending used for the responses, always CRLF outside of tests.
+    pub line_ending: LineEnding,
+    /// # TLS Available
+    /// 
+    /// This field represents if the server has a TLS acceptor, so STARTTLS is advertised and accepted.
+    pub tls_available: bool,
+    /// # STARTTLS Attempts
+    /// 
+    /// This field represents the number of STARTTLS attempts in this session.
+    pub starttls_attempts: usize,
+    /// # Max STARTTLS Attempts
+    /// 
+    /// This field represents the max STARTTLS attempts allowed in this session, unlimited if None.
+    pub max_starttls_attempts: Option<usize>,
+    /// # Close On STARTTLS Limit
+    /// 
+    /// This field represents if the connection is closed when the STARTTLS attempts are exceeded, otherwise it continues in plaintext.
+    pub close_on_starttls_limit: bool,
+    /// # Transaction Started At
+    /// 
+    /// This field represents when the current transaction (MAIL FROM) started, None if there is no transaction.
+    pub transaction_started_at: Option<Instant>,
+    /// # Max Transaction Duration
+    /// 
+    /// This field represents the max duration of a transaction from MAIL FROM to the end of DATA, unlimited if None.
+    pub max_transaction_duration: Option<Duration>,
+    /// # Envelope
+    /// 
+    /// This field represents the envelope of the current transaction, None if there is no transaction.
+    pub envelope: Option<Envelope>,
+    /// # Require EHLO
+    /// 
+    /// This field represents if plain HELO is refused, forcing the client to negotiate the extensions with EHLO.
+    pub require_ehlo: bool,
+    /// # Require TLS
+    /// 
+    /// This field represents if the client must upgrade the connection with STARTTLS before AUTH and MAIL.
+    pub require_tls: bool,
+    /// # Wrap Long Replies
+    /// 
+    /// This field represents if the replies longer than the SMTP line limit are wrapped into multiline replies.
+    pub wrap_long_replies: bool,
+    /// # Authenticated
+    /// 
+    /// This field represents if the client authenticated successfully (the AUTH controller answered 235) in this session.
+    pub authenticated: bool,
+    /// # Auth Identity
+    /// 
+    /// This field represents who authenticated, as returned by the AUTH controller, None if unknown or not authenticated.
+    pub auth_identity: Option<String>,
+    /// # Auth Authzid
+    /// 
+    /// This field represents the authorization identity requested by the client on AUTH, if any.
+    pub auth_authzid: Option<String>,
+    /// # Maintenance
+    /// 
+    /// This field represents the reply to every command but QUIT while the server is in maintenance, no controller is called then.
+    pub maintenance: Option<Message>,
+    /// # Pending AUTH
+    /// 
+    /// This field represents the AUTH exchange waiting for the response of the client to a 334 challenge, the next line is that response.
+    pub pending_auth: Option<PendingAuth>,
+    /// # Reject Repeated AUTH
+    /// 
+    /// This field represents if AUTH is refused with 503 once the client is authenticated, instead of calling the AUTH controller again.
+    pub reject_repeated_auth: bool,
+    /// # Reject Empty Body
+    /// 
+    /// This field represents if a message without body (only headers) is refused with 554 at the end of DATA.
+    pub reject_empty_body: bool,
+    /// # Handshake Semaphore
+    /// 
+    /// This field represents the semaphore shared by all the connections to bound the concurrent TLS handshakes, unlimited if None.
+    pub handshake_semaphore: Option<Arc<Semaphore>>,
+    /// # Controller Permits
+    /// 
+    /// This field represents the permits of this connection for the work started by its controllers, like the DNS lookups or
+    /// callouts of `on_rcpt_cmd` running in background tasks. Each task holds a permit (`acquire_owned`) while it runs,
+    /// so a client sending many commands can't fan them out unbounded. Unlimited unless `set_max_controller_concurrency` is set.
+    pub controller_permits: Arc<Semaphore>,
+    /// # Dedupe Recipients
+    /// 
+    /// This field represents if a recipient repeated in the same transaction is ignored instead of added twice to the envelope.
+    pub dedupe_recipients: bool,
+    /// # Command Delays
+    /// 
+    /// This field represents the time waited before handling each command, to simulate a slow server.
+    pub command_delays: Arc<HashMap<Commands, Duration>>,
+    /// # REQUIRETLS Enabled
+    /// 
+    /// This field represents if the REQUIRETLS extension (RFC 8689) is advertised on TLS connections and its MAIL FROM parameter handled.
+    pub requiretls_enabled: bool,
+    /// # 8BITMIME Enabled
+    /// 
+    /// This field represents if 8BITMIME (RFC 6152) is advertised and `BODY=8BITMIME` accepted in MAIL FROM.
+    pub eight_bit_mime: bool,
+    /// # SMTPUTF8 Enabled
+    /// 
+    /// This field represents if SMTPUTF8 (RFC 6531) is advertised and its MAIL FROM parameter accepted, allowing UTF-8 addresses.
+    pub smtputf8_enabled: bool,
+    /// # HELO
+    /// 
+    /// This field represents the identity given by the client in the last accepted HELO/EHLO, None before it.
+    pub helo: Option<String>,
+    /// # Max Path Length
+    /// 
+    /// This field represents the max length of the MAIL and RCPT path including the angle brackets, longer ones are rejected with 501.
+    pub max_path_length: usize,
+    /// # Echo Addresses
+    /// 
+    /// This field represents if the default MAIL reply echoes the address, like `250 2.1.0 <jean@nervio.us> Sender ok`.
+    pub echo_addresses: bool,
+    /// # Malformed Mail Status
+    /// 
+    /// This field represents the status code replied when the DATA can't be parsed as a mail, 554 by default.
+    pub malformed_mail_status: StatusCodes,
+    /// # Default RCPT Response
+    /// 
+    /// This field represents the reply for recipients that no route accepts, `550 Relay access denied` by default.
+    /// It's the reply of RCPT without controller, and the RCPT controller returns it instead of building its own rejection.
+    pub default_rcpt_response: Message,
+    /// # Recipient Max Size
+    /// 
+    /// This field represents the size limit set by the RCPT controller for the recipient being processed.
+    pub recipient_max_size: Option<usize>,
+    /// # SPF Limits
+    /// 
+    /// This field represents the limits applied by the SPF checks of this connection.
+    #[cfg(feature = "spf-experimental")]
+    pub spf_limits: SPFLimits,
 }
 
 impl<T> SMTPConnection<T> {
     /// # New
     ///
-    /// This function creates a new SMTPConnection.
+    /// This function creates a new SMTPConnection without socket, waiting for commands and with the default limits.
+    pub fn new(dns_resolver: Arc<Mutex<TokioAsyncResolver>>) -> Self
+    where
+        T: Default,
+    {
+        SMTPConnection {
+            use_tls: false,
+            peer_addr: None,
+            auth_stats: Arc::new(AuthStats::default()),
+            tls_buff_socket: None,
+            tcp_buff_socket: None,
+            buffer: Vec::new(),
+            mail_buffer: Vec::new(),
+            max_command_size: DEFAULT_MAX_COMMAND_SIZE,
+            pipelining: true,
+            discarding_command: false,
+            discarding_data: false,
+            status: SMTPConnectionStatus::WaitingCommand,
+            dns_resolver,
+            state: Arc::new(Mutex::new(T::default())),
+            tracing_commands: Vec::new(),
+            command_counts: HashMap::new(),
+            session_bytes: 0,
+            command_bytes: 0,
+            data_bytes: 0,
+            max_session_bytes: None,
+            helo_policy: None,
+            line_ending: LineEnding::CRLF,
+            tls_available: false,
+            starttls_attempts: 0,
+            max_starttls_attempts: None,
+            close_on_starttls_limit: false,
+            transaction_started_at: None,
+            max_transaction_duration: None,
+            envelope: None,
+            require_ehlo: false,
+            require_tls: false,
+            wrap_long_replies: false,
+            authenticated: false,
+            auth_identity: None,
+            auth_authzid: None,
+            pending_auth: None,
+            maintenance: None,
+            reject_repeated_auth: false,
+            reject_empty_body: false,
+            handshake_semaphore: None,
+            controller_permits: Arc::new(Semaphore::new(Semaphore::MAX_PERMITS)),
+            dedupe_recipients: false,
+            command_delays: Arc::new(HashMap::new()),
+            requiretls_enabled: false,
+            eight_bit_mime: true,
+            smtputf8_enabled: false,
+            helo: None,
+            max_path_length: DEFAULT_MAX_PATH_LENGTH,
+            echo_addresses: false,
+            malformed_mail_status: StatusCodes::TransactionFailed,
+            default_rcpt_response: Message::new(
+                StatusCodes::RequestedActionNotTakenMailboxUnavailable,
+                "Relay access denied".to_string(),
+            ),
+            recipient_max_size: None,
+            #[cfg(feature = "spf-experimental")]
+            spf_limits: SPFLimits::default(),
+        }
+    }
+
+    /// # Record Command
+    ///
+    /// This function traces a received command, keeping only the last `MAX_TRACING_COMMANDS` and a count per command.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    /// use neo_email::connection::{SMTPConnection, MAX_TRACING_COMMANDS};
+    /// use std::sync::Arc;
+    /// use tokio::sync::Mutex;
+    /// use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+    /// use trust_dns_resolver::TokioAsyncResolver;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    ///     let mut conn = SMTPConnection::<()>::new(Arc::new(Mutex::new(resolver)));
+    ///     assert_eq!(conn.last_command(), None);
+    ///
+    ///     for command in [Commands::EHLO, Commands::MAIL, Commands::RCPT, Commands::RCPT] {
+    ///         conn.record_command(command);
+    ///     }
+    ///
+    ///     assert_eq!(conn.command_count(&Commands::RCPT), 2);
+    ///     assert_eq!(conn.last_command(), Some(&Commands::RCPT));
+    ///     assert!(conn.has_command(&Commands::MAIL));
+    ///     assert!(!conn.has_command(&Commands::AUTH));
+    ///
+    ///     // A long session doesn't grow the traced commands
+    ///     for _ in 0..10_000 {
+    ///         conn.record_command(Commands::NOOP);
+    ///     }
+    ///     conn.record_command(Commands::UNKNOWN("XFOO".to_string()));
+    ///     conn.record_command(Commands::UNKNOWN("XBAR".to_string()));
+    ///
+    ///     assert_eq!(conn.tracing_commands.len(), MAX_TRACING_COMMANDS);
+    ///     assert_eq!(conn.command_counts.len(), 5);
+    ///     assert_eq!(conn.command_count(&Commands::NOOP), 10_000);
+    ///     assert_eq!(conn.command_count(&Commands::UNKNOWN(String::new())), 2);
+    ///     assert!(conn.has_command(&Commands::EHLO));
+    /// }
+    /// ```
+    pub fn record_command(&mut self, command: Commands) {
+        // Unknown commands are counted together, to not grow the counts with client garbage
+        let key = match command {
+            Commands::UNKNOWN(_) => Commands::UNKNOWN(String::new()),
+            ref command => command.clone(),
+        };
+        *self.command_counts.entry(key).or_insert(0) += 1;
+
+        if self.tracing_commands.len() >= MAX_TRACING_COMMANDS {
+            self.tracing_commands.remove(0);
+        }
+        self.tracing_commands.push(command);
+    }
+
+    /// # Command Count
+    ///
+    /// This function returns how many times a command was received in this session.
+    /// All unknown commands are counted together.
+    pub fn command_count(&self, command: &Commands) -> usize {
+        let key = match command {
+            Commands::UNKNOWN(_) => Commands::UNKNOWN(String::new()),
+            command => command.clone(),
+        };
+        self.command_counts.get(&key).copied().unwrap_or(0)
+    }
+
+    /// # Last Command
+    ///
+    /// This function returns the last command received in this session.
+    pub fn last_command(&self) -> Option<&Commands> {
+        self.tracing_commands.last()
+    }
+
+    /// # Has Command
+    ///
+    /// This function returns if a command was received in this session (e.g. was AUTH attempted?).
+    pub fn has_command(&self, command: &Commands) -> bool {
+        self.command_count(command) > 0
+    }
+
+    /// # Write Socket
+    ///
+    /// This function writes to the socket.
+    /// Depending on the connection, it will write to the TLS socket or the TCP socket.
     pub async fn write_socket(&self, data: &[u8]) -> std::io::Result<()> {
         if self.use_tls {
             log::trace!("[✏️] Writing to TLS socket");
@@ -101,6 +482,90 @@ impl<T> SMTPConnection<T> {
         Ok(())
     }
 
+    /// # Write Message
+    ///
+    /// This function serializes a response with the connection line ending and writes it to the socket.
+    /// `is_last` indicates if it is the last line of a multiline response.
+    pub async fn write_message(&self, message: &Message, is_last: bool) -> std::io::Result<()> {
+        self.write_socket(&self.serialize_messages(std::slice::from_ref(message), is_last))
+            .await
+    }
+
+    /// # Write Messages
+    ///
+    /// This function writes a (possibly multiline) response, only the last message is marked as last.
+    pub async fn write_messages(&self, messages: &[Message]) -> std::io::Result<()> {
+        self.write_socket(&self.serialize_messages(messages, true))
+            .await
+    }
+
+    /// # Send Reply
+    ///
+    /// This function writes a reply made of one or more lines (see `Message::multiline`), every line but the last one
+    /// is written with the `-` separator.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::message::Message;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use tokio::net::{TcpListener, TcpStream};
+    /// use tokio::sync::Mutex;
+    /// use std::sync::Arc;
+    /// use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+    /// use trust_dns_resolver::TokioAsyncResolver;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = listener.local_addr().unwrap();
+    ///     let client = tokio::spawn(async move {
+    ///         let mut stream = TcpStream::connect(addr).await.unwrap();
+    ///         let mut wire = Vec::new();
+    ///         stream.read_to_end(&mut wire).await.unwrap();
+    ///         wire
+    ///     });
+    ///
+    ///     let (socket, _) = listener.accept().await.unwrap();
+    ///     let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    ///     let mut conn = SMTPConnection::<()>::new(Arc::new(Mutex::new(resolver)));
+    ///     conn.tcp_buff_socket = Some(Arc::new(Mutex::new(tokio::io::BufStream::new(socket))));
+    ///     conn.send_reply(Message::multiline(
+    ///         StatusCodes::OK,
+    ///         vec!["Hello".to_string(), "SIZE 1024".to_string(), "HELP".to_string()],
+    ///     ))
+    ///     .await
+    ///     .unwrap();
+    ///     drop(conn);
+    ///
+    ///     assert_eq!(client.await.unwrap(), b"250-Hello\r\n250-SIZE 1024\r\n250 HELP\r\n");
+    /// }
+    /// ```
+    pub async fn send_reply(&self, reply: Vec<Message>) -> std::io::Result<()> {
+        self.write_messages(&reply).await
+    }
+
+    /// # Serialize Messages
+    ///
+    /// This function serializes the messages with the connection line ending, wrapping the long ones if `wrap_long_replies` is set.
+    fn serialize_messages(&self, messages: &[Message], is_last: bool) -> Vec<u8> {
+        let lines = if self.wrap_long_replies {
+            messages.iter().flat_map(Message::wrap).collect::<Vec<Message>>()
+        } else {
+            messages.to_vec()
+        };
+
+        let mut bytes = Vec::new();
+        for (i, line) in lines.iter().enumerate() {
+            let is_last = is_last && i == lines.len() - 1;
+            bytes.extend(line.as_bytes_with(is_last, self.line_ending));
+        }
+        bytes
+    }
+
     /// # Read Socket
     ///
     /// This function reads from the socket.
@@ -140,10 +605,7 @@ impl<T> SMTPConnection<T> {
                     .peer_addr()?)
             } else {
                 log::trace!("[🚫] No socket to read from");
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "No socket to read from",
-                ))
+                Err(std::io::Error::other("No socket to read from"))
             }
         } else {
             if let Some(tcp_buff_socket) = &self.tcp_buff_socket {
@@ -151,10 +613,7 @@ impl<T> SMTPConnection<T> {
                 Ok(tcp_buff_socket.get_ref().peer_addr()?)
             } else {
                 log::trace!("[🚫] No socket to read from");
-                Err(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    "No socket to read from",
-                ))
+                Err(std::io::Error::other("No socket to read from"))
             }
         }
     }
@@ -188,6 +647,229 @@ impl<T> SMTPConnection<T> {
         self.buffer.clear();
         self.mail_buffer.clear();
         self.status = SMTPConnectionStatus::WaitingCommand;
+        self.reset_transaction();
+    }
+
+    /// # Reset Session
+    ///
+    /// This function forgets everything negotiated in the session, as required after STARTTLS (RFC 3207 4.2):
+    /// the HELO/EHLO identity, the authentication, the transaction and the traced commands. The client must send EHLO again.
+    /// The command counts and the byte counters are kept, they bound the whole connection.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    /// use neo_email::connection::SMTPConnection;
+    /// use std::sync::Arc;
+    /// use tokio::sync::Mutex;
+    /// use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+    /// use trust_dns_resolver::TokioAsyncResolver;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    ///     let mut conn = SMTPConnection::<()>::new(Arc::new(Mutex::new(resolver)));
+    ///     conn.record_command(Commands::EHLO);
+    ///     conn.record_command(Commands::AUTH);
+    ///     conn.helo = Some("mail.nervio.us".to_string());
+    ///     conn.authenticated = true;
+    ///     conn.auth_identity = Some("jean".to_string());
+    ///
+    ///     conn.reset_session();
+    ///     assert!(conn.tracing_commands.is_empty());
+    ///     assert_eq!(conn.helo, None);
+    ///     assert!(!conn.authenticated);
+    ///     assert_eq!(conn.auth_identity, None);
+    ///     assert!(conn.has_command(&Commands::AUTH));
+    /// }
+    /// ```
+    pub fn reset_session(&mut self) {
+        self.buffer.clear();
+        self.mail_buffer.clear();
+        self.status = SMTPConnectionStatus::WaitingCommand;
+        self.reset_transaction();
+        self.tracing_commands.clear();
+        self.helo = None;
+        self.authenticated = false;
+        self.auth_identity = None;
+        self.auth_authzid = None;
+        self.pending_auth = None;
+    }
+
+    /// # Count Read Bytes
+    ///
+    /// This function charges the bytes read from the client to the session, and to the commands or data depending on the status.
+    pub fn count_read_bytes(&mut self, n: usize) {
+        self.session_bytes += n;
+        if self.status == SMTPConnectionStatus::WaitingData {
+            self.data_bytes += n;
+        } else {
+            self.command_bytes += n;
+        }
+    }
+
+    /// # Command Bytes
+    ///
+    /// This function returns the bytes read while waiting commands in this session.
+    pub fn command_bytes(&self) -> usize {
+        self.command_bytes
+    }
+
+    /// # Data Bytes
+    ///
+    /// This function returns the bytes read during DATA in this session.
+    pub fn data_bytes(&self) -> usize {
+        self.data_bytes
+    }
+
+    /// # Total Bytes
+    ///
+    /// This function returns all the bytes read in this session, the same counter used by the session bytes cap.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_email::OnEmailController;
+    /// use neo_email::mail::Mail;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+    ///     let conn = conn.lock().await;
+    ///     let size = conn.envelope.as_ref().map(|envelope| envelope.size).unwrap_or_default();
+    ///     let counters = format!("{} {} {} {}", size, conn.command_bytes(), conn.data_bytes(), conn.total_bytes());
+    ///     Message::new(StatusCodes::OK, counters)
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()));
+    ///     server.on_email(OnEmailController::new(on_email)).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     let commands = ["MAIL FROM:<jean@nervio.us>\r\n", "RCPT TO:<admin@nervio.us>\r\n", "DATA\r\n"];
+    ///     for command in commands {
+    ///         writer.write_all(command.as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///
+    ///     let message = "From: jean@nervio.us\r\nSubject: Test\r\n\r\nHello World\r\n";
+    ///     writer.write_all(format!("{}.\r\n", message).as_bytes()).await.unwrap();
+    ///
+    ///     let command_bytes: usize = commands.iter().map(|command| command.len()).sum();
+    ///     let data_bytes = message.len() + ".\r\n".len();
+    ///     assert_eq!(
+    ///         lines.next_line().await.unwrap().unwrap(),
+    ///         format!("250 {} {} {} {}", message.len(), command_bytes, data_bytes, command_bytes + data_bytes)
+    ///     );
+    /// }
+    /// ```
+    pub fn total_bytes(&self) -> usize {
+        self.session_bytes
+    }
+
+    /// # Set Recipient Max Size
+    ///
+    /// This function is meant to be called from the RCPT controller to limit the size of the messages for the current recipient (e.g. its quota).
+    /// If the recipient is accepted, the message must fit in the smallest limit of the accepted recipients, else it's rejected with 552 during DATA.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_rcpt::OnRCPTCommandController;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_rcpt(conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<Message, Message> {
+    ///     let quota = if data.contains("<small@") { 100 } else { 1000 };
+    ///     conn.lock().await.set_recipient_max_size(quota);
+    ///     Ok(Message::new(StatusCodes::OK, "Ok".to_string()))
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .on_rcpt_cmd(OnRCPTCommandController::new(on_rcpt))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     // 500 bytes, between the limits of both recipients
+    ///     let message = format!("Subject: Quota\r\n\r\n{}\r\n.\r\n", "A".repeat(500));
+    ///
+    ///     let big = ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<big@nervio.us>", "DATA"];
+    ///     for command in big {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///     writer.write_all(message.as_bytes()).await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///
+    ///     let both = ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<big@nervio.us>", "RCPT TO:<small@nervio.us>", "DATA"];
+    ///     for command in both {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///     writer.write_all(message.as_bytes()).await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("552 "));
+    /// }
+    /// ```
+    pub fn set_recipient_max_size(&mut self, max_size: usize) {
+        self.recipient_max_size = Some(max_size);
+    }
+
+    /// # Start Transaction
+    ///
+    /// This function starts a new mail transaction (MAIL FROM) with the given sender.
+    pub fn start_transaction(&mut self, sender: Option<EmailAddress>) {
+        self.envelope = Some(Envelope::new(sender));
+        self.transaction_started_at = Some(Instant::now());
+    }
+
+    /// # Reset Transaction
+    ///
+    /// This function clears the current mail transaction (sender and recipients), keeping the session state like TLS or authentication.
+    pub fn reset_transaction(&mut self) {
+        self.envelope = None;
+        self.transaction_started_at = None;
+    }
+
+    /// # Transaction Expired
+    ///
+    /// This function returns if the current transaction exceeded the max transaction duration.
+    pub fn transaction_expired(&self) -> bool {
+        match (self.transaction_started_at, self.max_transaction_duration) {
+            (Some(started_at), Some(max_duration)) => started_at.elapsed() > max_duration,
+            _ => false,
+        }
     }
 
     /// # Close Connection
@@ -211,11 +893,52 @@ impl<T> SMTPConnection<T> {
 
 /// # Upgrade Connection to TLS
 /// 
-/// This function upgrades the connection to TLS.
+/// This function upgrades the connection to TLS. If the handshake fails or times out the plaintext socket is put back,
+/// so the connection can reply 454 and continue in plaintext.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::server::SMTPServer;
+/// use neo_email::testing::read_response;
+/// use std::net::SocketAddr;
+/// use tokio::io::{AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio_native_tls::native_tls;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server.set_tls_acceptor(acceptor.into()).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut reader = BufReader::new(reader);
+///     read_response(&mut reader).await;
+///
+///     // Garbage instead of a ClientHello fails the handshake
+///     writer.write_all(b"STARTTLS\r\n").await.unwrap();
+///     assert_eq!(read_response(&mut reader).await[0].0, 220);
+///     writer.write_all(b"Not a TLS ClientHello\r\n").await.unwrap();
+///     assert_eq!(
+///         read_response(&mut reader).await,
+///         vec![(454, "TLS not available due to temporary reason".to_string())]
+///     );
+///
+///     // The session goes on in plaintext
+///     writer.write_all(b"NOOP\r\n").await.unwrap();
+///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+/// }
+/// ```
 pub async fn upgrade_to_tls<B>(
     conn: Arc<Mutex<SMTPConnection<B>>>,
     tls_acceptor: Option<Arc<Mutex<tokio_native_tls::TlsAcceptor>>>,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     log::trace!("[🌐🔒] Upgrading connection to TLS");
 
     let tls_acceptor = match tls_acceptor {
@@ -227,6 +950,21 @@ pub async fn upgrade_to_tls<B>(
     let mut conn_locked = conn.lock().await;
     log::trace!("[🌐🔒] Connection locked");
 
+    // Wait for a handshake permit, the wait counts against the handshake timeout
+    let handshake_timeout = Duration::from_secs(10);
+    let started_at = Instant::now();
+    let _permit = match conn_locked.handshake_semaphore.clone() {
+        Some(semaphore) => match timeout(handshake_timeout, semaphore.acquire_owned()).await {
+            Ok(Ok(permit)) => Some(permit),
+            Ok(Err(err)) => return Err(err.into()),
+            Err(_) => {
+                log::error!("[🌐🔒🚫] Timed out waiting for a TLS handshake permit");
+                return Err("Timed out waiting for a TLS handshake permit".into());
+            }
+        },
+        None => None,
+    };
+
     // Take out the TcpStream from the connection and set tcp_buff_socket to None
     let tcp_buff_socket = conn_locked
         .tcp_buff_socket
@@ -235,6 +973,8 @@ pub async fn upgrade_to_tls<B>(
     let tcp_buff_socket = Arc::try_unwrap(tcp_buff_socket).map_err(|_| "Failed to unwrap Arc")?;
     let tcp_buff_socket = tcp_buff_socket.into_inner();
     let tcp_stream = tcp_buff_socket.into_inner();
+    // The handshake consumes the stream, a duplicate of the socket keeps the connection if it fails
+    let (tcp_stream, fallback) = duplicate_socket(tcp_stream)?;
 
     // Acquire the TlsAcceptor and accept the TcpStream to create a TlsStream
     log::trace!("[🌐🔒] Locking TLS Acceptor");
@@ -243,17 +983,25 @@ pub async fn upgrade_to_tls<B>(
 
     log::trace!("[🌐🔒🟢] Accepting TLS connection");
 
-    let tls_stream = match timeout(Duration::from_secs(10), tls_acceptor.accept(tcp_stream)).await {
+    let tls_stream = match timeout(handshake_timeout.saturating_sub(started_at.elapsed()), tls_acceptor.accept(tcp_stream)).await {
         Ok(Ok(tls_stream)) => {
             log::trace!("[🌐🔒🟢] TLS connection Accepted");
             tls_stream
         }
         Ok(Err(err)) => {
-            log::error!("[🌐🔒🚫] Error during TLS handshake: {}", err);
+            if let Some(suppressed) = TLS_FAILURES.check() {
+                log::error!("[🌐🔒🚫] Error during TLS handshake: {}{}", err, suppressed);
+            }
+            discard_pending(&fallback);
+            conn_locked.tcp_buff_socket = Some(Arc::new(Mutex::new(BufStream::new(fallback))));
             return Err(err.into());
         }
         Err(_) => {
-            log::error!("[🌐🔒🚫] TLS handshake timed out");
+            if let Some(suppressed) = TLS_FAILURES.check() {
+                log::error!("[🌐🔒🚫] TLS handshake timed out{}", suppressed);
+            }
+            discard_pending(&fallback);
+            conn_locked.tcp_buff_socket = Some(Arc::new(Mutex::new(BufStream::new(fallback))));
             return Err("TLS handshake timed out".into());
         }
     };
@@ -261,7 +1009,30 @@ pub async fn upgrade_to_tls<B>(
     // Set the tls_buff_socket to the new TlsStream wrapped in BufStream
     conn_locked.tls_buff_socket = Some(Arc::new(Mutex::new(BufStream::new(tls_stream))));
     conn_locked.use_tls = true;
-    conn_locked.status = SMTPConnectionStatus::WaitingCommand;
+    conn_locked.reset_session();
 
     Ok(())
 }
+
+/// # Duplicate Socket
+///
+/// This function duplicates the descriptor of the socket, both streams use the same connection,
+/// which stays open until both are dropped.
+fn duplicate_socket(tcp_stream: TcpStream) -> std::io::Result<(TcpStream, TcpStream)> {
+    let std_stream = tcp_stream.into_std()?;
+    let duplicate = std_stream.try_clone()?;
+    Ok((TcpStream::from_std(std_stream)?, TcpStream::from_std(duplicate)?))
+}
+
+/// # Discard Pending
+///
+/// This function drops the bytes already received on the socket, the rest of a failed handshake isn't a command.
+/// The client only sends commands again after reading the 454 reply.
+fn discard_pending(tcp_stream: &TcpStream) {
+    let mut buf = [0; 1024];
+    while let Ok(read) = tcp_stream.try_read(&mut buf) {
+        if read == 0 {
+            break;
+        }
+    }
+}
@@ -1,7 +1,7 @@
-/// # Controllers
-/// 
-/// This module contains all the controllers for the SMTP server.
-/// The controllers are responsible for handling the commands and data from the client in a custom way.
+//! # Controllers
+//! 
+//! This module contains all the controllers for the SMTP server.
+//! The controllers are responsible for handling the commands and data from the client in a custom way.
 
 /// # on_conn
 /// 
@@ -15,10 +15,22 @@ pub mod on_auth;
 /// 
 /// This module contains the controller for the QUIT command, usually used to close the connection.
 pub mod on_close;
+/// # on_content_filter
+/// 
+/// This module contains the controller for filtering the received emails, usually used for spam/virus scanning.
+pub mod on_content_filter;
 /// # on_data
 /// 
 /// This module contains the controller for the DATA command, usually used to send the email data.
 pub mod on_email;
+/// # on_error
+/// 
+/// This module contains the controller for the errors of the connections, usually used for monitoring.
+pub mod on_error;
+/// # on_expn
+/// 
+/// This module contains the controller for the EXPN command, usually used to list the members of a mailing list.
+pub mod on_expn;
 /// # on_mail_cmd
 /// 
 /// This module contains the controller for the MAIL command, usually used to set the sender of the email.
@@ -31,6 +43,10 @@ pub mod on_rcpt;
 /// 
 /// This module contains the controller for the RSET command, usually used to reset the connection.
 pub mod on_reset;
+/// # on_tls
+/// 
+/// This module contains the controller for the STARTTLS upgrade, usually used to wipe the custom state of the session.
+pub mod on_tls;
 /// # on_unknown_command
 /// 
 /// This module contains the controller for the unknown command, usually used to handle unknown commands.
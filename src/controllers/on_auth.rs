@@ -3,16 +3,122 @@ use core::fmt;
 use std::{future::Future, pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 
+/// # AuthOutcome
+///
+/// This struct represents a successful AUTH, the reply and who authenticated.
+/// A plain `Message` converts into an outcome without identity, so the controllers returning `Result<Message, Message>` keep working.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthOutcome {
+    /// # Message
+    ///
+    /// The reply sent to the client, usually 235.
+    pub message: Message,
+    /// # Identity
+    ///
+    /// The authentication identity (authcid), e.g. the username.
+    pub identity: Option<String>,
+    /// # Authzid
+    ///
+    /// The authorization identity, if the client asked to act as another identity.
+    pub authzid: Option<String>,
+}
+
+impl AuthOutcome {
+    /// # New
+    ///
+    /// This function creates a new AuthOutcome for the given reply and identity.
+    pub fn new(message: Message, identity: String) -> Self {
+        AuthOutcome {
+            message,
+            identity: Some(identity),
+            authzid: None,
+        }
+    }
+
+    /// # With Authzid
+    ///
+    /// This function sets the authorization identity.
+    pub fn with_authzid(mut self, authzid: String) -> Self {
+        self.authzid = Some(authzid);
+        self
+    }
+}
+
+impl From<Message> for AuthOutcome {
+    fn from(message: Message) -> Self {
+        AuthOutcome {
+            message,
+            identity: None,
+            authzid: None,
+        }
+    }
+}
+
 /// # OnAuthController
 ///
 /// This struct represents a controller that is called when auth command is received.
+/// It returns an `AuthOutcome` (or just a `Message`) when the client can continue, or the error message to close the connection.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_auth::{AuthOutcome, OnAuthController};
+/// use neo_email::controllers::on_email::OnEmailController;
+/// use neo_email::mail::Mail;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_auth(_conn: Arc<Mutex<SMTPConnection<()>>>, _data: String) -> Result<AuthOutcome, Message> {
+///     let message = Message::new(StatusCodes::AuthenticationSuccessful, "Authentication successful".to_string());
+///     Ok(AuthOutcome::new(message, "jean".to_string()))
+/// }
+///
+/// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+///     // Echo who authenticated
+///     let identity = conn.lock().await.auth_identity.clone().unwrap_or_default();
+///     Message::new(StatusCodes::OK, identity)
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+///         .on_auth(OnAuthController::new(on_auth))
+///         .on_email(OnEmailController::new(on_email))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     for command in ["AUTH PLAIN AGplYW4AcGFzc3dvcmQ=", "MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"] {
+///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+///         lines.next_line().await.unwrap();
+///     }
+///     writer.write_all(b"Subject: Test\r\n\r\nHello\r\n.\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "250 jean");
+/// }
+/// ```
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnAuthController<B>(
     pub  Arc<
         dyn Fn(
                 Arc<Mutex<SMTPConnection<B>>>,
                 String,
-            ) -> Pin<Box<dyn Future<Output = Result<Message, Message>> + Send>>
+            ) -> Pin<Box<dyn Future<Output = Result<AuthOutcome, Message>> + Send>>
             + Send
             + Sync
             + 'static,
@@ -23,14 +129,16 @@ impl<B> OnAuthController<B> {
     /// # New
     ///
     /// This function creates a new OnAuthController.
-    pub fn new<F, Fut>(f: F) -> Self
+    pub fn new<F, Fut, R>(f: F) -> Self
     where
         F: Fn(Arc<Mutex<SMTPConnection<B>>>, String) -> Fut + Send + Sync + 'static,
-        Fut: Future<Output = Result<Message, Message>> + Send + 'static,
+        Fut: Future<Output = Result<R, Message>> + Send + 'static,
+        R: Into<AuthOutcome>,
     {
         let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>, data: String| {
-            Box::pin(f(conn, data))
-                as Pin<Box<dyn Future<Output = Result<Message, Message>> + Send>>
+            let fut = f(conn, data);
+            Box::pin(async move { fut.await.map(Into::into) })
+                as Pin<Box<dyn Future<Output = Result<AuthOutcome, Message>> + Send>>
         };
 
         OnAuthController(Arc::new(wrapped_fn))
@@ -1,5 +1,5 @@
 use core::fmt;
-use std::sync::Arc;
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use tokio::sync::Mutex;
 
@@ -8,21 +8,66 @@ use crate::connection::SMTPConnection;
 /// # OnCloseController
 ///
 /// This struct represents a controller that is called when an connection is Close.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_close::OnCloseController;
+/// use neo_email::server::SMTPServer;
+/// use std::net::SocketAddr;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// static SESSION_BYTES: AtomicUsize = AtomicUsize::new(0);
+///
+/// // An audit log write could be awaited here
+/// async fn on_close(conn: Arc<Mutex<SMTPConnection<()>>>) {
+///     let session_bytes = conn.lock().await.total_bytes();
+///     SESSION_BYTES.store(session_bytes, Ordering::SeqCst);
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_close(OnCloseController::new(on_close))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///     writer.write_all(b"NOOP\r\nQUIT\r\n").await.unwrap();
+///     while lines.next_line().await.unwrap().is_some() {}
+///
+///     assert_eq!(SESSION_BYTES.load(Ordering::SeqCst), 12);
+/// }
+/// ```
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnCloseController<B>(
-    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static>,
+    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync + 'static>,
 );
 
 impl<B> OnCloseController<B> {
     /// # New
     ///
     /// This function creates a new OnCloseController.
-    pub fn new<F, T>(f: F) -> Self
+    pub fn new<F, Fut>(f: F) -> Self
     where
-        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static,
-        T: 'static + Clone + Send + Sync,
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
     {
-        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| f(conn);
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| {
+            Box::pin(f(conn)) as Pin<Box<dyn Future<Output = ()> + Send>>
+        };
 
         OnCloseController(Arc::new(wrapped_fn))
     }
@@ -1,28 +1,77 @@
 use core::fmt;
-use std::sync::Arc;
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use tokio::sync::Mutex;
 
-use crate::connection::SMTPConnection;
+use crate::{connection::SMTPConnection, message::Message};
 
 /// # OnConnController
 ///
-/// This struct represents a controller that is called when an connection is opened.
+/// This struct represents a controller that is called when an connection is opened, before the greeting.
+/// It returns `Ok(None)` to send the default `220` greeting, `Ok(Some(message))` to send another greeting,
+/// or `Err(message)` to refuse the connection (e.g. a blocklisted IP): the message is sent and the connection closed.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_conn::OnConnController;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_conn(conn: Arc<Mutex<SMTPConnection<()>>>) -> Result<Option<Message>, Message> {
+///     // A blocklist lookup could be awaited here
+///     let peer_addr = conn.lock().await.peer_addr;
+///     match peer_addr.map(|addr| addr.ip()) {
+///         Some(IpAddr::V4(ip)) if ip == Ipv4Addr::LOCALHOST => Err(Message::new(
+///             StatusCodes::TransactionFailed,
+///             "5.7.1 Your IP is blocklisted".to_string(),
+///         )),
+///         _ => Ok(Some(Message::new(StatusCodes::SMTPServiceReady, "mx.nervio.us ESMTP".to_string()))),
+///     }
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_conn(OnConnController::new(on_conn))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     // Refused instead of greeted, then closed
+///     let mut lines = BufReader::new(TcpStream::connect(addr).await.unwrap()).lines();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "554 5.7.1 Your IP is blocklisted");
+///     assert!(lines.next_line().await.unwrap().is_none());
+/// }
+/// ```
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnConnController<B>(
-    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static>,
+    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> Pin<Box<dyn Future<Output = Result<Option<Message>, Message>> + Send>> + Send + Sync + 'static>,
 );
 
 impl<B> OnConnController<B> {
     /// # New
     ///
     /// This function creates a new OnConnController.
-    pub fn new<F, T>(f: F) -> Self
+    pub fn new<F, Fut>(f: F) -> Self
     where
-        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static,
-        T: 'static + Clone + Send + Sync,
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Option<Message>, Message>> + Send + 'static,
     {
-        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| f(conn);
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| {
+            Box::pin(f(conn)) as Pin<Box<dyn Future<Output = Result<Option<Message>, Message>> + Send>>
+        };
 
         OnConnController(Arc::new(wrapped_fn))
     }
@@ -0,0 +1,143 @@
+use core::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::{connection::SMTPConnection, envelope::Envelope, mail::Mail, message::Message};
+
+/// # FilterVerdict
+///
+/// This enum represents the verdict of the content filter for a received email.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterVerdict {
+    /// # Accept
+    ///
+    /// The email is accepted as is and passed to `on_email`.
+    Accept,
+    /// # Reject
+    ///
+    /// The email is rejected with the given message, `on_email` is not called.
+    Reject(Message),
+    /// # Quarantine
+    ///
+    /// The email is accepted and passed to `on_email` with `Envelope::quarantined` set, so it can be stored apart.
+    Quarantine,
+    /// # Modify
+    ///
+    /// The email body is replaced with the given bytes (e.g. after adding a spam header or rewriting) and passed to `on_email`.
+    Modify(Vec<u8>),
+}
+
+/// # OnContentFilterController
+///
+/// This struct represents a controller that is called when an email is received, before `on_email` and before the final reply.
+/// It receives the parsed mail and the envelope of the transaction, and returns a `FilterVerdict`.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_content_filter::{FilterVerdict, OnContentFilterController};
+/// use neo_email::controllers::on_email::OnEmailController;
+/// use neo_email::envelope::Envelope;
+/// use neo_email::mail::Mail;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn filter(_conn: Arc<Mutex<SMTPConnection<()>>>, mail: Mail<Vec<u8>>, _envelope: Envelope) -> FilterVerdict {
+///     let body = String::from_utf8_lossy(&mail.body).to_string();
+///     if body.contains("viagra") {
+///         FilterVerdict::Reject(Message::new(StatusCodes::TransactionFailed, "Spam detected".to_string()))
+///     } else {
+///         FilterVerdict::Modify(body.replace("Hello", "Hi").into_bytes())
+///     }
+/// }
+///
+/// async fn on_email(_conn: Arc<Mutex<SMTPConnection<()>>>, mail: Mail<Vec<u8>>) -> Message {
+///     // Echo the first line of the body, to see the modification in the reply
+///     let body = String::from_utf8_lossy(&mail.body).to_string();
+///     Message::new(StatusCodes::OK, body.lines().next().unwrap_or_default().to_string())
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+///         .on_content_filter(OnContentFilterController::new(filter))
+///         .on_email(OnEmailController::new(on_email))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     let mut replies = Vec::new();
+///     for body in ["Hello World", "Buy viagra"] {
+///         for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"] {
+///             writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+///             lines.next_line().await.unwrap();
+///         }
+///         let mail = format!("From: jean@nervio.us\r\nSubject: Test\r\n\r\n{}\r\n.\r\n", body);
+///         writer.write_all(mail.as_bytes()).await.unwrap();
+///         replies.push(lines.next_line().await.unwrap().unwrap());
+///     }
+///
+///     assert_eq!(replies[0], "250 Hi World");
+///     assert_eq!(replies[1], "554 Spam detected");
+/// }
+/// ```
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct OnContentFilterController<B>(
+    pub  Arc<
+        dyn Fn(
+                Arc<Mutex<SMTPConnection<B>>>,
+                Mail<Vec<u8>>,
+                Envelope,
+            ) -> Pin<Box<dyn Future<Output = FilterVerdict> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    >,
+);
+
+impl<B> OnContentFilterController<B> {
+    /// # New
+    ///
+    /// This function creates a new OnContentFilterController.
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>, Mail<Vec<u8>>, Envelope) -> Fut
+            + Send
+            + Sync
+            + 'static,
+        Fut: Future<Output = FilterVerdict> + Send + 'static,
+    {
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>,
+                               mail: Mail<Vec<u8>>,
+                               envelope: Envelope| {
+            Box::pin(f(conn, mail, envelope))
+                as Pin<Box<dyn Future<Output = FilterVerdict> + Send>>
+        };
+
+        OnContentFilterController(Arc::new(wrapped_fn))
+    }
+}
+
+impl<B> fmt::Debug for OnContentFilterController<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure")
+    }
+}
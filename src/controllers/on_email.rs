@@ -10,16 +10,177 @@ use crate::{
     message::Message,
 };
 
+/// # Email Reply
+///
+/// This enum represents the result of the `on_email` controller, a single reply for the whole transaction
+/// or one reply per accepted recipient in the order of the envelope.
+///
+/// SMTP has a single reply to the DATA command, so the per-recipient replies are collapsed. When a recipient accepted
+/// the mail the reply is its 2xx, as a failure would make the client resend the mail to every recipient, including the ones
+/// that already got it. The controller must then bounce (DSN) the mail of the failed recipients to the sender itself.
+/// When none accepted the reply is the worst one (a 5xx over a 4xx, the first one on a tie).
+/// An empty list is replied as if there was no controller.
+///
+/// The per-recipient replies of LMTP (RFC 2033) and the generation of the DSN parts aren't supported yet.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_email::{EmailReply, OnEmailController};
+/// use neo_email::mail::Mail;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> EmailReply {
+///     let conn = conn.lock().await;
+///     let recipients = conn.envelope.as_ref().map(|envelope| envelope.recipients.clone()).unwrap_or_default();
+///     recipients
+///         .iter()
+///         .map(|recipient| match recipient.username.as_str() {
+///             "full" => Message::new(StatusCodes::ExceededStorageAllocation, format!("{} mailbox full", recipient)),
+///             "busy" => Message::new(StatusCodes::RequestedActionAbortedLocalErrorInProcessing, format!("{} try later", recipient)),
+///             _ => Message::new(StatusCodes::OK, format!("{} delivered", recipient)),
+///         })
+///         .collect::<Vec<_>>()
+///         .into()
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     // Collapsing, an accepted recipient wins, otherwise the first worst reply
+///     let failures = vec![
+///         Message::new(StatusCodes::RequestedActionAbortedLocalErrorInProcessing, "a try later".to_string()),
+///         Message::new(StatusCodes::ExceededStorageAllocation, "b mailbox full".to_string()),
+///         Message::new(StatusCodes::TransactionFailed, "c rejected".to_string()),
+///     ];
+///     assert_eq!(EmailReply::from(failures.clone()).collapse().unwrap().message, "b mailbox full");
+///     let mut replies = failures.clone();
+///     replies.push(Message::new(StatusCodes::OK, "d delivered".to_string()));
+///     assert_eq!(EmailReply::from(replies).collapse().unwrap().message, "d delivered");
+///     assert!(EmailReply::from(Vec::new()).collapse().is_none());
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+///         .on_email(OnEmailController::new(on_email))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     for (recipients, expected) in [
+///         (vec!["jean", "admin"], "250 jean@nervio.us delivered"),
+///         (vec!["jean", "busy", "admin"], "250 jean@nervio.us delivered"),
+///         (vec!["busy", "full", "jean"], "250 jean@nervio.us delivered"),
+///         (vec!["busy", "full"], "552 full@nervio.us mailbox full"),
+///     ] {
+///         writer.write_all(b"MAIL FROM:<sender@nervio.us>\r\n").await.unwrap();
+///         lines.next_line().await.unwrap();
+///         for recipient in recipients {
+///             writer.write_all(format!("RCPT TO:<{}@nervio.us>\r\n", recipient).as_bytes()).await.unwrap();
+///             lines.next_line().await.unwrap();
+///         }
+///         writer.write_all(b"DATA\r\n").await.unwrap();
+///         lines.next_line().await.unwrap();
+///
+///         writer.write_all(b"Subject: Hi\r\n\r\nHello\r\n.\r\n").await.unwrap();
+///         assert_eq!(lines.next_line().await.unwrap().unwrap(), expected);
+///     }
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmailReply {
+    /// # Message
+    ///
+    /// A single reply for every recipient.
+    Message(Message),
+    /// # Per Recipient
+    ///
+    /// A reply per accepted recipient, in the order of the envelope. The client gets a single reply, a 2xx if any
+    /// recipient accepted the mail, so the failed recipients must be bounced by the controller.
+    PerRecipient(Vec<Message>),
+}
+
+impl EmailReply {
+    /// # Collapse
+    ///
+    /// This function returns the reply to send to the SMTP client, the first 2xx of the per-recipient replies,
+    /// otherwise the worst of them, None if there are none.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::controllers::on_email::EmailReply;
+    /// use neo_email::message::Message;
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// let accepted = Message::new(StatusCodes::OK, "jean@nervio.us delivered".to_string());
+    /// let rejected = Message::new(StatusCodes::TransactionFailed, "admin@nervio.us rejected".to_string());
+    ///
+    /// // One recipient accepted and one rejected, the accepted one wins whatever the order
+    /// let reply = EmailReply::from(vec![rejected.clone(), accepted.clone()]).collapse();
+    /// assert_eq!(reply, Some(accepted.clone()));
+    /// let reply = EmailReply::from(vec![accepted.clone(), rejected.clone()]).collapse();
+    /// assert_eq!(reply, Some(accepted.clone()));
+    ///
+    /// // The first 2xx wins
+    /// let other = Message::new(StatusCodes::OK, "admin@nervio.us delivered".to_string());
+    /// let reply = EmailReply::from(vec![rejected.clone(), accepted.clone(), other]).collapse();
+    /// assert_eq!(reply, Some(accepted));
+    /// ```
+    pub fn collapse(self) -> Option<Message> {
+        match self {
+            EmailReply::Message(message) => Some(message),
+            EmailReply::PerRecipient(messages) => {
+                // A failure reply would make the client deliver again to the recipients that accepted the mail
+                if let Some(accepted) = messages.iter().find(|message| message.is_positive_completion()) {
+                    return Some(accepted.clone());
+                }
+                messages
+                    .into_iter()
+                    .rev()
+                    .max_by_key(|message| message.status.code() / 100)
+            }
+        }
+    }
+}
+
+impl From<Message> for EmailReply {
+    fn from(message: Message) -> Self {
+        EmailReply::Message(message)
+    }
+}
+
+impl From<Vec<Message>> for EmailReply {
+    fn from(messages: Vec<Message>) -> Self {
+        EmailReply::PerRecipient(messages)
+    }
+}
+
 /// # OnEmailController
 ///
 /// This struct represents a controller that is called when an email is received.
+/// It can return a single `Message`, or an `EmailReply` with a reply per recipient.
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnEmailController<B>(
     pub  Arc<
         dyn Fn(
                 Arc<Mutex<SMTPConnection<B>>>,
                 Box<dyn MailTrait>,
-            ) -> Pin<Box<dyn Future<Output = Message> + Send>>
+            ) -> Pin<Box<dyn Future<Output = EmailReply> + Send>>
             + Send
             + Sync
             + 'static,
@@ -30,11 +191,12 @@ impl<B> OnEmailController<B> {
     /// # New
     ///
     /// This function creates a new OnEmailController.
-    pub fn new<F, T, Fut>(f: F) -> Self
+    pub fn new<F, T, Fut, R>(f: F) -> Self
     where
         F: Fn(Arc<Mutex<SMTPConnection<B>>>, Mail<T>) -> Fut + Send + Sync + 'static,
         T: 'static + Clone + Send + Sync,
-        Fut: Future<Output = Message> + Send + 'static,
+        Fut: Future<Output = R> + Send + 'static,
+        R: Into<EmailReply>,
     {
         let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>,
                                mail_trait: Box<dyn MailTrait>| {
@@ -42,7 +204,8 @@ impl<B> OnEmailController<B> {
                 .as_any()
                 .downcast_ref::<Mail<T>>()
                 .expect("Invalid type");
-            Box::pin(f(conn, mail.clone())) as Pin<Box<dyn Future<Output = Message> + Send>>
+            let reply = f(conn, mail.clone());
+            Box::pin(async move { reply.await.into() }) as Pin<Box<dyn Future<Output = EmailReply> + Send>>
         };
 
         OnEmailController(Arc::new(wrapped_fn))
@@ -0,0 +1,96 @@
+use core::fmt;
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc};
+
+use crate::errors::ServerError;
+
+/// # OnErrorController
+///
+/// This struct represents a controller that is called when a connection fails (a TLS handshake, a malformed command,
+/// a timeout...), with the error and the address of the client, None if it couldn't be read.
+/// It's meant for monitoring, e.g. alerting on a spike of handshake failures that could be a downgrade attack or an expired certificate.
+/// The server handles the error the same way with or without it.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::controllers::on_error::OnErrorController;
+/// use neo_email::errors::ServerError;
+/// use neo_email::server::SMTPServer;
+/// use std::net::SocketAddr;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::mpsc;
+/// use tokio_native_tls::native_tls;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+///
+///     let (sender, mut errors) = mpsc::unbounded_channel();
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_tls_acceptor(acceptor.into())
+///         .on_error(OnErrorController::new(move |peer_addr: Option<SocketAddr>, error: ServerError| {
+///             let sender = sender.clone();
+///             async move {
+///                 let _ = sender.send((peer_addr, error));
+///             }
+///         }))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let stream = TcpStream::connect(addr).await.unwrap();
+///     let client_addr = stream.local_addr().unwrap();
+///     let (reader, mut writer) = stream.into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     // A malformed command
+///     writer.write_all(b"\xff\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("500 "));
+///     let (peer_addr, error) = errors.recv().await.unwrap();
+///     assert_eq!(peer_addr, Some(client_addr));
+///     assert!(matches!(error, ServerError::InvalidCommand(_)));
+///
+///     // Plaintext instead of a ClientHello fails the handshake
+///     writer.write_all(b"STARTTLS\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("220 "));
+///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+///     let (peer_addr, error) = errors.recv().await.unwrap();
+///     assert_eq!(peer_addr, Some(client_addr));
+///     assert!(matches!(error, ServerError::TlsHandshakeFailed(_)), "{}", error);
+/// }
+/// ```
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct OnErrorController(
+    pub Arc<dyn Fn(Option<SocketAddr>, ServerError) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync + 'static>,
+);
+
+impl OnErrorController {
+    /// # New
+    ///
+    /// This function creates a new OnErrorController.
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Option<SocketAddr>, ServerError) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let wrapped_fn = move |peer_addr: Option<SocketAddr>, error: ServerError| {
+            Box::pin(f(peer_addr, error)) as Pin<Box<dyn Future<Output = ()> + Send>>
+        };
+
+        OnErrorController(Arc::new(wrapped_fn))
+    }
+}
+
+impl fmt::Debug for OnErrorController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure")
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{connection::SMTPConnection, message::Message};
+use core::fmt;
+use std::{future::Future, pin::Pin, sync::Arc};
+use tokio::sync::{mpsc::Receiver, Mutex};
+
+/// # OnExpnCommandController
+///
+/// This struct represents a controller that is called when the EXPN command is received, with the name of the mailing list.
+/// It returns a receiver of the members, so a big list is sent while it's being read instead of buffered,
+/// or the message to reply when the list can't be expanded (e.g. `550 No such mailing list`).
+///
+/// Every member is a reply line like `Jean Vides <jean@nervio.us>`, sent as `250-<member>` and the last one as `250 <member>`.
+/// A list without members is replied with `250 Mailing list is empty`.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_expn::OnExpnCommandController;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::{mpsc, Mutex};
+///
+/// async fn on_expn(_conn: Arc<Mutex<SMTPConnection<()>>>, list: String) -> Result<mpsc::Receiver<String>, Message> {
+///     if list != "everyone" {
+///         return Err(Message::new(StatusCodes::RequestedActionNotTakenMailboxUnavailable, "No such mailing list".to_string()));
+///     }
+///
+///     // A bounded channel, the members are read while they are sent
+///     let (members, receiver) = mpsc::channel(16);
+///     tokio::spawn(async move {
+///         for i in 1..=500 {
+///             if members.send(format!("Member {} <member{}@nervio.us>", i, i)).await.is_err() {
+///                 break;
+///             }
+///         }
+///     });
+///     Ok(receiver)
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_expn_cmd(OnExpnCommandController::new(on_expn))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     writer.write_all(b"EXPN everyone\r\n").await.unwrap();
+///     let mut members = Vec::new();
+///     loop {
+///         let line = lines.next_line().await.unwrap().unwrap();
+///         members.push(line.clone());
+///         if !line.starts_with("250-") {
+///             break;
+///         }
+///     }
+///     assert_eq!(members.len(), 500);
+///     assert_eq!(members[0], "250-Member 1 <member1@nervio.us>");
+///     assert_eq!(members[498], "250-Member 499 <member499@nervio.us>");
+///     assert_eq!(members[499], "250 Member 500 <member500@nervio.us>");
+///
+///     writer.write_all(b"EXPN nobody\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "550 No such mailing list");
+/// }
+/// ```
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct OnExpnCommandController<B>(
+    pub  Arc<
+        dyn Fn(
+                Arc<Mutex<SMTPConnection<B>>>,
+                String,
+            ) -> Pin<Box<dyn Future<Output = Result<Receiver<String>, Message>> + Send>>
+            + Send
+            + Sync
+            + 'static,
+    >,
+);
+
+impl<B> OnExpnCommandController<B> {
+    /// # New
+    ///
+    /// This function creates a new OnExpnCommandController.
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>, String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Receiver<String>, Message>> + Send + 'static,
+    {
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>, data: String| {
+            Box::pin(f(conn, data))
+                as Pin<Box<dyn Future<Output = Result<Receiver<String>, Message>> + Send>>
+        };
+
+        OnExpnCommandController(Arc::new(wrapped_fn))
+    }
+}
+
+impl<B> fmt::Debug for OnExpnCommandController<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure")
+    }
+}
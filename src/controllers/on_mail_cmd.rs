@@ -1,17 +1,107 @@
-use crate::{connection::SMTPConnection, message::Message};
+use crate::{command::Commands, connection::SMTPConnection, message::Message};
 use core::fmt;
+use hashbrown::HashMap;
 use std::{future::Future, pin::Pin, sync::Arc};
 use tokio::sync::Mutex;
 
+/// # Mail Command
+///
+/// This struct represents the MAIL command given to the `OnMailCommandController`, the raw data after `MAIL`
+/// and its ESMTP parameters already parsed (see `Commands::parse_mail_parameters`).
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_mail_cmd::{MailCommand, OnMailCommandController};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// // Refuses the senders that declare a message bigger than 1000 bytes
+/// async fn on_mail_cmd(_conn: Arc<Mutex<SMTPConnection<()>>>, command: MailCommand) -> Result<Message, Message> {
+///     let declared_size = command.parameter("SIZE").and_then(|size| size.parse::<usize>().ok());
+///     match declared_size {
+///         Some(size) if size > 1000 => Ok(Message::new(StatusCodes::ExceededStorageAllocation, "Too big".to_string())),
+///         _ => Ok(Message::new(StatusCodes::OK, format!("Ok body={:?}", command.parameter("BODY")))),
+///     }
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .on_mail_cmd(OnMailCommandController::new(on_mail_cmd))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     writer.write_all(b"MAIL FROM:<a@b.com> SIZE=20480 BODY=8BITMIME\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("552 "));
+///     writer.write_all(b"MAIL FROM:<a@b.com> size=1000 body=7BIT\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "250 Ok body=Some(\"7BIT\")");
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailCommand {
+    /// # Data
+    ///
+    /// The data after `MAIL`, like `FROM:<jean@nervio.us> SIZE=1000`.
+    pub data: String,
+    /// # Parameters
+    ///
+    /// The ESMTP parameters by uppercased keyword, None for the parameters without value.
+    pub parameters: HashMap<String, Option<String>>,
+}
+
+impl MailCommand {
+    /// # New
+    ///
+    /// This function creates the MailCommand parsing the parameters of the data.
+    pub fn new(data: String) -> Self {
+        let parameters = Commands::parse_mail_parameters(&data);
+        MailCommand { data, parameters }
+    }
+
+    /// # Parameter
+    ///
+    /// This function returns the value of the parameter, the keyword is case-insensitive.
+    /// None if the parameter wasn't given or has no value, see `has_parameter` for the latter.
+    pub fn parameter(&self, keyword: &str) -> Option<&str> {
+        self.parameters
+            .get(&keyword.to_ascii_uppercase())
+            .and_then(|value| value.as_deref())
+    }
+
+    /// # Has Parameter
+    ///
+    /// This function returns if the parameter was given, with or without value.
+    pub fn has_parameter(&self, keyword: &str) -> bool {
+        self.parameters.contains_key(&keyword.to_ascii_uppercase())
+    }
+}
+
 /// # OnMailCommandController
 ///
-/// This struct represents a controller that is called when auth command is received.
+/// This struct represents a controller that is called when a MAIL command is received, with its parsed parameters.
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnMailCommandController<B>(
     pub  Arc<
         dyn Fn(
                 Arc<Mutex<SMTPConnection<B>>>,
-                String,
+                MailCommand,
             ) -> Pin<Box<dyn Future<Output = Result<Message, Message>> + Send>>
             + Send
             + Sync
@@ -25,11 +115,11 @@ impl<B> OnMailCommandController<B> {
     /// This function creates a new OnMailCommandController.
     pub fn new<F, Fut>(f: F) -> Self
     where
-        F: Fn(Arc<Mutex<SMTPConnection<B>>>, String) -> Fut + Send + Sync + 'static,
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>, MailCommand) -> Fut + Send + Sync + 'static,
         Fut: Future<Output = Result<Message, Message>> + Send + 'static,
     {
-        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>, data: String| {
-            Box::pin(f(conn, data))
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>, command: MailCommand| {
+            Box::pin(f(conn, command))
                 as Pin<Box<dyn Future<Output = Result<Message, Message>> + Send>>
         };
 
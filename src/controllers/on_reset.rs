@@ -1,5 +1,5 @@
 use core::fmt;
-use std::sync::Arc;
+use std::{future::Future, pin::Pin, sync::Arc};
 
 use tokio::sync::Mutex;
 
@@ -9,20 +9,23 @@ use crate::connection::SMTPConnection;
 ///
 /// This struct represents a controller that is called when an connection is reset.
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnResetController<B>(
-    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static>,
+    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync + 'static>,
 );
 
 impl<B> OnResetController<B> {
     /// # New
     ///
     /// This function creates a new OnResetController.
-    pub fn new<F, T>(f: F) -> Self
+    pub fn new<F, Fut>(f: F) -> Self
     where
-        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> () + Send + Sync + 'static,
-        T: 'static + Clone + Send + Sync,
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
     {
-        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| f(conn);
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| {
+            Box::pin(f(conn)) as Pin<Box<dyn Future<Output = ()> + Send>>
+        };
 
         OnResetController(Arc::new(wrapped_fn))
     }
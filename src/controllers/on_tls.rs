@@ -0,0 +1,111 @@
+use core::fmt;
+use std::{future::Future, pin::Pin, sync::Arc};
+
+use tokio::sync::Mutex;
+
+use crate::connection::SMTPConnection;
+
+/// # OnTlsController
+///
+/// This struct represents a controller that is called once each time a connection is upgraded to TLS with STARTTLS,
+/// after the handshake succeeded. The server already forgot the session (HELO/EHLO identity, authentication, transaction),
+/// the controller wipes the custom state, logs the upgrade or applies a policy.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_tls::OnTlsController;
+/// use neo_email::server::SMTPServer;
+/// use std::net::SocketAddr;
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+/// use tokio_native_tls::native_tls;
+///
+/// static UPGRADES: AtomicUsize = AtomicUsize::new(0);
+///
+/// async fn on_tls(conn: Arc<Mutex<SMTPConnection<()>>>) {
+///     assert!(conn.lock().await.use_tls);
+///     UPGRADES.fetch_add(1, Ordering::SeqCst);
+/// }
+///
+/// // Sends STARTTLS and, if asked, completes the handshake and runs a command over TLS
+/// async fn starttls(addr: SocketAddr, handshake: bool) {
+///     let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+///     let mut line = String::new();
+///     plain.read_line(&mut line).await.unwrap();
+///     plain.write_all(b"STARTTLS\r\n").await.unwrap();
+///     line.clear();
+///     plain.read_line(&mut line).await.unwrap();
+///     assert!(line.starts_with("220 "));
+///     if !handshake {
+///         return;
+///     }
+///
+///     let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+///     let tls = tokio_native_tls::TlsConnector::from(connector)
+///         .connect("localhost", plain.into_inner())
+///         .await
+///         .unwrap();
+///     let mut tls = BufReader::new(tls);
+///     tls.write_all(b"NOOP\r\n").await.unwrap();
+///     line.clear();
+///     tls.read_line(&mut line).await.unwrap();
+///     assert!(line.starts_with("250 "));
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_tls_acceptor(acceptor.into())
+///         .on_tls(OnTlsController::new(on_tls))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     // Once per successful upgrade, never for a client that gives up before the handshake
+///     starttls(addr, true).await;
+///     starttls(addr, false).await;
+///     starttls(addr, true).await;
+///     tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+///     assert_eq!(UPGRADES.load(Ordering::SeqCst), 2);
+/// }
+/// ```
+#[derive(Clone)]
+#[allow(clippy::type_complexity)]
+pub struct OnTlsController<B>(
+    pub Arc<dyn Fn(Arc<Mutex<SMTPConnection<B>>>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync + 'static>,
+);
+
+impl<B> OnTlsController<B> {
+    /// # New
+    ///
+    /// This function creates a new OnTlsController.
+    pub fn new<F, Fut>(f: F) -> Self
+    where
+        F: Fn(Arc<Mutex<SMTPConnection<B>>>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let wrapped_fn = move |conn: Arc<Mutex<SMTPConnection<B>>>| {
+            Box::pin(f(conn)) as Pin<Box<dyn Future<Output = ()> + Send>>
+        };
+
+        OnTlsController(Arc::new(wrapped_fn))
+    }
+}
+
+impl<B> fmt::Debug for OnTlsController<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Closure")
+    }
+}
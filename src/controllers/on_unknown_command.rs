@@ -7,6 +7,7 @@ use tokio::sync::Mutex;
 ///
 /// This struct represents a controller that is called when auth command is received.
 #[derive(Clone)]
+#[allow(clippy::type_complexity)]
 pub struct OnUnknownCommandController<B>(
     pub  Arc<
         dyn Fn(
@@ -0,0 +1,235 @@
+use hashbrown::HashMap;
+
+use crate::mail::EmailAddress;
+
+/// # Envelope
+///
+/// This struct represents the envelope of the current mail transaction, started by MAIL FROM and
+/// cleared after DATA, RSET or a new HELO/EHLO.
+///
+/// ## Example
+///
+/// A MAIL FROM during a transaction is a bad sequence of commands, and a new EHLO clears the pending transaction,
+/// so DATA needs a new MAIL FROM.
+///
+/// ```rust
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server.set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()));
+///     server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+///     writer.write_all(b"RCPT TO:<admin@nervio.us>\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+///
+///     writer.write_all(b"MAIL FROM:<other@nervio.us>\r\n").await.unwrap();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "503 Bad sequence of commands");
+///
+///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+///     while !lines.next_line().await.unwrap().unwrap().starts_with("250 ") {}
+///
+///     writer.write_all(b"DATA\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("503 "));
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Envelope {
+    /// # Sender
+    ///
+    /// The reverse-path given in MAIL FROM, None for the null sender `<>` (usually bounces) or if it couldn't be parsed.
+    pub sender: Option<EmailAddress>,
+    /// # Recipients
+    ///
+    /// The forward-paths given in RCPT TO.
+    pub recipients: Vec<EmailAddress>,
+    /// # Quarantined
+    ///
+    /// If the content filter quarantined the email of this transaction.
+    pub quarantined: bool,
+    /// # Size
+    ///
+    /// The size in bytes of the message received in DATA (without the terminating `.` line), 0 until the data is complete.
+    pub size: usize,
+    /// # Declared Size
+    ///
+    /// The size declared with the MAIL FROM `SIZE` parameter (RFC 1870), None if the sender didn't declare it.
+    pub declared_size: Option<usize>,
+    /// # Require TLS
+    ///
+    /// If the sender asked with the MAIL FROM `REQUIRETLS` parameter that the message is only relayed over TLS (RFC 8689).
+    pub require_tls: bool,
+    /// # Max Size
+    ///
+    /// The smallest size limit set by the RCPT controller for the accepted recipients, enforced during DATA along with the server max size.
+    pub max_size: Option<usize>,
+    /// # Body Type
+    ///
+    /// The body type declared with the MAIL FROM `BODY` parameter (RFC 6152), None if the sender didn't declare it.
+    pub body_type: Option<BodyType>,
+    /// # Auth
+    ///
+    /// The submitter given with the MAIL FROM `AUTH` parameter (RFC 4954 5), `<>` if the client doesn't know it.
+    /// None if it wasn't given, or if the client isn't authenticated as the parameter is only trusted from authenticated clients.
+    /// Unlike the authentication of the connection, it's set for each message.
+    pub auth: Option<String>,
+    /// # SMTPUTF8
+    ///
+    /// If the sender asked with the MAIL FROM `SMTPUTF8` parameter for a transaction with UTF-8 addresses and headers (RFC 6531).
+    pub smtputf8: bool,
+}
+
+impl Envelope {
+    /// # New
+    ///
+    /// This function creates a new Envelope for the given sender without recipients.
+    pub fn new(sender: Option<EmailAddress>) -> Self {
+        Envelope {
+            sender,
+            recipients: Vec::new(),
+            quarantined: false,
+            size: 0,
+            declared_size: None,
+            require_tls: false,
+            max_size: None,
+            body_type: None,
+            auth: None,
+            smtputf8: false,
+        }
+    }
+
+    /// # Has Recipient
+    ///
+    /// This function returns if the recipient is already in the envelope, the domains are compared case-insensitively
+    /// and the local parts exactly, as their case-sensitivity is up to the destination (RFC 5321 2.4).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::envelope::Envelope;
+    /// use neo_email::mail::EmailAddress;
+    ///
+    /// let mut envelope = Envelope::new(None);
+    /// envelope.recipients.push(EmailAddress::from_string("admin@nervio.us").unwrap());
+    ///
+    /// assert!(envelope.has_recipient(&EmailAddress::from_string("admin@NERVIO.us").unwrap()));
+    /// assert!(!envelope.has_recipient(&EmailAddress::from_string("Admin@nervio.us").unwrap()));
+    /// ```
+    pub fn has_recipient(&self, recipient: &EmailAddress) -> bool {
+        let recipient = recipient.normalized();
+        self.recipients
+            .iter()
+            .any(|existing| existing.normalized() == recipient)
+    }
+
+    /// # Recipients By Domain
+    ///
+    /// This function groups the recipients by their lowercased domain, to route them. The recipients keep the form
+    /// given in RCPT TO, to be echoed or delivered as is.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::envelope::Envelope;
+    /// use neo_email::mail::EmailAddress;
+    ///
+    /// let mut envelope = Envelope::new(None);
+    /// envelope.recipients.push(EmailAddress::from_string("A@Example.COM").unwrap());
+    /// envelope.recipients.push(EmailAddress::from_string("a@example.com").unwrap());
+    ///
+    /// let routes = envelope.recipients_by_domain();
+    /// assert_eq!(routes.len(), 1);
+    /// let recipients = routes["example.com"].iter().map(|recipient| recipient.to_string()).collect::<Vec<_>>();
+    /// assert_eq!(recipients, vec!["A@Example.COM", "a@example.com"]);
+    /// ```
+    pub fn recipients_by_domain(&self) -> HashMap<String, Vec<EmailAddress>> {
+        let mut routes: HashMap<String, Vec<EmailAddress>> = HashMap::new();
+        for recipient in &self.recipients {
+            routes
+                .entry(recipient.normalized().domain)
+                .or_default()
+                .push(recipient.clone());
+        }
+        routes
+    }
+
+    /// # Requires 8BITMIME
+    ///
+    /// This function returns if the message was declared as 8-bit, so a relay can only send it as is to a next hop
+    /// that advertises 8BITMIME, otherwise it must be converted to 7-bit or bounced (RFC 6152 3).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::envelope::{BodyType, Envelope};
+    ///
+    /// let mut envelope = Envelope::new(None);
+    /// assert!(!envelope.requires_8bitmime());
+    ///
+    /// envelope.body_type = Some(BodyType::SevenBit);
+    /// assert!(!envelope.requires_8bitmime());
+    ///
+    /// envelope.body_type = Some(BodyType::EightBitMime);
+    /// assert!(envelope.requires_8bitmime());
+    /// ```
+    pub fn requires_8bitmime(&self) -> bool {
+        matches!(self.body_type, Some(BodyType::EightBitMime | BodyType::BinaryMime))
+    }
+}
+
+/// # Body Type
+///
+/// This enum represents the value of the MAIL FROM `BODY` parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BodyType {
+    /// # 7BIT
+    ///
+    /// The message only has 7-bit US-ASCII lines.
+    SevenBit,
+    /// # 8BITMIME
+    ///
+    /// The message may have 8-bit octets in its lines (RFC 6152).
+    EightBitMime,
+    /// # BINARYMIME
+    ///
+    /// The message may have arbitrary binary content, only transferable with BDAT (RFC 3030).
+    BinaryMime,
+}
+
+impl BodyType {
+    /// # From Parameter
+    ///
+    /// This function parses the value of the `BODY` parameter, case-insensitively.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::envelope::BodyType;
+    ///
+    /// assert_eq!(BodyType::from_parameter("8bitmime"), Some(BodyType::EightBitMime));
+    /// assert_eq!(BodyType::from_parameter("7BIT"), Some(BodyType::SevenBit));
+    /// assert_eq!(BodyType::from_parameter("8BIT"), None);
+    /// ```
+    pub fn from_parameter(value: &str) -> Option<Self> {
+        match value.to_uppercase().as_str() {
+            "7BIT" => Some(BodyType::SevenBit),
+            "8BITMIME" => Some(BodyType::EightBitMime),
+            "BINARYMIME" => Some(BodyType::BinaryMime),
+            _ => None,
+        }
+    }
+}
@@ -1,10 +1,26 @@
 use std::fmt;
 
-use super::command::Commands;
+use super::{command::Commands, message::Message};
 
 /// # SMTP Error
 ///
 /// This enum represents the possible errors that can occur in the SMTP server.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::errors::Error;
+/// use std::error::Error as StdError;
+/// use trust_dns_resolver::error::ResolveError;
+///
+/// let dns_error = ResolveError::from("no connections available");
+/// let err = Error::DNSError("Failed to get SPF record".to_string(), Some(Box::new(dns_error)));
+///
+/// assert_eq!(err.to_string(), "DNS Error: Failed to get SPF record");
+/// let source = err.source().unwrap();
+/// assert!(source.downcast_ref::<ResolveError>().is_some());
+/// assert_eq!(source.to_string(), "no connections available");
+/// ```
 #[derive(Debug)]
 pub enum Error {
     /// # IO Error
@@ -17,20 +33,20 @@ pub enum Error {
     ParseError(String),
     /// # DKIM Error
     /// 
-    /// This error occurs when there is a DKIM error.
-    DKIMError(String),
+    /// This error occurs when there is a DKIM error, optionally carrying the underlying error.
+    DKIMError(String, Option<Box<dyn std::error::Error + Send + Sync>>),
     /// # SPF Error
     /// 
-    /// This error occurs when there is a SPF error.
-    SPFError(String),
+    /// This error occurs when there is a SPF error, optionally carrying the underlying error.
+    SPFError(String, Option<Box<dyn std::error::Error + Send + Sync>>),
     /// # DMARC Error
     /// 
     /// This error occurs when there is a DMARC error.
     DMARCError(String),
     /// # DNS Error
     /// 
-    /// This error occurs when there is a DNS error.
-    DNSError(String),
+    /// This error occurs when there is a DNS error, optionally carrying the underlying error.
+    DNSError(String, Option<Box<dyn std::error::Error + Send + Sync>>),
     /// # Unknown Command
     /// 
     /// This error occurs when there is an unknown command.
@@ -39,6 +55,68 @@ pub enum Error {
     /// 
     /// This error occurs when there is a custom error.
     CustomError(String),
+    /// # Delivery Error
+    /// 
+    /// This error occurs when a mail can't be delivered to any host of the domain, with the error of each host tried in order.
+    DeliveryError(String, Vec<(String, Error)>),
+    /// # Unexpected Reply
+    /// 
+    /// This error occurs when a server replies to the client with a status other than the expected, like a rejected recipient.
+    UnexpectedReply(Message),
+}
+
+/// # SMTP Error
+///
+/// An alias of `Error`, the name used by some of the modules and the documentation.
+pub type SMTPError = Error;
+
+/// # From std::io::Error for Error
+///
+/// This allows using `?` on the socket reads and writes.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::command::Commands;
+/// use neo_email::errors::{Error, SMTPError};
+/// use neo_email::message::Message;
+/// use neo_email::status_code::StatusCodes;
+///
+/// fn read() -> Result<(), SMTPError> {
+///     Err(std::io::Error::new(std::io::ErrorKind::ConnectionReset, "reset by peer"))?;
+///     Ok(())
+/// }
+/// assert!(matches!(read(), Err(Error::IoError(_))));
+///
+/// let errors: Vec<(SMTPError, &str)> = vec![
+///     (read().unwrap_err(), "IO Error: reset by peer"),
+///     (Error::ParseError("Invalid email address".to_string()), "Parse Error: Invalid email address"),
+///     (Error::DKIMError("Invalid signature".to_string(), None), "DKIM Error: Invalid signature"),
+///     (Error::SPFError("IP not allowed".to_string(), None), "SPF Error: IP not allowed"),
+///     (Error::DMARCError("Policy failed".to_string()), "DMARC Error: Policy failed"),
+///     (Error::DNSError("Timeout".to_string(), None), "DNS Error: Timeout"),
+///     (Error::UnknownCommand(Commands::EXPN), "Unknown Command: EXPN"),
+///     (Error::CustomError("Something".to_string()), "Custom Error: Something"),
+///     (
+///         Error::DeliveryError(
+///             "All hosts failed".to_string(),
+///             vec![("mx1.nervio.us".to_string(), Error::CustomError("Refused".to_string()))],
+///         ),
+///         "Delivery Error: All hosts failed, mx1.nervio.us (Custom Error: Refused)",
+///     ),
+///     (
+///         Error::UnexpectedReply(Message::new(StatusCodes::RequestedActionNotTakenMailboxUnavailable, "No such user".to_string())),
+///         "Unexpected Reply: 550 No such user",
+///     ),
+/// ];
+/// for (err, display) in errors {
+///     assert_eq!(err.to_string(), display);
+/// }
+/// ```
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::IoError(err)
+    }
 }
 
 /// # Display implementation for Error
@@ -47,15 +125,113 @@ impl fmt::Display for Error {
         match self {
             Error::IoError(err) => write!(f, "IO Error: {}", err),
             Error::ParseError(err) => write!(f, "Parse Error: {}", err),
-            Error::DKIMError(err) => write!(f, "DKIM Error: {}", err),
-            Error::SPFError(err) => write!(f, "SPF Error: {}", err),
+            Error::DKIMError(err, _) => write!(f, "DKIM Error: {}", err),
+            Error::SPFError(err, _) => write!(f, "SPF Error: {}", err),
             Error::DMARCError(err) => write!(f, "DMARC Error: {}", err),
-            Error::DNSError(err) => write!(f, "DNS Error: {}", err),
+            Error::DNSError(err, _) => write!(f, "DNS Error: {}", err),
             Error::UnknownCommand(cmd) => write!(f, "Unknown Command: {:?}", cmd),
             Error::CustomError(msg) => write!(f, "Custom Error: {}", msg),
+            Error::DeliveryError(msg, attempts) => {
+                write!(f, "Delivery Error: {}", msg)?;
+                for (host, err) in attempts {
+                    write!(f, ", {} ({})", host, err)?;
+                }
+                Ok(())
+            }
+            Error::UnexpectedReply(reply) => write!(f, "Unexpected Reply: {} {}", reply.status, reply.message),
         }
     }
 }
 
 /// # Standard Error implementation for Error
-impl std::error::Error for Error {}
+///
+/// The underlying error (if any) is exposed through `source()`.
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(err) => Some(err),
+            Error::DKIMError(_, Some(err))
+            | Error::SPFError(_, Some(err))
+            | Error::DNSError(_, Some(err)) => Some(err.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// # Server Error
+///
+/// This enum represents the errors that stop the server from running, and the errors of a connection
+/// reported to the `OnErrorController`.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::errors::ServerError;
+/// use neo_email::server::SMTPServer;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // The server wasn't bound to an address
+///     let err = SMTPServer::<()>::new().run().await.unwrap_err();
+///     assert!(matches!(err, ServerError::NotBound));
+///     assert_eq!(err.to_string(), "Server Error: bind must be called before run");
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ServerError {
+    /// # Not Bound
+    ///
+    /// This error occurs when the server is run without calling `bind` first.
+    NotBound,
+    /// # TLS Handshake Failed
+    ///
+    /// This error occurs when the upgrade of a connection with STARTTLS fails, with the reason.
+    TlsHandshakeFailed(String),
+    /// # Invalid Command
+    ///
+    /// This error occurs when a command line can't be parsed, with the reason.
+    InvalidCommand(String),
+    /// # Operation Timeout
+    ///
+    /// This error occurs when the client doesn't complete an operation (e.g. a command) within the max operation duration.
+    OperationTimeout,
+    /// # Session Timeout
+    ///
+    /// This error occurs when the connection lasts more than the max session duration.
+    SessionTimeout,
+    /// # Handler Panicked
+    ///
+    /// This error occurs when the task handling a connection panicked, usually in a controller, with the panic message.
+    HandlerPanicked(String),
+    /// # IO
+    ///
+    /// This error occurs when an IO operation of the server fails, like accepting a connection.
+    Io(std::io::Error),
+}
+
+/// # Display implementation for ServerError
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::NotBound => write!(f, "Server Error: bind must be called before run"),
+            ServerError::TlsHandshakeFailed(reason) => write!(f, "Server Error: TLS handshake failed: {}", reason),
+            ServerError::InvalidCommand(reason) => write!(f, "Server Error: invalid command: {}", reason),
+            ServerError::OperationTimeout => write!(f, "Server Error: operation timed out"),
+            ServerError::SessionTimeout => write!(f, "Server Error: session timed out"),
+            ServerError::HandlerPanicked(message) => write!(f, "Server Error: connection handler panicked: {}", message),
+            ServerError::Io(err) => write!(f, "Server Error: IO error: {}", err),
+        }
+    }
+}
+
+/// # Standard Error implementation for ServerError
+impl std::error::Error for ServerError {}
+
+/// # From std::io::Error for ServerError
+///
+/// This allows using `?` on the IO errors in the functions returning server errors, like `run_once`.
+impl From<std::io::Error> for ServerError {
+    fn from(err: std::io::Error) -> Self {
+        ServerError::Io(err)
+    }
+}
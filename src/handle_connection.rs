@@ -1,14 +1,17 @@
-use std::{sync::Arc, time::Duration};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
 
 use tokio::{sync::Mutex, time::timeout};
 use tokio_native_tls::TlsAcceptor;
 
 use crate::{
     client_message::ClientMessage,
-    command::{handle_command, Commands},
+    command::{handle_auth_response, handle_command, Commands},
     connection::{upgrade_to_tls, SMTPConnection, SMTPConnectionStatus},
+    controllers::{on_content_filter::FilterVerdict, on_email::EmailReply, on_error::OnErrorController},
+    errors::ServerError,
+    log_throttle::{READ_ERRORS, SYNTAX_ERRORS, TLS_FAILURES},
     mail::Mail,
-    message::Message,
+    message::{Message, CRLF},
     server::Controllers,
     status_code::StatusCodes,
 };
@@ -16,6 +19,62 @@ use crate::{
 /// # handle_connection_with_timeout
 ///
 /// This function is responsible for handling the connection with the client, including the TLS handshake, and the SMTP commands, also dispatching the controllers configuring a timeout for session and operation.
+///
+/// The connection is handled in its own task, so a panic in a controller only ends that connection: it's logged,
+/// the client gets a 421 and the socket is closed, while the server keeps accepting connections.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_mail_cmd::{MailCommand, OnMailCommandController};
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// async fn on_mail_cmd(_conn: Arc<Mutex<SMTPConnection<()>>>, command: MailCommand) -> Result<Message, Message> {
+///     if command.data.contains("bug@") {
+///         panic!("Buggy controller");
+///     }
+///     Ok(Message::builder().status(StatusCodes::OK).message("Ok".to_string()).build())
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .workers(2)
+///         .on_mail_cmd(OnMailCommandController::new(on_mail_cmd))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     for _ in 0..2 {
+///         let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///         let mut lines = BufReader::new(reader).lines();
+///         lines.next_line().await.unwrap();
+///
+///         writer.write_all(b"MAIL FROM:<bug@nervio.us>\r\n").await.unwrap();
+///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("421 "));
+///         assert!(lines.next_line().await.unwrap().is_none());
+///     }
+///
+///     // The server is still up for the next clients
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+/// }
+/// ```
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_connection_with_timeout<B>(
     use_tls: bool,
     tls_acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
@@ -28,54 +87,114 @@ pub async fn handle_connection_with_timeout<B>(
 ) where
     B: 'static + Default + Send + Sync + Clone,
 {
-    // Dispatch on_conn controller (if exists)
-    if let Some(on_conn) = &controllers.on_conn {
-        let on_conn = on_conn.0.clone();
-        on_conn(mutex_con.clone());
-    }
-
     let mutex_conn_for_handle_connection = mutex_con.clone();
-    // Start the main loop for handling the connection with a max session duration
-    match timeout(
-        max_session_duration,
-        handle_connection(
-            use_tls,
-            tls_acceptor,
-            mutex_conn_for_handle_connection,
-            controllers,
-            max_size,
-            allowed_commands,
-            max_op_duration,
-        ),
-    )
-    .await
-    {
-        Ok(_) => (),
-        Err(_) => {
+    let on_error = controllers.on_error.clone();
+    // Start the main loop for handling the connection with a max session duration, in its own task to catch the panics of the controllers
+    let session = tokio::spawn(async move {
+        // Dispatch on_conn controller (if exists), it can replace the greeting or refuse the connection
+        let greeting = match &controllers.on_conn {
+            Some(on_conn) => {
+                let on_conn = on_conn.0.clone();
+                on_conn(mutex_conn_for_handle_connection.clone()).await
+            }
+            None => Ok(None),
+        };
+        let greeting = match greeting {
+            Ok(greeting) => greeting.unwrap_or_else(|| {
+                Message::builder()
+                    .status(StatusCodes::SMTPServiceReady)
+                    .message("SMTP Service Ready".to_string())
+                    .build()
+            }),
+            Err(rejection) => {
+                log::trace!("[🚫] Connection refused by the on_conn controller");
+                let conn = mutex_conn_for_handle_connection.lock().await;
+                let _ = conn
+                    .write_message(&rejection, true)
+                    .await
+                    .map_err(|err| log::error!("{}", err));
+                let _ = conn.close().await.map_err(|err| log::error!("{}", err));
+                return Ok(());
+            }
+        };
+
+        timeout(
+            max_session_duration,
+            handle_connection(
+                use_tls,
+                tls_acceptor,
+                mutex_conn_for_handle_connection,
+                greeting,
+                controllers,
+                max_size,
+                allowed_commands,
+                max_op_duration,
+            ),
+        )
+        .await
+    });
+
+    match session.await {
+        Ok(Ok(_)) => (),
+        Ok(Err(_)) => {
+            let peer_addr = mutex_con.lock().await.peer_addr;
+            report_error(&on_error, peer_addr, ServerError::SessionTimeout).await;
+
             let conn = mutex_con.lock().await;
             let _ = conn
-                .write_socket(
+                .write_message(
                     &Message::builder()
                         .status(StatusCodes::ServiceClosingTransmissionChannel)
                         .message("Service closing transmission channel".to_string())
-                        .build()
-                        .as_bytes(true),
+                        .build(),
+                    true,
+                )
+                .await
+                .map_err(|err| log::error!("{}", err));
+
+            let _ = conn.close().await.map_err(|err| log::error!("{}", err));
+        }
+        Err(err) if err.is_panic() => {
+            log::error!("[💥] Connection handler panicked, closing the connection: {:?}", err);
+            let panic = err.into_panic();
+            let message = panic
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| panic.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+
+            // The guards held by the task were released while unwinding
+            let peer_addr = mutex_con.lock().await.peer_addr;
+            report_error(&on_error, peer_addr, ServerError::HandlerPanicked(message)).await;
+
+            let conn = mutex_con.lock().await;
+            let _ = conn
+                .write_message(
+                    &Message::builder()
+                        .status(StatusCodes::ServiceNotAvailable)
+                        .message("Local error in processing, closing transmission channel".to_string())
+                        .build(),
+                    true,
                 )
                 .await
                 .map_err(|err| log::error!("{}", err));
 
             let _ = conn.close().await.map_err(|err| log::error!("{}", err));
         }
+        Err(err) => log::error!("[💥] Connection handler cancelled: {}", err),
     }
 }
 
 /// # handle_connection
 ///
 /// This function is responsible for handling the connection with the client, including the TLS handshake, and the SMTP commands, also dispatching the controllers.
+/// The session starts by sending the greeting.
+#[allow(clippy::too_many_arguments)]
 pub async fn handle_connection<B>(
     use_tls: bool,
     tls_acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
     mutex_con: Arc<Mutex<SMTPConnection<B>>>,
+    greeting: Message,
     controllers: Controllers<B>,
     max_size: usize,
     allowed_commands: Vec<Commands>,
@@ -87,18 +206,12 @@ pub async fn handle_connection<B>(
     // Send the initial message to the client
     let conn = mutex_con.lock().await;
     // Send the initial message to the client that lets the client know that the server is ready
-    match conn
-        .write_socket(
-            &Message::builder()
-                .status(StatusCodes::SMTPServiceReady)
-                .message("SMTP Service Ready".to_string())
-                .build()
-                .as_bytes(true),
-        )
-        .await
-    {
+    match conn.write_message(&greeting, true).await {
         Ok(_) => (),
-        Err(err) => panic!("{}", err),
+        Err(err) => {
+            log::error!("[❌] Failed to send the greeting: {}", err);
+            return;
+        }
     };
 
     // Drop the lock to the connection
@@ -111,7 +224,6 @@ pub async fn handle_connection<B>(
         match timeout(
             max_op_duration,
             handle_connection_logic(
-                use_tls,
                 tls_acceptor.clone(),
                 mutex_con.clone(),
                 controllers.clone(),
@@ -125,20 +237,18 @@ pub async fn handle_connection<B>(
             Ok(HandleConnectionFlow::Break) => break,
             Err(_) => {
                 log::trace!("[⏳] Timeout reached, closing connection");
+                let peer_addr = mutex_con.lock().await.peer_addr;
+                report_error(&controllers.on_error, peer_addr, ServerError::OperationTimeout).await;
                 break;
             }
         }
     }
 
-    // Drop the lock to the connection
-    let conn = mutex_con.lock().await;
-
     // Dispatch on_close controller (if exists)
-    controllers.on_close.as_ref().map(|on_close| {
+    if let Some(on_close) = &controllers.on_close {
         let on_close = on_close.0.clone();
-        drop(conn);
-        let _ = on_close(mutex_con.clone());
-    });
+        on_close(mutex_con.clone()).await;
+    }
 
     // Re-lock the connection to send the final message to the client
     let conn = mutex_con.lock().await;
@@ -146,12 +256,12 @@ pub async fn handle_connection<B>(
     // Send the final message to the client
     log::trace!("[👋] Sending final message to client to close");
     let _ = conn
-        .write_socket(
+        .write_message(
             &Message::builder()
                 .status(StatusCodes::ServiceClosingTransmissionChannel)
                 .message("Service closing transmission channel".to_string())
-                .build()
-                .as_bytes(true),
+                .build(),
+            true,
         )
         .await
         .map_err(|err| log::error!("{}", err));
@@ -160,6 +270,87 @@ pub async fn handle_connection<B>(
     let _ = conn.close().await.map_err(|err| log::error!("{}", err));
 }
 
+/// # Report Error
+///
+/// This function dispatches the on_error controller (if exists) with an error of the connection.
+async fn report_error(on_error: &Option<OnErrorController>, peer_addr: Option<SocketAddr>, error: ServerError) {
+    if let Some(on_error) = on_error {
+        let on_error = on_error.0.clone();
+        on_error(peer_addr, error).await;
+    }
+}
+
+/// # Read Chunk Size
+///
+/// The bytes read from the socket at once, commands and mails longer than it are accumulated over several reads.
+const READ_CHUNK_SIZE: usize = 2048;
+
+/// # Data Terminator
+///
+/// The line with a single dot that ends the mail data, with the line break before it.
+const DATA_TERMINATOR: &[u8] = b"\r\n.\r\n";
+
+/// # Find CRLF
+///
+/// This function returns the position of the first line break of the buffer.
+fn find_crlf(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(CRLF.len()).position(|window| window == CRLF.as_bytes())
+}
+
+/// # Find Data End
+///
+/// This function returns where the mail data ends (after the terminating `.` line), searching the terminator from the given position.
+/// An empty mail is only the `.` line, it can be found at the start of the data.
+fn find_data_end(mail_buffer: &[u8], from: usize, at_data_start: bool) -> Option<usize> {
+    let dot_line = &DATA_TERMINATOR[CRLF.len()..];
+    if at_data_start && mail_buffer.starts_with(dot_line) {
+        return Some(dot_line.len());
+    }
+
+    mail_buffer[from..]
+        .windows(DATA_TERMINATOR.len())
+        .position(|window| window == DATA_TERMINATOR)
+        .map(|position| from + position + DATA_TERMINATOR.len())
+}
+
+/// # Unstuff Dots
+///
+/// This function removes the dot the client added before the lines of the mail starting with a dot (RFC 5321 4.5.2),
+/// the terminating `.` line at `content_len` is kept.
+fn unstuff_dots(mail_buffer: &mut Vec<u8>, content_len: usize) {
+    let mut written = 0;
+    let mut line_start = true;
+    for read in 0..mail_buffer.len() {
+        let byte = mail_buffer[read];
+        let stuffed = line_start && byte == b'.' && read < content_len;
+        line_start = byte == b'\n';
+        if !stuffed {
+            mail_buffer[written] = byte;
+            written += 1;
+        }
+    }
+    mail_buffer.truncate(written);
+}
+
+/// # Reject Pipelining
+///
+/// This function replies to a client that pipelined commands while the pipelining is disabled, and closes the connection.
+async fn reject_pipelining<B>(conn: &SMTPConnection<B>) -> HandleConnectionFlow {
+    log::trace!("[🚫] Client pipelined commands without PIPELINING, closing connection");
+    let _ = conn
+        .write_message(
+            &Message::builder()
+                .status(StatusCodes::TransactionFailed)
+                .message("Improper use of SMTP command pipelining".to_string())
+                .build(),
+            true,
+        )
+        .await
+        .map_err(|err| log::error!("{}", err));
+
+    HandleConnectionFlow::Break
+}
+
 /// # HandleConnectionFlow
 /// 
 /// This enum represents the possible flows that can occur while handling the connection.
@@ -177,8 +368,128 @@ pub enum HandleConnectionFlow {
 /// # handle_connection_logic
 /// 
 /// This function is responsible for handling the connection logic, including the TLS handshake, and the SMTP commands, also dispatching the controllers.
+///
+/// The data is accumulated across reads, so the `\r\n.\r\n` terminator is detected wherever the reads split it.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::controllers::on_email::OnEmailController;
+/// use neo_email::mail::Mail;
+/// use neo_email::message::Message;
+/// use neo_email::server::SMTPServer;
+/// use neo_email::status_code::StatusCodes;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio::sync::Mutex;
+///
+/// static RECEIVED: std::sync::Mutex<Vec<Vec<u8>>> = std::sync::Mutex::new(Vec::new());
+///
+/// async fn on_email(_conn: Arc<Mutex<SMTPConnection<()>>>, mail: Mail<Vec<u8>>) -> Message {
+///     RECEIVED.lock().unwrap().push(mail.body);
+///     Message::new(StatusCodes::OK, "Received".to_string())
+/// }
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+///         .on_email(OnEmailController::new(on_email))
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let stream = TcpStream::connect(addr).await.unwrap();
+///     stream.set_nodelay(true).unwrap();
+///     let (reader, mut writer) = stream.into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     lines.next_line().await.unwrap();
+///
+///     let terminator = b"\r\n.\r\n";
+///     // The terminator split at every position, and one byte per read
+///     let mut splits = (1..terminator.len()).map(|at| vec![&terminator[..at], &terminator[at..]]).collect::<Vec<_>>();
+///     splits.push(terminator.chunks(1).collect());
+///
+///     for pieces in &splits {
+///         for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"] {
+///             writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+///             lines.next_line().await.unwrap();
+///         }
+///
+///         writer.write_all(b"Subject: Split\r\n\r\nHello\r\nWorld").await.unwrap();
+///         for piece in pieces {
+///             tokio::time::sleep(Duration::from_millis(20)).await;
+///             writer.write_all(piece).await.unwrap();
+///         }
+///         assert_eq!(lines.next_line().await.unwrap().unwrap(), "250 Received");
+///
+///         // Exactly one boundary, the next command is read as a command
+///         writer.write_all(b"NOOP\r\n").await.unwrap();
+///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+///     }
+///
+///     // No bytes lost or duplicated
+///     let received = RECEIVED.lock().unwrap();
+///     assert_eq!(received.len(), splits.len());
+///     assert!(received.iter().all(|body| body == b"Hello\r\nWorld\r\n"));
+/// }
+/// ```
+///
+/// The commands pipelined after STARTTLS were sent in plaintext, they are discarded instead of being run once the session is protected.
+///
+/// ```rust
+/// use neo_email::server::SMTPServer;
+/// use std::net::SocketAddr;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+/// use tokio_native_tls::native_tls;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+///
+///     let mut server = SMTPServer::<()>::new();
+///     server
+///         .set_tls_acceptor(acceptor.into())
+///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+///         .await
+///         .unwrap();
+///     let addr = server.local_addr().unwrap();
+///     tokio::spawn(async move { server.run().await });
+///
+///     let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+///     plain.read_line(&mut String::new()).await.unwrap();
+///
+///     // An injected RSET in the same packet as STARTTLS
+///     plain.write_all(b"STARTTLS\r\nRSET\r\n").await.unwrap();
+///     let mut line = String::new();
+///     plain.read_line(&mut line).await.unwrap();
+///     assert!(line.starts_with("220 "));
+///
+///     let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+///     let tls = tokio_native_tls::TlsConnector::from(connector)
+///         .connect("localhost", plain.into_inner())
+///         .await
+///         .unwrap();
+///     let mut tls = BufReader::new(tls);
+///
+///     // The first reply over TLS is the one of the first command sent over TLS
+///     tls.write_all(b"NOOP\r\n").await.unwrap();
+///     let mut line = String::new();
+///     tls.read_line(&mut line).await.unwrap();
+///     assert_eq!(line, "250 NOOP Command successful\r\n");
+/// }
+/// ```
 pub async fn handle_connection_logic<B>(
-    use_tls: bool,
     tls_acceptor: Option<Arc<Mutex<TlsAcceptor>>>,
     mutex_con: Arc<Mutex<SMTPConnection<B>>>,
     controllers: Controllers<B>,
@@ -189,134 +500,338 @@ where
     B: 'static + Default + Send + Sync + Clone,
 {
     let mut conn = mutex_con.lock().await;
-    let mut buf = [0; 2048];
-
-    // Read from the socket
-    let n = conn.read_socket(&mut buf).await.unwrap_or_else(|err| {
-        log::trace!("[🕵️‍♂️💻] Error reading from socket: {}", err);
-        0
-    });
+    let mut buf = [0; READ_CHUNK_SIZE];
+
+    // The pipelined input left by the previous command or message is processed before reading again
+    let has_command_line = conn.status == SMTPConnectionStatus::WaitingCommand
+        && !conn.discarding_command
+        && find_crlf(&conn.buffer).is_some();
+    let pipelined_data = if conn.status == SMTPConnectionStatus::WaitingData && !conn.buffer.is_empty() {
+        std::mem::take(&mut conn.buffer)
+    } else {
+        Vec::new()
+    };
 
-    // Check if the buffer is empty, if so close the connection
-    if n == 0 {
-        drop(conn);
-        log::trace!("[🖥️🔒] Connection closed by client");
-        return HandleConnectionFlow::Break;
-    }
+    let chunk: &[u8] = if has_command_line {
+        &[]
+    } else if !pipelined_data.is_empty() {
+        &pipelined_data
+    } else {
+        // Read from the socket
+        let n = conn.read_socket(&mut buf).await.unwrap_or_else(|err| {
+            if let Some(suppressed) = READ_ERRORS.check() {
+                log::trace!("[🕵️‍♂️💻] Error reading from socket: {}{}", err, suppressed);
+            }
+            0
+        });
 
-    // Check if the buffer size is greater than 2048, if so reset the buffer
-    if conn.status == SMTPConnectionStatus::WaitingCommand && conn.buffer.len() + n > 2048 {
-        let _ = conn
-            .write_socket(
-                &Message::builder()
-                    .status(StatusCodes::ExceededStorageAllocation)
-                    .message("Buffer size exceeded, Resetting buffer".to_string())
-                    .build()
-                    .as_bytes(true),
-            )
-            .await
-            .map_err(|err| log::error!("{}", err));
+        // Check if the buffer is empty, if so close the connection
+        if n == 0 {
+            drop(conn);
+            log::trace!("[🖥️🔒] Connection closed by client");
+            return HandleConnectionFlow::Break;
+        }
 
-        conn.buffer.clear();
+        // Check if the client exceeded the bytes allowed for the whole session, if so close the connection
+        conn.count_read_bytes(n);
+        if let Some(max_session_bytes) = conn.max_session_bytes {
+            if conn.session_bytes > max_session_bytes {
+                log::trace!(
+                    "[🚫] Session bytes exceeded ({} > {}), closing connection",
+                    conn.session_bytes,
+                    max_session_bytes
+                );
+                let _ = conn
+                    .write_message(
+                        &Message::builder()
+                            .status(StatusCodes::ServiceNotAvailable)
+                            .message("Session size limit exceeded, closing connection".to_string())
+                            .build(),
+                        true,
+                    )
+                    .await
+                    .map_err(|err| log::error!("{}", err));
 
-        controllers.on_reset.as_ref().map(|on_reset| {
-            let on_reset = on_reset.0.clone();
-            drop(conn);
-            let _ = on_reset(mutex_con.clone());
-        });
+                return HandleConnectionFlow::Break;
+            }
+        }
 
-        return HandleConnectionFlow::Continue;
-    }
+        &buf[..n]
+    };
 
-    if conn.status == SMTPConnectionStatus::WaitingData && conn.mail_buffer.len() + n > max_size {
+    // Check if the current transaction took too long, if so abort it closing the connection
+    if conn.transaction_expired() {
+        log::trace!("[⏳] Transaction timeout reached, closing connection");
         let _ = conn
-            .write_socket(
+            .write_message(
                 &Message::builder()
-                    .status(StatusCodes::ExceededStorageAllocation)
-                    .message("Buffer size exceeded, Resetting buffer".to_string())
-                    .build()
-                    .as_bytes(true),
+                    .status(StatusCodes::RequestedActionAbortedLocalErrorInProcessing)
+                    .message("Transaction timeout exceeded".to_string())
+                    .build(),
+                true,
             )
             .await
             .map_err(|err| log::error!("{}", err));
 
-        conn.mail_buffer.clear();
-
-        controllers.on_reset.as_ref().map(|on_reset| {
-            let on_reset = on_reset.0.clone();
-            drop(conn);
-            let _ = on_reset(mutex_con.clone());
-        });
-
-        return HandleConnectionFlow::Continue;
+        return HandleConnectionFlow::Break;
     }
 
+    // The recipients may have a smaller limit than the server
+    let max_size = match conn.envelope.as_ref().and_then(|envelope| envelope.max_size) {
+        Some(recipients_max_size) => recipients_max_size.min(max_size),
+        None => max_size,
+    };
+
+    let mut data_complete = false;
     if conn.status == SMTPConnectionStatus::WaitingData {
-        conn.mail_buffer.extend_from_slice(&buf[..n]);
+        // An oversized mail is still read until its terminator to stay in sync with the client, the 552 is the reply to the DATA
+        if !conn.discarding_data && conn.mail_buffer.len() + chunk.len() > max_size {
+            log::trace!("[📧] Mail exceeded the max size, discarding it until the terminator");
+            conn.discarding_data = true;
+            let keep_from = conn.mail_buffer.len().saturating_sub(DATA_TERMINATOR.len() - 1);
+            conn.mail_buffer.drain(..keep_from);
+        }
+
+        // Only the new bytes are scanned, with the tail that may hold the start of a split terminator
+        let search_from = conn.mail_buffer.len().saturating_sub(DATA_TERMINATOR.len() - 1);
+        conn.mail_buffer.extend_from_slice(chunk);
+
+        let at_data_start = !conn.discarding_data;
+        if let Some(end) = find_data_end(&conn.mail_buffer, search_from, at_data_start) {
+            // The commands pipelined after the message are kept for the next iterations
+            let pipelined = conn.mail_buffer.split_off(end);
+            conn.buffer.clear();
+            conn.buffer.extend_from_slice(&pipelined);
+            data_complete = true;
+        } else if conn.discarding_data {
+            // While discarding only the tail is kept, so the terminator is detected even if it's split between reads
+            let keep_from = conn.mail_buffer.len().saturating_sub(DATA_TERMINATOR.len() - 1);
+            conn.mail_buffer.drain(..keep_from);
+        }
     } else {
-        conn.buffer.extend_from_slice(&buf[..n]);
+        conn.buffer.extend_from_slice(chunk);
     }
 
-    // Check if the buffer ends with \r\n.\r\n that means that the client has sent the mail data
-    if conn.status == SMTPConnectionStatus::WaitingData && conn.mail_buffer.ends_with(b"\r\n.\r\n")
-    {
-        // Dispatch on_email controller (if exists)
-        if let Some(on_email) = &controllers.on_email {
-            let on_email = on_email.0.clone();
-            let mail = match Mail::<Vec<u8>>::from_bytes(conn.mail_buffer.clone()) {
-                Ok(mail) => mail,
-                Err(err) => {
-                    log::error!("{}", err);
-                    return HandleConnectionFlow::Continue;
-                }
+    // A command line longer than the limit is still read until its CRLF to stay in sync with the client, then replied with 500
+    if conn.status == SMTPConnectionStatus::WaitingCommand {
+        let line_end = find_crlf(&conn.buffer);
+        if conn.discarding_command || line_end.is_some_and(|end| end + CRLF.len() > conn.max_command_size) {
+            let Some(end) = line_end else {
+                // Keep the last byte, it may be the CR of the CRLF
+                let keep_from = conn.buffer.len().saturating_sub(1);
+                conn.buffer.drain(..keep_from);
+                return HandleConnectionFlow::Continue;
             };
 
-            conn.mail_buffer.clear();
+            let _ = conn
+                .write_message(
+                    &Message::builder()
+                        .status(StatusCodes::SyntaxError)
+                        .message("Line too long".to_string())
+                        .build(),
+                    true,
+                )
+                .await
+                .map_err(|err| log::error!("{}", err));
+
+            conn.discarding_command = false;
+            conn.buffer.drain(..end + CRLF.len());
 
-            // Drop conn, to allow lock on_email controller
             drop(conn);
-            let response = on_email(mutex_con.clone(), Box::new(mail)).await;
+            if let Some(on_reset) = &controllers.on_reset {
+                let on_reset = on_reset.0.clone();
+                on_reset(mutex_con.clone()).await;
+            }
 
-            let conn = mutex_con.lock().await;
+            return HandleConnectionFlow::Continue;
+        }
+
+        if line_end.is_none() && conn.buffer.len() > conn.max_command_size {
+            log::trace!("[🚫] Command line exceeded the max size, discarding it until the CRLF");
+            conn.discarding_command = true;
+            let keep_from = conn.buffer.len().saturating_sub(1);
+            conn.buffer.drain(..keep_from);
+            return HandleConnectionFlow::Continue;
+        }
+    }
+
+    // The client sent the mail data, ended by the terminator
+    if data_complete {
+        if !conn.pipelining && !conn.buffer.is_empty() {
+            return reject_pipelining(&conn).await;
+        }
+
+        if conn.discarding_data {
             let _ = conn
-                .write_socket(&response.as_bytes(true))
+                .write_message(
+                    &Message::builder()
+                        .status(StatusCodes::ExceededStorageAllocation)
+                        .message("Message exceeds the maximum size".to_string())
+                        .build(),
+                    true,
+                )
                 .await
-                .map_err(|err| {
-                    log::error!("{}", err);
-                });
-        } else {
-            let response = Message::builder()
-                .status(StatusCodes::OK)
-                .message("Message received".to_string())
-                .build()
-                .to_string(true);
+                .map_err(|err| log::error!("{}", err));
+
+            conn.discarding_data = false;
+            conn.status = SMTPConnectionStatus::WaitingCommand;
+            conn.mail_buffer.clear();
+            conn.mail_buffer.shrink_to_fit();
+            conn.reset_transaction();
+
+            drop(conn);
+            if let Some(on_reset) = &controllers.on_reset {
+                let on_reset = on_reset.0.clone();
+                on_reset(mutex_con.clone()).await;
+            }
 
-            conn.write_socket(response.as_bytes()).await.unwrap();
+            return HandleConnectionFlow::Continue;
         }
 
+        // The message size excludes the terminating `.` line and the dots escaping the lines starting with a dot
+        let content_len = conn.mail_buffer.len() - b".\r\n".len();
+        unstuff_dots(&mut conn.mail_buffer, content_len);
+        let size = conn.mail_buffer.len() - b".\r\n".len();
+        if let Some(envelope) = conn.envelope.as_mut() {
+            envelope.size = size;
+        }
+
+        let default_response = Message::builder()
+            .status(StatusCodes::OK)
+            .message(format!("Message received ({} bytes)", size))
+            .build();
+
+        // Only the headers are checked, a malformed mail is left to the parsing below
+        let empty_body = conn.reject_empty_body && Mail::view(&conn.mail_buffer).is_ok_and(|view| view.body.is_empty());
+
+        let response = if empty_body {
+            log::trace!("[📧] Email without body rejected");
+            drop(conn);
+            Message::builder()
+                .status(StatusCodes::TransactionFailed)
+                .message("Empty message".to_string())
+                .build()
+        } else if controllers.on_content_filter.is_some() || controllers.on_email.is_some()
+        {
+            match Mail::<Vec<u8>>::from_bytes(conn.mail_buffer.clone()) {
+                // Every DATA needs a final reply, a malformed message is rejected instead of leaving the client waiting
+                Err(err) => {
+                    log::error!("[📧] Malformed email: {}", err);
+                    let status = conn.malformed_mail_status.clone();
+                    drop(conn);
+                    Message::builder()
+                        .status(status)
+                        .message(format!("Malformed message: {}", err))
+                        .build()
+                }
+                Ok(mut mail) => {
+                    conn.mail_buffer.clear();
+                    let envelope = conn.envelope.clone().unwrap_or_default();
+
+                    // Drop conn, to allow lock on controllers
+                    drop(conn);
+
+                    // Dispatch on_content_filter controller (if exists), it runs before on_email
+                    let mut rejection = None;
+                    if let Some(on_content_filter) = &controllers.on_content_filter {
+                        let on_content_filter = on_content_filter.0.clone();
+                        match on_content_filter(mutex_con.clone(), mail.clone(), envelope).await {
+                            FilterVerdict::Accept => (),
+                            FilterVerdict::Reject(message) => rejection = Some(message),
+                            FilterVerdict::Quarantine => {
+                                log::trace!("[📧] Email quarantined by content filter");
+                                if let Some(envelope) = mutex_con.lock().await.envelope.as_mut() {
+                                    envelope.quarantined = true;
+                                }
+                            }
+                            FilterVerdict::Modify(body) => mail.body = body,
+                        }
+                    }
+
+                    match (rejection, &controllers.on_email) {
+                        (Some(rejection), _) => {
+                            log::trace!("[📧] Email rejected by content filter");
+                            rejection
+                        }
+                        // Dispatch on_email controller (if exists)
+                        (None, Some(on_email)) => {
+                            let on_email = on_email.0.clone();
+                            let reply = on_email(mutex_con.clone(), Box::new(mail)).await;
+                            if let EmailReply::PerRecipient(replies) = &reply {
+                                log::trace!("[📧] Per-recipient replies: {:?}", replies);
+                            }
+                            reply.collapse().unwrap_or(default_response)
+                        }
+                        (None, None) => default_response,
+                    }
+                }
+            }
+        } else {
+            drop(conn);
+            default_response
+        };
+
+        let conn = mutex_con.lock().await;
+        let _ = conn
+            .write_message(&response, true)
+            .await
+            .map_err(|err| log::error!("{}", err));
+        drop(conn);
+
         log::trace!("[📧] Email received, Relocking connection to ensure mail_buffer to be clean");
         let mut conn = mutex_con.lock().await;
         // Set the status to WaitingCommand
         conn.status = SMTPConnectionStatus::WaitingCommand;
-        conn.buffer.clear();
+        // The buffer was sized for this message, an idle connection doesn't keep it
         conn.mail_buffer.clear();
+        conn.mail_buffer.shrink_to_fit();
+        conn.reset_transaction();
         log::trace!("[📧] Connection status set to WaitingCommand");
         return HandleConnectionFlow::Continue;
     }
 
-    // Check if the buffer ends with \r\n that means that the client has sent a command
-    if conn.status == SMTPConnectionStatus::WaitingCommand && conn.buffer.ends_with(b"\r\n") {
-        // Parse the buffer into a ClientMessage
-        let mut client_message = match ClientMessage::<String>::from_bytes(conn.buffer.clone()) {
+    // Check if the buffer has a \r\n that means that the client has sent a command, the pipelined ones are handled one by one
+    let line_end = find_crlf(&conn.buffer);
+    if let (SMTPConnectionStatus::WaitingCommand, Some(end)) = (&conn.status, line_end) {
+        let line = conn.buffer.drain(..end + CRLF.len()).collect::<Vec<u8>>();
+        if !conn.pipelining && !conn.buffer.is_empty() {
+            return reject_pipelining(&conn).await;
+        }
+
+        // The line answers the challenge of an AUTH in progress, it isn't a command
+        if let Some(pending_auth) = conn.pending_auth.take() {
+            drop(conn);
+            let response = String::from_utf8_lossy(&line[..end]).to_string();
+            let (messages, status) = handle_auth_response(mutex_con.clone(), &controllers, pending_auth, response).await;
+
+            let mut conn = mutex_con.lock().await;
+            conn.status = status.clone();
+            let _ = conn
+                .send_reply(messages)
+                .await
+                .map_err(|err| log::error!("{}", err));
+
+            return match status {
+                SMTPConnectionStatus::Closed => HandleConnectionFlow::Break,
+                _ => HandleConnectionFlow::Continue,
+            };
+        }
+
+        // Parse the line into a ClientMessage
+        let mut client_message = match ClientMessage::<String>::from_bytes(line) {
             Ok(msg) => msg,
             Err(err) => {
+                if let Some(suppressed) = SYNTAX_ERRORS.check() {
+                    log::debug!("[🚫] Invalid command: {}{}", err, suppressed);
+                }
+                report_error(&controllers.on_error, conn.peer_addr, ServerError::InvalidCommand(err.to_string())).await;
                 match conn
-                    .write_socket(
+                    .write_message(
                         &Message::builder()
                             .status(StatusCodes::SyntaxError)
                             .message(err.to_string())
-                            .build()
-                            .as_bytes(true),
+                            .build(),
+                        true,
                     )
                     .await
                 {
@@ -331,28 +846,43 @@ where
             }
         };
 
+        if let Some(delay) = conn.command_delays.get(&client_message.command).copied() {
+            log::trace!("[⏳] Delaying the {:?} command {:?}", client_message.command, delay);
+            drop(conn);
+            tokio::time::sleep(delay).await;
+            conn = mutex_con.lock().await;
+        }
+
         if client_message.command == Commands::QUIT {
             log::trace!("[🚪] Connection closed by client");
             return HandleConnectionFlow::Break;
+        } else if let Some(maintenance) = &conn.maintenance {
+            log::trace!("[🚧] Server in maintenance, replying {:?}", client_message.command);
+            let _ = conn
+                .write_message(maintenance, true)
+                .await
+                .map_err(|err| log::error!("{}", err));
+
+            return HandleConnectionFlow::Continue;
         } else if client_message.command == Commands::RSET {
             log::trace!("[🔄] Connection Reset Request, cleaning buffers and waiting commands...");
-            conn.buffer.clear();
             conn.mail_buffer.clear();
             conn.status = SMTPConnectionStatus::WaitingCommand;
+            conn.reset_transaction();
 
             log::trace!("[🔄] Connection Resetted, running on_reset controller...");
             if let Some(on_reset) = &controllers.on_reset {
                 let on_reset = on_reset.0.clone();
                 drop(conn);
-                let _ = on_reset(mutex_con.clone());
+                on_reset(mutex_con.clone()).await;
             } else {
                 let _ = conn
-                    .write_socket(
+                    .write_message(
                         &Message::builder()
                             .status(StatusCodes::OK)
                             .message("Connection reset".to_string())
-                            .build()
-                            .as_bytes(true),
+                            .build(),
+                        true,
                     )
                     .await
                     .map_err(|err| log::error!("{}", err));
@@ -366,7 +896,7 @@ where
 
         // Drop the lock to the connection
         drop(conn);
-        let (mut response, status) = match handle_command(
+        let (response, status) = match handle_command(
             mutex_con.clone(),
             controllers.clone(),
             &mut client_message,
@@ -379,12 +909,12 @@ where
             Err(err) => {
                 let conn = mutex_con.lock().await;
                 let _ = conn
-                    .write_socket(
+                    .write_message(
                         &Message::builder()
                             .status(StatusCodes::TransactionFailed)
                             .message(err.to_string())
-                            .build()
-                            .as_bytes(true),
+                            .build(),
+                        true,
                     )
                     .await
                     .map_err(|err| log::error!("{}", err));
@@ -405,33 +935,27 @@ where
         // Set the new status
         conn.status = status;
 
-        // Get the last index of alls messages (because last message is different)
-        let last_index = response.len() - 1;
         // Get the tls_acceptor to upgrade the connection to TLS (if needed)
         let tls_acceptor = tls_acceptor.clone();
 
         // Check if client want to start TLS and if the server supports it
         if conn.status == SMTPConnectionStatus::Closed {
-            for (i, message) in response.iter_mut().enumerate() {
-                let is_last = i == last_index;
-                let bytes = message.as_bytes(is_last);
-                conn.write_socket(&bytes).await.unwrap();
-            }
+            let _ = conn.send_reply(response).await.map_err(|err| log::error!("{}", err));
             conn.buffer.clear();
             return HandleConnectionFlow::Break;
-        } else if conn.status == SMTPConnectionStatus::StartTLS && use_tls && tls_acceptor.is_some()
-        {
+        } else if conn.status == SMTPConnectionStatus::StartTLS {
+            // The octets after STARTTLS were sent in plaintext, a MITM could have injected them (RFC 3207 6),
+            // they are dropped before the handshake so nothing read before it is processed after it
+            let discarded = conn.buffer.len() + conn.mail_buffer.len();
+            if discarded > 0 {
+                log::debug!("[🌐🔒🚫] Discarding {} bytes pipelined after STARTTLS", discarded);
+            }
+            conn.buffer.clear();
+            conn.mail_buffer.clear();
+            conn.discarding_command = false;
+
             // let know the client that we are ready to start TLS
-            match conn
-                .write_socket(
-                    &Message::builder()
-                        .status(StatusCodes::SMTPServiceReady)
-                        .message("Ready to start TLS".to_string())
-                        .build()
-                        .as_bytes(true),
-                )
-                .await
-            {
+            match conn.send_reply(response).await {
                 Ok(_) => (),
                 Err(err) => {
                     log::error!("{}", err);
@@ -443,58 +967,53 @@ where
             drop(conn);
             match upgrade_to_tls(mutex_con.clone(), tls_acceptor).await {
                 Ok(_) => {
-                    log::trace!("[🌐🔒🟢] Connection upgraded to TLS");
+                    log::trace!("[🌐🔒🟢] Connection upgraded to TLS, running on_tls controller...");
 
-                    let mut conn = mutex_con.lock().await;
-                    conn.buffer.clear();
-                    conn.status = SMTPConnectionStatus::WaitingCommand;
+                    // The session was already reset by the upgrade, the controller resets the custom state
+                    if let Some(on_tls) = &controllers.on_tls {
+                        let on_tls = on_tls.0.clone();
+                        on_tls(mutex_con.clone()).await;
+                    }
 
                     return HandleConnectionFlow::Continue;
                 }
                 Err(err) => {
-                    log::error!(
-                        "[🌐🔒🚫] An error ocurred while trying to upgrade to TLS {}",
-                        err
-                    );
+                    if let Some(suppressed) = TLS_FAILURES.check() {
+                        log::error!(
+                            "[🌐🔒🚫] An error ocurred while trying to upgrade to TLS {}{}",
+                            err,
+                            suppressed
+                        );
+                    }
 
                     let mut conn = mutex_con.lock().await;
-                    conn.write_socket(
-                        &Message::builder()
-                            .status(StatusCodes::TransactionFailed)
-                            .message("TLS not available".to_string())
-                            .build()
-                            .as_bytes(true),
-                    )
-                    .await
-                    .unwrap();
+                    report_error(&controllers.on_error, conn.peer_addr, ServerError::TlsHandshakeFailed(err.to_string())).await;
+
+                    // Without the plaintext socket back there is nothing to reply on, the connection is closed
+                    if conn.tcp_buff_socket.is_none() {
+                        conn.buffer.clear();
+                        conn.status = SMTPConnectionStatus::Closed;
+                        return HandleConnectionFlow::Break;
+                    }
+
+                    // The handshake failed on the plaintext socket, the client gets 454 and can continue without TLS
+                    let _ = conn
+                        .write_message(
+                            &Message::builder()
+                                .status(StatusCodes::TLSNotAvailable)
+                                .message("TLS not available due to temporary reason".to_string())
+                                .build(),
+                            true,
+                        )
+                        .await
+                        .map_err(|err| log::error!("{}", err));
 
                     conn.buffer.clear();
                     conn.status = SMTPConnectionStatus::WaitingCommand;
                 }
             };
-        } else if conn.status == SMTPConnectionStatus::StartTLS && !use_tls {
-            log::trace!("[🌐🔒🚫] TLS not available");
-
-            let _ = conn
-                .write_socket(
-                    &Message::builder()
-                        .status(StatusCodes::TransactionFailed)
-                        .message("TLS not available".to_string())
-                        .build()
-                        .as_bytes(true),
-                )
-                .await
-                .map_err(|err| log::error!("{}", err));
-
-            conn.buffer.clear();
-            conn.status = SMTPConnectionStatus::WaitingCommand;
         } else {
-            for (i, message) in response.iter_mut().enumerate() {
-                let is_last = i == last_index;
-                let bytes = message.as_bytes(is_last);
-                conn.write_socket(&bytes).await.unwrap();
-            }
-            conn.buffer.clear();
+            let _ = conn.send_reply(response).await.map_err(|err| log::error!("{}", err));
         }
     }
 
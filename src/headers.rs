@@ -130,6 +130,12 @@ pub enum EmailHeaders {
     /// Reference: [https://www.iana.org/go/rfc4021](https://www.iana.org/go/rfc4021)
     #[serde(rename = "Content-Transfer-Encoding")]
     ContentTransferEncoding, // https://www.iana.org/go/rfc4021
+    /// # Content-Disposition
+    /// 
+    /// The Content-Disposition header field can be used to specify if a body part is shown inline or is an attachment, and its filename.
+    /// Reference: [https://www.iana.org/go/rfc2183](https://www.iana.org/go/rfc2183)
+    #[serde(rename = "Content-Disposition")]
+    ContentDisposition, // https://www.iana.org/go/rfc2183
     /// # Conversion
     /// 
     /// The Conversion header field can be used to specify the conversion information for the message.
@@ -603,7 +609,7 @@ pub enum EmailHeaders {
     /// 
     /// The TLS-Required header field can be used to specify that TLS is required for the message.
     /// Reference: [https://www.iana.org/go/rfc8689](https://www.iana.org/go/rfc8689)
-    #[serde(rename = "TlS-Required")]
+    #[serde(rename = "TLS-Required")]
     TLSRequired, // https://www.iana.org/go/rfc8689
     /// # To
     /// 
@@ -711,6 +717,7 @@ impl EmailHeaders {
             EmailHeaders::ContentReturn => "Content-Return",
             EmailHeaders::ContentType => "Content-Type",
             EmailHeaders::ContentTransferEncoding => "Content-Transfer-Encoding",
+            EmailHeaders::ContentDisposition => "Content-Disposition",
             EmailHeaders::Conversion => "Conversion",
             EmailHeaders::ConversionWithLoss => "Conversion-With-Loss",
             EmailHeaders::DLExpansionHistory => "DL-Expansion-History",
@@ -787,7 +794,7 @@ impl EmailHeaders {
             EmailHeaders::Supersedes => "Supersedes",
             EmailHeaders::TLSReportDomain => "TLS-Report-Domain",
             EmailHeaders::TLSReportSubmitter => "TLS-Report-Submitter",
-            EmailHeaders::TLSRequired => "TlS-Required",
+            EmailHeaders::TLSRequired => "TLS-Required",
             EmailHeaders::To => "To",
             EmailHeaders::VBRInfo => "VBR-Info",
             EmailHeaders::X400ContentIdentifier => "X400-Content-Identifier",
@@ -827,6 +834,7 @@ impl EmailHeaders {
             "content-return" => EmailHeaders::ContentReturn,
             "content-type" => EmailHeaders::ContentType,
             "content-transfer-encoding" => EmailHeaders::ContentTransferEncoding,
+            "content-disposition" => EmailHeaders::ContentDisposition,
             "conversion" => EmailHeaders::Conversion,
             "conversion-with-loss" => EmailHeaders::ConversionWithLoss,
             "dl-expansion-history" => EmailHeaders::DLExpansionHistory,
@@ -936,3 +944,224 @@ impl fmt::Display for EmailHeaders {
         write!(f, "{}", &serialized[1..serialized.len() - 1])
     }
 }
+
+/// # Header Field
+///
+/// A header field of a mail, its name and its unfolded value. The fields parsed from a mail keep their original bytes,
+/// folding and line ending included, to be serialized back unchanged.
+#[derive(Debug, Clone)]
+pub struct HeaderField {
+    /// # Name
+    ///
+    /// The name of the header field.
+    pub name: EmailHeaders,
+    /// # Value
+    ///
+    /// The unfolded value of the header field, with its whitespace collapsed.
+    pub value: String,
+    raw: Option<Vec<u8>>,
+}
+
+impl HeaderField {
+    /// # New
+    ///
+    /// This function creates a new header field, serialized as `Name: value`.
+    pub fn new(name: EmailHeaders, value: &str) -> Self {
+        HeaderField {
+            name,
+            value: value.to_string(),
+            raw: None,
+        }
+    }
+
+    /// # Raw
+    ///
+    /// This function returns the original bytes of the header field, none if it wasn't parsed from a mail or was edited.
+    pub fn raw(&self) -> Option<&[u8]> {
+        self.raw.as_deref()
+    }
+
+    /// # With Raw
+    ///
+    /// This function sets the original bytes of the header field.
+    pub(crate) fn with_raw(mut self, raw: Vec<u8>) -> Self {
+        self.raw = Some(raw);
+        self
+    }
+
+    /// # Raw Mut
+    ///
+    /// This function returns the original bytes of the header field to append a continuation line.
+    pub(crate) fn raw_mut(&mut self) -> Option<&mut Vec<u8>> {
+        self.raw.as_mut()
+    }
+
+    /// # To Bytes
+    ///
+    /// This function serializes the header field, its original bytes if it has them, otherwise `Name: value` and CRLF.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match &self.raw {
+            Some(raw) => raw.clone(),
+            None => format!("{}: {}\r\n", self.name.to_string(), self.value).into_bytes(),
+        }
+    }
+}
+
+/// The original bytes are only a serialization detail, the fields are compared by name and value
+impl PartialEq for HeaderField {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.value == other.value
+    }
+}
+
+impl Eq for HeaderField {}
+
+/// # Headers
+///
+/// The header fields of a mail in the order of the mail, repeated fields like the `Received` trace headers included.
+/// The lookups by name return the first occurrence.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::headers::{EmailHeaders, Headers};
+///
+/// let mut headers = Headers::new();
+/// headers.append(EmailHeaders::Received, "from b.nervio.us by mx.nervio.us");
+/// headers.append(EmailHeaders::Subject, "Hello");
+/// headers.append(EmailHeaders::Received, "from a.nervio.us by b.nervio.us");
+///
+/// assert_eq!(headers.len(), 3);
+/// assert_eq!(headers[&EmailHeaders::Received], "from b.nervio.us by mx.nervio.us");
+/// assert_eq!(headers.get_all(&EmailHeaders::Received).len(), 2);
+///
+/// // Insert replaces every occurrence, keeping the position of the first one
+/// headers.insert(EmailHeaders::Received, "from c.nervio.us by mx.nervio.us");
+/// assert_eq!(headers.get_all(&EmailHeaders::Received), ["from c.nervio.us by mx.nervio.us"]);
+/// assert_eq!(headers.iter().next().unwrap().name, EmailHeaders::Received);
+///
+/// assert_eq!(headers.remove(&EmailHeaders::Subject), Some("Hello".to_string()));
+/// assert!(!headers.contains_key(&EmailHeaders::Subject));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<HeaderField>,
+}
+
+impl Headers {
+    /// # New
+    ///
+    /// This function creates an empty list of header fields.
+    pub fn new() -> Self {
+        Headers::default()
+    }
+
+    /// # Get
+    ///
+    /// This function returns the value of the first occurrence of the header.
+    pub fn get(&self, name: &EmailHeaders) -> Option<&String> {
+        self.fields.iter().find(|field| &field.name == name).map(|field| &field.value)
+    }
+
+    /// # Get All
+    ///
+    /// This function returns the value of every occurrence of the header in order, empty if there is none.
+    pub fn get_all(&self, name: &EmailHeaders) -> Vec<&String> {
+        self.fields
+            .iter()
+            .filter(|field| &field.name == name)
+            .map(|field| &field.value)
+            .collect()
+    }
+
+    /// # Contains Key
+    ///
+    /// This function returns if the header is present.
+    pub fn contains_key(&self, name: &EmailHeaders) -> bool {
+        self.fields.iter().any(|field| &field.name == name)
+    }
+
+    /// # Append
+    ///
+    /// This function adds a header field after the others, keeping the previous occurrences.
+    pub fn append(&mut self, name: EmailHeaders, value: &str) {
+        self.push(HeaderField::new(name, value));
+    }
+
+    /// # Push
+    ///
+    /// This function adds a header field after the others.
+    pub fn push(&mut self, field: HeaderField) {
+        self.fields.push(field);
+    }
+
+    /// # Insert
+    ///
+    /// This function sets the value of a header, replacing the first occurrence in place and removing the others,
+    /// or adding it after the others. It returns the previous value of the first occurrence.
+    pub fn insert(&mut self, name: EmailHeaders, value: &str) -> Option<String> {
+        let Some(index) = self.fields.iter().position(|field| field.name == name) else {
+            self.append(name, value);
+            return None;
+        };
+        let previous = std::mem::replace(&mut self.fields[index], HeaderField::new(name.clone(), value));
+        let mut rest = self.fields.split_off(index + 1);
+        rest.retain(|field| field.name != name);
+        self.fields.extend(rest);
+        Some(previous.value)
+    }
+
+    /// # Remove
+    ///
+    /// This function removes every occurrence of the header, returning the value of the first one.
+    pub fn remove(&mut self, name: &EmailHeaders) -> Option<String> {
+        let previous = self.get(name).cloned();
+        self.fields.retain(|field| &field.name != name);
+        previous
+    }
+
+    /// # Len
+    ///
+    /// This function returns the number of header fields, counting every occurrence.
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    /// # Is Empty
+    ///
+    /// This function returns if there are no header fields.
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    /// # Iter
+    ///
+    /// This function returns an iterator over the header fields in order.
+    pub fn iter(&self) -> std::slice::Iter<'_, HeaderField> {
+        self.fields.iter()
+    }
+
+    /// # Last Mut
+    ///
+    /// This function returns the last header field, where a continuation line is appended while parsing.
+    pub(crate) fn last_mut(&mut self) -> Option<&mut HeaderField> {
+        self.fields.last_mut()
+    }
+}
+
+impl std::ops::Index<&EmailHeaders> for Headers {
+    type Output = String;
+
+    fn index(&self, name: &EmailHeaders) -> &String {
+        self.get(name).unwrap_or_else(|| panic!("Header {} not found", name))
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = &'a HeaderField;
+    type IntoIter = std::slice::Iter<'a, HeaderField>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.fields.iter()
+    }
+}
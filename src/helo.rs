@@ -0,0 +1,180 @@
+use std::{net::IpAddr, sync::Arc};
+
+use tokio::sync::Mutex;
+use trust_dns_resolver::TokioAsyncResolver;
+
+use crate::command::parse_helo_identity;
+
+/// # HELO Identity
+///
+/// This enum represents the identity given by the client in HELO/EHLO, see `command::parse_helo_identity`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum HeloIdentity {
+    /// # Domain
+    ///
+    /// A fully qualified domain name, lowercased and without the trailing dot.
+    Domain(String),
+    /// # Address Literal
+    ///
+    /// The IP address of a bracketed address literal, like `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+    AddressLiteral(IpAddr),
+    /// # Invalid
+    ///
+    /// Neither a FQDN nor an address literal.
+    Invalid,
+}
+
+/// # HELO Policy
+///
+/// This enum represents how strict the server is with the identity given in HELO/EHLO.
+/// Policies are cumulative, `RequireFcrdns` also requires a resolvable FQDN.
+///
+/// Address literals (e.g. `[192.0.2.1]` or `[IPv6:2001:db8::1]`) are always accepted, as they are legal when the client has no hostname.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum HeloPolicy {
+    /// # Require FQDN
+    ///
+    /// The identity must be a syntactically valid fully qualified domain name.
+    RequireFqdn,
+    /// # Require Resolvable
+    ///
+    /// The identity must be a FQDN with at least one A or AAAA record.
+    RequireResolvable,
+    /// # Require FCrDNS
+    ///
+    /// The identity must be a FQDN that resolves to the client IP, and the PTR of the client IP must point back to it (Forward-confirmed reverse DNS).
+    RequireFcrdns,
+}
+
+impl HeloPolicy {
+    /// # Validate
+    ///
+    /// Check the HELO/EHLO identity of a client against the policy.
+    ///
+    /// `identity` is the data given after HELO/EHLO
+    /// `peer_ip` is the IP address of the client
+    /// `dns_resolver` is the DNS resolver used for the forward and reverse lookups
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::helo::HeloPolicy;
+    /// use std::net::IpAddr;
+    /// use std::sync::Arc;
+    /// use tokio::sync::Mutex;
+    /// use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
+    /// use trust_dns_resolver::TokioAsyncResolver;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let resolver = TokioAsyncResolver::tokio(ResolverConfig::default(), ResolverOpts::default());
+    ///     let resolver = Arc::new(Mutex::new(resolver));
+    ///     let peer_ip: IpAddr = "192.0.2.1".parse().unwrap();
+    ///
+    ///     // Address literals are legal HELO identities
+    ///     assert!(HeloPolicy::RequireFcrdns.validate("[192.0.2.1]", peer_ip, resolver.clone()).await);
+    ///
+    ///     assert!(!HeloPolicy::RequireFqdn.validate("localhost", peer_ip, resolver.clone()).await);
+    ///     assert!(!HeloPolicy::RequireResolvable.validate("mx.unresolvable.invalid", peer_ip, resolver).await);
+    /// }
+    /// ```
+    pub async fn validate(
+        &self,
+        identity: &str,
+        peer_ip: IpAddr,
+        dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    ) -> bool {
+        let identity = match parse_helo_identity(identity) {
+            HeloIdentity::AddressLiteral(_) => return true,
+            HeloIdentity::Domain(domain) => domain,
+            HeloIdentity::Invalid => {
+                log::trace!("[🚫] HELO identity {} is not a FQDN", identity.trim());
+                return false;
+            }
+        };
+
+        if *self == HeloPolicy::RequireFqdn {
+            return true;
+        }
+
+        // Forward lookup of the identity
+        let dns_resolver = dns_resolver.lock().await;
+        let forward_ips = match dns_resolver.lookup_ip(format!("{}.", identity)).await {
+            Ok(lookup) => lookup.iter().collect::<Vec<IpAddr>>(),
+            Err(err) => {
+                log::trace!("[🚫] HELO identity {} is not resolvable: {}", identity, err);
+                return false;
+            }
+        };
+
+        if forward_ips.is_empty() {
+            return false;
+        }
+
+        if *self == HeloPolicy::RequireResolvable {
+            return true;
+        }
+
+        if !forward_ips.contains(&peer_ip) {
+            log::trace!("[🚫] HELO identity {} doesn't resolve to {}", identity, peer_ip);
+            return false;
+        }
+
+        // Reverse lookup of the client IP, one of the PTR records must match the identity
+        match dns_resolver.reverse_lookup(peer_ip).await {
+            Ok(lookup) => lookup.iter().any(|name| {
+                name.to_utf8().trim_end_matches('.').to_lowercase() == identity
+            }),
+            Err(err) => {
+                log::trace!("[🚫] Reverse lookup of {} failed: {}", peer_ip, err);
+                false
+            }
+        }
+    }
+}
+
+/// # address_literal
+///
+/// Parse an address literal like `[192.0.2.1]` or `[IPv6:2001:db8::1]`.
+pub(crate) fn address_literal(identity: &str) -> Option<IpAddr> {
+    let literal = identity
+        .strip_prefix('[')
+        .and_then(|literal| literal.strip_suffix(']'))?;
+
+    match literal.get(..5) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("IPv6:") => literal[5..]
+            .parse::<std::net::Ipv6Addr>()
+            .ok()
+            .map(IpAddr::V6),
+        _ => literal.parse::<std::net::Ipv4Addr>().ok().map(IpAddr::V4),
+    }
+}
+
+/// # is_fqdn
+///
+/// Check if the identity is a syntactically valid fully qualified domain name.
+pub(crate) fn is_fqdn(identity: &str) -> bool {
+    let labels = identity.split('.').collect::<Vec<&str>>();
+
+    if labels.len() < 2 || identity.len() > 253 {
+        return false;
+    }
+
+    let valid_labels = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+
+    // The top level domain can't be numeric, that would be a bare IP address
+    let numeric_tld = labels
+        .last()
+        .map(|tld| tld.chars().all(|c| c.is_ascii_digit()))
+        .unwrap_or(true);
+
+    valid_labels && !numeric_tld
+}
@@ -0,0 +1,124 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use crate::errors::Error;
+
+/// # IP CIDR
+///
+/// This struct represents an IPv4 or IPv6 network in CIDR notation, like `10.0.0.0/8` or `2001:db8::/32`.
+/// A single address without prefix length is a network with only that address.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::ip_cidr::IpCidr;
+/// use std::net::IpAddr;
+///
+/// let internal: IpCidr = "10.0.0.0/8".parse().unwrap();
+/// assert!(internal.contains("10.1.2.3".parse().unwrap()));
+/// assert!(!internal.contains("11.0.0.1".parse().unwrap()));
+///
+/// // The host bits are ignored, and IPv4-mapped IPv6 peers (dual stack sockets) match the IPv4 networks
+/// let internal: IpCidr = "192.168.1.77/24".parse().unwrap();
+/// assert_eq!(internal.to_string(), "192.168.1.0/24");
+/// assert!(internal.contains("::ffff:192.168.1.20".parse().unwrap()));
+///
+/// let host: IpCidr = "2001:db8::1".parse().unwrap();
+/// assert!(host.contains("2001:db8::1".parse().unwrap()));
+/// assert!(!host.contains("2001:db8::2".parse().unwrap()));
+/// assert!("0.0.0.0/0".parse::<IpCidr>().unwrap().contains("203.0.113.1".parse().unwrap()));
+///
+/// assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+/// assert!("10.0.0/8".parse::<IpCidr>().is_err());
+/// assert!(IpCidr::new("2001:db8::".parse::<IpAddr>().unwrap(), 129).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpCidr {
+    network: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpCidr {
+    /// # New
+    ///
+    /// This function creates the network of the address with the given prefix length, up to 32 for IPv4 and 128 for IPv6.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self, Error> {
+        let network = match address {
+            IpAddr::V4(address) if prefix_len <= 32 => {
+                let mask = u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0);
+                IpAddr::from((u32::from(address) & mask).to_be_bytes())
+            }
+            IpAddr::V6(address) if prefix_len <= 128 => {
+                let mask = u128::MAX.checked_shl(128 - prefix_len as u32).unwrap_or(0);
+                IpAddr::from((u128::from(address) & mask).to_be_bytes())
+            }
+            _ => {
+                return Err(Error::ParseError(format!(
+                    "Invalid prefix length /{} for {}",
+                    prefix_len, address
+                )))
+            }
+        };
+
+        Ok(Self { network, prefix_len })
+    }
+
+    /// # Network
+    ///
+    /// This function returns the first address of the network.
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    /// # Prefix Len
+    ///
+    /// This function returns the number of leading bits that identify the network.
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// # Contains
+    ///
+    /// This function returns if the address is in the network. IPv4-mapped IPv6 addresses are compared as IPv4.
+    pub fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix_len as u32).unwrap_or(0);
+                u32::from(address) & mask == u32::from(network)
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix_len as u32).unwrap_or(0);
+                u128::from(address) & mask == u128::from(network)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || Error::ParseError(format!("Invalid CIDR {}", s));
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (address, Some(prefix_len)),
+            None => (s.trim(), None),
+        };
+
+        let address = address.parse::<IpAddr>().map_err(|_| invalid())?;
+        let prefix_len = match (prefix_len, address) {
+            (Some(prefix_len), _) => prefix_len.parse::<u8>().map_err(|_| invalid())?,
+            (None, IpAddr::V4(_)) => 32,
+            (None, IpAddr::V6(_)) => 128,
+        };
+
+        IpCidr::new(address, prefix_len)
+    }
+}
+
+impl fmt::Display for IpCidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix_len)
+    }
+}
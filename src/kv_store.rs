@@ -0,0 +1,183 @@
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use hashbrown::HashMap;
+
+use crate::errors::{Error, SMTPError};
+
+/// # KV Store
+///
+/// This trait represents a key-value store with expiring keys, shared by the anti-abuse features (greylisting, deduplication,
+/// rate limiting, callout caching) so they don't implement their own expiry. `MemoryKvStore` keeps the keys in the process,
+/// an implementation backed by a shared store (like Redis) lets several processes see the same state.
+pub trait KvStore: Send + Sync {
+    /// # Get
+    ///
+    /// Get the value of the key, None if it doesn't exist or expired.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, SMTPError>> + Send;
+
+    /// # Set With TTL
+    ///
+    /// Set the value of the key, replacing the previous one, expiring after `ttl`.
+    fn set_with_ttl(&self, key: &str, value: Vec<u8>, ttl: Duration) -> impl Future<Output = Result<(), SMTPError>> + Send;
+
+    /// # Incr
+    ///
+    /// Increment the counter of the key atomically and return its new value. A key that doesn't exist starts from 0 and
+    /// expires after `ttl`, the increments of an existing key keep its expiry (a fixed window for rate limits).
+    fn incr(&self, key: &str, ttl: Duration) -> impl Future<Output = Result<i64, SMTPError>> + Send;
+}
+
+/// # Memory KV Store
+///
+/// This struct is the in-process `KvStore`, a map behind a mutex. The expired keys are removed when they are read,
+/// and all of them are swept when the map doubles its size, so keys that are never read again don't accumulate.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::kv_store::{KvStore, MemoryKvStore};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let store = Arc::new(MemoryKvStore::new());
+///
+///     // The keys expire after their TTL
+///     store.set_with_ttl("greylist:192.0.2.1", b"seen".to_vec(), Duration::from_millis(100)).await.unwrap();
+///     assert_eq!(store.get("greylist:192.0.2.1").await.unwrap(), Some(b"seen".to_vec()));
+///     tokio::time::sleep(Duration::from_millis(150)).await;
+///     assert_eq!(store.get("greylist:192.0.2.1").await.unwrap(), None);
+///     assert!(store.is_empty());
+///
+///     // The increments are atomic
+///     let mut tasks = Vec::new();
+///     for _ in 0..10 {
+///         let store = store.clone();
+///         tasks.push(tokio::spawn(async move {
+///             for _ in 0..100 {
+///                 store.incr("rate:192.0.2.1", Duration::from_secs(60)).await.unwrap();
+///             }
+///         }));
+///     }
+///     for task in tasks {
+///         task.await.unwrap();
+///     }
+///     assert_eq!(store.incr("rate:192.0.2.1", Duration::from_secs(60)).await.unwrap(), 1001);
+///     assert_eq!(store.get("rate:192.0.2.1").await.unwrap(), Some(b"1001".to_vec()));
+///
+///     // A counter starts again once expired, and only counters can be incremented
+///     store.incr("rate:192.0.2.2", Duration::from_millis(100)).await.unwrap();
+///     tokio::time::sleep(Duration::from_millis(150)).await;
+///     assert_eq!(store.incr("rate:192.0.2.2", Duration::from_millis(100)).await.unwrap(), 1);
+///     store.set_with_ttl("name", b"jean".to_vec(), Duration::from_secs(60)).await.unwrap();
+///     assert!(store.incr("name", Duration::from_secs(60)).await.is_err());
+/// }
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryKvStore {
+    entries: Mutex<MemoryEntries>,
+}
+
+/// # Memory Entries
+///
+/// The values with their expiry, and the size that triggers the next sweep.
+#[derive(Debug, Default)]
+struct MemoryEntries {
+    values: HashMap<String, (Vec<u8>, Instant)>,
+    sweep_at: usize,
+}
+
+/// # Min Sweep Size
+///
+/// Smaller maps are never swept, reading the keys is enough.
+const MIN_SWEEP_SIZE: usize = 1024;
+
+impl MemoryKvStore {
+    /// # New
+    ///
+    /// This function creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Len
+    ///
+    /// This function returns the number of keys that didn't expire.
+    pub fn len(&self) -> usize {
+        let now = Instant::now();
+        self.lock().values.values().filter(|(_, expires_at)| *expires_at > now).count()
+    }
+
+    /// # Is Empty
+    ///
+    /// This function returns if all the keys expired or there are none.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// # Lock
+    ///
+    /// The entries, also after a panic of another thread as they are never left half updated.
+    fn lock(&self) -> std::sync::MutexGuard<'_, MemoryEntries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl MemoryEntries {
+    /// # Get
+    ///
+    /// The value of the key if it didn't expire, removing it otherwise.
+    fn get(&mut self, key: &str, now: Instant) -> Option<&mut (Vec<u8>, Instant)> {
+        if self.values.get(key).is_some_and(|(_, expires_at)| *expires_at <= now) {
+            self.values.remove(key);
+        }
+        self.values.get_mut(key)
+    }
+
+    /// # Insert
+    ///
+    /// Insert the value, sweeping the expired keys when the map doubled since the last sweep.
+    fn insert(&mut self, key: &str, value: Vec<u8>, expires_at: Instant, now: Instant) {
+        if self.values.len() >= self.sweep_at.max(MIN_SWEEP_SIZE) {
+            self.values.retain(|_, (_, expires_at)| *expires_at > now);
+            self.sweep_at = self.values.len() * 2;
+        }
+        self.values.insert(key.to_string(), (value, expires_at));
+    }
+}
+
+impl KvStore for MemoryKvStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, SMTPError> {
+        let mut entries = self.lock();
+        Ok(entries.get(key, Instant::now()).map(|(value, _)| value.clone()))
+    }
+
+    async fn set_with_ttl(&self, key: &str, value: Vec<u8>, ttl: Duration) -> Result<(), SMTPError> {
+        let now = Instant::now();
+        self.lock().insert(key, value, now + ttl, now);
+        Ok(())
+    }
+
+    async fn incr(&self, key: &str, ttl: Duration) -> Result<i64, SMTPError> {
+        let now = Instant::now();
+        let mut entries = self.lock();
+        match entries.get(key, now) {
+            Some((value, _)) => {
+                let counter = std::str::from_utf8(value)
+                    .ok()
+                    .and_then(|value| value.parse::<i64>().ok())
+                    .ok_or(Error::ParseError(format!("The value of {} isn't a counter", key)))?
+                    .saturating_add(1);
+                *value = counter.to_string().into_bytes();
+                Ok(counter)
+            }
+            None => {
+                entries.insert(key, b"1".to_vec(), now + ttl, now);
+                Ok(1)
+            }
+        }
+    }
+}
@@ -17,6 +17,7 @@
 //! use neo_email::connection::SMTPConnection;
 //! use neo_email::controllers::on_auth::OnAuthController;
 //! use neo_email::controllers::on_email::OnEmailController;
+//! use neo_email::controllers::on_rcpt::OnRCPTCommandController;
 //! use neo_email::headers::EmailHeaders;
 //! use neo_email::mail::Mail;
 //! use neo_email::message::Message;
@@ -35,19 +36,22 @@
 //!    let addr = SocketAddr::from(([127, 0, 0, 1], 2526));
 //!    // Create the server
 //!    SMTPServer::<ConnectionState>::new()
-//!        // Set the number of workers to 1
-//!        .workers(1)
+//!        // Handle up to 100 connections at once
+//!        .workers(100)
 //!        // Set an controller to dispatch when an authentication is received
 //!        .on_auth(OnAuthController::new(on_auth))
 //!        // Set an controller to dispatch when an email is received
 //!        .on_email(OnEmailController::new(on_email))
+//!        // Set a controller to accept the recipients, without it every recipient gets `550 Relay access denied`
+//!        .on_rcpt_cmd(OnRCPTCommandController::new(on_rcpt))
 //!        // Bind the server to the address
 //!        .bind(addr)
 //!        .await
 //!        .unwrap()
 //!        // Run the server
 //!        .run()
-//!        .await;
+//!        .await
+//!        .unwrap();
 //! }
 //!
 //! // This function is called when an authentication is received
@@ -72,15 +76,31 @@
 //!        .build())
 //! }
 //! 
+//! // This function is called when a recipient is received (RCPT TO)
+//! // Only the recipients of our domain are accepted, the others get the default response (`550 Relay access denied`)
+//! pub async fn on_rcpt(conn: Arc<Mutex<SMTPConnection<ConnectionState>>>, data: String) -> Result<Message, Message> {
+//!    let conn = conn.lock().await;
+//!
+//!    // Data is the raw data after RCPT, example: TO:<admin@nervio.us>
+//!    if data.trim_end().to_lowercase().ends_with("@nervio.us>") {
+//!       return Ok(Message::builder()
+//!          .status(StatusCodes::OK)
+//!          .message("Ok".to_string())
+//!          .build());
+//!    }
+//!
+//!    Ok(conn.default_rcpt_response.clone())
+//! }
+//!
 //! // This function is called when an email is received
 //! // The mail is a struct that contains the email data, in this case the raw email data in a Vec<u8>
-//! // Headers are parsed in a hashmap and the body is a Vec<u8>
+//! // Headers are parsed in the order of the mail and the body is a Vec<u8>
 //! pub async fn on_email(conn: Arc<Mutex<SMTPConnection<ConnectionState>>>, mail: Mail<Vec<u8>>) -> Message {
 //!    let conn = conn.lock().await;
 //!    let state = conn.state.lock().await;
 //!
 //!    // Extract headers
-//!    let headers = mail.headers.clone(); // get the hashmap
+//!    let headers = mail.headers.clone(); // get the header fields
 //!    let _subject = headers.get(&EmailHeaders::Subject).unwrap(); // get the Option<Subject> header
 //!
 //!    // Check if the user is authenticated from state set in on_auth
@@ -115,15 +135,27 @@
 //! - `smtp-experimental-headers` - Enable experimental mail headers feature
 //! - `smtp-experimental` - Enable SMTP experimental features (includes `smtp-experimental-headers`)
 //! - `spf-experimental` - Enable Sender Policy Framework experimental features
-//! - `dkim-experimental` - Enable DomainKeys Identified Mail experimental features (includes `sha1`, `sha2`, `base64`)` (NOT AVAILABLE)
-//! - `utilities-experimental` - Enable utilities experimental features (includes `spf-experimental` and `dkim-experimental`)
+//! - `psl-experimental` - Enable Public Suffix List experimental features (includes `publicsuffix`)
+//! - `dmarc-experimental` - Enable DMARC experimental features (includes `psl-experimental`)
+//! - `dkim-experimental` - Enable DomainKeys Identified Mail experimental features (includes `sha1`, `sha2`, `base64`, `openssl`)
+//! - `clamav-experimental` - Enable the antivirus scanner trait and the ClamAV (clamd) scanner
+//! - `utilities-experimental` - Enable utilities experimental features (includes `psl-experimental`, `dmarc-experimental`, `spf-experimental`, `dkim-experimental` and `clamav-experimental`)
 //! - `experimental` - Enable all experimental features (includes `utilities-experimental`)
+//! - `test-utils` - Enable testing helpers like LF-only responses and the `testing` reply parser (never for production)
 //! 
 //! ## License
 //! 
 //! Licensed under the MIT license. See LICENSE for more information.
 //! 
 
+/// # Auth Stats
+/// 
+/// This module contains the counters of the SPF, DKIM and DMARC outcomes.
+pub mod auth_stats;
+/// # Client
+/// 
+/// This module contains the outbound side, to relay the accepted mail to the servers of other domains.
+pub mod client;
 /// # Client Message
 pub mod client_message;
 /// # Command
@@ -132,10 +164,28 @@ pub mod command;
 pub mod connection;
 /// # Controllers
 pub mod controllers;
+/// # Envelope
+pub mod envelope;
 /// # Errors
 pub mod errors;
 /// # Handle Connection
 pub mod handle_connection;
+/// # HELO
+/// 
+/// This module contains the HELO/EHLO identity policies.
+pub mod helo;
+/// # IP CIDR
+/// 
+/// This module contains the IP networks in CIDR notation, to apply policies to ranges of clients.
+pub mod ip_cidr;
+/// # KV Store
+/// 
+/// This module contains the key-value store with expiring keys shared by the anti-abuse features.
+pub mod kv_store;
+/// # Log Throttle
+/// 
+/// This module contains the sampling of the noisiest log sites, to avoid log floods under attack.
+pub mod log_throttle;
 /// # Headers
 /// 
 /// This module contains the headers for the email, this headers are used to parse the email headers.
@@ -177,6 +227,10 @@ pub mod mail;
 ///     .message("OK".to_string())
 ///     .build();
 pub mod message;
+/// # MIME
+/// 
+/// This module contains the MIME parsing used by the mail, like the attachments.
+pub mod mime;
 /// # Server
 /// 
 /// This module contains the SMTP server, from this you can create a fully customizable SMTP server with Commands, Controllers, States and more.
@@ -191,16 +245,17 @@ pub mod message;
 /// async fn main() {
 ///     let addr = SocketAddr::from(([127, 0, 0, 1], 2526));
 ///     // Create the server
-///     SMTPServer::new()
-///         // Set the number of workers to 1
-///         .workers(1)
+///     SMTPServer::<()>::new()
+///         // Handle up to 100 connections at once
+///         .workers(100)
 ///         // Bind the server to the address
 ///         .bind(addr)
 ///         .await
 ///         .unwrap()
 ///         // Run the server
 ///         .run()
-///         .await;
+///         .await
+///         .unwrap();
 /// }
 pub mod server;
 /// # Status Code
@@ -219,6 +274,11 @@ pub mod server;
 ///     .build();
 /// ```
 pub mod status_code;
+/// # Testing
+/// 
+/// This module contains helpers to assert the replies of the server in tests.
+#[cfg(any(test, feature = "test-utils"))]
+pub mod testing;
 /// # Utilities
 /// 
 /// This module contains utilities for the SMTP server for example SPF, DKIM and DMARC
@@ -0,0 +1,153 @@
+use std::fmt;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+/// # Limits
+///
+/// The process-wide limits of the throttled log sites, None logs everything.
+static LIMITS: RwLock<Option<(u64, Duration)>> = RwLock::new(None);
+
+/// # Syntax Errors
+///
+/// Commands that couldn't be parsed.
+pub(crate) static SYNTAX_ERRORS: LogThrottle = LogThrottle::new();
+
+/// # Read Errors
+///
+/// Errors reading from the client sockets.
+pub(crate) static READ_ERRORS: LogThrottle = LogThrottle::new();
+
+/// # TLS Failures
+///
+/// Failed or timed out TLS handshakes.
+pub(crate) static TLS_FAILURES: LogThrottle = LogThrottle::new();
+
+/// # Accept Errors
+///
+/// Errors accepting new TCP connections.
+pub(crate) static ACCEPT_ERRORS: LogThrottle = LogThrottle::new();
+
+/// # Set Log Throttle
+///
+/// This function limits the throttled log sites to `burst` messages per `interval` each, the suppressed messages are
+/// counted and reported with the next message logged. None disables the throttling, the default.
+///
+/// The limits and the log sites (syntax errors, socket read errors, TLS failures and accept errors) are process-wide,
+/// they apply to every server of the process, so it's set once at startup rather than per server.
+pub fn set_log_throttle(limits: Option<(u64, Duration)>) {
+    *LIMITS.write().unwrap_or_else(PoisonError::into_inner) =
+        limits.map(|(burst, interval)| (burst.max(1), interval));
+}
+
+/// # Log Throttle
+///
+/// This struct represents a log site that can be sampled under a flood (malformed commands, failed handshakes...),
+/// so the logging doesn't become the bottleneck or fill the disks.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::log_throttle::{set_log_throttle, LogThrottle};
+/// use std::time::Duration;
+///
+/// static NOISY: LogThrottle = LogThrottle::new();
+///
+/// // Everything is logged by default
+/// assert!((0..10).all(|_| NOISY.check().is_some()));
+///
+/// set_log_throttle(Some((3, Duration::from_millis(100))));
+/// assert!((0..3).all(|_| NOISY.check().is_some()));
+/// assert!((0..5).all(|_| NOISY.check().is_none()));
+///
+/// // The next interval logs again, with the count of the suppressed messages
+/// std::thread::sleep(Duration::from_millis(150));
+/// let suppressed = NOISY.check().unwrap();
+/// assert_eq!(suppressed.count(), 5);
+/// assert_eq!(format!("Handshake failed{}", suppressed), "Handshake failed (5 similar messages suppressed)");
+/// assert_eq!(NOISY.check().unwrap().to_string(), "");
+/// ```
+#[derive(Debug)]
+pub struct LogThrottle {
+    state: Mutex<ThrottleState>,
+}
+
+#[derive(Debug)]
+struct ThrottleState {
+    window_started_at: Option<Instant>,
+    logged: u64,
+    suppressed: u64,
+}
+
+impl LogThrottle {
+    /// # New
+    ///
+    /// This function creates a new LogThrottle, it can be used in a static.
+    pub const fn new() -> Self {
+        LogThrottle {
+            state: Mutex::new(ThrottleState {
+                window_started_at: None,
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+
+    /// # Check
+    ///
+    /// This function returns if the message must be logged, with the messages suppressed since the last one logged.
+    pub fn check(&self) -> Option<Suppressed> {
+        let limits = *LIMITS.read().unwrap_or_else(PoisonError::into_inner);
+        let Some((burst, interval)) = limits else {
+            return Some(Suppressed(0));
+        };
+
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let window_expired = match state.window_started_at {
+            Some(started_at) => now.duration_since(started_at) >= interval,
+            None => true,
+        };
+        if window_expired {
+            state.window_started_at = Some(now);
+            state.logged = 0;
+        }
+
+        if state.logged < burst {
+            state.logged += 1;
+            Some(Suppressed(std::mem::take(&mut state.suppressed)))
+        } else {
+            state.suppressed += 1;
+            None
+        }
+    }
+}
+
+impl Default for LogThrottle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// # Suppressed
+///
+/// This struct represents the messages suppressed before the one being logged, it displays as a summary to append to the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Suppressed(u64);
+
+impl Suppressed {
+    /// # Count
+    ///
+    /// This function returns the number of messages suppressed.
+    pub fn count(&self) -> u64 {
+        self.0
+    }
+}
+
+impl fmt::Display for Suppressed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            0 => Ok(()),
+            count => write!(f, " ({} similar messages suppressed)", count),
+        }
+    }
+}
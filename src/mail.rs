@@ -1,9 +1,12 @@
+use std::fmt;
 use std::str::from_utf8;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use crate::errors::Error;
 
-use super::headers::EmailHeaders;
-use hashbrown::HashMap;
+use super::headers::{EmailHeaders, HeaderField, Headers};
+use crate::mime::Attachment;
 
 /// # Mail
 ///
@@ -11,7 +14,7 @@ use hashbrown::HashMap;
 ///
 /// ## Fields
 ///
-/// * `headers` - A HashMap of EmailHeaders and its values.
+/// * `headers` - The header fields in the order of the mail.
 /// * `body` - The body of the email.
 /// 
 /// ## Example
@@ -22,16 +25,17 @@ use hashbrown::HashMap;
 /// let raw_email = b"From: Jean<jean@nervio.com>\nSubject: Hello\n\nHello, World!";
 /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).unwrap();
 /// ```
-#[derive(Debug, PartialEq, Eq)]
+#[derive(PartialEq, Eq)]
 pub struct Mail<T> {
     /// # Headers
     ///
-    /// A HashMap of EmailHeaders and its values.
+    /// The header fields in the order of the mail, repeated ones like the `Received` trace headers included.
+    /// The lookups by name return the first occurrence.
     ///
     /// ## Example
     ///
     /// `From -> "jean@nervio.us"`
-    pub headers: HashMap<EmailHeaders, String>,
+    pub headers: Headers,
     /// # Body
     ///
     /// The body of the email.
@@ -41,7 +45,9 @@ pub struct Mail<T> {
 impl<T> Mail<T> {
     /// # From Bytes
     /// 
-    /// This function creates a new Mail from bytes.
+    /// This function creates a new Mail from bytes. The SMTP data terminator (`\r\n.\r\n`) is not part of the mail,
+    /// and also ends the headers, so a message with only headers has an empty body. A first line starting with whitespace
+    /// is an error, see `from_bytes_with_policy`.
     /// 
     /// ## Example
     /// 
@@ -50,46 +56,214 @@ impl<T> Mail<T> {
     /// 
     /// let raw_email = b"From: Jean<jean@nervio.com>\nSubject: Hello\n\nHello, World!";
     /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).unwrap();
+    ///
+    /// // Headers, blank line and the data terminator
+    /// let mail = Mail::<Vec<u8>>::from_bytes(b"Subject: Hello\r\n\r\n.\r\n".to_vec()).unwrap();
+    /// assert_eq!(mail.headers.len(), 1);
+    /// assert!(mail.body.is_empty());
+    ///
+    /// // Headers followed directly by the data terminator
+    /// let mail = Mail::<Vec<u8>>::from_bytes(b"Subject: Hello\r\n.\r\n".to_vec()).unwrap();
+    /// assert_eq!(mail.headers.len(), 1);
+    /// assert!(mail.body.is_empty());
+    ///
+    /// // Folded headers are unfolded with a single space
+    /// use neo_email::headers::EmailHeaders;
+    /// let raw_email = concat!(
+    ///     "Subject: Hello\r\n",
+    ///     " World\r\n",
+    ///     "To: Jean <jean@nervio.us>,\r\n",
+    ///     "\t\tAdmin <admin@nervio.us>,\r\n",
+    ///     "    Postmaster <postmaster@nervio.us>\r\n",
+    ///     "\r\n",
+    ///     "Hello\r\n",
+    /// );
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    /// assert_eq!(mail.headers[&EmailHeaders::Subject], "Hello World");
+    /// assert_eq!(
+    ///     mail.headers[&EmailHeaders::To],
+    ///     "Jean <jean@nervio.us>, Admin <admin@nervio.us>, Postmaster <postmaster@nervio.us>"
+    /// );
+    /// assert_eq!(mail.body, b"Hello\r\n");
     /// ```
     pub fn from_bytes(bytes: Vec<u8>) -> Result<Mail<T>, String>
     where
         T: From<Vec<u8>>,
     {
-        let mut headers = HashMap::new();
-        let mut body = Vec::new();
+        Self::parse(bytes, LeadingWhitespace::Error, None)
+    }
+
+    /// # From Bytes With Policy
+    ///
+    /// This function creates a new Mail from bytes like `from_bytes`, with the given handling of a mail whose first line
+    /// starts with whitespace (a continuation line without header to continue).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::{LeadingWhitespace, Mail};
+    ///
+    /// let raw_email = b"  Hello, World!\r\nSubject: not a header\r\n\r\nBye\r\n";
+    ///
+    /// // Rejected by default
+    /// assert!(Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).is_err());
+    /// assert!(Mail::<Vec<u8>>::from_bytes_with_policy(raw_email.to_vec(), LeadingWhitespace::Error).is_err());
+    ///
+    /// // Or the whole mail is body
+    /// let mail = Mail::<Vec<u8>>::from_bytes_with_policy(raw_email.to_vec(), LeadingWhitespace::Body).unwrap();
+    /// assert!(mail.headers.is_empty());
+    /// assert_eq!(mail.body, raw_email.to_vec());
+    /// ```
+    pub fn from_bytes_with_policy(bytes: Vec<u8>, leading_whitespace: LeadingWhitespace) -> Result<Mail<T>, String>
+    where
+        T: From<Vec<u8>>,
+    {
+        Self::parse(bytes, leading_whitespace, None)
+    }
+
+    /// # From Bytes Lenient
+    ///
+    /// This function creates a new Mail from bytes like `from_bytes`, but recovers from the non-fatal defects
+    /// found in real-world mail (the malformed header lines are skipped, invalid UTF-8 is replaced) and reports them as warnings.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::{Mail, ParseWarning};
+    ///
+    /// // A header without colon and a continuation line before any header
+    /// let raw_email = b" continued from nowhere\r\nFrom: jean@nervio.us\r\nThis line has no colon\r\nSubject: Hello\r\n\r\nHello, World!\r\n";
+    /// assert!(Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).is_err());
+    ///
+    /// let (mail, warnings) = Mail::<Vec<u8>>::from_bytes_lenient(raw_email.to_vec());
+    /// assert_eq!(mail.headers.get(&EmailHeaders::From), Some(&"jean@nervio.us".to_string()));
+    /// assert_eq!(mail.headers.get(&EmailHeaders::Subject), Some(&"Hello".to_string()));
+    /// assert_eq!(mail.body, b"Hello, World!\r\n");
+    /// assert_eq!(
+    ///     warnings,
+    ///     vec![ParseWarning::OrphanContinuation { line: 1 }, ParseWarning::MissingColon { line: 3 }]
+    /// );
+    /// ```
+    pub fn from_bytes_lenient(bytes: Vec<u8>) -> (Mail<T>, Vec<ParseWarning>)
+    where
+        T: From<Vec<u8>>,
+    {
+        let mut warnings = Vec::new();
+        match Self::parse(bytes, LeadingWhitespace::Error, Some(&mut warnings)) {
+            Ok(mail) => (mail, warnings),
+            // Every defect is recoverable in lenient mode
+            Err(err) => unreachable!("Lenient parsing failed: {}", err),
+        }
+    }
+
+    /// # Parse
+    ///
+    /// This function parses the mail, failing on the first defect unless the warnings are collected.
+    fn parse(
+        bytes: Vec<u8>,
+        leading_whitespace: LeadingWhitespace,
+        mut warnings: Option<&mut Vec<ParseWarning>>,
+    ) -> Result<Mail<T>, String>
+    where
+        T: From<Vec<u8>>,
+    {
+        let mut headers = Headers::new();
+
+        // Drop the data terminator, keeping the line ending of the last line
+        let bytes = match bytes.strip_suffix(b".\r\n") {
+            Some(content) if content.ends_with(b"\r\n") => content,
+            _ => &bytes[..],
+        };
         let mut lines = bytes.split(|&b| b == b'\n').peekable();
         let mut header_complete = false;
 
-        while let Some(line) = lines.next() {
-            if line.is_empty() || line == b"\r" {
-                header_complete = true;
-                break;
-            }
+        // The whitespace can't start a header, the mail may have no headers
+        let starts_with_whitespace = matches!(bytes.first(), Some(&b' ') | Some(&b'\t'));
 
-            if let Some(&b' ') | Some(&b'\t') = line.first() {
-                if let Some(last_header) = headers.keys().last().cloned() {
-                    let value: &mut String = headers.get_mut(&last_header).unwrap();
-                    value.push_str(from_utf8(line).map_err(|_| "Invalid header value")?);
+        if starts_with_whitespace && leading_whitespace == LeadingWhitespace::Body {
+            header_complete = true;
+        } else {
+            for (index, line) in lines.by_ref().enumerate() {
+                let line_number = index + 1;
+                let raw = [line, b"\n"].concat();
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+                // A blank line, or the end of the data for headers-only mails, ends the headers
+                if line.is_empty() {
+                    header_complete = true;
+                    break;
+                }
+
+                if let Some(&b' ') | Some(&b'\t') = line.first() {
+                    if let Some(field) = headers.last_mut() {
+                        // Unfolding (RFC 5322 2.2.3), the line break and the folding whitespace become a single space
+                        let continuation = match from_utf8(line) {
+                            Ok(line) => line.to_string(),
+                            Err(_) => {
+                                recover(&mut warnings, ParseWarning::InvalidUtf8 { line: line_number }, "Invalid header value")?;
+                                String::from_utf8_lossy(line).to_string()
+                            }
+                        };
+                        let continuation = continuation.split_whitespace().collect::<Vec<&str>>().join(" ");
+                        if !continuation.is_empty() {
+                            if !field.value.is_empty() {
+                                field.value.push(' ');
+                            }
+                            field.value.push_str(&continuation);
+                        }
+                        if let Some(field_raw) = field.raw_mut() {
+                            field_raw.extend_from_slice(&raw);
+                        }
+                        continue;
+                    }
+
+                    recover(
+                        &mut warnings,
+                        ParseWarning::OrphanContinuation { line: line_number },
+                        "Invalid header continuation without header",
+                    )?;
                     continue;
                 }
-            }
 
-            let mut parts = line.splitn(2, |&b| b == b':');
-            let key = parts.next().ok_or("Invalid header")?;
-            let value = parts.next().ok_or("Invalid header value not exist")?;
-            let value = from_utf8(value).map_err(|_| "Invalid header value")?.trim();
-            let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
+                let mut parts = line.splitn(2, |&b| b == b':');
+                let key = parts.next().ok_or("Invalid header")?;
+                let Some(value) = parts.next() else {
+                    recover(&mut warnings, ParseWarning::MissingColon { line: line_number }, "Invalid header value not exist")?;
+                    continue;
+                };
+                let value = match from_utf8(value) {
+                    Ok(value) => value.to_string(),
+                    Err(_) => {
+                        recover(&mut warnings, ParseWarning::InvalidUtf8 { line: line_number }, "Invalid header value")?;
+                        String::from_utf8_lossy(value).to_string()
+                    }
+                };
+                let value = value.split_whitespace().collect::<Vec<&str>>().join(" ");
 
-            headers.insert(EmailHeaders::from_bytes(key)?, value.to_owned());
+                let key = match EmailHeaders::from_bytes(key) {
+                    Ok(key) => key,
+                    Err(err) => {
+                        recover(&mut warnings, ParseWarning::InvalidHeaderName { line: line_number }, &err)?;
+                        continue;
+                    }
+                };
+                headers.push(HeaderField::new(key, &value).with_raw(raw));
+            }
+        }
+
+        if !header_complete {
+            recover(&mut warnings, ParseWarning::UnterminatedHeaders, "Invalid mail format")?;
         }
 
-        if header_complete {
-            for line in lines {
-                body.extend_from_slice(line);
-                body.push(b'\n');
+        let mut body = Vec::new();
+        while let Some(line) = lines.next() {
+            // Splitting leaves an empty segment after the last line ending
+            if line.is_empty() && lines.peek().is_none() {
+                break;
             }
-        } else {
-            return Err("Invalid mail format".to_string());
+            body.extend_from_slice(line);
+            body.push(b'\n');
         }
 
         Ok(Mail {
@@ -97,6 +271,748 @@ impl<T> Mail<T> {
             body: body.into(),
         })
     }
+
+    /// # Get
+    ///
+    /// This function returns the value of the header, the first one if it's repeated.
+    pub fn get(&self, header: &EmailHeaders) -> Option<&String> {
+        self.headers.get(header)
+    }
+
+    /// # Get All
+    ///
+    /// This function returns every value of the header in the order of the mail, empty if the mail doesn't have it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = concat!(
+    ///     "Received: from c.nervio.us by mx.nervio.us\r\n",
+    ///     "Received: from b.nervio.us by c.nervio.us\r\n",
+    ///     "Subject: Trace\r\n",
+    ///     "Received: from a.nervio.us by b.nervio.us\r\n",
+    ///     "\r\n",
+    ///     "Hello\r\n",
+    /// );
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    ///
+    /// assert_eq!(
+    ///     mail.get_all(&EmailHeaders::Received),
+    ///     [
+    ///         "from c.nervio.us by mx.nervio.us",
+    ///         "from b.nervio.us by c.nervio.us",
+    ///         "from a.nervio.us by b.nervio.us",
+    ///     ]
+    /// );
+    /// assert_eq!(mail.get(&EmailHeaders::Received).unwrap(), "from c.nervio.us by mx.nervio.us");
+    /// assert_eq!(mail.get_all(&EmailHeaders::Subject), ["Trace"]);
+    /// assert!(mail.get_all(&EmailHeaders::Comments).is_empty());
+    /// ```
+    pub fn get_all(&self, header: &EmailHeaders) -> Vec<&String> {
+        self.headers.get_all(header)
+    }
+
+    /// # From Domain
+    ///
+    /// This function returns the lowercased domain of the `From` header (RFC5322.From), this is the domain that DMARC aligns against the SPF and DKIM authenticated domains.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = b"From: Jean <jean@Nervio.US>\nSubject: Hello\n\nHello, World!";
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).unwrap();
+    /// assert_eq!(mail.from_domain(), Some("nervio.us".to_string()));
+    /// ```
+    pub fn from_domain(&self) -> Option<String> {
+        let from = self.headers.get(&EmailHeaders::From)?;
+
+        // Prefer the address between angle brackets, for example `Jean <jean@nervio.us>`
+        let address = match (from.rfind('<'), from.rfind('>')) {
+            (Some(start), Some(end)) if start < end => &from[start + 1..end],
+            _ => from.as_str(),
+        };
+
+        // The domain is everything after the last `@`
+        let (_, domain) = address.trim().rsplit_once('@')?;
+        let domain = domain.trim().trim_end_matches('.').to_lowercase();
+
+        if domain.is_empty() {
+            return None;
+        }
+
+        Some(domain)
+    }
+
+    /// # Decoded Header
+    ///
+    /// This function returns the value of the header with its RFC 2047 encoded-words (`=?charset?B|Q?text?=`) decoded.
+    /// The whitespace between adjacent encoded-words is dropped, UTF-8, ISO-8859-1 and US-ASCII charsets are supported
+    /// and the encoded-words in other charsets are kept as is.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = b"From: =?ISO-8859-1?Q?Andr=E9?= Pirard <pirard@nervio.us>\nSubject: =?UTF-8?B?wqFIb2xhLCA=?= =?UTF-8?Q?se=C3?= =?UTF-8?Q?=B1or?= and welcome\n\nHello, World!";
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.to_vec()).unwrap();
+    ///
+    /// assert_eq!(mail.decoded_header(&EmailHeaders::Subject), Some("¡Hola, señor and welcome".to_string()));
+    /// assert_eq!(mail.decoded_header(&EmailHeaders::From), Some("André Pirard <pirard@nervio.us>".to_string()));
+    /// assert_eq!(mail.decoded_header(&EmailHeaders::To), None);
+    /// ```
+    pub fn decoded_header(&self, header: &EmailHeaders) -> Option<String> {
+        self.headers.get(header).map(|value| decode_encoded_words(value))
+    }
+
+    /// # Attachments
+    ///
+    /// This function walks the MIME tree of the mail and returns the parts that are attachments, a `Content-Disposition: attachment`
+    /// or a `filename`/`name` parameter, with their transfer encoding (base64 or quoted-printable) decoded.
+    /// The filenames in RFC 2047 encoded-words or RFC 2231 extended parameters are decoded.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = concat!(
+    ///     "From: Jean <jean@nervio.us>\r\n",
+    ///     "Subject: Invoice\r\n",
+    ///     "MIME-Version: 1.0\r\n",
+    ///     "Content-Type: multipart/mixed; boundary=\"frontier\"\r\n",
+    ///     "\r\n",
+    ///     "This is a multipart message.\r\n",
+    ///     "--frontier\r\n",
+    ///     "Content-Type: text/plain; charset=utf-8\r\n",
+    ///     "\r\n",
+    ///     "The invoice is attached.\r\n",
+    ///     "--frontier\r\n",
+    ///     "Content-Type: application/pdf; name=\"invoice.pdf\"\r\n",
+    ///     "Content-Disposition: attachment; filename*=UTF-8''factura%20n%C2%BA1.pdf\r\n",
+    ///     "Content-Transfer-Encoding: base64\r\n",
+    ///     "\r\n",
+    ///     "JVBERi0xLjQKJcOkw7zDtsOfCjEgMCBv\r\n",
+    ///     "YmoKPDwvVHlwZS9DYXRhbG9nPj4KZW5kb2JqCg==\r\n",
+    ///     "--frontier\r\n",
+    ///     "Content-Type: text/plain; name=\"=?UTF-8?Q?notas_=C3=BAtiles.txt?=\"\r\n",
+    ///     "Content-Transfer-Encoding: quoted-printable\r\n",
+    ///     "\r\n",
+    ///     "Caf=C3=A9 con le=\r\n",
+    ///     "che\r\n",
+    ///     "--frontier--\r\n",
+    /// );
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    /// let attachments = mail.attachments();
+    /// assert_eq!(attachments.len(), 2);
+    ///
+    /// assert_eq!(attachments[0].filename, Some("factura nº1.pdf".to_string()));
+    /// assert_eq!(attachments[0].content_type, "application/pdf");
+    /// assert!(attachments[0].data.starts_with(b"%PDF-1.4\n"));
+    /// assert!(attachments[0].data.ends_with(b"endobj\n"));
+    ///
+    /// assert_eq!(attachments[1].filename, Some("notas útiles.txt".to_string()));
+    /// assert_eq!(attachments[1].data, "Café con leche".as_bytes());
+    /// ```
+    pub fn attachments(&self) -> Vec<Attachment>
+    where
+        T: AsRef<[u8]>,
+    {
+        let mut attachments = Vec::new();
+        crate::mime::collect_attachments(&self.headers, self.body.as_ref(), &mut attachments);
+        attachments
+    }
+
+    /// # Decoded Body
+    ///
+    /// This function returns the body with its Content-Transfer-Encoding decoded (quoted-printable or base64), the body is returned
+    /// unchanged for `7bit`, `8bit`, `binary`, the unknown encodings or without the header. The raw body is kept in the mail.
+    ///
+    /// The line breaks and whitespace between the base64 chunks are ignored, an invalid base64 body is a parse error.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = concat!(
+    ///     "From: jean@nervio.us\r\n",
+    ///     "Content-Type: text/plain; charset=utf-8\r\n",
+    ///     "Content-Transfer-Encoding: quoted-printable\r\n",
+    ///     "\r\n",
+    ///     "J'interdis aux marchands de vanter trop leurs marchandises. Car ils se font =\r\n",
+    ///     "vite p=C3=A9dagogues et t'enseignent comme but ce qui n'est par essence qu'=\r\n",
+    ///     "un moyen, a=3db and a=3Db, price=\r\n",
+    ///     "s in =E2=82=AC=20\r\n",
+    ///     "= \r\n",
+    ///     "Bye=",
+    /// );
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    ///
+    /// let body = String::from_utf8(mail.decoded_body().unwrap()).unwrap();
+    /// assert_eq!(
+    ///     body,
+    ///     "J'interdis aux marchands de vanter trop leurs marchandises. Car ils se font vite pédagogues \
+    ///      et t'enseignent comme but ce qui n'est par essence qu'un moyen, a=b and a=b, prices in € \r\nBye"
+    /// );
+    /// // The raw body is untouched
+    /// assert!(mail.body.starts_with(b"J'interdis"));
+    /// assert!(String::from_utf8_lossy(&mail.body).contains("p=C3=A9dagogues"));
+    ///
+    /// // Without encoding the body is returned as is
+    /// let mail = Mail::<Vec<u8>>::from_bytes(b"Subject: Plain\r\n\r\na=3Db\r\n".to_vec()).unwrap();
+    /// assert_eq!(mail.decoded_body().unwrap(), b"a=3Db\r\n");
+    ///
+    /// // Base64 wrapped at 76 columns
+    /// let greeting = "Hello, World! ".repeat(8);
+    /// let raw_email = concat!(
+    ///     "From: jean@nervio.us\r\n",
+    ///     "Content-Transfer-Encoding: base64\r\n",
+    ///     "\r\n",
+    ///     "SGVsbG8sIFdvcmxkISBIZWxsbywgV29ybGQhIEhlbGxvLCBXb3JsZCEgSGVsbG8sIFdvcmxkISBI\r\n",
+    ///     "ZWxsbywgV29ybGQhIEhlbGxvLCBXb3JsZCEgSGVsbG8sIFdvcmxkISBIZWxsbywgV29ybGQhIA==\r\n",
+    /// );
+    /// let mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    /// assert_eq!(mail.decoded_body().unwrap(), greeting.as_bytes());
+    ///
+    /// let mail = Mail::<Vec<u8>>::from_bytes(b"Content-Transfer-Encoding: Base64\r\n\r\nSGVs bG8s\r\n\tIFdvcmxkIQ==\r\n".to_vec()).unwrap();
+    /// assert_eq!(mail.decoded_body().unwrap(), b"Hello, World!");
+    ///
+    /// let mail = Mail::<Vec<u8>>::from_bytes(b"Content-Transfer-Encoding: base64\r\n\r\nNot base64!\r\n".to_vec()).unwrap();
+    /// assert!(mail.decoded_body().is_err());
+    ///
+    /// for encoding in ["7bit", "8bit", "binary", "x-unknown"] {
+    ///     let raw_email = format!("Content-Transfer-Encoding: {}\r\n\r\nSGVsbG8=\r\n", encoding);
+    ///     let mail = Mail::<Vec<u8>>::from_bytes(raw_email.into_bytes()).unwrap();
+    ///     assert_eq!(mail.decoded_body().unwrap(), b"SGVsbG8=\r\n");
+    /// }
+    /// ```
+    pub fn decoded_body(&self) -> Result<Vec<u8>, Error>
+    where
+        T: AsRef<[u8]>,
+    {
+        let encoding = self
+            .headers
+            .get(&EmailHeaders::ContentTransferEncoding)
+            .map(|encoding| encoding.trim().to_lowercase())
+            .unwrap_or_default();
+
+        Ok(match encoding.as_str() {
+            "quoted-printable" => crate::mime::decode_quoted_printable(self.body.as_ref()),
+            "base64" => {
+                let text = self
+                    .body
+                    .as_ref()
+                    .iter()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .map(|&b| b as char)
+                    .collect::<String>();
+                decode_base64(&text).ok_or_else(|| Error::ParseError("Invalid base64 body".to_string()))?
+            }
+            _ => self.body.as_ref().to_vec(),
+        })
+    }
+
+    /// # To Bytes
+    ///
+    /// This function serializes the mail as an Internet Message Format message: the headers in order, a blank line and the body.
+    /// The parsed header fields are written byte for byte, folding included, so the trace headers and the DKIM signatures survive a relay.
+    /// The body is written as is, it should use CRLF line endings and end with one to parse back to the same body.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = concat!(
+    ///     "Received: from b.nervio.us by mx.nervio.us\r\n",
+    ///     "DKIM-Signature: v=1; a=rsa-sha256; d=nervio.us;\r\n",
+    ///     "\th=from:subject; b=c2lnbmF0dXJl\r\n",
+    ///     "Subject:  Hello\r\n",
+    ///     "Received: from a.nervio.us by b.nervio.us\r\n",
+    ///     "\r\n",
+    ///     "Hello\r\n",
+    /// );
+    /// let mut mail = Mail::<Vec<u8>>::from_bytes(raw_email.as_bytes().to_vec()).unwrap();
+    /// assert_eq!(mail.to_bytes(), raw_email.as_bytes());
+    ///
+    /// // An edited header is written as `Name: value`
+    /// mail.headers.insert(EmailHeaders::Subject, "Bye");
+    /// assert_eq!(
+    ///     String::from_utf8(mail.to_bytes()).unwrap(),
+    ///     raw_email.replace("Subject:  Hello", "Subject: Bye")
+    /// );
+    /// ```
+    pub fn to_bytes(&self) -> Vec<u8>
+    where
+        T: AsRef<[u8]>,
+    {
+        let mut bytes = Vec::new();
+        for field in &self.headers {
+            bytes.extend_from_slice(&field.to_bytes());
+        }
+        bytes.extend_from_slice(b"\r\n");
+        bytes.extend_from_slice(self.body.as_ref());
+        bytes
+    }
+
+    /// # Builder
+    ///
+    /// This function returns a MailBuilder, to build a mail from its headers and body.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::Mail;
+    ///
+    /// let mail = Mail::builder()
+    ///     .header(EmailHeaders::To, "admin@nervio.us")
+    ///     .header(EmailHeaders::Subject, "Hello")
+    ///     .body(b"Hello, World!\r\n".to_vec())
+    ///     .default_from("Jean <jean@nervio.us>")
+    ///     .default_date()
+    ///     .default_message_id("nervio.us")
+    ///     .build();
+    ///
+    /// assert_eq!(mail.headers.get(&EmailHeaders::From), Some(&"Jean <jean@nervio.us>".to_string()));
+    /// assert!(mail.headers[&EmailHeaders::MessageId].ends_with("@nervio.us>"));
+    /// assert_eq!(mail.headers.len(), 5);
+    ///
+    /// // Serialize and parse it back
+    /// let bytes = mail.to_bytes();
+    /// assert!(bytes.starts_with(b"To: admin@nervio.us\r\nSubject: Hello\r\nFrom: Jean <jean@nervio.us>\r\nDate: "));
+    /// assert!(bytes.ends_with(b"@nervio.us>\r\n\r\nHello, World!\r\n"));
+    /// assert_eq!(Mail::<Vec<u8>>::from_bytes(bytes).unwrap(), mail);
+    ///
+    /// // The given headers take precedence over the defaults
+    /// let mail = Mail::<Vec<u8>>::builder()
+    ///     .header(EmailHeaders::From, "admin@nervio.us")
+    ///     .default_from("jean@nervio.us")
+    ///     .build();
+    /// assert_eq!(mail.headers[&EmailHeaders::From], "admin@nervio.us");
+    /// ```
+    pub fn builder() -> MailBuilder<T> {
+        MailBuilder {
+            headers: Headers::new(),
+            body: None,
+            default_from: None,
+            default_date: false,
+            default_message_id: None,
+        }
+    }
+}
+
+/// # Mail Builder
+///
+/// This struct is a builder for the Mail struct, the defaults are only set for the headers not given.
+#[derive(Debug, Clone)]
+pub struct MailBuilder<T> {
+    headers: Headers,
+    body: Option<T>,
+    default_from: Option<String>,
+    default_date: bool,
+    default_message_id: Option<String>,
+}
+
+impl<T> MailBuilder<T> {
+    /// # Header
+    ///
+    /// This function sets a header, replacing its previous value.
+    pub fn header(mut self, header: EmailHeaders, value: &str) -> Self {
+        self.headers.insert(header, value);
+        self
+    }
+
+    /// # Body
+    ///
+    /// This function sets the body of the mail.
+    pub fn body(mut self, body: T) -> Self {
+        self.body = Some(body);
+        self
+    }
+
+    /// # Default From
+    ///
+    /// This function sets the From header to the given mailbox if it isn't set.
+    pub fn default_from(mut self, from: &str) -> Self {
+        self.default_from = Some(from.to_string());
+        self
+    }
+
+    /// # Default Date
+    ///
+    /// This function sets the Date header to the time of the build if it isn't set.
+    pub fn default_date(mut self) -> Self {
+        self.default_date = true;
+        self
+    }
+
+    /// # Default Message-Id
+    ///
+    /// This function sets the Message-Id header to a new unique identifier on the given domain if it isn't set.
+    pub fn default_message_id(mut self, domain: &str) -> Self {
+        self.default_message_id = Some(domain.to_string());
+        self
+    }
+
+    /// # Build
+    ///
+    /// This function builds the mail, the body is empty if not set. The defaults go after the given headers.
+    pub fn build(self) -> Mail<T>
+    where
+        T: Default,
+    {
+        let mut headers = self.headers;
+        if let Some(from) = self.default_from {
+            if !headers.contains_key(&EmailHeaders::From) {
+                headers.append(EmailHeaders::From, &from);
+            }
+        }
+        if self.default_date && !headers.contains_key(&EmailHeaders::Date) {
+            headers.append(EmailHeaders::Date, &format_date(SystemTime::now()));
+        }
+        if let Some(domain) = self.default_message_id {
+            if !headers.contains_key(&EmailHeaders::MessageId) {
+                headers.append(EmailHeaders::MessageId, &new_message_id(&domain));
+            }
+        }
+
+        Mail {
+            headers,
+            body: self.body.unwrap_or_default(),
+        }
+    }
+}
+
+/// # Format Date
+///
+/// This function formats the time as a RFC 5322 date in UTC, for example `Sat, 17 Oct 2026 09:05:00 +0000`.
+fn format_date(time: SystemTime) -> String {
+    const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+
+    let secs = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let days = secs / 86400;
+    let (hour, minute, second) = (secs % 86400 / 3600, secs % 3600 / 60, secs % 60);
+
+    // Civil date from the days since the epoch (Howard Hinnant's algorithm)
+    let z = days + 719468;
+    let era = z / 146097;
+    let day_of_era = z % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} +0000",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        hour,
+        minute,
+        second
+    )
+}
+
+/// # New Message-Id
+///
+/// This function returns a new unique Message-Id on the domain, from the current time, the process and a counter.
+fn new_message_id(domain: &str) -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    format!(
+        "<{:x}.{:x}.{:x}@{}>",
+        now.as_nanos(),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed),
+        domain
+    )
+}
+
+/// # Leading Whitespace
+///
+/// This enum represents how to parse a mail whose first line starts with a space or a tab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LeadingWhitespace {
+    /// # Error
+    ///
+    /// Fail, the line is a continuation of no header.
+    #[default]
+    Error,
+    /// # Body
+    ///
+    /// Parse the mail as a body without headers.
+    Body,
+}
+
+/// # Parse Warning
+///
+/// This enum represents a defect of a mail recovered by `Mail::from_bytes_lenient`, with the 1-based line where it was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseWarning {
+    /// # Missing Colon
+    ///
+    /// A header line without colon, it was skipped.
+    MissingColon {
+        /// The line of the header
+        line: usize,
+    },
+    /// # Orphan Continuation
+    ///
+    /// A continuation line (starting with whitespace) before any header, it was skipped.
+    OrphanContinuation {
+        /// The line of the continuation
+        line: usize,
+    },
+    /// # Invalid UTF-8
+    ///
+    /// A header value with invalid UTF-8 (e.g. raw 8-bit bytes), the invalid bytes were replaced.
+    InvalidUtf8 {
+        /// The line of the header
+        line: usize,
+    },
+    /// # Invalid Header Name
+    ///
+    /// A header name that couldn't be parsed, the header was skipped.
+    InvalidHeaderName {
+        /// The line of the header
+        line: usize,
+    },
+    /// # Unterminated Headers
+    ///
+    /// The mail ended without the line break after the last header, the body is empty.
+    UnterminatedHeaders,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::MissingColon { line } => write!(f, "Header without colon at line {}", line),
+            ParseWarning::OrphanContinuation { line } => {
+                write!(f, "Continuation line without header at line {}", line)
+            }
+            ParseWarning::InvalidUtf8 { line } => write!(f, "Invalid UTF-8 in header at line {}", line),
+            ParseWarning::InvalidHeaderName { line } => write!(f, "Invalid header name at line {}", line),
+            ParseWarning::UnterminatedHeaders => write!(f, "Headers not terminated"),
+        }
+    }
+}
+
+/// # Recover
+///
+/// This function records the warning when parsing leniently, otherwise it fails with the error.
+fn recover(warnings: &mut Option<&mut Vec<ParseWarning>>, warning: ParseWarning, err: &str) -> Result<(), String> {
+    match warnings {
+        Some(warnings) => {
+            warnings.push(warning);
+            Ok(())
+        }
+        None => Err(err.to_string()),
+    }
+}
+
+/// # Decode Encoded Words
+///
+/// This function decodes the RFC 2047 encoded-words of a header value, the adjacent words of the same charset
+/// are decoded together as a multibyte character can be split between them.
+pub(crate) fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::new();
+    // Bytes of the previous encoded-words not decoded yet, with their charset
+    let mut pending: Option<(String, Vec<u8>)> = None;
+    // Whitespace after an encoded-word, dropped if another encoded-word follows
+    let mut gap = String::new();
+    let mut rest = value;
+
+    while !rest.is_empty() {
+        if let Some((charset, bytes, len)) = parse_encoded_word(rest) {
+            match pending.as_mut() {
+                Some((pending_charset, pending_bytes)) if *pending_charset == charset => {
+                    pending_bytes.extend(bytes)
+                }
+                _ => {
+                    flush_encoded_words(&mut decoded, pending.take());
+                    pending = Some((charset, bytes));
+                }
+            }
+            gap.clear();
+            rest = &rest[len..];
+            continue;
+        }
+
+        let c = rest.chars().next().unwrap_or_default();
+        if pending.is_some() && c.is_whitespace() {
+            gap.push(c);
+        } else {
+            flush_encoded_words(&mut decoded, pending.take());
+            decoded.push_str(&gap);
+            gap.clear();
+            decoded.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+
+    flush_encoded_words(&mut decoded, pending);
+    decoded.push_str(&gap);
+    decoded
+}
+
+/// # Flush Encoded Words
+///
+/// This function decodes the bytes of the pending encoded-words with their charset.
+fn flush_encoded_words(decoded: &mut String, pending: Option<(String, Vec<u8>)>) {
+    if let Some((charset, bytes)) = pending {
+        match charset.as_str() {
+            "utf-8" | "utf8" => decoded.push_str(&String::from_utf8_lossy(&bytes)),
+            // ISO-8859-1 maps every byte to the same code point, US-ASCII is a subset
+            _ => decoded.extend(bytes.iter().map(|&b| b as char)),
+        }
+    }
+}
+
+/// # Parse Encoded Word
+///
+/// This function parses the encoded-word at the start of the input, returning its lowercased charset,
+/// the decoded bytes and its length. None if the input doesn't start with a supported encoded-word.
+fn parse_encoded_word(input: &str) -> Option<(String, Vec<u8>, usize)> {
+    let word = input.strip_prefix("=?")?;
+    let mut parts = word.splitn(3, '?');
+    let charset = parts.next()?;
+    let encoding = parts.next()?;
+    let text_and_rest = parts.next()?;
+    let text = &text_and_rest[..text_and_rest.find("?=")?];
+
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    // The charset can have a language suffix (RFC 2231), for example `UTF-8*en`
+    let charset = charset.split('*').next()?.to_lowercase();
+    if !matches!(
+        charset.as_str(),
+        "utf-8" | "utf8" | "iso-8859-1" | "latin1" | "us-ascii"
+    ) {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => decode_base64(text)?,
+        "Q" | "q" => decode_q(text)?,
+        _ => return None,
+    };
+
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + text.len() + 2;
+    Some((charset, bytes, len))
+}
+
+/// # Decode Q
+///
+/// This function decodes the Q encoding of RFC 2047, quoted-printable with `_` as space.
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut input = text.bytes();
+    while let Some(b) = input.next() {
+        match b {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [input.next()?, input.next()?];
+                bytes.push(u8::from_str_radix(from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+/// # Decode Base64
+///
+/// This function decodes standard base64 with optional padding.
+pub(crate) fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len() * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for b in text.bytes().take_while(|&b| b != b'=') {
+        let value = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+    Some(bytes)
+}
+
+/// # Debug Body Preview
+///
+/// The number of body bytes shown by the Debug implementation of Mail.
+const DEBUG_BODY_PREVIEW: usize = 64;
+
+/// # Debug implementation for Mail
+///
+/// The body is summarized (length and the first bytes) to not dump huge or sensitive emails to the logs, use `Mail::debug_full` for the complete dump.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::mail::Mail;
+///
+/// let mut raw_email = b"From: Jean <jean@nervio.us>\nSubject: Hello\n\n".to_vec();
+/// raw_email.extend(vec![b'A'; 1024 * 1024]);
+/// let mail = Mail::<Vec<u8>>::from_bytes(raw_email).unwrap();
+///
+/// let debug = format!("{:?}", mail);
+/// assert!(debug.len() < 512);
+/// assert!(debug.contains("1048577 bytes"));
+/// assert!(mail.debug_full().len() > 1024 * 1024);
+/// ```
+impl<T: AsRef<[u8]>> fmt::Debug for Mail<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let body = self.body.as_ref();
+        let preview = String::from_utf8_lossy(&body[..body.len().min(DEBUG_BODY_PREVIEW)]);
+        let ellipsis = if body.len() > DEBUG_BODY_PREVIEW { "..." } else { "" };
+
+        f.debug_struct("Mail")
+            .field("headers", &self.headers)
+            .field(
+                "body",
+                &format_args!("<{} bytes: {:?}{}>", body.len(), preview, ellipsis),
+            )
+            .finish()
+    }
+}
+
+impl<T: fmt::Debug> Mail<T> {
+    /// # Debug Full
+    ///
+    /// This function returns the complete Debug dump of the mail, including the whole body.
+    pub fn debug_full(&self) -> String {
+        format!(
+            "Mail {{ headers: {:?}, body: {:?} }}",
+            self.headers, self.body
+        )
+    }
 }
 
 /// # Mail Trait
@@ -122,6 +1038,132 @@ impl<T: Clone + Send + Sync + 'static> Clone for Mail<T> {
     }
 }
 
+impl Mail<Vec<u8>> {
+    /// # View
+    ///
+    /// This function parses the mail into a `MailView` borrowing the bytes, without allocating the header names and values.
+    /// The mail is validated like `from_bytes` (but the header values aren't required to be UTF-8), use the owned `Mail`
+    /// when the bytes are dropped or the mail is modified.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::headers::EmailHeaders;
+    /// use neo_email::mail::Mail;
+    ///
+    /// let raw_email = concat!(
+    ///     "Received: from b.nervio.us by mx.nervio.us\r\n",
+    ///     "Subject: Hello\r\n",
+    ///     " World\r\n",
+    ///     "X-Spam-Score: 7.5\r\n",
+    ///     "Received: from a.nervio.us by b.nervio.us\r\n",
+    ///     "\r\n",
+    ///     "Hello\r\n",
+    ///     ".\r\n",
+    /// );
+    /// let view = Mail::view(raw_email.as_bytes()).unwrap();
+    ///
+    /// // Header names are case-insensitive, the folding is kept in the value
+    /// assert_eq!(view.get(&EmailHeaders::Subject), Some(&b"Hello\r\n World"[..]));
+    /// assert_eq!(view.get(&EmailHeaders::Unknown("x-spam-score".to_string())), Some(&b"7.5"[..]));
+    /// assert_eq!(view.get_all(&EmailHeaders::Received).count(), 2);
+    /// assert_eq!(view.body, b"Hello\r\n");
+    ///
+    /// // The same defects as `from_bytes` are errors
+    /// assert!(Mail::view(b"This line has no colon\r\n\r\nHello\r\n").is_err());
+    /// assert!(Mail::view(b" Hello\r\n\r\n").is_err());
+    /// assert!(Mail::view(b"Subject: Hello").is_err());
+    /// ```
+    pub fn view(bytes: &[u8]) -> Result<MailView<'_>, String> {
+        // Drop the data terminator, keeping the line ending of the last line
+        let bytes = match bytes.strip_suffix(b".\r\n") {
+            Some(content) if content.ends_with(b"\r\n") => content,
+            _ => bytes,
+        };
+
+        // The header names and the range of their values, extended by the continuation lines
+        let mut headers: Vec<(&[u8], usize, usize)> = Vec::new();
+        let mut start = 0;
+        let body_start = loop {
+            let Some(line_length) = bytes[start..].iter().position(|&b| b == b'\n') else {
+                // The last line has no line ending, it's the end of the data for headers-only mails if blank
+                if start == bytes.len() {
+                    break start;
+                }
+                return Err("Invalid mail format".to_string());
+            };
+            let next = start + line_length + 1;
+            let line = &bytes[start..start + line_length];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line_end = start + line.len();
+
+            if line.is_empty() {
+                break next;
+            }
+
+            if let Some(&b' ') | Some(&b'\t') = line.first() {
+                let (_, _, value_end) = headers
+                    .last_mut()
+                    .ok_or("Invalid header continuation without header")?;
+                *value_end = line_end;
+            } else {
+                let colon = line
+                    .iter()
+                    .position(|&b| b == b':')
+                    .ok_or("Invalid header value not exist")?;
+                from_utf8(&line[..colon]).map_err(|_| "Invalid header")?;
+                headers.push((&line[..colon], start + colon + 1, line_end));
+            }
+            start = next;
+        };
+
+        Ok(MailView {
+            headers: headers
+                .into_iter()
+                .map(|(name, value_start, value_end)| (name, bytes[value_start..value_end].trim_ascii()))
+                .collect(),
+            body: &bytes[body_start..],
+        })
+    }
+}
+
+/// # Mail View
+///
+/// This struct represents an email message borrowing the received bytes, created with `Mail::view`.
+/// It's meant for the controllers that only read some headers (e.g. spam scoring), parsing it doesn't allocate
+/// but the values are the raw bytes: folded values keep their line breaks, and encoded words aren't decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MailView<'a> {
+    /// # Headers
+    ///
+    /// The header names and values in the order of the mail, the values without the surrounding whitespace.
+    pub headers: Vec<(&'a [u8], &'a [u8])>,
+    /// # Body
+    ///
+    /// The body of the email, everything after the blank line ending the headers.
+    pub body: &'a [u8],
+}
+
+impl<'a> MailView<'a> {
+    /// # Get
+    ///
+    /// This function returns the value of the header, the first one if it's repeated.
+    pub fn get(&self, header: &EmailHeaders) -> Option<&'a [u8]> {
+        self.get_all(header).next()
+    }
+
+    /// # Get All
+    ///
+    /// This function returns every value of the header in the order of the mail.
+    pub fn get_all<'b>(&'b self, header: &'b EmailHeaders) -> impl Iterator<Item = &'a [u8]> + 'b {
+        let name = header.to_string().as_bytes();
+        self.headers
+            .iter()
+            .filter(move |(header_name, _)| header_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
 /// # Email Address
 /// 
 /// This struct represents an email address.
@@ -178,10 +1220,51 @@ impl EmailAddress {
         Ok(EmailAddress { username, domain })
     }
 
-    /// # To String
-    /// 
-    /// This function converts EmailAdress to a String.
-    pub fn to_string(&self) -> String {
-        format!("{}@{}", self.username, self.domain)
+    /// # Is ASCII
+    ///
+    /// This function returns if the address is ASCII only, otherwise it can only be used in a SMTPUTF8 transaction (RFC 6531).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::EmailAddress;
+    ///
+    /// assert!(EmailAddress::from_string("jean@nervio.us").unwrap().is_ascii());
+    /// assert!(!EmailAddress::from_string("jérôme@nervio.us").unwrap().is_ascii());
+    /// assert!(!EmailAddress::from_string("jean@例子.广告").unwrap().is_ascii());
+    /// ```
+    pub fn is_ascii(&self) -> bool {
+        self.username.is_ascii() && self.domain.is_ascii()
+    }
+
+    /// # Normalized
+    ///
+    /// This function returns a copy with the domain lowercased, to compare and route the addresses consistently.
+    /// The local part keeps its case, as its case-sensitivity is up to the destination (RFC 5321 2.4).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::EmailAddress;
+    ///
+    /// let address = EmailAddress::from_string("A@Example.COM").unwrap();
+    /// assert_eq!(address.normalized().to_string(), "A@example.com");
+    /// // The original is untouched
+    /// assert_eq!(address.to_string(), "A@Example.COM");
+    /// ```
+    pub fn normalized(&self) -> EmailAddress {
+        EmailAddress {
+            username: self.username.clone(),
+            domain: self.domain.to_lowercase(),
+        }
+    }
+}
+
+/// # Display implementation for EmailAddress
+///
+/// This implementation formats the EmailAddress as `username@domain`.
+impl fmt::Display for EmailAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.username, self.domain)
     }
 }
@@ -1,4 +1,71 @@
-use super::status_code::StatusCodes;
+use super::{mail::EmailAddress, status_code::StatusCodes};
+
+/// # CRLF
+///
+/// The line ending required by SMTP (RFC 5321), every response line ends with it.
+pub const CRLF: &str = "\r\n";
+
+/// # Max Reply Line
+///
+/// The max length of a reply line in octets including the status code and the CRLF (RFC 5321 4.5.3.1.5).
+pub const MAX_REPLY_LINE: usize = 512;
+
+/// # Line Ending
+///
+/// This enum represents the line ending used when serializing responses to the client.
+/// CRLF is the only production option, LF is only available with the `test-utils` feature to test client tolerance.
+/// The enum is non exhaustive, so enabling the feature doesn't break the matches on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[non_exhaustive]
+pub enum LineEnding {
+    /// # CRLF
+    ///
+    /// `\r\n`, as required by SMTP.
+    #[default]
+    CRLF,
+    /// # LF
+    ///
+    /// `\n`, only for testing lenient clients.
+    #[cfg(any(test, feature = "test-utils"))]
+    LF,
+}
+
+impl LineEnding {
+    /// # As Str
+    ///
+    /// This function returns the line ending as a string.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LineEnding::CRLF => CRLF,
+            #[cfg(any(test, feature = "test-utils"))]
+            LineEnding::LF => "\n",
+        }
+    }
+}
+
+/// # Parse Reply Line
+///
+/// This function parses a reply line without its line ending into its code, if it's the last line of the reply,
+/// and its text. A line without a 3 digit code, or with a separator other than space or `-`, is malformed.
+///
+/// ```rust
+/// use neo_email::message::parse_reply_line;
+///
+/// assert_eq!(parse_reply_line("250-SIZE 10485760"), Some((250, false, "SIZE 10485760")));
+/// assert_eq!(parse_reply_line("250 STARTTLS"), Some((250, true, "STARTTLS")));
+/// assert_eq!(parse_reply_line("354"), Some((354, true, "")));
+/// assert_eq!(parse_reply_line("25O Ok"), None);
+/// assert_eq!(parse_reply_line("250_Ok"), None);
+/// ```
+pub fn parse_reply_line(line: &str) -> Option<(u16, bool, &str)> {
+    let code = line.get(..3).and_then(|code| code.parse::<u16>().ok())?;
+    let is_last = match line.as_bytes().get(3) {
+        None | Some(b' ') => true,
+        Some(b'-') => false,
+        Some(_) => return None,
+    };
+    Some((code, is_last, line.get(4..).unwrap_or_default()))
+}
 
 /// # Message
 /// 
@@ -27,6 +94,14 @@ pub struct Message {
     /// 
     /// The message to be sent.
     pub message: String,
+    /// # Enhanced
+    /// 
+    /// The enhanced status code (RFC 3463) written before the message as `class.subject.detail`, None to write only the message.
+    /// 
+    /// ## Example
+    /// 
+    /// `Some((5, 1, 1))` for `550 5.1.1 User unknown`
+    pub enhanced: Option<(u8, u16, u16)>,
 }
 
 /// # Message Builder
@@ -46,6 +121,7 @@ pub struct Message {
 pub struct MessageBuilder {
     status: Option<StatusCodes>,
     message: Option<String>,
+    enhanced: Option<(u8, u16, u16)>,
 }
 
 impl Message {
@@ -53,7 +129,127 @@ impl Message {
     /// 
     /// This function creates a new message.
     pub fn new(status: StatusCodes, message: String) -> Self {
-        Self { status, message }
+        Self {
+            status,
+            message,
+            enhanced: None,
+        }
+    }
+
+    /// # Multiline
+    ///
+    /// This function creates the messages of a multiline reply, one per line, all sharing the status code.
+    /// The `-` and space separators are chosen when the reply is written, by `SMTPConnection::send_reply`.
+    /// Without lines a single line with an empty text is returned, so the reply is never empty.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// let reply = Message::multiline(
+    ///     StatusCodes::OK,
+    ///     vec!["Hello".to_string(), "PIPELINING".to_string(), "HELP".to_string()],
+    /// );
+    /// let last = reply.len() - 1;
+    /// let wire = reply
+    ///     .iter()
+    ///     .enumerate()
+    ///     .map(|(i, line)| line.to_string(i == last))
+    ///     .collect::<String>();
+    /// assert_eq!(wire, "250-Hello\r\n250-PIPELINING\r\n250 HELP\r\n");
+    ///
+    /// assert_eq!(Message::multiline(StatusCodes::OK, vec![]), vec![Message::new(StatusCodes::OK, String::new())]);
+    /// ```
+    pub fn multiline(status: StatusCodes, lines: Vec<String>) -> Vec<Message> {
+        if lines.is_empty() {
+            return vec![Message::new(status, String::new())];
+        }
+
+        lines
+            .into_iter()
+            .map(|line| Message::new(status.clone(), line))
+            .collect()
+    }
+
+    /// # Is Positive Completion
+    ///
+    /// This function returns if the status of the message is a 2xx.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// let message = Message::new(StatusCodes::ExceededStorageAllocation, "Too big".to_string());
+    /// assert!(message.is_permanent_negative());
+    /// assert!(!message.is_positive_completion());
+    /// assert!(Message::new(StatusCodes::OK, "Ok".to_string()).is_positive_completion());
+    /// assert!(Message::new(StatusCodes::StartMailInput, "Go ahead".to_string()).is_positive_intermediate());
+    /// assert!(Message::new(StatusCodes::InsufficientSystemStorage, "Later".to_string()).is_transient_negative());
+    /// ```
+    pub fn is_positive_completion(&self) -> bool {
+        self.status.is_positive_completion()
+    }
+
+    /// # Is Positive Intermediate
+    ///
+    /// This function returns if the status of the message is a 3xx.
+    pub fn is_positive_intermediate(&self) -> bool {
+        self.status.is_positive_intermediate()
+    }
+
+    /// # Is Transient Negative
+    ///
+    /// This function returns if the status of the message is a 4xx.
+    pub fn is_transient_negative(&self) -> bool {
+        self.status.is_transient_negative()
+    }
+
+    /// # Is Permanent Negative
+    ///
+    /// This function returns if the status of the message is a 5xx.
+    pub fn is_permanent_negative(&self) -> bool {
+        self.status.is_permanent_negative()
+    }
+
+    /// # Sender Ok
+    ///
+    /// This function returns the 250 reply accepting the sender and echoing it, like Postfix does. None is the null sender `<>`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::mail::EmailAddress;
+    /// use neo_email::message::Message;
+    ///
+    /// let sender = EmailAddress::from_string("jean@nervio.us").unwrap();
+    /// assert_eq!(Message::sender_ok(Some(&sender)).to_string(true), "250 2.1.0 <jean@nervio.us> Sender ok\r\n");
+    /// assert_eq!(Message::sender_ok(None).to_string(true), "250 2.1.0 <> Sender ok\r\n");
+    ///
+    /// let recipient = EmailAddress::from_string("admin@nervio.us").unwrap();
+    /// assert_eq!(Message::recipient_ok(&recipient).to_string(true), "250 2.1.5 <admin@nervio.us> Recipient ok\r\n");
+    /// ```
+    pub fn sender_ok(sender: Option<&EmailAddress>) -> Self {
+        let sender = sender.map(|sender| sender.to_string()).unwrap_or_default();
+        Self::builder()
+            .status(StatusCodes::OK)
+            .enhanced(2, 1, 0)
+            .message(format!("<{}> Sender ok", sender))
+            .build()
+    }
+
+    /// # Recipient Ok
+    ///
+    /// This function returns the 250 reply accepting the recipient and echoing it, like Postfix does.
+    pub fn recipient_ok(recipient: &EmailAddress) -> Self {
+        Self::builder()
+            .status(StatusCodes::OK)
+            .enhanced(2, 1, 5)
+            .message(format!("<{}> Recipient ok", recipient))
+            .build()
     }
 
     /// # Builder
@@ -65,15 +261,48 @@ impl Message {
 
     /// # To String
     ///
-    /// This function converts the message to a string.
+    /// This function converts the message to a string ended with CRLF.
     pub fn to_string(&self, is_last: bool) -> String {
+        self.to_string_with(is_last, LineEnding::CRLF)
+    }
+
+    /// # To String With
+    ///
+    /// This function converts the message to a string ended with the given line ending.
+    ///
+    /// ```rust
+    /// use neo_email::message::{LineEnding, Message};
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// let message = Message::new(StatusCodes::OK, "Ok".to_string());
+    /// assert_eq!(message.to_string_with(true, LineEnding::CRLF), "250 Ok\r\n");
+    /// assert_eq!(message.to_string_with(false, LineEnding::CRLF), "250-Ok\r\n");
+    ///
+    /// // The enhanced status code follows the separator on every line
+    /// let message = Message::builder()
+    ///     .status(StatusCodes::RequestedActionNotTakenMailboxUnavailable)
+    ///     .enhanced(5, 1, 1)
+    ///     .message("User unknown".to_string())
+    ///     .build();
+    /// assert_eq!(message.to_string_with(true, LineEnding::CRLF), "550 5.1.1 User unknown\r\n");
+    /// assert_eq!(message.to_string_with(false, LineEnding::CRLF), "550-5.1.1 User unknown\r\n");
+    /// ```
+    pub fn to_string_with(&self, is_last: bool, line_ending: LineEnding) -> String {
         // If it is the last message, return the status code and message with a space
         // If it is not the last message, return the status code and message with a dash
-        if is_last {
-            format!("{} {}\r\n", self.status.to_string(), self.message)
-        } else {
-            format!("{}-{}\r\n", self.status.to_string(), self.message)
-        }
+        let separator = if is_last { ' ' } else { '-' };
+        let enhanced = match self.enhanced {
+            Some((class, subject, detail)) => format!("{}.{}.{} ", class, subject, detail),
+            None => String::new(),
+        };
+        format!(
+            "{}{}{}{}{}",
+            self.status,
+            separator,
+            enhanced,
+            self.message,
+            line_ending.as_str()
+        )
     }
 
     /// # As Bytes
@@ -82,6 +311,88 @@ impl Message {
     pub fn as_bytes(&self, is_last: bool) -> Vec<u8> {
         self.to_string(is_last).as_bytes().to_vec()
     }
+
+    /// # As Bytes With
+    ///
+    /// This function converts the message to bytes ended with the given line ending.
+    pub fn as_bytes_with(&self, is_last: bool, line_ending: LineEnding) -> Vec<u8> {
+        self.to_string_with(is_last, line_ending).into_bytes()
+    }
+
+    /// # Wrap
+    ///
+    /// This function splits the message text on word boundaries into messages sharing the status code,
+    /// so every serialized line fits in `MAX_REPLY_LINE` octets. Words longer than a line are split.
+    /// Every line keeps the enhanced status code (RFC 2034 4).
+    ///
+    /// ```rust
+    /// use neo_email::message::{Message, MAX_REPLY_LINE};
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// let text = "lorem ipsum ".repeat(85).trim().to_string();
+    /// assert!(text.len() > 1000);
+    ///
+    /// let lines = Message::new(StatusCodes::OK, text.clone()).wrap();
+    /// assert!(lines.len() > 1);
+    /// for (i, line) in lines.iter().enumerate() {
+    ///     let serialized = line.to_string(i == lines.len() - 1);
+    ///     assert!(serialized.len() <= MAX_REPLY_LINE);
+    ///     assert!(serialized.starts_with(if i == lines.len() - 1 { "250 " } else { "250-" }));
+    /// }
+    ///
+    /// let rejoined = lines.iter().map(|line| line.message.as_str()).collect::<Vec<_>>().join(" ");
+    /// assert_eq!(rejoined, text);
+    /// ```
+    pub fn wrap(&self) -> Vec<Message> {
+        // Status code, separator, enhanced status code and CRLF
+        let enhanced = self
+            .enhanced
+            .map_or(0, |(class, subject, detail)| format!("{}.{}.{} ", class, subject, detail).len());
+        let max_text = MAX_REPLY_LINE - 4 - enhanced - CRLF.len();
+
+        if self.message.len() <= max_text {
+            return vec![self.clone()];
+        }
+
+        let mut lines = Vec::new();
+        let mut current = String::new();
+        for word in self.message.split_whitespace() {
+            let mut word = word;
+
+            // Split the words that don't fit in a line on their own
+            while word.len() > max_text {
+                let mut split_at = max_text;
+                while !word.is_char_boundary(split_at) {
+                    split_at -= 1;
+                }
+                if !current.is_empty() {
+                    lines.push(std::mem::take(&mut current));
+                }
+                lines.push(word[..split_at].to_string());
+                word = &word[split_at..];
+            }
+
+            if !current.is_empty() && current.len() + 1 + word.len() > max_text {
+                lines.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(word);
+        }
+        if !current.is_empty() || lines.is_empty() {
+            lines.push(current);
+        }
+
+        lines
+            .into_iter()
+            .map(|line| Message {
+                status: self.status.clone(),
+                message: line,
+                enhanced: self.enhanced,
+            })
+            .collect()
+    }
 }
 
 impl MessageBuilder {
@@ -101,6 +412,14 @@ impl MessageBuilder {
         self
     }
 
+    /// # Set Enhanced
+    /// 
+    /// This function sets the enhanced status code (RFC 3463) of the message, e.g. `.enhanced(2, 1, 5)` for `250 2.1.5`.
+    pub fn enhanced(mut self, class: u8, subject: u16, detail: u16) -> Self {
+        self.enhanced = Some((class, subject, detail));
+        self
+    }
+
     /// # Build
     /// 
     /// This function builds the message.
@@ -108,6 +427,7 @@ impl MessageBuilder {
         Message {
             status: self.status.unwrap(),
             message: self.message.unwrap(),
+            enhanced: self.enhanced,
         }
     }
 }
@@ -0,0 +1,319 @@
+use hashbrown::HashMap;
+
+use crate::headers::{EmailHeaders, Headers};
+use crate::mail::{decode_base64, decode_encoded_words, Mail};
+
+/// # Attachment
+///
+/// This struct represents a decoded attachment of a mail.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    /// # Filename
+    ///
+    /// The decoded filename, from the `filename` parameter of Content-Disposition or the `name` parameter of Content-Type.
+    pub filename: Option<String>,
+    /// # Content Type
+    ///
+    /// The lowercased media type of the part, for example `application/pdf`.
+    pub content_type: String,
+    /// # Data
+    ///
+    /// The content of the part with its transfer encoding decoded.
+    pub data: Vec<u8>,
+}
+
+/// # Header Parameters
+///
+/// This function splits a structured header value like `attachment; filename="a.pdf"` into its lowercased value
+/// and its parameters, with lowercased names and decoded values (RFC 2231 extended values and RFC 2047 encoded-words).
+pub fn header_parameters(value: &str) -> (String, HashMap<String, String>) {
+    let mut fields = split_unquoted(value, ';').into_iter();
+    let main = fields.next().unwrap_or_default().trim().to_lowercase();
+
+    // Parameter segments by name, with their RFC 2231 section and if they are percent-encoded
+    let mut segments: HashMap<String, Vec<(usize, bool, String)>> = HashMap::new();
+    for field in fields {
+        let Some((name, value)) = field.split_once('=') else {
+            continue;
+        };
+        let name = name.trim().to_lowercase();
+        let value = unquote(value.trim());
+
+        let (name, encoded) = match name.strip_suffix('*') {
+            Some(name) => (name.to_string(), true),
+            None => (name, false),
+        };
+        let (name, section) = match name.rsplit_once('*') {
+            Some((base, section)) if section.parse::<usize>().is_ok() => {
+                (base.to_string(), section.parse().unwrap_or_default())
+            }
+            _ => (name, 0),
+        };
+
+        segments.entry(name).or_default().push((section, encoded, value));
+    }
+
+    let mut parameters = HashMap::new();
+    for (name, mut segments) in segments {
+        segments.sort_by_key(|(section, _, _)| *section);
+
+        // The charset is declared in the first segment, for example `UTF-8''r%C3%A9sum%C3%A9.pdf`
+        let mut charset = None;
+        let mut bytes = Vec::new();
+        for (index, (_, encoded, value)) in segments.iter().enumerate() {
+            if !encoded {
+                bytes.extend_from_slice(value.as_bytes());
+                continue;
+            }
+
+            let mut value = value.as_str();
+            if index == 0 {
+                let mut parts = value.splitn(3, '\'');
+                if let (Some(declared), Some(_language), Some(rest)) = (parts.next(), parts.next(), parts.next()) {
+                    charset = Some(declared.to_lowercase());
+                    value = rest;
+                }
+            }
+            bytes.extend(percent_decode(value));
+        }
+
+        let value = match charset.as_deref() {
+            Some("iso-8859-1") | Some("latin1") | Some("us-ascii") => bytes.iter().map(|&b| b as char).collect(),
+            _ => String::from_utf8_lossy(&bytes).to_string(),
+        };
+        parameters.insert(name, decode_encoded_words(&value));
+    }
+
+    (main, parameters)
+}
+
+/// # Decode Quoted-Printable
+///
+/// This function decodes a quoted-printable body (RFC 2045), `=` at the end of a line (or of the body) is a soft line break,
+/// even with trailing whitespace added in transit. The invalid escapes are kept as is.
+pub fn decode_quoted_printable(bytes: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'=' {
+            decoded.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+
+        let rest = &bytes[i + 1..];
+        let padding = rest.iter().take_while(|&&b| b == b' ' || b == b'\t').count();
+        let after_padding = &rest[padding..];
+        if after_padding.starts_with(b"\r\n") {
+            i += 1 + padding + 2;
+        } else if after_padding.starts_with(b"\n") || after_padding.is_empty() {
+            i += 1 + padding + 1;
+        } else if let Some(byte) = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+        {
+            decoded.push(byte);
+            i += 3;
+        } else {
+            // Not a valid escape, keep it as is
+            decoded.push(b'=');
+            i += 1;
+        }
+    }
+    decoded
+}
+
+/// # Collect Attachments
+///
+/// This function walks a MIME entity, recursing into the multipart ones, and collects its attachments.
+pub(crate) fn collect_attachments(
+    headers: &Headers,
+    body: &[u8],
+    attachments: &mut Vec<Attachment>,
+) {
+    let (content_type, type_parameters) = match headers.get(&EmailHeaders::ContentType) {
+        Some(value) => header_parameters(value),
+        None => ("text/plain".to_string(), HashMap::new()),
+    };
+
+    if content_type.starts_with("multipart/") {
+        let Some(boundary) = type_parameters.get("boundary") else {
+            return;
+        };
+
+        for part in split_multipart(body, boundary) {
+            let (part_headers, part_body) = split_headers(&part);
+            let mut raw_headers = part_headers.to_vec();
+            raw_headers.extend_from_slice(b"\r\n");
+            if let Ok(part_mail) = Mail::<Vec<u8>>::from_bytes(raw_headers) {
+                collect_attachments(&part_mail.headers, part_body, attachments);
+            }
+        }
+        return;
+    }
+
+    let (disposition, disposition_parameters) = match headers.get(&EmailHeaders::ContentDisposition) {
+        Some(value) => header_parameters(value),
+        None => (String::new(), HashMap::new()),
+    };
+    let filename = disposition_parameters
+        .get("filename")
+        .or_else(|| type_parameters.get("name"))
+        .cloned();
+
+    if disposition != "attachment" && filename.is_none() {
+        return;
+    }
+
+    let encoding = headers
+        .get(&EmailHeaders::ContentTransferEncoding)
+        .map(|encoding| encoding.trim().to_lowercase())
+        .unwrap_or_default();
+    let data = match encoding.as_str() {
+        "base64" => {
+            let text = body
+                .iter()
+                .filter(|b| !b.is_ascii_whitespace())
+                .map(|&b| b as char)
+                .collect::<String>();
+            match decode_base64(&text) {
+                Some(data) => data,
+                None => {
+                    log::warn!("[📎] Invalid base64 in attachment {:?}", filename);
+                    return;
+                }
+            }
+        }
+        "quoted-printable" => decode_quoted_printable(body),
+        _ => body.to_vec(),
+    };
+
+    attachments.push(Attachment {
+        filename,
+        content_type,
+        data,
+    });
+}
+
+/// # Split Multipart
+///
+/// This function returns the body parts between the boundary delimiters, the line break before a delimiter belongs to it (RFC 2046).
+fn split_multipart(body: &[u8], boundary: &str) -> Vec<Vec<u8>> {
+    let delimiter = format!("--{}", boundary);
+    let close_delimiter = format!("--{}--", boundary);
+
+    let mut parts = Vec::new();
+    // None while in the preamble
+    let mut current: Option<Vec<&[u8]>> = None;
+    for line in body.split(|&b| b == b'\n') {
+        let trimmed = line.strip_suffix(b"\r").unwrap_or(line).trim_ascii_end();
+        let is_close = trimmed == close_delimiter.as_bytes();
+        if is_close || trimmed == delimiter.as_bytes() {
+            if let Some(lines) = current.take() {
+                let mut part = lines.join(&b'\n');
+                if part.ends_with(b"\r") {
+                    part.pop();
+                }
+                parts.push(part);
+            }
+            if is_close {
+                break;
+            }
+            current = Some(Vec::new());
+        } else if let Some(lines) = current.as_mut() {
+            lines.push(line);
+        }
+    }
+    parts
+}
+
+/// # Split Headers
+///
+/// This function splits a body part in its headers and its content at the first blank line.
+fn split_headers(part: &[u8]) -> (&[u8], &[u8]) {
+    // A part without headers starts with the blank line
+    if let Some(content) = part.strip_prefix(b"\r\n").or_else(|| part.strip_prefix(b"\n")) {
+        return (&[], content);
+    }
+
+    if let Some(index) = part.windows(4).position(|window| window == b"\r\n\r\n") {
+        return (&part[..index + 2], &part[index + 4..]);
+    }
+    if let Some(index) = part.windows(2).position(|window| window == b"\n\n") {
+        return (&part[..index + 1], &part[index + 2..]);
+    }
+    (part, &[])
+}
+
+/// # Split Unquoted
+///
+/// This function splits the value by the separator, ignoring the separators between double quotes.
+fn split_unquoted(value: &str, separator: char) -> Vec<&str> {
+    let mut fields = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                fields.push(&value[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    fields.push(&value[start..]);
+    fields
+}
+
+/// # Unquote
+///
+/// This function removes the double quotes of a quoted-string, unescaping its quoted pairs.
+fn unquote(value: &str) -> String {
+    let Some(inner) = value.strip_prefix('"').and_then(|value| value.strip_suffix('"')) else {
+        return value.to_string();
+    };
+
+    let mut unquoted = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unquoted.extend(chars.next()),
+            c => unquoted.push(c),
+        }
+    }
+    unquoted
+}
+
+/// # Percent Decode
+///
+/// This function decodes the `%XX` escapes of a RFC 2231 extended value.
+fn percent_decode(value: &str) -> Vec<u8> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = match bytes[i] {
+            b'%' => bytes
+                .get(i + 1..i + 3)
+                .and_then(|hex| std::str::from_utf8(hex).ok())
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok()),
+            _ => None,
+        };
+        match byte {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
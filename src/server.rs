@@ -1,23 +1,37 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
 use std::time::Duration;
 use std::{net::SocketAddr, sync::Arc};
-use tokio::io::BufStream;
-use tokio::sync::Mutex;
+use hashbrown::HashMap;
+use tokio::io::{AsyncWriteExt, BufStream};
+use tokio::sync::{Mutex, Notify, Semaphore};
 use trust_dns_resolver::config::{ResolverConfig, ResolverOpts};
 use trust_dns_resolver::TokioAsyncResolver;
 
+use crate::auth_stats::AuthStats;
 use crate::controllers::on_auth::OnAuthController;
 use crate::controllers::on_conn::OnConnController;
+use crate::controllers::on_content_filter::OnContentFilterController;
 use crate::controllers::on_mail_cmd::OnMailCommandController;
 use crate::controllers::on_rcpt::OnRCPTCommandController;
+use crate::controllers::on_error::OnErrorController;
+use crate::controllers::on_expn::OnExpnCommandController;
 use crate::controllers::on_unknown_command::OnUnknownCommandController;
+use crate::errors::ServerError;
 use crate::handle_connection::handle_connection_with_timeout;
+use crate::helo::HeloPolicy;
+use crate::ip_cidr::IpCidr;
+use crate::log_throttle::ACCEPT_ERRORS;
+use crate::message::{LineEnding, Message};
+use crate::status_code::StatusCodes;
+#[cfg(feature = "spf-experimental")]
+use crate::utilities::spf::SPFLimits;
 
 use super::command::Commands;
-use super::connection::SMTPConnection;
-use super::connection::SMTPConnectionStatus;
+use super::connection::{SMTPConnection, DEFAULT_MAX_COMMAND_SIZE, DEFAULT_MAX_PATH_LENGTH};
 use super::controllers::on_close::OnCloseController;
 use super::controllers::on_email::OnEmailController;
 use super::controllers::on_reset::OnResetController;
+use super::controllers::on_tls::OnTlsController;
 
 /// # SMTPServer
 ///
@@ -25,7 +39,7 @@ use super::controllers::on_reset::OnResetController;
 ///
 /// ## Example
 ///
-/// ```rust
+/// ```rust,no_run
 /// use neo_email::server::SMTPServer;
 /// use std::net::SocketAddr;
 ///
@@ -40,8 +54,8 @@ use super::controllers::on_reset::OnResetController;
 /// async fn main() {
 /// let addr = SocketAddr::from(([127, 0, 0, 1], 2526));
 /// SMTPServer::<ConnectionState>::new()
-///        // Set the number of workers to 1
-///        .workers(1)
+///        // Handle up to 100 connections at once
+///        .workers(100)
 ///        // Set the TLS acceptor
 ///        // .set_tls_acceptor(tokio_tls_acceptor)
 ///        // Set an controller to dispatch when an authentication is received
@@ -61,9 +75,11 @@ use super::controllers::on_reset::OnResetController;
 ///        .unwrap()
 ///        // Run the server
 ///        .run()
-///        .await;
+///        .await
+///        .unwrap();
 /// }
 /// ```
+#[derive(Clone)]
 pub struct SMTPServer<B> {
     /// # use_tls
     ///
@@ -75,12 +91,8 @@ pub struct SMTPServer<B> {
     listener: Option<Arc<tokio::net::TcpListener>>,
     /// # workers
     ///
-    /// This field is responsible for holding the number of workers that will be used in the ThreadPool.
-    workers: usize,
-    /// # threads_pool
-    ///
-    /// This field is responsible for holding the ThreadPool that will be used by the server.
-    threads_pool: Option<Arc<rayon::ThreadPool>>,
+    /// This field is responsible for holding the semaphore that bounds the connections handled at once, unbounded if None.
+    workers: Option<Arc<Semaphore>>,
     /// # tls_acceptor
     ///
     /// This field is responsible for holding the TLS Acceptor that will be used by the server.
@@ -98,6 +110,39 @@ pub struct SMTPServer<B> {
 
     max_session_duration: Duration,
     max_op_duration: Duration,
+    max_session_bytes: Option<usize>,
+    max_command_size: usize,
+    pipelining: bool,
+    helo_policy: Option<HeloPolicy>,
+    line_ending: LineEnding,
+    max_starttls_attempts: Option<usize>,
+    close_on_starttls_limit: bool,
+    max_transaction_duration: Option<Duration>,
+    require_ehlo: bool,
+    require_tls: Option<Vec<IpCidr>>,
+    wrap_long_replies: bool,
+    reject_repeated_auth: bool,
+    reject_empty_body: bool,
+    reuse_port: bool,
+    accept_loops: usize,
+    handshake_semaphore: Option<Arc<Semaphore>>,
+    max_controller_concurrency: usize,
+    dedupe_recipients: bool,
+    command_delays: Arc<HashMap<Commands, Duration>>,
+    requiretls_enabled: bool,
+    eight_bit_mime: bool,
+    smtputf8_enabled: bool,
+    max_path_length: usize,
+    echo_addresses: bool,
+    malformed_mail_status: StatusCodes,
+    default_rcpt_response: Option<Message>,
+    maintenance: Option<Message>,
+    overload_rejection: Option<(usize, Message)>,
+    handle: ServerHandle,
+    #[cfg(feature = "spf-experimental")]
+    spf_limits: SPFLimits,
+    #[cfg(feature = "spf-experimental")]
+    spf_prewarm_domains: Vec<String>,
     dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
 }
 
@@ -112,16 +157,24 @@ pub struct Controllers<B> {
     pub on_auth: Option<OnAuthController<B>>,
     /// # on_email controller
     pub on_email: Option<OnEmailController<B>>,
+    /// # on_content_filter controller
+    pub on_content_filter: Option<OnContentFilterController<B>>,
     /// # on_reset controller
     pub on_reset: Option<OnResetController<B>>,
+    /// # on_tls controller
+    pub on_tls: Option<OnTlsController<B>>,
     /// # on_close controller
     pub on_close: Option<OnCloseController<B>>,
     /// # on_mail_cmd controller
     pub on_mail_cmd: Option<OnMailCommandController<B>>,
     /// # on_rcpt_cmd controller
     pub on_rcpt_cmd: Option<OnRCPTCommandController<B>>,
+    /// # on_expn_cmd controller
+    pub on_expn_cmd: Option<OnExpnCommandController<B>>,
     /// # on_unknown_cmd controller
     pub on_unknown_cmd: Option<OnUnknownCommandController<B>>,
+    /// # on_error controller
+    pub on_error: Option<OnErrorController>,
 }
 
 /// # Clone for Controllers
@@ -129,22 +182,154 @@ pub struct Controllers<B> {
 /// This implementation is responsible for cloning the Controllers struct.
 impl<B> Clone for Controllers<B>
 where
-    B: Default + Send + Sync + Clone,
+    B: Clone,
 {
     fn clone(&self) -> Self {
         Controllers {
             on_conn: self.on_conn.clone(),
             on_auth: self.on_auth.clone(),
             on_email: self.on_email.clone(),
+            on_content_filter: self.on_content_filter.clone(),
             on_reset: self.on_reset.clone(),
+            on_tls: self.on_tls.clone(),
             on_close: self.on_close.clone(),
             on_mail_cmd: self.on_mail_cmd.clone(),
             on_rcpt_cmd: self.on_rcpt_cmd.clone(),
+            on_expn_cmd: self.on_expn_cmd.clone(),
             on_unknown_cmd: self.on_unknown_cmd.clone(),
+            on_error: self.on_error.clone(),
+        }
+    }
+}
+
+/// # ServerHandle
+///
+/// This struct is a cloneable handle to the state of a running SMTPServer, meant for health checks and orchestration.
+/// It can be queried from anywhere (e.g. an user-built HTTP health endpoint) while the server runs.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::server::SMTPServer;
+/// use std::net::SocketAddr;
+/// use std::time::Duration;
+/// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+/// use tokio::net::TcpStream;
+///
+/// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+/// async fn main() {
+///     let mut server = SMTPServer::<()>::new();
+///     server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+///     let addr = server.local_addr().unwrap();
+///     let handle = server.handle();
+///     let server = tokio::spawn(async move { server.run().await });
+///     assert_eq!(handle.active_connections(), 0);
+///
+///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+///     let mut lines = BufReader::new(reader).lines();
+///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "220 SMTP Service Ready");
+///     assert_eq!(handle.active_connections(), 1);
+///
+///     writer.write_all(b"QUIT\r\n").await.unwrap();
+///     while lines.next_line().await.unwrap().is_some() {}
+///
+///     // The connection task finishes right after the socket is closed
+///     while handle.active_connections() > 0 {
+///         tokio::time::sleep(Duration::from_millis(10)).await;
+///     }
+///     assert_eq!(handle.total_processed(), 1);
+///
+///     // Draining stops accepting new connections and returns from run
+///     handle.drain();
+///     assert!(handle.is_draining());
+///     server.await.unwrap();
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerHandle {
+    state: Arc<ServerState>,
+}
+
+#[derive(Debug, Default)]
+struct ServerState {
+    auth_stats: Arc<AuthStats>,
+    active_connections: AtomicUsize,
+    total_processed: AtomicU64,
+    draining: AtomicBool,
+    drain_notify: Notify,
+}
+
+impl ServerHandle {
+    /// # active_connections
+    ///
+    /// The number of connections being handled right now.
+    pub fn active_connections(&self) -> usize {
+        self.state.active_connections.load(Ordering::SeqCst)
+    }
+
+    /// # is_draining
+    ///
+    /// If the server was asked to drain, no new connections are accepted but the active ones are finished.
+    pub fn is_draining(&self) -> bool {
+        self.state.draining.load(Ordering::SeqCst)
+    }
+
+    /// # total_processed
+    ///
+    /// The number of connections handled to completion since the server started.
+    pub fn total_processed(&self) -> u64 {
+        self.state.total_processed.load(Ordering::SeqCst)
+    }
+
+    /// # auth_stats
+    ///
+    /// The outcomes of the SPF, DKIM and DMARC checks of all the connections, see `AuthStats`.
+    pub fn auth_stats(&self) -> Arc<AuthStats> {
+        self.state.auth_stats.clone()
+    }
+
+    /// # drain
+    ///
+    /// Stop accepting new connections, `run` returns while the active connections keep being handled,
+    /// wait for `active_connections` to reach 0 before exiting the process.
+    pub fn drain(&self) {
+        log::info!("[🚰] Draining, no new connections will be accepted");
+        self.state.draining.store(true, Ordering::SeqCst);
+        // Stores a permit if the accept loop isn't waiting yet
+        self.state.drain_notify.notify_one();
+    }
+
+    /// # track_connection
+    ///
+    /// Count a new active connection, until the returned guard is dropped.
+    fn track_connection(&self) -> ConnectionGuard {
+        self.state.active_connections.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard {
+            state: self.state.clone(),
         }
     }
 }
 
+/// # ConnectionGuard
+///
+/// Decrements the active connections when dropped, also if the connection task panics.
+struct ConnectionGuard {
+    state: Arc<ServerState>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.state.active_connections.fetch_sub(1, Ordering::SeqCst);
+        self.state.total_processed.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+impl<B> Default for SMTPServer<B> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<B> SMTPServer<B> {
     /// # new
     ///
@@ -157,18 +342,21 @@ impl<B> SMTPServer<B> {
         SMTPServer {
             use_tls: false,
             listener: None,
-            workers: 1,
-            threads_pool: None,
+            workers: None,
             tls_acceptor: None,
             controllers: Controllers {
                 on_conn: None,
                 on_auth: None,
                 on_email: None,
+                on_content_filter: None,
                 on_reset: None,
+                on_tls: None,
                 on_close: None,
                 on_mail_cmd: None,
                 on_rcpt_cmd: None,
+                on_expn_cmd: None,
                 on_unknown_cmd: None,
+                on_error: None,
             },
             max_size: 1024 * 1024 * 10, // 10MB
             allowed_commands: vec![
@@ -188,22 +376,150 @@ impl<B> SMTPServer<B> {
             ],
             max_session_duration: Duration::from_secs(300),
             max_op_duration: Duration::from_secs(30),
+            max_session_bytes: None,
+            max_command_size: DEFAULT_MAX_COMMAND_SIZE,
+            pipelining: true,
+            helo_policy: None,
+            line_ending: LineEnding::CRLF,
+            max_starttls_attempts: None,
+            close_on_starttls_limit: false,
+            max_transaction_duration: None,
+            require_ehlo: false,
+            require_tls: None,
+            wrap_long_replies: false,
+            reject_repeated_auth: false,
+            reject_empty_body: false,
+            reuse_port: false,
+            accept_loops: 1,
+            handshake_semaphore: None,
+            max_controller_concurrency: Semaphore::MAX_PERMITS,
+            dedupe_recipients: false,
+            command_delays: Arc::new(HashMap::new()),
+            requiretls_enabled: false,
+            eight_bit_mime: true,
+            smtputf8_enabled: false,
+            max_path_length: DEFAULT_MAX_PATH_LENGTH,
+            echo_addresses: false,
+            malformed_mail_status: StatusCodes::TransactionFailed,
+            default_rcpt_response: None,
+            maintenance: None,
+            overload_rejection: None,
+            handle: ServerHandle::default(),
+            #[cfg(feature = "spf-experimental")]
+            spf_limits: SPFLimits::default(),
+            #[cfg(feature = "spf-experimental")]
+            spf_prewarm_domains: Vec::new(),
             dns_resolver,
         }
     }
 
     /// # workers
     ///
-    /// Set the number of workers to be used in the ThreadPool, 1 by default.
+    /// Set the number of connections handled at once, unbounded by default. The connections are tasks on the current
+    /// Tokio runtime, the ones over the limit are accepted and wait for a handled one to close before being greeted.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::time::timeout;
+    ///
+    /// async fn connect(addr: SocketAddr) -> BufReader<TcpStream> {
+    ///     BufReader::new(TcpStream::connect(addr).await.unwrap())
+    /// }
+    ///
+    /// async fn greeting(client: &mut BufReader<TcpStream>) -> String {
+    ///     let mut line = String::new();
+    ///     client.read_line(&mut line).await.unwrap();
+    ///     line
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.workers(2).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     let handle = server.handle();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let mut first = connect(addr).await;
+    ///     assert!(greeting(&mut first).await.starts_with("220 "));
+    ///     let mut second = connect(addr).await;
+    ///     assert!(greeting(&mut second).await.starts_with("220 "));
+    ///
+    ///     // The third waits until a connection closes
+    ///     let mut third = connect(addr).await;
+    ///     assert!(timeout(Duration::from_millis(300), greeting(&mut third)).await.is_err());
+    ///     first.write_all(b"QUIT\r\n").await.unwrap();
+    ///     assert!(greeting(&mut third).await.starts_with("220 "));
+    ///     drop((second, third));
+    ///
+    ///     // Concurrent short-lived connections queue behind the limit, they are just tasks, no thread is created for each
+    ///     let clients = (0..4).map(|_| {
+    ///         tokio::spawn(async move {
+    ///             for _ in 0..3 {
+    ///                 let mut client = connect(addr).await;
+    ///                 assert!(greeting(&mut client).await.starts_with("220 "));
+    ///                 client.write_all(b"QUIT\r\n").await.unwrap();
+    ///                 client.read_to_end(&mut Vec::new()).await.unwrap();
+    ///             }
+    ///         })
+    ///     });
+    ///     timeout(Duration::from_secs(10), async {
+    ///         for client in clients.collect::<Vec<_>>() {
+    ///             client.await.unwrap();
+    ///         }
+    ///         while handle.total_processed() < 15 {
+    ///             tokio::time::sleep(Duration::from_millis(10)).await;
+    ///         }
+    ///     })
+    ///     .await
+    ///     .unwrap();
+    /// }
+    /// ```
     pub fn workers(&mut self, workers: usize) -> &mut Self {
         log::info!("[🚧] Setting workers to {}", workers);
-        self.workers = workers;
+        self.workers = Some(Arc::new(Semaphore::new(workers)));
         self
     }
 
     /// # set_tls_acceptor
     ///
     /// Set the TLS Acceptor to be used when upgrading the connection to TLS.
+    /// Without an acceptor STARTTLS isn't advertised in EHLO and the command gets `502`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     // No TLS acceptor
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     read_response(&mut reader).await;
+    ///
+    ///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///     assert!(!read_response(&mut reader).await.iter().any(|(_, keyword)| keyword == "STARTTLS"));
+    ///
+    ///     writer.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await, vec![(502, "TLS not supported".to_string())]);
+    /// }
+    /// ```
     pub fn set_tls_acceptor(&mut self, acceptor: tokio_native_tls::TlsAcceptor) -> &mut Self {
         log::debug!("[📃] TLS Acceptor set");
         self.use_tls = true;
@@ -211,6 +527,156 @@ impl<B> SMTPServer<B> {
         self
     }
 
+    /// # set_max_concurrent_handshakes
+    ///
+    /// Set the max TLS handshakes running at the same time across all the connections, unlimited by default.
+    /// The connections over the limit wait for a permit (within the 10 seconds handshake timeout, otherwise the upgrade fails with 454) while the plaintext ones keep being served.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio_native_tls::native_tls;
+    ///
+    /// async fn starttls(addr: SocketAddr) -> TcpStream {
+    ///     let mut stream = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    ///     let mut line = String::new();
+    ///     stream.read_line(&mut line).await.unwrap();
+    ///     stream.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///     line.clear();
+    ///     stream.read_line(&mut line).await.unwrap();
+    ///     assert!(line.starts_with("220 "));
+    ///     stream.into_inner()
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+    ///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+    ///     let identity = native_tls::Identity::from_pkcs8(cert, key).unwrap();
+    ///     let acceptor = native_tls::TlsAcceptor::new(identity).unwrap();
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .workers(4)
+    ///         .set_tls_acceptor(acceptor.into())
+    ///         .set_max_concurrent_handshakes(2)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     // Two clients take the permits and never send their ClientHello
+    ///     let stalled = vec![starttls(addr).await, starttls(addr).await];
+    ///
+    ///     // A third handshake has to wait
+    ///     let stream = starttls(addr).await;
+    ///     let handshake = tokio::spawn(async move {
+    ///         let connector = native_tls::TlsConnector::builder()
+    ///             .danger_accept_invalid_certs(true)
+    ///             .build()
+    ///             .unwrap();
+    ///         tokio_native_tls::TlsConnector::from(connector)
+    ///             .connect("localhost", stream)
+    ///             .await
+    ///             .is_ok()
+    ///     });
+    ///     tokio::time::sleep(Duration::from_millis(500)).await;
+    ///     assert!(!handshake.is_finished());
+    ///
+    ///     // Once a stalled client leaves, its permit goes to the waiting handshake
+    ///     drop(stalled);
+    ///     assert!(tokio::time::timeout(Duration::from_secs(5), handshake).await.unwrap().unwrap());
+    /// }
+    /// ```
+    pub fn set_max_concurrent_handshakes(&mut self, max_handshakes: usize) -> &mut Self {
+        log::debug!("[📃] Setting max concurrent TLS handshakes to {}", max_handshakes);
+        self.handshake_semaphore = Some(Arc::new(Semaphore::new(max_handshakes)));
+        self
+    }
+
+    /// # set_max_controller_concurrency
+    ///
+    /// Set the max number of tasks each connection may run at once for its controllers, unlimited by default.
+    /// The server doesn't start those tasks, the controllers that spawn work (e.g. a DNS lookup or a callout per RCPT)
+    /// acquire a permit of `SMTPConnection::controller_permits` in each task and hold it until the task ends,
+    /// the next tasks wait for a permit.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_rcpt::OnRCPTCommandController;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::atomic::{AtomicUsize, Ordering};
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+    /// static MAX_IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+    /// static DONE: AtomicUsize = AtomicUsize::new(0);
+    ///
+    /// // Accepts the recipient right away and verifies it in the background, one task per RCPT
+    /// async fn on_rcpt_cmd(conn: Arc<Mutex<SMTPConnection<()>>>, _data: String) -> Result<Message, Message> {
+    ///     let permits = conn.lock().await.controller_permits.clone();
+    ///     tokio::spawn(async move {
+    ///         let _permit = permits.acquire_owned().await.unwrap();
+    ///         let in_flight = IN_FLIGHT.fetch_add(1, Ordering::SeqCst) + 1;
+    ///         MAX_IN_FLIGHT.fetch_max(in_flight, Ordering::SeqCst);
+    ///         // The callout
+    ///         tokio::time::sleep(Duration::from_millis(50)).await;
+    ///         IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+    ///         DONE.fetch_add(1, Ordering::SeqCst);
+    ///     });
+    ///     Ok(Message::new(StatusCodes::OK, "Ok".to_string()))
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_max_controller_concurrency(2)
+    ///         .on_rcpt_cmd(OnRCPTCommandController::new(on_rcpt_cmd))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     lines.next_line().await.unwrap();
+    ///     for i in 0..10 {
+    ///         writer.write_all(format!("RCPT TO:<user{}@nervio.us>\r\n", i).as_bytes()).await.unwrap();
+    ///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///     }
+    ///
+    ///     while DONE.load(Ordering::SeqCst) < 10 {
+    ///         tokio::time::sleep(Duration::from_millis(10)).await;
+    ///     }
+    ///     assert_eq!(MAX_IN_FLIGHT.load(Ordering::SeqCst), 2);
+    /// }
+    /// ```
+    pub fn set_max_controller_concurrency(&mut self, max_tasks: usize) -> &mut Self {
+        log::debug!("[📃] Setting max controller concurrency per connection to {}", max_tasks);
+        self.max_controller_concurrency = max_tasks.min(Semaphore::MAX_PERMITS);
+        self
+    }
+
     /// # set_dns_resolver
     ///
     /// Set the DNS Resolver to be used when resolving the domain of the email.
@@ -225,6 +691,75 @@ impl<B> SMTPServer<B> {
     ///
     /// Set the max size of the email that can be received.
     /// size in bytes
+    ///
+    /// A bigger email is read until its terminator without being stored and then rejected with 552,
+    /// so the connection stays in sync and the client can continue. A MAIL FROM declaring a bigger `SIZE`
+    /// is rejected with 552 right away, before any data is sent (RFC 1870 6.1).
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()));
+    ///     server.set_max_size(100).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     let transaction = ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"];
+    ///     for command in transaction {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///
+    ///     // Oversized, with dot-stuffed lines that look like the terminator and reads split inside it
+    ///     let chunks = [
+    ///         "Subject: Big\r\n\r\n",
+    ///         &"A long line of the body\r\n".repeat(10),
+    ///         "..\r\n",
+    ///         "..\r",
+    ///         "\n...\r\n",
+    ///         "\r\n.",
+    ///         "\r",
+    ///         "\n",
+    ///     ];
+    ///     for chunk in chunks {
+    ///         writer.write_all(chunk.as_bytes()).await.unwrap();
+    ///         tokio::time::sleep(Duration::from_millis(20)).await;
+    ///     }
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "552 Message exceeds the maximum size");
+    ///
+    ///     // The connection is in sync, the next transaction works
+    ///     for command in transaction {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///     writer.write_all(b"Subject: Small\r\n\r\nHi\r\n.\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///
+    ///     // The declared size is checked in MAIL FROM
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us> SIZE=50000000\r\n").await.unwrap();
+    ///     assert_eq!(
+    ///         lines.next_line().await.unwrap().unwrap(),
+    ///         "552 5.3.4 Message size exceeds fixed maximum message size"
+    ///     );
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us> SIZE=100\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    /// }
+    /// ```
     pub fn set_max_size(&mut self, max_size: usize) -> &mut Self {
         log::debug!("[📃] Setting max size to {}", max_size);
         self.max_size = max_size;
@@ -242,7 +777,7 @@ impl<B> SMTPServer<B> {
 
     /// # on_conn
     /// 
-    /// Set the OnConnController to be used when a connection is opened.
+    /// Set the OnConnController to be used when a connection is opened, it can replace the greeting or refuse the connection.
     pub fn on_conn(&mut self, on_conn: OnConnController<B>) -> &mut Self {
         log::debug!("[📃] Setting OnConnController");
         self.controllers.on_conn = Some(on_conn);
@@ -267,6 +802,15 @@ impl<B> SMTPServer<B> {
         self
     }
 
+    /// # on_content_filter
+    ///
+    /// Set the OnContentFilterController to be used when a email is received, it runs before the OnEmailController and can reject, quarantine or modify the email.
+    pub fn on_content_filter(&mut self, on_content_filter: OnContentFilterController<B>) -> &mut Self {
+        log::debug!("[📃] Setting OnContentFilterController");
+        self.controllers.on_content_filter = Some(on_content_filter);
+        self
+    }
+
     /// # on_reset
     ///
     /// Set the OnResetController to be used when a connection is reset.
@@ -276,6 +820,88 @@ impl<B> SMTPServer<B> {
         self
     }
 
+    /// # on_tls
+    ///
+    /// Set the OnTlsController to be used when a connection is upgraded to TLS with STARTTLS.
+    /// The HELO/EHLO identity, the authentication and the transaction are already forgotten when it runs (RFC 3207 4.2),
+    /// it's where the custom state is wiped. The client must send EHLO again.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_tls::OnTlsController;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use std::sync::{mpsc, Arc};
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    /// use tokio_native_tls::native_tls;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+    ///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+    ///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+    ///
+    ///     // Reports the session seen by the controller, and wipes the custom state (a greeting counter)
+    ///     let (sender, receiver) = mpsc::channel();
+    ///     let sender = Arc::new(std::sync::Mutex::new(sender));
+    ///     let mut server = SMTPServer::<u32>::new();
+    ///     server
+    ///         .set_tls_acceptor(acceptor.into())
+    ///         .on_tls(OnTlsController::new(move |conn: Arc<Mutex<SMTPConnection<u32>>>| {
+    ///             let sender = sender.clone();
+    ///             async move {
+    ///                 let conn = conn.lock().await;
+    ///                 *conn.state.lock().await = 0;
+    ///                 let session = (conn.tracing_commands.len(), conn.helo.clone(), conn.use_tls);
+    ///                 sender.lock().unwrap().send(session).unwrap();
+    ///             }
+    ///         }))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    ///     read_response(&mut plain).await;
+    ///     plain.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///     read_response(&mut plain).await;
+    ///     plain.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut plain).await[0].0, 220);
+    ///
+    ///     let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+    ///     let tls = tokio_native_tls::TlsConnector::from(connector)
+    ///         .connect("localhost", plain.into_inner())
+    ///         .await
+    ///         .unwrap();
+    ///     let mut tls = BufReader::new(tls);
+    ///     tls.write_all(b"NOOP\r\n").await.unwrap();
+    ///     read_response(&mut tls).await;
+    ///
+    ///     // No command traced and no identity once upgraded
+    ///     assert_eq!(receiver.recv().unwrap(), (0, None, true));
+    /// }
+    /// ```
+    pub fn on_tls(&mut self, on_tls: OnTlsController<B>) -> &mut Self {
+        log::debug!("[📃] Setting OnTlsController");
+        self.controllers.on_tls = Some(on_tls);
+        self
+    }
+
+    /// # on_error
+    ///
+    /// Set the OnErrorController to be used when a connection fails, none by default.
+    pub fn on_error(&mut self, on_error: OnErrorController) -> &mut Self {
+        log::debug!("[📃] Setting OnErrorController");
+        self.controllers.on_error = Some(on_error);
+        self
+    }
+
     /// # on_close
     ///
     /// Set the OnCloseController to be used when a connection will be closed.
@@ -303,6 +929,15 @@ impl<B> SMTPServer<B> {
         self
     }
 
+    /// # on_expn_cmd
+    ///
+    /// Set the OnExpnCommandController to be used when an expn command is received, to stream the members of a mailing list.
+    pub fn on_expn_cmd(&mut self, on_expn_cmd: OnExpnCommandController<B>) -> &mut Self {
+        log::debug!("[📃] Setting OnExpnCommandController");
+        self.controllers.on_expn_cmd = Some(on_expn_cmd);
+        self
+    }
+
     /// # on_unknown_cmd
     /// 
     /// Set the OnUnknownCommandController to be used when an unknown command is received.
@@ -330,104 +965,1652 @@ impl<B> SMTPServer<B> {
         self
     }
 
+    /// # set_max_session_bytes
+    ///
+    /// Set the max bytes (commands and data) that a client can send in a whole session,
+    /// when exceeded the connection is closed with 421, unlimited by default.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.set_max_session_bytes(4096).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let mut client = TcpStream::connect(addr).await.unwrap();
+    ///     // Stream many long commands, never starting a DATA
+    ///     let garbage = format!("NOOP {}\r\n", "x".repeat(1000));
+    ///     for _ in 0..16 {
+    ///         if client.write_all(garbage.as_bytes()).await.is_err() {
+    ///             break;
+    ///         }
+    ///     }
+    ///
+    ///     let mut replies = Vec::new();
+    ///     let _ = client.read_to_end(&mut replies).await;
+    ///     assert!(String::from_utf8_lossy(&replies).contains("421 "));
+    /// }
+    /// ```
+    pub fn set_max_session_bytes(&mut self, max_session_bytes: usize) -> &mut Self {
+        log::debug!("[📃] Setting max session bytes to {}", max_session_bytes);
+        self.max_session_bytes = Some(max_session_bytes);
+        self
+    }
+
+    /// # set_max_command_size
+    ///
+    /// Set the max length of a command line in octets including the CRLF, 2048 by default.
+    /// Longer lines are read until their CRLF and replied with 500, a line can span any number of reads.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_max_command_size(8192)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     // Longer than a read, sent in pieces
+    ///     let command = format!("NOOP {}\r\n", "x".repeat(5000));
+    ///     for piece in command.as_bytes().chunks(1000) {
+    ///         writer.write_all(piece).await.unwrap();
+    ///         tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    ///     }
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///
+    ///     // Over the limit, the connection stays usable
+    ///     let command = format!("NOOP {}\r\n", "x".repeat(10000));
+    ///     writer.write_all(command.as_bytes()).await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "500 Line too long");
+    ///
+    ///     writer.write_all(b"NOOP\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    /// }
+    /// ```
+    pub fn set_max_command_size(&mut self, max_command_size: usize) -> &mut Self {
+        log::debug!("[📃] Setting max command size to {}", max_command_size);
+        self.max_command_size = max_command_size;
+        self
+    }
+
+    /// # set_pipelining
+    ///
+    /// Set if the server supports command pipelining (RFC 2920), enabled by default.
+    /// When disabled PIPELINING isn't advertised in the EHLO, and a client that sends a command before the reply
+    /// to the previous one is replied with 554 and disconnected.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     for pipelining in [true, false] {
+    ///         let mut server = SMTPServer::<()>::new();
+    ///         server
+    ///             .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///             .set_pipelining(pipelining)
+    ///             .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///             .await
+    ///             .unwrap();
+    ///         let addr = server.local_addr().unwrap();
+    ///         tokio::spawn(async move { server.run().await });
+    ///
+    ///         let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///         let mut reader = BufReader::new(reader);
+    ///         read_response(&mut reader).await;
+    ///
+    ///         writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///         let keywords = read_response(&mut reader).await;
+    ///         assert_eq!(keywords.iter().any(|(_, keyword)| keyword == "PIPELINING"), pipelining);
+    ///
+    ///         // The whole transaction in a single write
+    ///         writer
+    ///             .write_all(b"MAIL FROM:<jean@nervio.us>\r\nRCPT TO:<admin@nervio.us>\r\nDATA\r\n")
+    ///             .await
+    ///             .unwrap();
+    ///
+    ///         if pipelining {
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 354);
+    ///
+    ///             writer.write_all(b"Subject: Hi\r\n\r\nHello\r\n.\r\nNOOP\r\n").await.unwrap();
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///         } else {
+    ///             let reply = read_response(&mut reader).await;
+    ///             assert_eq!(reply, vec![(554, "Improper use of SMTP command pipelining".to_string())]);
+    ///             // The rest of the pipeline isn't processed, the connection is closed
+    ///             assert_eq!(read_response(&mut reader).await[0].0, 221);
+    ///             assert_eq!(reader.read(&mut [0; 1]).await.unwrap(), 0);
+    ///         }
+    ///     }
+    /// }
+    /// ```
+    pub fn set_pipelining(&mut self, pipelining: bool) -> &mut Self {
+        log::debug!("[📃] Setting pipelining to {}", pipelining);
+        self.pipelining = pipelining;
+        self
+    }
+
+    /// # validate_helo
+    ///
+    /// Set the policy to validate the HELO/EHLO identity of the clients, rejecting with 550 when it fails.
+    /// Address literals like `[192.0.2.1]` are always accepted.
+    pub fn validate_helo(&mut self, policy: HeloPolicy) -> &mut Self {
+        log::debug!("[📃] Setting HELO policy to {:?}", policy);
+        self.helo_policy = Some(policy);
+        self
+    }
+
+    /// # require_ehlo
+    ///
+    /// Refuse plain HELO with 502, forcing the clients to use EHLO to negotiate the extensions (AUTH, SIZE, STARTTLS...), disabled by default.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.require_ehlo(true).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     read_response(&mut reader).await;
+    ///
+    ///     writer.write_all(b"HELO client.nervio.us\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 502);
+    ///
+    ///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///     assert!(read_response(&mut reader).await.len() > 1);
+    /// }
+    /// ```
+    pub fn require_ehlo(&mut self, require_ehlo: bool) -> &mut Self {
+        log::debug!("[📃] Setting require EHLO to {}", require_ehlo);
+        self.require_ehlo = require_ehlo;
+        self
+    }
+
+    /// # require_tls_except
+    ///
+    /// Require the clients to upgrade the connection with STARTTLS before AUTH and MAIL, refused with 530 in plaintext (RFC 3207 4),
+    /// except the clients of the given ranges (e.g. trusted internal relays). An empty list requires TLS from every client.
+    /// Disabled by default, it only makes sense with a TLS acceptor.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::ip_cidr::IpCidr;
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio_native_tls::native_tls;
+    ///
+    /// async fn server(exceptions: &str) -> SocketAddr {
+    ///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+    ///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+    ///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_tls_acceptor(acceptor.into())
+    ///         .require_tls_except(vec![exceptions.parse::<IpCidr>().unwrap()])
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///     addr
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     // The peer is inside the exception range, plaintext is fine
+    ///     let mut plain = BufReader::new(TcpStream::connect(server("127.0.0.0/8").await).await.unwrap());
+    ///     let mut line = String::new();
+    ///     plain.read_line(&mut line).await.unwrap();
+    ///     plain.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     line.clear();
+    ///     plain.read_line(&mut line).await.unwrap();
+    ///     assert!(line.starts_with("250 "));
+    ///
+    ///     // The peer is outside of it, AUTH and MAIL need TLS
+    ///     let mut plain = BufReader::new(TcpStream::connect(server("10.0.0.0/8").await).await.unwrap());
+    ///     line.clear();
+    ///     plain.read_line(&mut line).await.unwrap();
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "AUTH PLAIN AGplYW4AcGFzc3dvcmQ="] {
+    ///         plain.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         line.clear();
+    ///         plain.read_line(&mut line).await.unwrap();
+    ///         assert!(line.starts_with("530 "), "{}", line);
+    ///     }
+    ///
+    ///     plain.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///     line.clear();
+    ///     plain.read_line(&mut line).await.unwrap();
+    ///     assert!(line.starts_with("220 "));
+    ///     let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+    ///     let tls = tokio_native_tls::TlsConnector::from(connector)
+    ///         .connect("localhost", plain.into_inner())
+    ///         .await
+    ///         .unwrap();
+    ///     let mut tls = BufReader::new(tls);
+    ///     tls.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     line.clear();
+    ///     tls.read_line(&mut line).await.unwrap();
+    ///     assert!(line.starts_with("250 "));
+    /// }
+    /// ```
+    pub fn require_tls_except(&mut self, exceptions: Vec<IpCidr>) -> &mut Self {
+        log::debug!(
+            "[📃] Requiring TLS except from {}",
+            exceptions.iter().map(|cidr| cidr.to_string()).collect::<Vec<String>>().join(", ")
+        );
+        self.require_tls = Some(exceptions);
+        self
+    }
+
+    /// # dedupe_recipients
+    ///
+    /// Ignore a recipient repeated in the same transaction (domains compared case-insensitively), answering `250 Duplicate recipient ignored`
+    /// so the email is delivered once per recipient, disabled by default.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_email::OnEmailController;
+    /// use neo_email::mail::Mail;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+    ///     // Echo the recipients of the envelope
+    ///     let conn = conn.lock().await;
+    ///     let recipients = conn.envelope.as_ref().map(|envelope| envelope.recipients.clone()).unwrap_or_default();
+    ///     let recipients = recipients.iter().map(|recipient| recipient.to_string()).collect::<Vec<_>>();
+    ///     Message::new(StatusCodes::OK, recipients.join(","))
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .dedupe_recipients(true)
+    ///         .on_email(OnEmailController::new(on_email))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     let mut replies = Vec::new();
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "RCPT TO:<admin@NERVIO.US>", "DATA"] {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         replies.push(lines.next_line().await.unwrap().unwrap());
+    ///     }
+    ///     assert_eq!(replies[2], "250 Duplicate recipient ignored");
+    ///
+    ///     writer.write_all(b"Subject: Test\r\n\r\nHello\r\n.\r\n").await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "250 admin@nervio.us");
+    /// }
+    /// ```
+    pub fn dedupe_recipients(&mut self, dedupe_recipients: bool) -> &mut Self {
+        log::debug!("[📃] Setting dedupe recipients to {}", dedupe_recipients);
+        self.dedupe_recipients = dedupe_recipients;
+        self
+    }
+
+    /// # set_command_delays
+    ///
+    /// Set the time waited before handling each command, replacing the previous delays, none by default.
+    /// This turns the server into a slow server to test the timeouts and the retries of SMTP clients,
+    /// a delay longer than the max op duration closes the connection like a server that stopped answering.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::command::Commands;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::time::{Duration, Instant};
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .set_command_delays([(Commands::RCPT, Duration::from_millis(500))])
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     let mut elapsed = Vec::new();
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>"] {
+    ///         let started_at = Instant::now();
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///         elapsed.push(started_at.elapsed());
+    ///     }
+    ///     assert!(elapsed[0] < Duration::from_millis(500));
+    ///     assert!(elapsed[1] >= Duration::from_millis(500));
+    /// }
+    /// ```
+    pub fn set_command_delays(&mut self, delays: impl IntoIterator<Item = (Commands, Duration)>) -> &mut Self {
+        let delays = delays.into_iter().collect::<HashMap<_, _>>();
+        log::debug!("[📃] Setting command delays to {:?}", delays);
+        self.command_delays = Arc::new(delays);
+        self
+    }
+
+    /// # reject_when_overloaded
+    ///
+    /// Reject the new connections while there are `threshold` or more active connections, writing the response
+    /// (usually a 421) and closing right after accepting, without starting the handling of the connection.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .reject_when_overloaded(1, Message::new(StatusCodes::ServiceNotAvailable, "Too busy, try later".to_string()))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     // The first connection is handled and kept open
+    ///     let first = TcpStream::connect(addr).await.unwrap();
+    ///     let mut first = BufReader::new(first).lines();
+    ///     assert_eq!(first.next_line().await.unwrap().unwrap(), "220 SMTP Service Ready");
+    ///
+    ///     // Over the threshold, the connection gets the 421 and is closed
+    ///     let second = TcpStream::connect(addr).await.unwrap();
+    ///     let mut second = BufReader::new(second).lines();
+    ///     assert_eq!(second.next_line().await.unwrap().unwrap(), "421 Too busy, try later");
+    ///     assert_eq!(second.next_line().await.unwrap(), None);
+    /// }
+    /// ```
+    pub fn reject_when_overloaded(&mut self, threshold: usize, response: Message) -> &mut Self {
+        log::debug!("[📃] Rejecting connections over {} active connections", threshold);
+        self.overload_rejection = Some((threshold, response));
+        self
+    }
+
+    /// # set_malformed_mail_status
+    ///
+    /// Set the status code replied when the DATA received can't be parsed as a mail (e.g. the first line isn't a header),
+    /// 554 by default, use 451 to make the sender retry. The transaction is reset and the client can send another command.
+    /// Mails are only parsed when there is an `on_email` or `on_content_filter` controller.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_email::OnEmailController;
+    /// use neo_email::mail::Mail;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_email(_conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+    ///     Message::new(StatusCodes::OK, "Queued".to_string())
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .on_email(OnEmailController::new(on_email))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"] {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///
+    ///     // The first line isn't a header
+    ///     writer.write_all(b"Hello, this is not a header\r\n.\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("554 "));
+    ///
+    ///     // The connection is back waiting commands
+    ///     writer.write_all(b"NOOP\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    /// }
+    /// ```
+    pub fn set_malformed_mail_status(&mut self, status: StatusCodes) -> &mut Self {
+        log::debug!("[📃] Setting malformed mail status to {}", status);
+        self.malformed_mail_status = status;
+        self
+    }
+
+    /// # set_default_rcpt_response
+    ///
+    /// Set the reply for recipients that no route accepts, `550 Relay access denied` by default. It's the reply of RCPT
+    /// without controller, and it's available to the RCPT controller as `SMTPConnection::default_rcpt_response`,
+    /// so the unknown recipient policy lives in one place.
+    ///
+    /// ## Example
+    ///
+    /// Without RCPT controller every recipient gets the default response, the server doesn't relay.
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     lines.next_line().await.unwrap();
+    ///     writer.write_all(b"RCPT TO:<someone@example.com>\r\n").await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "550 Relay access denied");
+    ///
+    ///     // Without recipients there is no mail to receive
+    ///     writer.write_all(b"DATA\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("503 "));
+    /// }
+    /// ```
+    ///
+    /// A RCPT controller routing some domains returns it for the others.
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_rcpt::OnRCPTCommandController;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_rcpt(conn: Arc<Mutex<SMTPConnection<()>>>, data: String) -> Result<Message, Message> {
+    ///     // Only nervio.us is routed, everything else gets the default response
+    ///     if data.ends_with("@nervio.us>") {
+    ///         Ok(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///     } else {
+    ///         Ok(conn.lock().await.default_rcpt_response.clone())
+    ///     }
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(
+    ///             StatusCodes::RequestedActionNotTakenMailboxUnavailable,
+    ///             "No such user here".to_string(),
+    ///         ))
+    ///         .on_rcpt_cmd(OnRCPTCommandController::new(on_rcpt))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"RCPT TO:<admin@nervio.us>\r\n").await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "250 Ok");
+    ///
+    ///     writer.write_all(b"RCPT TO:<someone@example.com>\r\n").await.unwrap();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "550 No such user here");
+    /// }
+    /// ```
+    pub fn set_default_rcpt_response(&mut self, response: Message) -> &mut Self {
+        log::debug!("[📃] Setting default RCPT response to {} {}", response.status, response.message);
+        self.default_rcpt_response = Some(response);
+        self
+    }
+
+    /// # maintenance_mode
+    ///
+    /// Put the server in maintenance, every command after the greeting but QUIT is replied with the given message
+    /// (usually a `421`) without calling the controllers, so the clients connect and retry later.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .maintenance_mode(Message::new(
+    ///             StatusCodes::ServiceNotAvailable,
+    ///             "Down for maintenance, try again later".to_string(),
+    ///         ))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("220 "));
+    ///
+    ///     for command in ["EHLO client.nervio.us", "MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA", "RSET"] {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         assert_eq!(lines.next_line().await.unwrap().unwrap(), "421 Down for maintenance, try again later");
+    ///     }
+    ///
+    ///     writer.write_all(b"QUIT\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("221 "));
+    ///     assert!(lines.next_line().await.unwrap().is_none());
+    /// }
+    /// ```
+    pub fn maintenance_mode(&mut self, message: Message) -> &mut Self {
+        log::debug!("[📃] Setting maintenance mode with {} {}", message.status, message.message);
+        self.maintenance = Some(message);
+        self
+    }
+
+    /// # enable_requiretls
+    ///
+    /// Enable the REQUIRETLS extension (RFC 8689), disabled by default. It's advertised in EHLO only on TLS connections,
+    /// the MAIL FROM `REQUIRETLS` parameter sets `Envelope::require_tls` and it's rejected with 530 over plaintext.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_email::OnEmailController;
+    /// use neo_email::mail::Mail;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    /// use tokio_native_tls::native_tls;
+    ///
+    /// async fn on_email(conn: Arc<Mutex<SMTPConnection<()>>>, _mail: Mail<Vec<u8>>) -> Message {
+    ///     let require_tls = conn.lock().await.envelope.as_ref().is_some_and(|envelope| envelope.require_tls);
+    ///     Message::new(StatusCodes::OK, format!("require_tls={}", require_tls))
+    /// }
+    ///
+    /// // Sends a command and returns the reply lines
+    /// async fn command<S: AsyncRead + AsyncWrite + Unpin>(stream: &mut BufReader<S>, command: &str) -> Vec<(u16, String)> {
+    ///     stream.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///     read_response(stream).await
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+    ///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+    ///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .workers(2)
+    ///         .set_tls_acceptor(acceptor.into())
+    ///         .enable_requiretls(true)
+    ///         .on_email(OnEmailController::new(on_email))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     // Over plaintext REQUIRETLS is not offered and rejected
+    ///     let mut plain = BufReader::new(TcpStream::connect(addr).await.unwrap());
+    ///     read_response(&mut plain).await;
+    ///     assert!(!command(&mut plain, "EHLO client.nervio.us").await.iter().any(|(_, keyword)| keyword == "REQUIRETLS"));
+    ///     let reply = command(&mut plain, "MAIL FROM:<jean@nervio.us> REQUIRETLS").await;
+    ///     assert_eq!(reply[0].0, 530);
+    ///
+    ///     // Over TLS it's offered and reaches the envelope
+    ///     command(&mut plain, "STARTTLS").await;
+    ///     let connector = native_tls::TlsConnector::builder().danger_accept_invalid_certs(true).build().unwrap();
+    ///     let tls = tokio_native_tls::TlsConnector::from(connector)
+    ///         .connect("localhost", plain.into_inner())
+    ///         .await
+    ///         .unwrap();
+    ///     let mut tls = BufReader::new(tls);
+    ///
+    ///     assert!(command(&mut tls, "EHLO client.nervio.us").await.iter().any(|(_, keyword)| keyword == "REQUIRETLS"));
+    ///     assert_eq!(command(&mut tls, "MAIL FROM:<jean@nervio.us> REQUIRETLS").await[0].0, 250);
+    ///     command(&mut tls, "RCPT TO:<admin@nervio.us>").await;
+    ///     command(&mut tls, "DATA").await;
+    ///     let reply = command(&mut tls, "Subject: Test\r\n\r\nHello\r\n.").await;
+    ///     assert_eq!(reply, vec![(250, "require_tls=true".to_string())]);
+    /// }
+    /// ```
+    pub fn enable_requiretls(&mut self, enable: bool) -> &mut Self {
+        log::debug!("[📃] Setting REQUIRETLS to {}", enable);
+        self.requiretls_enabled = enable;
+        self
+    }
+
+    /// # set_8bitmime
+    ///
+    /// Set if the server accepts 8-bit messages (RFC 6152), enabled by default. When disabled 8BITMIME isn't advertised
+    /// and a MAIL FROM with `BODY=8BITMIME` is rejected with 555, useful when the mail is relayed to 7-bit only servers.
+    /// The declared body type is kept in `Envelope::body_type`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .workers(2)
+    ///         .set_8bitmime(false)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     read_response(&mut reader).await;
+    ///
+    ///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///     assert!(!read_response(&mut reader).await.iter().any(|(_, keyword)| keyword == "8BITMIME"));
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us> BODY=8BITMIME\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 555);
+    ///
+    ///     // 7-bit messages are still accepted
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us> BODY=7BIT\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+    /// }
+    /// ```
+    pub fn set_8bitmime(&mut self, enable: bool) -> &mut Self {
+        log::debug!("[📃] Setting 8BITMIME to {}", enable);
+        self.eight_bit_mime = enable;
+        self
+    }
+
+    /// # enable_smtputf8
+    ///
+    /// Set if SMTPUTF8 (RFC 6531) is advertised, disabled by default. When enabled a MAIL FROM with the `SMTPUTF8`
+    /// parameter starts a transaction where the sender and the recipients may have UTF-8 local parts and domains,
+    /// kept in `Envelope::smtputf8`. Non-ASCII addresses are rejected with 553 in any other transaction,
+    /// and the parameter with 555 when disabled.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// async fn server(enable: bool) -> SocketAddr {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()));
+    ///     server.enable_smtputf8(enable).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///     addr
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let (reader, mut writer) = TcpStream::connect(server(true).await).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     read_response(&mut reader).await;
+    ///
+    ///     writer.write_all(b"EHLO client.nervio.us\r\n").await.unwrap();
+    ///     assert!(read_response(&mut reader).await.iter().any(|(_, keyword)| keyword == "SMTPUTF8"));
+    ///
+    ///     // The UTF-8 mailbox needs the parameter
+    ///     writer.write_all("MAIL FROM:<jérôme@nervio.us>\r\n".as_bytes()).await.unwrap();
+    ///     assert!(matches!(&read_response(&mut reader).await[0], (553, text) if text.starts_with("5.6.7 ")));
+    ///     writer.write_all("MAIL FROM:<jérôme@nervio.us> SMTPUTF8\r\n".as_bytes()).await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///     writer.write_all("RCPT TO:<用户@例子.广告>\r\n".as_bytes()).await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///
+    ///     // Only in the transaction that asked for it
+    ///     writer.write_all(b"RSET\r\nMAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     read_response(&mut reader).await;
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+    ///     writer.write_all("RCPT TO:<用户@例子.广告>\r\n".as_bytes()).await.unwrap();
+    ///     assert!(matches!(&read_response(&mut reader).await[0], (553, text) if text.starts_with("5.6.7 ")));
+    ///
+    ///     // Without SMTPUTF8 the parameter isn't recognized
+    ///     let (reader, mut writer) = TcpStream::connect(server(false).await).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     read_response(&mut reader).await;
+    ///     writer.write_all("MAIL FROM:<jérôme@nervio.us> SMTPUTF8\r\n".as_bytes()).await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 555);
+    /// }
+    /// ```
+    pub fn enable_smtputf8(&mut self, enable: bool) -> &mut Self {
+        log::debug!("[📃] Setting SMTPUTF8 to {}", enable);
+        self.smtputf8_enabled = enable;
+        self
+    }
+
+    /// # set_max_path_length
+    ///
+    /// Set the max length of the MAIL and RCPT path including the angle brackets, 256 octets by default as in RFC 5321.
+    /// Longer paths, or local parts over 64 octets, are rejected with 501 before the address is parsed.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .workers(2)
+    ///         .set_max_path_length(64)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     // A path of 67 octets
+    ///     let domain = format!("{}.nervio.us", "a".repeat(50));
+    ///     writer.write_all(format!("MAIL FROM:<jean@{}>\r\n", domain).as_bytes()).await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("501 "));
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///     writer.write_all(format!("RCPT TO:<admin@{}>\r\n", domain).as_bytes()).await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("501 "));
+    ///     writer.write_all(b"RCPT TO:<admin@nervio.us>\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    /// }
+    /// ```
+    pub fn set_max_path_length(&mut self, max_path_length: usize) -> &mut Self {
+        log::debug!("[📃] Setting max path length to {}", max_path_length);
+        self.max_path_length = max_path_length;
+        self
+    }
+
+    /// # set_echo_addresses
+    ///
+    /// Set if the MAIL reply echoes the accepted address, like `250 2.1.0 <jean@nervio.us> Sender ok`, disabled by default (`250 Ok`).
+    /// It applies to the reply without controller, without RCPT controller the recipients get the default RCPT response
+    /// (`set_default_rcpt_response`). The controllers can build the same replies with `Message::sender_ok` and `Message::recipient_ok`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// async fn replies(echo_addresses: bool) -> Vec<String> {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .workers(2)
+    ///         .set_echo_addresses(echo_addresses)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     let mut replies = Vec::new();
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "RSET", "MAIL FROM:<>"] {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         replies.push(lines.next_line().await.unwrap().unwrap());
+    ///     }
+    ///     replies
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     assert_eq!(
+    ///         replies(true).await,
+    ///         vec![
+    ///             "250 2.1.0 <jean@nervio.us> Sender ok",
+    ///             "250 Connection reset",
+    ///             "250 2.1.0 <> Sender ok",
+    ///         ]
+    ///     );
+    ///     assert_eq!(replies(false).await, vec!["250 Ok", "250 Connection reset", "250 Ok"]);
+    /// }
+    /// ```
+    pub fn set_echo_addresses(&mut self, echo_addresses: bool) -> &mut Self {
+        log::debug!("[📃] Setting echo addresses to {}", echo_addresses);
+        self.echo_addresses = echo_addresses;
+        self
+    }
+
+    /// # wrap_long_replies
+    ///
+    /// Wrap the replies longer than the SMTP line limit (512 octets) into multiline replies with the same status code, disabled by default.
+    pub fn wrap_long_replies(&mut self, wrap_long_replies: bool) -> &mut Self {
+        log::debug!("[📃] Setting wrap long replies to {}", wrap_long_replies);
+        self.wrap_long_replies = wrap_long_replies;
+        self
+    }
+
+    /// # reject_repeated_auth
+    ///
+    /// Answer AUTH with 503 once the client authenticated successfully (RFC 4954), without calling the `on_auth` controller again, disabled by default.
+    /// The connection counts as authenticated when `on_auth` answers with 235.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::connection::SMTPConnection;
+    /// use neo_email::controllers::on_auth::OnAuthController;
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use std::sync::Arc;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio::sync::Mutex;
+    ///
+    /// async fn on_auth(_conn: Arc<Mutex<SMTPConnection<()>>>, _data: String) -> Result<Message, Message> {
+    ///     Ok(Message::new(StatusCodes::AuthenticationSuccessful, "Authentication successful".to_string()))
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .on_auth(OnAuthController::new(on_auth))
+    ///         .reject_repeated_auth(true)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"AUTH PLAIN AGplYW4AcGFzc3dvcmQ=\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("235 "));
+    ///
+    ///     writer.write_all(b"AUTH PLAIN AGplYW4AcGFzc3dvcmQ=\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("503 "));
+    /// }
+    /// ```
+    pub fn reject_repeated_auth(&mut self, reject_repeated_auth: bool) -> &mut Self {
+        log::debug!("[📃] Setting reject repeated AUTH to {}", reject_repeated_auth);
+        self.reject_repeated_auth = reject_repeated_auth;
+        self
+    }
+
+    /// # reject_empty_body
+    ///
+    /// Refuse the messages without body, only headers, with `554 Empty message` instead of accepting them, disabled by default.
+    /// They are refused before the `on_content_filter` and `on_email` controllers, and the client can start another transaction.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::message::Message;
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::status_code::StatusCodes;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// async fn send(reject_empty_body: bool, data: &[u8]) -> String {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_default_rcpt_response(Message::new(StatusCodes::OK, "Ok".to_string()))
+    ///         .reject_empty_body(reject_empty_body)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     for command in ["MAIL FROM:<jean@nervio.us>", "RCPT TO:<admin@nervio.us>", "DATA"] {
+    ///         writer.write_all(format!("{}\r\n", command).as_bytes()).await.unwrap();
+    ///         lines.next_line().await.unwrap();
+    ///     }
+    ///     writer.write_all(data).await.unwrap();
+    ///     lines.next_line().await.unwrap().unwrap()
+    /// }
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let headers_only = b"Subject: Hello\r\n\r\n.\r\n";
+    ///     assert_eq!(send(true, headers_only).await, "554 Empty message");
+    ///     assert!(send(false, headers_only).await.starts_with("250 "));
+    ///
+    ///     // A body is still accepted
+    ///     assert!(send(true, b"Subject: Hello\r\n\r\nHello\r\n.\r\n").await.starts_with("250 "));
+    /// }
+    /// ```
+    pub fn reject_empty_body(&mut self, reject_empty_body: bool) -> &mut Self {
+        log::debug!("[📃] Setting reject empty body to {}", reject_empty_body);
+        self.reject_empty_body = reject_empty_body;
+        self
+    }
+
+    /// # set_max_starttls_attempts
+    ///
+    /// Set the max STARTTLS attempts per connection, further STARTTLS commands get 454.
+    /// If `close_connection` is true the connection is closed when the limit is exceeded, otherwise it continues in plaintext.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use neo_email::testing::read_response;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    /// use tokio_native_tls::native_tls;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let cert = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.crt"));
+    ///     let key = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/localhost.key"));
+    ///     let acceptor = native_tls::TlsAcceptor::new(native_tls::Identity::from_pkcs8(cert, key).unwrap()).unwrap();
+    ///
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_tls_acceptor(acceptor.into())
+    ///         .set_max_starttls_attempts(2, false)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut reader = BufReader::new(reader);
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 220);
+    ///
+    ///     // The client answers the 220 in plaintext, so the handshake fails and the connection stays in plaintext
+    ///     for _ in 0..2 {
+    ///         writer.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///         assert_eq!(read_response(&mut reader).await[0].0, 220);
+    ///         writer.write_all(b"Not a TLS ClientHello\r\n").await.unwrap();
+    ///         assert_eq!(read_response(&mut reader).await[0].0, 454);
+    ///     }
+    ///
+    ///     // The attempts are exhausted
+    ///     writer.write_all(b"STARTTLS\r\n").await.unwrap();
+    ///     assert!(read_response(&mut reader).await[0].1.starts_with("Too many STARTTLS"));
+    ///
+    ///     // The connection continues in plaintext
+    ///     writer.write_all(b"NOOP\r\n").await.unwrap();
+    ///     assert_eq!(read_response(&mut reader).await[0].0, 250);
+    /// }
+    /// ```
+    pub fn set_max_starttls_attempts(
+        &mut self,
+        max_attempts: usize,
+        close_connection: bool,
+    ) -> &mut Self {
+        log::debug!(
+            "[📃] Setting max STARTTLS attempts to {} (close connection: {})",
+            max_attempts,
+            close_connection
+        );
+        self.max_starttls_attempts = Some(max_attempts);
+        self.close_on_starttls_limit = close_connection;
+        self
+    }
+
+    /// # set_response_line_ending
+    ///
+    /// Set the line ending of the responses, only for testing client tolerance, SMTP requires CRLF.
+    #[cfg(feature = "test-utils")]
+    #[doc(hidden)]
+    pub fn set_response_line_ending(&mut self, line_ending: LineEnding) -> &mut Self {
+        log::debug!("[📃] Setting response line ending to {:?}", line_ending);
+        self.line_ending = line_ending;
+        self
+    }
+
+    /// # set_max_transaction_duration
+    ///
+    /// Set the max duration of a transaction, measured from MAIL FROM to the end of DATA and reset per transaction.
+    /// When exceeded the transaction is aborted with 451 and the connection is closed.
+    ///
+    /// The timeouts work together:
+    /// - `max_session_duration` bounds the whole connection.
+    /// - `max_op_duration` bounds the wait for each read, so it catches idle clients.
+    /// - `max_transaction_duration` catches clients that keep a transaction open sending bytes from time to time, it is checked when data is received.
+    /// - `max_session_bytes` bounds the bytes sent, independent of time.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use std::time::Duration;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_max_transaction_duration(Duration::from_millis(200))
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     tokio::spawn(async move { server.run().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     lines.next_line().await.unwrap();
+    ///
+    ///     writer.write_all(b"MAIL FROM:<jean@nervio.us>\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("250 "));
+    ///
+    ///     // A slow client, still under the operation timeout
+    ///     tokio::time::sleep(Duration::from_millis(300)).await;
+    ///     writer.write_all(b"RCPT TO:<admin@nervio.us>\r\n").await.unwrap();
+    ///     assert!(lines.next_line().await.unwrap().unwrap().starts_with("451 "));
+    /// }
+    /// ```
+    pub fn set_max_transaction_duration(&mut self, duration: Duration) -> &mut Self {
+        log::debug!("[📃] Setting max transaction duration to {:?}", duration);
+        self.max_transaction_duration = Some(duration);
+        self
+    }
+
+    /// # set_spf_limits
+    ///
+    /// Set the limits of the SPF checks done with `check_spf`, exceeding any of them is a permerror.
+    /// By default 10 redirects, 10 includes and 10 DNS lookups (RFC 7208 4.6.4).
+    #[cfg(feature = "spf-experimental")]
+    pub fn set_spf_limits(&mut self, max_redirects: u8, max_includes: u8, max_dns_lookups: u8) -> &mut Self {
+        log::debug!(
+            "[📃] Setting SPF limits to {} redirects, {} includes and {} DNS lookups",
+            max_redirects,
+            max_includes,
+            max_dns_lookups
+        );
+        self.spf_limits = SPFLimits {
+            max_redirects,
+            max_includes,
+            max_dns_lookups,
+        };
+        self
+    }
+
+    /// # prewarm_spf
+    ///
+    /// Set the domains whose SPF records are fetched before accepting connections, so the first SPF check of known high volume senders hits the resolver cache.
+    /// Failures are logged and don't abort the startup.
+    #[cfg(feature = "spf-experimental")]
+    pub fn prewarm_spf(&mut self, domains: Vec<String>) -> &mut Self {
+        log::debug!("[📃] Setting SPF prewarm domains to {:?}", domains);
+        self.spf_prewarm_domains = domains;
+        self
+    }
+
+    /// # set_reuse_port
+    ///
+    /// Bind the listener with `SO_REUSEADDR` and `SO_REUSEPORT`, so the old and the new process can share the port during a rolling restart, disabled by default.
+    /// `SO_REUSEPORT` is only set on Unix platforms that support it (Linux, BSDs, macOS), elsewhere only `SO_REUSEADDR` is set. It must be called before `bind`.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    ///
+    /// #[tokio::main]
+    /// async fn main() {
+    ///     let mut old_server = SMTPServer::<()>::new();
+    ///     old_server.set_reuse_port(true).bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = old_server.local_addr().unwrap();
+    ///
+    ///     // The new process binds the same port while the old one is still listening
+    ///     let mut new_server = SMTPServer::<()>::new();
+    ///     let result = new_server.set_reuse_port(true).bind(addr).await;
+    ///     #[cfg(any(target_os = "linux", target_os = "macos", target_os = "freebsd"))]
+    ///     assert!(result.is_ok());
+    ///
+    ///     // Without it the port is busy
+    ///     let mut other_server = SMTPServer::<()>::new();
+    ///     assert!(other_server.bind(addr).await.is_err());
+    ///     # let _ = result;
+    /// }
+    /// ```
+    pub fn set_reuse_port(&mut self, reuse_port: bool) -> &mut Self {
+        log::debug!("[📃] Setting reuse port to {}", reuse_port);
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// # set_accept_loops
+    ///
+    /// Set how many tasks accept connections concurrently in `run`, 1 by default. At high connection rates a single accept loop
+    /// is bound to one core, several loops spread the accepts over the runtime workers. It's capped to the worker threads of the
+    /// runtime, more loops than workers only contend for the listener.
+    ///
+    /// The loops share the listener: when a connection arrives every waiting loop is woken but only one gets it, the others
+    /// go back to wait. With `set_reuse_port` the kernel can balance between listeners instead (Linux, the BSDs), that
+    /// is done by running one server per listener bound to the same address, each with its own loop.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server
+    ///         .set_accept_loops(4)
+    ///         .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+    ///         .await
+    ///         .unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     let handle = server.handle();
+    ///     let server = tokio::spawn(async move { server.run().await });
+    ///
+    ///     // Every connection is accepted once
+    ///     let mut clients = Vec::new();
+    ///     for _ in 0..20 {
+    ///         clients.push(tokio::spawn(async move {
+    ///             let mut lines = BufReader::new(TcpStream::connect(addr).await.unwrap()).lines();
+    ///             lines.next_line().await.unwrap().unwrap()
+    ///         }));
+    ///     }
+    ///     for client in clients {
+    ///         assert_eq!(client.await.unwrap(), "220 SMTP Service Ready");
+    ///     }
+    ///
+    ///     // Draining stops all the loops
+    ///     handle.drain();
+    ///     server.await.unwrap().unwrap();
+    /// }
+    /// ```
+    pub fn set_accept_loops(&mut self, accept_loops: usize) -> &mut Self {
+        log::debug!("[📃] Setting accept loops to {}", accept_loops);
+        self.accept_loops = accept_loops;
+        self
+    }
+
     /// # bind
     ///
     /// This function is responsible for binding the SMTPServer to a specific address.
     pub async fn bind(&mut self, address: SocketAddr) -> Result<&mut Self, tokio::io::Error> {
         log::info!("[🔗 ] Binding to {}", address);
-        let listener = tokio::net::TcpListener::bind(address).await?;
+        let listener = if self.reuse_port {
+            let socket = if address.is_ipv4() {
+                tokio::net::TcpSocket::new_v4()?
+            } else {
+                tokio::net::TcpSocket::new_v6()?
+            };
+
+            socket.set_reuseaddr(true)?;
+            #[cfg(all(
+                unix,
+                not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))
+            ))]
+            socket.set_reuseport(true)?;
+
+            socket.bind(address)?;
+            // Same backlog as `TcpListener::bind`
+            socket.listen(1024)?
+        } else {
+            tokio::net::TcpListener::bind(address).await?
+        };
         self.listener = Some(Arc::new(listener));
         Ok(self)
     }
 
+    /// # handle
+    ///
+    /// This function returns a ServerHandle to query the state of the server or drain it, it can be taken before running.
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// # local_addr
+    ///
+    /// This function returns the address the SMTPServer is bound to, useful after binding the port 0.
+    pub fn local_addr(&self) -> Result<SocketAddr, tokio::io::Error> {
+        match &self.listener {
+            Some(listener) => listener.local_addr(),
+            None => Err(tokio::io::Error::new(
+                tokio::io::ErrorKind::NotConnected,
+                "There isn't listener",
+            )),
+        }
+    }
+
     /// # run
     ///
     /// This function is responsible for running the SMTPServer, accepting connections and handling them, binding is required before running.
-    pub async fn run(&mut self)
+    /// It returns `ServerError::NotBound` if `bind` wasn't called, otherwise it runs until the server is drained.
+    pub async fn run(&mut self) -> Result<(), ServerError>
     where
         B: 'static + Default + Send + Sync + Clone,
     {
-        // Clone the listener to be used in the main loop
-        let listener = match self.listener.clone() {
-            Some(lstnr) => lstnr,
-            None => panic!("There isn't listener"),
-        };
+        let listener = self.prepare().await?;
 
-        // Build the ThreadPool with the number of workers, 1 by default
-        log::info!("[🚧] Building ThreadPool with {} workers", self.workers);
-        self.threads_pool = match rayon::ThreadPoolBuilder::new()
-            .num_threads(self.workers)
-            .build()
-        {
-            Ok(pool) => Some(Arc::new(pool)),
-            Err(err) => panic!("{}", err),
-        };
+        let workers = tokio::runtime::Handle::current().metrics().num_workers();
+        let accept_loops = self.accept_loops.clamp(1, workers);
+        if accept_loops == 1 {
+            self.accept_loop(&listener).await;
+        } else {
+            // Each loop needs its own copy of the server to be spawned, they share the listener and the handle
+            log::info!("[🔧] Starting {} accept loops", accept_loops);
+            let server = Arc::new(self.clone());
+            let mut loops = tokio::task::JoinSet::new();
+            for _ in 0..accept_loops {
+                let server = server.clone();
+                let listener = listener.clone();
+                loops.spawn(async move { server.accept_loop(&listener).await });
+            }
+            while let Some(result) = loops.join_next().await {
+                if let Err(err) = result {
+                    log::error!("[🔧] Accept loop failed: {}", err);
+                }
+            }
+        }
+
+        log::info!("[🚰] Stopped accepting connections");
+        Ok(())
+    }
 
+    /// # accept_loop
+    ///
+    /// This function accepts connections and spawns their handling until the server is drained.
+    async fn accept_loop(&self, listener: &tokio::net::TcpListener)
+    where
+        B: 'static + Default + Send + Sync + Clone,
+    {
         // Start the main loop for accepting connections
         log::info!("[🔧] Starting main loop for accepting connections");
-        loop {
-            // Accept a new connection
-            let (socket, _) = match listener.accept().await {
+        let state = self.handle.state.clone();
+        while !state.draining.load(Ordering::SeqCst) {
+            // Accept a new connection, unless the server starts draining
+            let accepted = tokio::select! {
+                accepted = listener.accept() => accepted,
+                _ = state.drain_notify.notified() => {
+                    // Pass the wake up on to the next accept loop, if any
+                    state.drain_notify.notify_one();
+                    continue;
+                }
+            };
+
+            let (socket, _) = match accepted {
                 Ok(conn) => conn,
                 Err(err) => {
-                    log::error!(
-                        "An error ocurred while trying to accept and TcpStream connection {}",
-                        err
-                    );
+                    if let Some(suppressed) = ACCEPT_ERRORS.check() {
+                        log::error!(
+                            "An error ocurred while trying to accept and TcpStream connection {}{}",
+                            err,
+                            suppressed
+                        );
+                    }
                     continue;
                 }
             };
 
-            log::trace!(
-                "[🔍] Connection received from {}",
-                socket.peer_addr().unwrap()
-            );
-
-            // Clone the thread pool, use_tls, tls_acceptor and controllers to be used in the tokio::spawn
-            let pool = self.threads_pool.clone();
-            let use_tls = self.use_tls;
-            let tls_acceptor = self.tls_acceptor.clone();
-            let controllers = self.controllers.clone();
-            let max_size = self.max_size;
-            let allowed_commands = self.allowed_commands.clone();
-            let max_session_duration = self.max_session_duration;
-            let max_op_duration = self.max_op_duration;
-            let dns_resolver = self.dns_resolver.clone();
-
-            // Spawn a new task to handle the connection
-            tokio::spawn(async move {
-                log::trace!("[🟢] Initializing TCP connection");
-
-                // Create a new SMTPConnection and wrap it in an Arc<Mutex> to be shared safely between threads
-                let conn = Arc::new(Mutex::new(SMTPConnection {
-                    use_tls: false,
-                    tls_buff_socket: None,
-                    tcp_buff_socket: Some(Arc::new(Mutex::new(BufStream::new(socket)))),
-                    buffer: Vec::new(),
-                    mail_buffer: Vec::new(),
-                    status: SMTPConnectionStatus::WaitingCommand,
-                    dns_resolver,
-                    state: Arc::new(Mutex::new(B::default())),
-                    tracing_commands: Vec::new(),
-                }));
-
-                if let Some(pool) = pool {
-                    pool.install(|| {
-                        tokio::runtime::Runtime::new().unwrap().block_on(
-                            handle_connection_with_timeout(
-                                use_tls,
-                                tls_acceptor,
-                                conn,
-                                controllers,
-                                max_size,
-                                allowed_commands,
-                                max_session_duration,
-                                max_op_duration,
-                            ),
-                        );
+            // Shed the load before starting the handling of the connection
+            if let Some((threshold, response)) = &self.overload_rejection {
+                if state.active_connections.load(Ordering::SeqCst) >= *threshold {
+                    log::trace!("[🚦] Overloaded, rejecting connection");
+                    let response = response.as_bytes_with(true, self.line_ending);
+                    tokio::spawn(async move {
+                        let mut socket = socket;
+                        let _ = socket.write_all(&response).await;
+                        let _ = socket.shutdown().await;
                     });
+                    continue;
                 }
-            });
+            }
+
+            self.handle_socket(socket);
         }
     }
+
+    /// # run_once
+    ///
+    /// This function accepts a single connection, handles it to completion and returns, binding is required before running.
+    /// It's meant for tests and simple tools, the setup is the same as `run`.
+    /// It returns `ServerError::NotBound` if `bind` wasn't called and `ServerError::Io` if the connection can't be accepted.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::server::SMTPServer;
+    /// use std::net::SocketAddr;
+    /// use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    /// use tokio::net::TcpStream;
+    ///
+    /// #[tokio::main(flavor = "multi_thread", worker_threads = 2)]
+    /// async fn main() {
+    ///     let mut server = SMTPServer::<()>::new();
+    ///     server.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await.unwrap();
+    ///     let addr = server.local_addr().unwrap();
+    ///     let server = tokio::spawn(async move { server.run_once().await });
+    ///
+    ///     let (reader, mut writer) = TcpStream::connect(addr).await.unwrap().into_split();
+    ///     let mut lines = BufReader::new(reader).lines();
+    ///     assert_eq!(lines.next_line().await.unwrap().unwrap(), "220 SMTP Service Ready");
+    ///
+    ///     writer.write_all(b"QUIT\r\n").await.unwrap();
+    ///     while lines.next_line().await.unwrap().is_some() {}
+    ///
+    ///     // The server returns once the connection is closed
+    ///     server.await.unwrap().unwrap();
+    /// }
+    /// ```
+    pub async fn run_once(&mut self) -> Result<(), ServerError>
+    where
+        B: 'static + Default + Send + Sync + Clone,
+    {
+        let listener = self.prepare().await?;
+
+        log::info!("[🔧] Waiting for a single connection");
+        let (socket, _) = listener.accept().await?;
+
+        if let Err(err) = self.handle_socket(socket).await {
+            log::error!("[🔧] Connection task failed: {}", err);
+        }
+        Ok(())
+    }
+
+    /// # prepare
+    ///
+    /// This function warms the caches before accepting connections, returning the listener.
+    async fn prepare(&mut self) -> Result<Arc<tokio::net::TcpListener>, ServerError> {
+        // Clone the listener to be used in the main loop
+        let listener = match self.listener.clone() {
+            Some(lstnr) => lstnr,
+            None => return Err(ServerError::NotBound),
+        };
+
+        // Prewarm the DNS resolver cache with the SPF records of the configured domains
+        #[cfg(feature = "spf-experimental")]
+        if !self.spf_prewarm_domains.is_empty() {
+            log::info!("[🌐] Prewarming SPF records");
+            crate::utilities::spf::prewarm_spf_records(
+                self.dns_resolver.clone(),
+                &self.spf_prewarm_domains,
+            )
+            .await;
+        }
+
+        Ok(listener)
+    }
+
+    /// # handle_socket
+    ///
+    /// This function spawns the task that handles an accepted connection.
+    fn handle_socket(&self, socket: tokio::net::TcpStream) -> tokio::task::JoinHandle<()>
+    where
+        B: 'static + Default + Send + Sync + Clone,
+    {
+        let peer_addr = socket.peer_addr().ok();
+
+        match peer_addr {
+            Some(peer_addr) => log::trace!("[🔍] Connection received from {}", peer_addr),
+            None => log::trace!("[🔍] Connection received from an unknown peer"),
+        }
+
+        // Clone the workers, use_tls, tls_acceptor and controllers to be used in the tokio::spawn
+        let workers = self.workers.clone();
+        let use_tls = self.use_tls;
+        let tls_acceptor = self.tls_acceptor.clone();
+        let controllers = self.controllers.clone();
+        let max_size = self.max_size;
+        let allowed_commands = self.allowed_commands.clone();
+        let max_session_duration = self.max_session_duration;
+        let max_op_duration = self.max_op_duration;
+        let max_session_bytes = self.max_session_bytes;
+        let max_command_size = self.max_command_size;
+        let pipelining = self.pipelining;
+        let helo_policy = self.helo_policy;
+        let line_ending = self.line_ending;
+        let max_starttls_attempts = self.max_starttls_attempts;
+        let close_on_starttls_limit = self.close_on_starttls_limit;
+        let max_transaction_duration = self.max_transaction_duration;
+        let require_ehlo = self.require_ehlo;
+        // The peers of the exception ranges may stay in plaintext, an unknown peer can't be in them
+        let require_tls = self.require_tls.as_ref().is_some_and(|exceptions| {
+            !peer_addr.is_some_and(|peer_addr| exceptions.iter().any(|cidr| cidr.contains(peer_addr.ip())))
+        });
+        let wrap_long_replies = self.wrap_long_replies;
+        let reject_repeated_auth = self.reject_repeated_auth;
+        let reject_empty_body = self.reject_empty_body;
+        let handshake_semaphore = self.handshake_semaphore.clone();
+        let controller_permits = Arc::new(Semaphore::new(self.max_controller_concurrency));
+        let dedupe_recipients = self.dedupe_recipients;
+        let command_delays = self.command_delays.clone();
+        let requiretls_enabled = self.requiretls_enabled;
+        let eight_bit_mime = self.eight_bit_mime;
+        let smtputf8_enabled = self.smtputf8_enabled;
+        let max_path_length = self.max_path_length;
+        let echo_addresses = self.echo_addresses;
+        let malformed_mail_status = self.malformed_mail_status.clone();
+        let default_rcpt_response = self.default_rcpt_response.clone();
+        let maintenance = self.maintenance.clone();
+        let tls_available = use_tls && tls_acceptor.is_some();
+        #[cfg(feature = "spf-experimental")]
+        let spf_limits = self.spf_limits;
+        let dns_resolver = self.dns_resolver.clone();
+        let connection_guard = self.handle.track_connection();
+        let auth_stats = self.handle.state.auth_stats.clone();
+
+        // Spawn a new task to handle the connection
+        tokio::spawn(async move {
+            let _connection_guard = connection_guard;
+            log::trace!("[🟢] Initializing TCP connection");
+
+            // Create a new SMTPConnection and wrap it in an Arc<Mutex> to be shared safely between threads
+            let mut conn = SMTPConnection {
+                tcp_buff_socket: Some(Arc::new(Mutex::new(BufStream::new(socket)))),
+                // Sized for a whole command line, the longest one that isn't refused
+                buffer: Vec::with_capacity(max_command_size),
+                peer_addr,
+                auth_stats,
+                tls_available,
+                max_session_bytes,
+                max_command_size,
+                pipelining,
+                helo_policy,
+                line_ending,
+                max_starttls_attempts,
+                close_on_starttls_limit,
+                max_transaction_duration,
+                require_ehlo,
+                require_tls,
+                wrap_long_replies,
+                reject_repeated_auth,
+                reject_empty_body,
+                handshake_semaphore,
+                controller_permits,
+                dedupe_recipients,
+                command_delays,
+                maintenance,
+                requiretls_enabled,
+                eight_bit_mime,
+                smtputf8_enabled,
+                max_path_length,
+                echo_addresses,
+                malformed_mail_status,
+                #[cfg(feature = "spf-experimental")]
+                spf_limits,
+                ..SMTPConnection::new(dns_resolver)
+            };
+            if let Some(default_rcpt_response) = default_rcpt_response {
+                conn.default_rcpt_response = default_rcpt_response;
+            }
+            let conn = Arc::new(Mutex::new(conn));
+
+            // Wait for a handled connection to close when there are too many
+            let _permit = match workers {
+                Some(workers) => match workers.acquire_owned().await {
+                    Ok(permit) => Some(permit),
+                    Err(_) => return,
+                },
+                None => None,
+            };
+
+            handle_connection_with_timeout(
+                use_tls,
+                tls_acceptor,
+                conn,
+                controllers,
+                max_size,
+                allowed_commands,
+                max_session_duration,
+                max_op_duration,
+            )
+            .await;
+        })
+    }
 }
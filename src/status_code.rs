@@ -1,3 +1,5 @@
+use core::fmt;
+
 use serde::{Deserialize, Serialize};
 
 /// # SMTP Status Codes
@@ -13,7 +15,7 @@ use serde::{Deserialize, Serialize};
 /// Message::builder()
 ///     .status(StatusCodes::AuthenticationSuccessful)
 ///     .message("Authenticated".to_string())
-///     .build()
+///     .build();
 /// ```
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum StatusCodes {
@@ -32,6 +34,8 @@ pub enum StatusCodes {
     /// # Cannot Verify User But Will Accept Message And Attempt Delivery
     CannotVerifyUserButWillAcceptMessageAndAttemptDelivery = 252,
 
+    /// # Server Challenge
+    ServerChallenge = 334,
     /// # Start Mail Input
     StartMailInput = 354,
 
@@ -43,6 +47,8 @@ pub enum StatusCodes {
     RequestedActionAbortedLocalErrorInProcessing = 451,
     /// # Insufficient System Storage
     InsufficientSystemStorage = 452,
+    /// # TLS Not Available Due To Temporary Reason
+    TLSNotAvailable = 454,
     /// # Server Unable To Accommodate Parameters
     ServerUnableToAccommodateParameters = 455,
 
@@ -58,6 +64,8 @@ pub enum StatusCodes {
     CommandParameterNotImplemented = 504,
     /// # Server Does Not Accept Mail
     ServerDoesNotAcceptMail = 521,
+    /// # Must Issue STARTTLS First
+    MustIssueStartTLSFirst = 530,
     /// # Authentication Credetials Invalid
     AuthenticationCredetialsInvalid = 535,
     /// # Recipient Address Rejected
@@ -72,45 +80,157 @@ pub enum StatusCodes {
     MailboxNameNotAllowed = 553,
     /// # Transaction Failed
     TransactionFailed = 554,
+    /// # MAIL FROM/RCPT TO Parameters Not Recognized Or Not Implemented
+    ParametersNotRecognized = 555,
 }
 
-/// # Status Codes
-/// 
-/// This struct contains methods for the StatusCodes enum.
 impl StatusCodes {
-    /// # To String
-    /// 
-    /// This function converts the status code to a string.
-    pub fn to_string(&self) -> String {
-        match self {
-            StatusCodes::HelpMessage => "214".to_string(),
-            StatusCodes::SMTPServiceReady => "220".to_string(),
-            StatusCodes::ServiceClosingTransmissionChannel => "221".to_string(),
-            StatusCodes::AuthenticationSuccessful => "235".to_string(),
-            StatusCodes::OK => "250".to_string(),
-            StatusCodes::UserNotLocalWillForward => "251".to_string(),
-            StatusCodes::CannotVerifyUserButWillAcceptMessageAndAttemptDelivery => {
-                "252".to_string()
-            }
-            StatusCodes::StartMailInput => "354".to_string(),
-            StatusCodes::ServiceNotAvailable => "421".to_string(),
-            StatusCodes::RequestedMailActionNotTakenMailboxUnavailable => "450".to_string(),
-            StatusCodes::RequestedActionAbortedLocalErrorInProcessing => "451".to_string(),
-            StatusCodes::InsufficientSystemStorage => "452".to_string(),
-            StatusCodes::ServerUnableToAccommodateParameters => "455".to_string(),
-            StatusCodes::SyntaxError => "500".to_string(),
-            StatusCodes::SyntaxErrorInParametersOrArguments => "501".to_string(),
-            StatusCodes::CommandNotImplemented => "502".to_string(),
-            StatusCodes::BadSequenceOfCommands => "503".to_string(),
-            StatusCodes::CommandParameterNotImplemented => "504".to_string(),
-            StatusCodes::ServerDoesNotAcceptMail => "521".to_string(),
-            StatusCodes::AuthenticationCredetialsInvalid => "535".to_string(),
-            StatusCodes::RecipientAddressRejected => "541".to_string(),
-            StatusCodes::RequestedActionNotTakenMailboxUnavailable => "550".to_string(),
-            StatusCodes::UserNotLocalTryForwarding => "551".to_string(),
-            StatusCodes::ExceededStorageAllocation => "552".to_string(),
-            StatusCodes::MailboxNameNotAllowed => "553".to_string(),
-            StatusCodes::TransactionFailed => "554".to_string(),
-        }
+    /// # Code
+    ///
+    /// This function returns the numeric status code, for example `250`.
+    pub fn code(&self) -> u16 {
+        self.clone() as u16
+    }
+
+    /// # From Code
+    ///
+    /// This function returns the status of a numeric code, None if the code isn't known, for example in a reply of another server.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// assert_eq!(StatusCodes::from_code(250), Some(StatusCodes::OK));
+    /// assert_eq!(StatusCodes::from_code(354), Some(StatusCodes::StartMailInput));
+    /// assert_eq!(StatusCodes::from_code(299), None);
+    /// ```
+    pub fn from_code(code: u16) -> Option<StatusCodes> {
+        let status = match code {
+            214 => StatusCodes::HelpMessage,
+            220 => StatusCodes::SMTPServiceReady,
+            221 => StatusCodes::ServiceClosingTransmissionChannel,
+            235 => StatusCodes::AuthenticationSuccessful,
+            250 => StatusCodes::OK,
+            251 => StatusCodes::UserNotLocalWillForward,
+            252 => StatusCodes::CannotVerifyUserButWillAcceptMessageAndAttemptDelivery,
+            334 => StatusCodes::ServerChallenge,
+            354 => StatusCodes::StartMailInput,
+            421 => StatusCodes::ServiceNotAvailable,
+            450 => StatusCodes::RequestedMailActionNotTakenMailboxUnavailable,
+            451 => StatusCodes::RequestedActionAbortedLocalErrorInProcessing,
+            452 => StatusCodes::InsufficientSystemStorage,
+            454 => StatusCodes::TLSNotAvailable,
+            455 => StatusCodes::ServerUnableToAccommodateParameters,
+            500 => StatusCodes::SyntaxError,
+            501 => StatusCodes::SyntaxErrorInParametersOrArguments,
+            502 => StatusCodes::CommandNotImplemented,
+            503 => StatusCodes::BadSequenceOfCommands,
+            504 => StatusCodes::CommandParameterNotImplemented,
+            521 => StatusCodes::ServerDoesNotAcceptMail,
+            530 => StatusCodes::MustIssueStartTLSFirst,
+            535 => StatusCodes::AuthenticationCredetialsInvalid,
+            541 => StatusCodes::RecipientAddressRejected,
+            550 => StatusCodes::RequestedActionNotTakenMailboxUnavailable,
+            551 => StatusCodes::UserNotLocalTryForwarding,
+            552 => StatusCodes::ExceededStorageAllocation,
+            553 => StatusCodes::MailboxNameNotAllowed,
+            554 => StatusCodes::TransactionFailed,
+            555 => StatusCodes::ParametersNotRecognized,
+            _ => return None,
+        };
+        Some(status)
+    }
+
+    /// # Is Positive Completion
+    ///
+    /// This function returns if the status code is a 2xx, the requested action was completed.
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::status_code::StatusCodes;
+    ///
+    /// assert!(StatusCodes::OK.is_positive_completion());
+    /// assert!(StatusCodes::SMTPServiceReady.is_positive_completion());
+    /// assert!(StatusCodes::AuthenticationSuccessful.is_positive_completion());
+    /// assert!(!StatusCodes::StartMailInput.is_positive_completion());
+    ///
+    /// assert!(StatusCodes::StartMailInput.is_positive_intermediate());
+    /// assert!(!StatusCodes::OK.is_positive_intermediate());
+    ///
+    /// assert!(StatusCodes::ServiceNotAvailable.is_transient_negative());
+    /// assert!(StatusCodes::TLSNotAvailable.is_transient_negative());
+    /// assert!(!StatusCodes::TransactionFailed.is_transient_negative());
+    ///
+    /// assert!(StatusCodes::TransactionFailed.is_permanent_negative());
+    /// assert!(StatusCodes::SyntaxError.is_permanent_negative());
+    /// assert!(!StatusCodes::RequestedActionAbortedLocalErrorInProcessing.is_permanent_negative());
+    /// ```
+    pub fn is_positive_completion(&self) -> bool {
+        (200..300).contains(&self.code())
+    }
+
+    /// # Is Positive Intermediate
+    ///
+    /// This function returns if the status code is a 3xx, more information is needed to complete the action (e.g. 354 after DATA).
+    pub fn is_positive_intermediate(&self) -> bool {
+        (300..400).contains(&self.code())
+    }
+
+    /// # Is Transient Negative
+    ///
+    /// This function returns if the status code is a 4xx, the action failed but may succeed if retried later.
+    pub fn is_transient_negative(&self) -> bool {
+        (400..500).contains(&self.code())
+    }
+
+    /// # Is Permanent Negative
+    ///
+    /// This function returns if the status code is a 5xx, the action failed and shouldn't be retried as is.
+    pub fn is_permanent_negative(&self) -> bool {
+        (500..600).contains(&self.code())
+    }
+}
+
+/// # Display implementation for StatusCodes
+/// 
+/// This implementation writes the numeric status code, for example `250`.
+impl fmt::Display for StatusCodes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self {
+            StatusCodes::HelpMessage => "214",
+            StatusCodes::SMTPServiceReady => "220",
+            StatusCodes::ServiceClosingTransmissionChannel => "221",
+            StatusCodes::AuthenticationSuccessful => "235",
+            StatusCodes::OK => "250",
+            StatusCodes::UserNotLocalWillForward => "251",
+            StatusCodes::CannotVerifyUserButWillAcceptMessageAndAttemptDelivery => "252",
+            StatusCodes::ServerChallenge => "334",
+            StatusCodes::StartMailInput => "354",
+            StatusCodes::ServiceNotAvailable => "421",
+            StatusCodes::RequestedMailActionNotTakenMailboxUnavailable => "450",
+            StatusCodes::RequestedActionAbortedLocalErrorInProcessing => "451",
+            StatusCodes::InsufficientSystemStorage => "452",
+            StatusCodes::TLSNotAvailable => "454",
+            StatusCodes::ServerUnableToAccommodateParameters => "455",
+            StatusCodes::SyntaxError => "500",
+            StatusCodes::SyntaxErrorInParametersOrArguments => "501",
+            StatusCodes::CommandNotImplemented => "502",
+            StatusCodes::BadSequenceOfCommands => "503",
+            StatusCodes::CommandParameterNotImplemented => "504",
+            StatusCodes::ServerDoesNotAcceptMail => "521",
+            StatusCodes::MustIssueStartTLSFirst => "530",
+            StatusCodes::AuthenticationCredetialsInvalid => "535",
+            StatusCodes::RecipientAddressRejected => "541",
+            StatusCodes::RequestedActionNotTakenMailboxUnavailable => "550",
+            StatusCodes::UserNotLocalTryForwarding => "551",
+            StatusCodes::ExceededStorageAllocation => "552",
+            StatusCodes::MailboxNameNotAllowed => "553",
+            StatusCodes::TransactionFailed => "554",
+            StatusCodes::ParametersNotRecognized => "555",
+        };
+
+        write!(f, "{}", code)
     }
 }
@@ -0,0 +1,102 @@
+use tokio::io::{AsyncBufRead, AsyncBufReadExt};
+
+use crate::{message::parse_reply_line, status_code::StatusCodes};
+
+/// # Parse Response
+///
+/// This function parses the replies read from the server into `(code, text)` pairs, one per line.
+/// The lines of a multiline reply (`250-` followed by a last `250 `) keep their text without the separator,
+/// and LF-only line endings are accepted too.
+///
+/// It panics on a malformed line (see `parse_reply_line`), as it's meant for tests.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::testing::parse_response;
+///
+/// // A multiline EHLO response
+/// let ehlo = b"250-Hello\r\n250-SIZE 10485760\r\n250-8BITMIME\r\n250 STARTTLS\r\n";
+/// assert_eq!(
+///     parse_response(ehlo),
+///     vec![
+///         (250, "Hello".to_string()),
+///         (250, "SIZE 10485760".to_string()),
+///         (250, "8BITMIME".to_string()),
+///         (250, "STARTTLS".to_string()),
+///     ]
+/// );
+///
+/// // A single line reply, and a code without text
+/// assert_eq!(parse_response(b"221 Bye\r\n"), vec![(221, "Bye".to_string())]);
+/// assert_eq!(parse_response(b"354\n"), vec![(354, "".to_string())]);
+/// ```
+pub fn parse_response(bytes: &[u8]) -> Vec<(u16, String)> {
+    String::from_utf8_lossy(bytes)
+        .split('\n')
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (code, _, text) = parse_reply_line(line).unwrap_or_else(|| panic!("Malformed reply line: {:?}", line));
+            (code, text.to_string())
+        })
+        .collect()
+}
+
+/// # Read Response
+///
+/// This function reads a whole reply from the server, every line of a multiline reply until the last one,
+/// and parses it with `parse_response`.
+///
+/// It panics if the connection closes before the last line.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::testing::read_response;
+/// use tokio::io::BufReader;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let mut reader = BufReader::new(&b"250-Hello\r\n250 PIPELINING\r\n221 Bye\r\n"[..]);
+///     assert_eq!(
+///         read_response(&mut reader).await,
+///         vec![(250, "Hello".to_string()), (250, "PIPELINING".to_string())]
+///     );
+///     assert_eq!(read_response(&mut reader).await, vec![(221, "Bye".to_string())]);
+/// }
+/// ```
+pub async fn read_response<R: AsyncBufRead + Unpin>(reader: &mut R) -> Vec<(u16, String)> {
+    let mut bytes = Vec::new();
+    loop {
+        let start = bytes.len();
+        if reader.read_until(b'\n', &mut bytes).await.unwrap() == 0 {
+            panic!("The server closed the connection mid reply: {:?}", String::from_utf8_lossy(&bytes));
+        }
+        let line = String::from_utf8_lossy(&bytes[start..]);
+        let line = line.trim_end_matches(['\r', '\n']);
+        if matches!(parse_reply_line(line), Some((_, true, _))) {
+            return parse_response(&bytes);
+        }
+    }
+}
+
+/// # Assert Response
+///
+/// This function asserts that the last reply read from the server has the given status, returning its text.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::status_code::StatusCodes;
+/// use neo_email::testing::assert_response;
+///
+/// assert_eq!(assert_response(b"250-Hello\r\n250 PIPELINING\r\n", StatusCodes::OK), "PIPELINING");
+/// ```
+pub fn assert_response(bytes: &[u8], status: StatusCodes) -> String {
+    let (code, text) = parse_response(bytes)
+        .pop()
+        .unwrap_or_else(|| panic!("Expected a {} reply, got nothing", status));
+    assert_eq!(code, status.code(), "Unexpected reply: {} {}", code, text);
+    text
+}
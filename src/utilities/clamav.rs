@@ -0,0 +1,300 @@
+use std::future::Future;
+use std::net::SocketAddr;
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+#[cfg(unix)]
+use tokio::net::UnixStream;
+
+use crate::controllers::on_content_filter::FilterVerdict;
+use crate::message::Message;
+use crate::status_code::StatusCodes;
+
+/// # Default Chunk Size
+///
+/// The size of the chunks streamed to clamd, it must be under the `StreamMaxLength` of clamd.
+pub const DEFAULT_CHUNK_SIZE: usize = 8192;
+
+/// # Default Timeout
+///
+/// The time a scan may take, from the connection to clamd to its reply.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// # Scan Result
+///
+/// This enum represents the result of scanning a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScanResult {
+    /// # Clean
+    ///
+    /// No threat was found.
+    Clean,
+    /// # Infected
+    ///
+    /// A threat was found, with the name of the signature (e.g. `Eicar-Test-Signature`).
+    Infected(String),
+    /// # Error
+    ///
+    /// The scanner couldn't scan the message (connection refused, timeout, unexpected reply...).
+    Error(String),
+}
+
+/// # Scan Failure Policy
+///
+/// This enum represents what to do with a message when the scanner fails.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ScanFailurePolicy {
+    /// # Accept
+    ///
+    /// Accept the message unscanned, mail keeps flowing while the scanner is down.
+    Accept,
+    /// # Defer
+    ///
+    /// Reject the message with 451, so the sender retries later.
+    #[default]
+    Defer,
+}
+
+impl ScanResult {
+    /// # To Verdict
+    ///
+    /// This function converts the result to a content filter verdict, infected messages are rejected with 550
+    /// and scanner failures are handled by the given policy.
+    pub fn to_verdict(&self, on_failure: ScanFailurePolicy) -> FilterVerdict {
+        match self {
+            ScanResult::Clean => FilterVerdict::Accept,
+            ScanResult::Infected(signature) => FilterVerdict::Reject(Message::new(
+                StatusCodes::RequestedActionNotTakenMailboxUnavailable,
+                format!("Message rejected, virus found: {}", signature),
+            )),
+            ScanResult::Error(err) => match on_failure {
+                ScanFailurePolicy::Accept => {
+                    log::warn!("[🦠] Scanner failed, accepting the message unscanned: {}", err);
+                    FilterVerdict::Accept
+                }
+                ScanFailurePolicy::Defer => {
+                    log::warn!("[🦠] Scanner failed, deferring the message: {}", err);
+                    FilterVerdict::Reject(Message::new(
+                        StatusCodes::RequestedActionAbortedLocalErrorInProcessing,
+                        "Antivirus temporarily unavailable, try again later".to_string(),
+                    ))
+                }
+            },
+        }
+    }
+}
+
+/// # Scanner
+///
+/// This trait represents an antivirus (or any content) scanner, to be called from the `on_content_filter` controller.
+pub trait Scanner: Send + Sync {
+    /// # Scan
+    ///
+    /// This function scans the given bytes.
+    fn scan(&self, bytes: &[u8]) -> impl Future<Output = ScanResult> + Send;
+}
+
+/// # ClamAV Address
+///
+/// This enum represents where clamd listens.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ClamavAddress {
+    /// # TCP
+    ///
+    /// The `TCPSocket` of clamd, usually port 3310.
+    Tcp(SocketAddr),
+    /// # Unix
+    ///
+    /// The `LocalSocket` of clamd, for example `/run/clamav/clamd.ctl`.
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+/// # ClamAV Scanner
+///
+/// This struct represents a scanner that streams the messages to clamd with the INSTREAM command.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::controllers::on_content_filter::FilterVerdict;
+/// use neo_email::utilities::clamav::{ClamavAddress, ClamavScanner, ScanFailurePolicy, ScanResult, Scanner};
+/// use std::net::SocketAddr;
+/// use std::time::Duration;
+/// use tokio::io::{AsyncReadExt, AsyncWriteExt};
+/// use tokio::net::TcpListener;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // Mock clamd, reads the INSTREAM chunks and finds the EICAR test string
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let addr = listener.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         loop {
+///             let (mut socket, _) = listener.accept().await.unwrap();
+///             let mut command = [0u8; 10];
+///             socket.read_exact(&mut command).await.unwrap();
+///             assert_eq!(&command, b"zINSTREAM\0");
+///
+///             let mut data = Vec::new();
+///             loop {
+///                 let len = socket.read_u32().await.unwrap() as usize;
+///                 if len == 0 {
+///                     break;
+///                 }
+///                 let mut chunk = vec![0u8; len];
+///                 socket.read_exact(&mut chunk).await.unwrap();
+///                 data.extend(chunk);
+///             }
+///
+///             let reply: &[u8] = if String::from_utf8_lossy(&data).contains("EICAR-STANDARD-ANTIVIRUS-TEST-FILE") {
+///                 b"stream: Eicar-Test-Signature FOUND\0"
+///             } else {
+///                 b"stream: OK\0"
+///             };
+///             socket.write_all(reply).await.unwrap();
+///         }
+///     });
+///
+///     let scanner = ClamavScanner::new(ClamavAddress::Tcp(addr)).set_chunk_size(16);
+///     assert_eq!(scanner.scan(b"Hello World, just a clean message").await, ScanResult::Clean);
+///
+///     let eicar = b"X5O!P%@AP[4\\PZX54(P^)7CC)7}$EICAR-STANDARD-ANTIVIRUS-TEST-FILE!$H+H*";
+///     let result = scanner.scan(eicar).await;
+///     assert_eq!(result, ScanResult::Infected("Eicar-Test-Signature".to_string()));
+///     match scanner.verdict(eicar).await {
+///         FilterVerdict::Reject(message) => assert_eq!(message.status.to_string(), "550"),
+///         verdict => panic!("Unexpected verdict {:?}", verdict),
+///     }
+///
+///     // Nobody listens there, the failure policy decides
+///     let unreachable = SocketAddr::from(([127, 0, 0, 1], 1));
+///     let scanner = ClamavScanner::new(ClamavAddress::Tcp(unreachable));
+///     assert!(matches!(scanner.scan(eicar).await, ScanResult::Error(_)));
+///     match scanner.verdict(eicar).await {
+///         FilterVerdict::Reject(message) => assert_eq!(message.status.to_string(), "451"),
+///         verdict => panic!("Unexpected verdict {:?}", verdict),
+///     }
+///
+///     let scanner = scanner.set_failure_policy(ScanFailurePolicy::Accept);
+///     assert_eq!(scanner.verdict(eicar).await, FilterVerdict::Accept);
+///
+///     // A clamd that never replies, the scan times out
+///     let stuck = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let scanner = ClamavScanner::new(ClamavAddress::Tcp(stuck.local_addr().unwrap())).set_timeout(Duration::from_millis(100));
+///     tokio::spawn(async move {
+///         let (_socket, _) = stuck.accept().await.unwrap();
+///         std::future::pending::<()>().await;
+///     });
+///     assert_eq!(scanner.scan(eicar).await, ScanResult::Error("Scan timed out after 100ms".to_string()));
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClamavScanner {
+    address: ClamavAddress,
+    chunk_size: usize,
+    on_failure: ScanFailurePolicy,
+    timeout: Duration,
+}
+
+impl ClamavScanner {
+    /// # New
+    ///
+    /// This function creates a new ClamavScanner for the given clamd address, deferring the messages when clamd fails.
+    pub fn new(address: ClamavAddress) -> Self {
+        ClamavScanner {
+            address,
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            on_failure: ScanFailurePolicy::default(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// # Set Chunk Size
+    ///
+    /// This function sets the size of the chunks streamed to clamd.
+    pub fn set_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// # Set Failure Policy
+    ///
+    /// This function sets what `verdict` does with the messages when clamd fails.
+    pub fn set_failure_policy(mut self, on_failure: ScanFailurePolicy) -> Self {
+        self.on_failure = on_failure;
+        self
+    }
+
+    /// # Set Timeout
+    ///
+    /// This function sets the time a scan may take, connecting, streaming the message and reading the reply of clamd,
+    /// 30 seconds by default. A scan that takes longer is a `ScanResult::Error`, handled by the failure policy.
+    pub fn set_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// # Verdict
+    ///
+    /// This function scans the bytes and returns the content filter verdict, ready to be returned from `on_content_filter`.
+    pub async fn verdict(&self, bytes: &[u8]) -> FilterVerdict {
+        self.scan(bytes).await.to_verdict(self.on_failure)
+    }
+
+    /// # INSTREAM
+    ///
+    /// This function streams the bytes to clamd in length prefixed chunks and returns its raw reply.
+    async fn instream<S>(&self, mut stream: S, bytes: &[u8]) -> std::io::Result<String>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        stream.write_all(b"zINSTREAM\0").await?;
+        for chunk in bytes.chunks(self.chunk_size) {
+            stream.write_u32(chunk.len() as u32).await?;
+            stream.write_all(chunk).await?;
+        }
+        // A zero length chunk ends the stream
+        stream.write_u32(0).await?;
+        stream.flush().await?;
+
+        let mut reply = Vec::new();
+        stream.read_to_end(&mut reply).await?;
+
+        Ok(String::from_utf8_lossy(&reply)
+            .trim_end_matches(['\0', '\n'])
+            .to_string())
+    }
+}
+
+impl Scanner for ClamavScanner {
+    async fn scan(&self, bytes: &[u8]) -> ScanResult {
+        let reply = async {
+            match &self.address {
+                ClamavAddress::Tcp(addr) => self.instream(TcpStream::connect(addr).await?, bytes).await,
+                #[cfg(unix)]
+                ClamavAddress::Unix(path) => self.instream(UnixStream::connect(path).await?, bytes).await,
+            }
+        };
+
+        // A stuck clamd must not hold the DATA reply forever
+        let reply = match tokio::time::timeout(self.timeout, reply).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(err)) => return ScanResult::Error(err.to_string()),
+            Err(_) => return ScanResult::Error(format!("Scan timed out after {:?}", self.timeout)),
+        };
+
+        // The reply looks like `stream: OK`, `stream: <signature> FOUND` or `<reason> ERROR`
+        let status = reply.strip_prefix("stream: ").unwrap_or(&reply);
+        if status == "OK" {
+            ScanResult::Clean
+        } else if let Some(signature) = status.strip_suffix(" FOUND") {
+            ScanResult::Infected(signature.to_string())
+        } else {
+            ScanResult::Error(reply)
+        }
+    }
+}
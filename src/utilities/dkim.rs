@@ -1,264 +1,751 @@
-/// # DKIM
-/// 
-/// This module contains the DKIM implementation.
-/// DKIM is used to verify the authenticity of the email.
-/// It uses a public key to verify the signature of the email.
-/// 
-/// Note: This module is not implemented yet.
-
-/*
-use crate::{connection::SMTPConnection, errors::Error};
+use crate::{auth_stats::AuthResult, connection::SMTPConnection, errors::{Error, SMTPError}, headers::EmailHeaders, mail::Mail};
 use base64::prelude::*;
-use openssl::{pkey::PKey, rsa::Rsa, sign::Verifier};
-use sha1::Digest;
+use hashbrown::HashMap;
+use openssl::{
+    hash::MessageDigest,
+    pkey::{PKey, Public},
+    rsa::Rsa,
+    sign::Verifier,
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 use tokio::sync::Mutex;
+use trust_dns_resolver::error::ResolveErrorKind;
+use trust_dns_resolver::proto::op::ResponseCode;
 use trust_dns_resolver::TokioAsyncResolver;
 
-/// # SPFRecordAll
+/// # Canonicalization
 ///
-/// Represents the policy to apply in the SPF record
-///
-/// - Aggresive: -all means that all IPs that are not listed in the SPF record are not allowed to send emails
-/// - Passive: ~all means that all IPs that are not listed in the SPF record are allowed to send emails but marked as spam
-/// - Permissive: +all means that all IPs that are not listed in the SPF record are allowed to send emails
-#[derive(Debug, Clone)]
-pub enum SPFRecordAll {
-    Aggresive, // -all means that all IPs that are not listed in the SPF record are not allowed to send emails
-    Passive, // ~all means that all IPs that are not listed in the SPF record are allowed to send emails but marked as spam
-    Permissive, // +all means that all IPs that are not listed in the SPF record are allowed to send emails
+/// Represents how the headers or the body are normalized before hashing (RFC 6376 Section 3.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Canonicalization {
+    /// # Simple
+    ///
+    /// Almost no change is tolerated, only the empty lines at the end of the body are ignored
+    Simple,
+    /// # Relaxed
+    ///
+    /// The whitespace is collapsed and the header names lowercased, surviving the usual rewrites of the relays
+    Relaxed,
 }
 
-/// # SPFRecord
+/// # HashAlgo
 ///
-/// Represents an SPF record
-/// Example `v=spf1 ip4:192.0.2.0 ip4:192.0.2.1 include:examplesender.email -all`
-#[derive(Debug, Clone)]
-pub struct DKIMRecord {
-    pub version: String,    // Always should be v=dkim1
-    pub public_key: String, // The public key
+/// Represents the hash algorithm of the signature, from the `a=` tag
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgo {
+    /// # SHA-1
+    ///
+    /// `rsa-sha1`, its signatures are a permanent error (RFC 8301 3.1), only used to compute body hashes
+    Sha1,
+    /// # SHA-256
+    ///
+    /// `rsa-sha256`
+    Sha256,
 }
 
-/// # DKIMRecord
-///
-/// DKIMRecord implementation
-impl DKIMRecord {
-    /// # new
+impl HashAlgo {
+    /// # Digest
     ///
-    /// Creates a new DKIMRecord
-    pub fn new(version: String, public_key: String) -> Self {
-        DKIMRecord {
-            version,
-            public_key,
+    /// Hash the data with the algorithm
+    fn digest(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlgo::Sha1 => Sha1::digest(data).to_vec(),
+            HashAlgo::Sha256 => Sha256::digest(data).to_vec(),
         }
     }
 
-    /// # from_string
+    /// # Message Digest
     ///
-    /// Parse a DNS DKIM record to a DKIMRecord struct
-    pub fn from_string(record: &str) -> Result<Self, Error> {
-        // Split the record by spaces
-        let record = record.split(";").collect::<Vec<&str>>();
-        // Remove trailing spaces
-        let record = record.iter().map(|s| s.trim()).collect::<Vec<&str>>();
-        // Check if the record has at least 2 elements
-        if record.len() < 2 {
-            return Err(Error::DKIMError("Invalid DKIM record".to_string()));
+    /// The OpenSSL digest used to verify the signature
+    fn message_digest(&self) -> MessageDigest {
+        match self {
+            HashAlgo::Sha1 => MessageDigest::sha1(),
+            HashAlgo::Sha256 => MessageDigest::sha256(),
         }
+    }
+}
 
-        // Check if the version is v=dkim1
-        if record[0] != "v=dkim1" && record[0] != "v=DKIM1" {
-            return Err(Error::DKIMError("Invalid DKIM version".to_string()));
+/// # DKIMVerification
+///
+/// Represents the result of verifying a DKIM signature (RFC 6376 Section 6.1)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DKIMVerification {
+    /// # Pass
+    ///
+    /// The signature is valid, the signing domain (`d=`) can be used for the DMARC alignment
+    Pass {
+        /// # Domain
+        ///
+        /// The signing domain
+        domain: String,
+        /// # Selector
+        ///
+        /// The selector of the public key
+        selector: String,
+    },
+    /// # Fail
+    ///
+    /// The body hash or the signature doesn't match, the message was modified or forged
+    Fail(String),
+    /// # Temporary Error
+    ///
+    /// The public key couldn't be retrieved, the verification may succeed later
+    TempError(String),
+    /// # Permanent Error
+    ///
+    /// The signature can't be verified, it's malformed, unsupported or its key doesn't exist
+    PermError(String),
+}
+
+impl From<&DKIMVerification> for AuthResult {
+    fn from(verification: &DKIMVerification) -> Self {
+        match verification {
+            DKIMVerification::Pass { .. } => AuthResult::Pass,
+            DKIMVerification::Fail(_) => AuthResult::Fail,
+            DKIMVerification::TempError(_) => AuthResult::TempError,
+            DKIMVerification::PermError(_) => AuthResult::PermError,
         }
+    }
+}
 
-        let mut version = String::new();
-        let mut public_key = String::new();
+/// # DKIMRecord
+///
+/// Represents the public key record published at `<selector>._domainkey.<domain>`
+/// Example `v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC...`
+#[derive(Debug, Clone)]
+pub struct DKIMRecord {
+    /// # Key Type
+    ///
+    /// The type of the key, `rsa` by default
+    pub key_type: String,
+    /// # Public Key
+    ///
+    /// The base64 public key, empty if it was revoked
+    pub public_key: String,
+}
 
-        for i in 0..record.len() {
-            let record = record[i];
-            if record.starts_with("v=") {
-                version = record[2..].to_string().to_lowercase();
-            } else if record.starts_with("p=") {
-                public_key = record[2..].to_string();
+impl DKIMRecord {
+    /// # from_string
+    ///
+    /// Parse a DNS DKIM record to a DKIMRecord struct
+    pub fn from_string(record: &str) -> Result<Self, Error> {
+        let tags = parse_tags(record)?;
+
+        // The version is optional, but if present it must be the first tag
+        if let Some(version) = tags.get("v") {
+            if version != "DKIM1" || !record.trim_start().starts_with('v') {
+                return Err(Error::DKIMError("Invalid DKIM record version".to_string(), None));
             }
         }
 
-        // Return the DKIM record
-        Ok(DKIMRecord::new(version, public_key))
+        let public_key = tags
+            .get("p")
+            .ok_or(Error::DKIMError("DKIM record without public key".to_string(), None))?;
+
+        Ok(DKIMRecord {
+            key_type: tags.get("k").cloned().unwrap_or("rsa".to_string()),
+            public_key: public_key.split_whitespace().collect(),
+        })
     }
 
     /// # get_dns_dkim_record
     ///
-    /// Get the DKIM record from the DNS
-    /// `remaining_redirects` is the number of redirects that the DNS resolver will follow
-    /// `dns_resolver` is the DNS resolver
-    /// `domain` is the domain to get the SPF record
+    /// Get the DKIM record of the signature from the DNS
     pub async fn get_dns_dkim_record(
         dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
-        dkim_header: DKIMHeader,
+        dkim_header: &DKIMHeader,
     ) -> Result<Self, Error> {
-        // Lock the DNS resolver
-        let dns_resolver_guarded = dns_resolver.lock().await;
-        // Get the DKIM record from the DNS
-        let txt_records = dns_resolver_guarded
-            .txt_lookup(format!("{}.", dkim_header.domain).as_str())
-            .await
-            .map_err(|_| Error::DNSError("Failed to get DKIM record".to_string()))?;
-
-        // Find the DKIM record for DKIM policy
-        let dkim_record = txt_records.iter().find(|record| {
-            record.to_string().starts_with("v=dkim1") || record.to_string().starts_with("v=DKIM1")
-        });
-
-        // Check if the DKIM record was found
-        /*let dkim_record = match dkim_record {
-            Some(record) => record.to_string(),
-            None => return Err(Error::SPFError("DKIM record not found".to_string())),
-        };*/
-
-        // test dkim record
-        let dkim_record = "v=DKIM1;t=s;p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQDiZDfLB7SBvT+I7uAiikct0qiJGXaFq5rL3qn8cX383VpNq0V7pCKlW3rpdPcHzG9LvV68kIvpdxZZDR+9z41JIFg79hA2FrHpZhCpyRKrpdJKR8nI0VXBHPWKWcVibvH45faDwNtQNwA7BvIkeMd48TzbXg3aOe1m1wuQOQ2UawIDAQAB".to_string();
-
-        // Parse the DKIM record
-        let parsed_dkim_record = match Self::from_string(dkim_record.as_str()) {
-            Ok(record) => record,
-            Err(e) => return Err(e),
-        };
-
-        // Return the DKIM record
-        Ok(parsed_dkim_record)
-    }
-}
+        let name = format!("{}._domainkey.{}.", dkim_header.selector, dkim_header.domain);
+        let dns_resolver = dns_resolver.lock().await;
+        let txt_records = dns_resolver.txt_lookup(name.as_str()).await.map_err(|err| {
+            Error::DNSError("Failed to get DKIM record".to_string(), Some(Box::new(err)))
+        })?;
 
-/// # dkim
-///
-/// Check if the email is valid with the DKIM record
-pub async fn dkim<B>(
-    conn: Arc<Mutex<SMTPConnection<B>>>,
-    dkim_header: String,
-    body: Vec<u8>,
-) -> Result<DKIMRecord, Error> {
-    let conn = conn.lock().await;
-    let dkim_header = DKIMHeader::from_string(dkim_header.as_str())?;
-    // Get the DKIM record from the DNS
-    let record =
-        DKIMRecord::get_dns_dkim_record(conn.dns_resolver.clone(), dkim_header.clone()).await?;
-    let pem_key = format_public_key(record.public_key.as_str());
-    let rsa = Rsa::public_key_from_pem(pem_key.as_bytes())
-        .map_err(|err| Error::DKIMError(err.to_string()))?;
-    let pkey = PKey::from_rsa(rsa).map_err(|err| Error::DKIMError(err.to_string()))?;
-
-    let alg = match dkim_header.algorithm.as_str() {
-        "rsa-sha1" => openssl::hash::MessageDigest::sha1(),
-        "rsa-sha256" => openssl::hash::MessageDigest::sha256(),
-        _ => return Err(Error::DKIMError("Invalid DKIM algorithm".to_string())),
-    };
+        // A record can be split in several strings
+        let record = txt_records
+            .iter()
+            .map(|txt| {
+                txt.txt_data()
+                    .iter()
+                    .map(|data| String::from_utf8_lossy(data))
+                    .collect::<String>()
+            })
+            .find(|record| record.contains("p="))
+            .ok_or(Error::DKIMError("DKIM record not found".to_string(), None))?;
 
-    let mut verifier =
-        Verifier::new(alg, &pkey).map_err(|e| Error::DKIMError(e.to_string()))?;
-    verifier
-        .set_rsa_padding(openssl::rsa::Padding::PKCS1)
-        .map_err(|e| Error::DKIMError(e.to_string()))?;
-
-    let clean_signature = dkim_header
-        .signature
-        .replace('\r', "")
-        .replace('\n', "")
-        .replace(' ', "");
-
-    // Decode the Base64 encoded signature
-    let mut signature_bytes = match BASE64_STANDARD.decode(clean_signature.as_bytes()) {
-        Ok(signature_bytes) => signature_bytes,
-        Err(e) => return Err(Error::DKIMError(e.to_string())),
-    };
-
-    // Verify the signature
-    verifier
-        .verify(&signature_bytes)
-        .map_err(|e| Error::DKIMError(e.to_string()))?;
+        Self::from_string(&record)
+    }
 
-    Ok(record)
-}
+    /// # Public Key
+    ///
+    /// Decode the public key, a SubjectPublicKeyInfo or a bare RSA public key
+    fn decode_public_key(&self) -> Result<PKey<Public>, Error> {
+        let der = BASE64_STANDARD
+            .decode(&self.public_key)
+            .map_err(|err| Error::DKIMError("Invalid DKIM public key".to_string(), Some(Box::new(err))))?;
 
-fn format_public_key(base64_key: &str) -> String {
-    let key = base64_key.replace("\n", "").replace("\r", "");
-    format!(
-        "-----BEGIN PUBLIC KEY-----\n{}\n-----END PUBLIC KEY-----",
-        key.chars()
-            .collect::<Vec<char>>()
-            .chunks(64)
-            .map(|chunk| chunk.iter().collect::<String>())
-            .collect::<Vec<String>>()
-            .join("\n")
-    )
+        PKey::public_key_from_der(&der)
+            .or_else(|_| Rsa::public_key_from_der_pkcs1(&der).and_then(PKey::from_rsa))
+            .map_err(|err| Error::DKIMError("Invalid DKIM public key".to_string(), Some(Box::new(err))))
+    }
 }
 
+/// # DKIMHeader
+///
+/// Represents a parsed DKIM-Signature header
 #[derive(Debug, Clone)]
 pub struct DKIMHeader {
+    /// # version
+    ///
+    /// Always should be 1
     pub version: String,
-    pub algorithm: String,
+    /// # algorithm
+    ///
+    /// The hash algorithm of the signature, only RSA signatures are supported
+    pub algorithm: HashAlgo,
+    /// # header_canonicalization
+    ///
+    /// The canonicalization of the signed headers
+    pub header_canonicalization: Canonicalization,
+    /// # body_canonicalization
+    ///
+    /// The canonicalization of the body
+    pub body_canonicalization: Canonicalization,
+    /// # domain
+    ///
+    /// The signing domain
     pub domain: String,
+    /// # selector
+    ///
+    /// The selector of the public key in the signing domain
     pub selector: String,
+    /// # headers
+    ///
+    /// The signed header names, in signing order
     pub headers: Vec<String>,
+    /// # body_hash
+    ///
+    /// The base64 hash of the canonicalized body
     pub body_hash: String,
+    /// # body_length
+    ///
+    /// The number of bytes of the canonicalized body that are signed, all of them if None
+    pub body_length: Option<usize>,
+    /// # expiration
+    ///
+    /// The UNIX time after which the signature isn't valid
+    pub expiration: Option<u64>,
+    /// # signature
+    ///
+    /// The base64 signature
     pub signature: String,
 }
 
 impl DKIMHeader {
+    /// # from_string
+    ///
+    /// Parse the value of a DKIM-Signature header
     pub fn from_string(header: &str) -> Result<Self, Error> {
-        // Split the record by spaces
-        let header = header.split(";").collect::<Vec<&str>>();
-        // Remove trailing spaces
-        let header = header.iter().map(|s| s.trim()).collect::<Vec<&str>>();
-        let mut version = String::new();
-        let mut algorithm = String::new();
-        let mut domain = String::new();
-        let mut selector = String::new();
-        let mut headers = Vec::new();
-        let mut body_hash = String::new();
-        let mut signature = String::new();
-
-        for i in 0..header.len() {
-            let record = header[i];
-            if record.starts_with("v=") {
-                version = record[2..].to_string();
-            } else if record.starts_with("a=") {
-                algorithm = record[2..].to_string();
-            } else if record.starts_with("d=") {
-                domain = record[2..].to_string();
-            } else if record.starts_with("s=") {
-                selector = record[2..].to_string();
-            } else if record.starts_with("h=") {
-                headers = record[2..].split(':').map(|s| s.to_string()).collect();
-            } else if record.starts_with("bh=") {
-                body_hash = record[3..].to_string();
-            } else if record.starts_with("b=") {
-                signature = record[2..].to_string();
-            }
+        let tags = parse_tags(header)?;
+        let required = |name: &str| {
+            tags.get(name)
+                .cloned()
+                .ok_or(Error::DKIMError(format!("Missing DKIM tag {}", name), None))
+        };
+
+        let version = required("v")?;
+        if version != "1" {
+            return Err(Error::DKIMError("Invalid DKIM version".to_string(), None));
         }
 
+        let algorithm = match required("a")?.to_lowercase().as_str() {
+            // SHA-1 signatures can be forged, they must not be considered valid (RFC 8301 3.1)
+            "rsa-sha1" => return Err(Error::DKIMError("rsa-sha1 signatures aren't accepted".to_string(), None)),
+            "rsa-sha256" => HashAlgo::Sha256,
+            _ => return Err(Error::DKIMError("Unsupported DKIM algorithm".to_string(), None)),
+        };
+
+        // `c=header/body`, the body defaults to simple and the tag to simple/simple
+        let canonicalization = tags.get("c").cloned().unwrap_or("simple".to_string());
+        let mut canonicalizations = canonicalization.splitn(2, '/');
+        let parse_canonicalization = |value: Option<&str>| match value.map(|value| value.to_lowercase()).as_deref() {
+            None | Some("simple") => Ok(Canonicalization::Simple),
+            Some("relaxed") => Ok(Canonicalization::Relaxed),
+            _ => Err(Error::DKIMError("Invalid DKIM canonicalization".to_string(), None)),
+        };
+        let header_canonicalization = parse_canonicalization(canonicalizations.next())?;
+        let body_canonicalization = parse_canonicalization(canonicalizations.next())?;
+
+        let headers = required("h")?
+            .split(':')
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .collect::<Vec<String>>();
+        if !headers.iter().any(|name| name.eq_ignore_ascii_case("From")) {
+            return Err(Error::DKIMError("The From header isn't signed".to_string(), None));
+        }
+
+        let parse_number = |name: &str| {
+            tags.get(name)
+                .map(|value| value.parse::<u64>())
+                .transpose()
+                .map_err(|err| Error::DKIMError(format!("Invalid DKIM tag {}", name), Some(Box::new(err))))
+        };
+
         Ok(DKIMHeader {
             version,
             algorithm,
-            domain,
-            selector,
+            header_canonicalization,
+            body_canonicalization,
+            domain: required("d")?.to_lowercase(),
+            selector: required("s")?,
             headers,
-            body_hash,
-            signature,
+            body_hash: required("bh")?.split_whitespace().collect(),
+            body_length: parse_number("l")?.map(|length| length as usize),
+            expiration: parse_number("x")?,
+            signature: required("b")?.split_whitespace().collect(),
         })
     }
+}
+
+/// # verify
+///
+/// Verify the DKIM signatures of the mail with the DNS resolver of the connection, see `verify_with_resolver`.
+/// The result is counted in the auth stats of the connection, an unsigned mail as `none`.
+pub async fn verify<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    mail: &Mail<Vec<u8>>,
+) -> Result<DKIMVerification, SMTPError> {
+    let (dns_resolver, auth_stats) = {
+        let conn = conn.lock().await;
+        (conn.dns_resolver.clone(), conn.auth_stats.clone())
+    };
+    let verification = verify_with_resolver(dns_resolver, mail).await;
+    match &verification {
+        Ok(verification) => auth_stats.dkim.record(verification.into()),
+        Err(_) => auth_stats.dkim.record(AuthResult::None),
+    }
+    verification
+}
+
+/// # verify_with_resolver
+///
+/// Verify the DKIM signatures of the mail, the first valid one passes, otherwise the result of the first signature is returned.
+/// It's an error if the mail isn't signed.
+///
+/// Only the `relaxed` header canonicalization can be verified, the mail doesn't keep the headers as received
+/// so the `simple` one is a permanent error. The body can use both.
+///
+/// As required by RFC 8301, `rsa-sha1` signatures and keys shorter than 1024 bits are permanent errors.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::mail::Mail;
+/// use neo_email::utilities::dkim::{verify_with_resolver, DKIMVerification};
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+/// use trust_dns_resolver::proto::rr::{rdata::TXT, RData, Record};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// const SIGNED_MAIL: &str = concat!(
+///     "DKIM-Signature: v=1; a=rsa-sha256; c=relaxed/relaxed; d=nervio.us; s=test;\r\n",
+///     "\th=from:to:subject; bh=cE2ZBeeKy6feocgYMrMOlDiZ92ejeWKmdLcpVyIubzc=;\r\n",
+///     "\tb=kvIiywC4GAgjy2B4OJsqtKU1H0IqjOdmE0J8r2LgyWBlblkRbal51m6NBNssA3CA\r\n",
+///     "\tX5ew9e15VdPQ5sltJKFMS003tjn0Rd/IAF3JFCvr3WN7vTtCIYPK5p5N7ezKv/aH\r\n",
+///     "\tRjNPSIesHsANJHJPCFrh15X31roAZ43jFy5YVHfodMk=\r\n",
+///     "From: Jean <jean@nervio.us>\r\n",
+///     "To: admin@nervio.us\r\n",
+///     "Subject: DKIM   test\r\n",
+///     "\tmessage\r\n",
+///     "Date: Sat, 17 Oct 2026 12:00:00 +0000\r\n",
+///     "\r\n",
+///     "Hello  World \r\n",
+///     "Second\tline\r\n",
+///     "\r\n",
+///     "\r\n",
+/// );
+///
+/// const PUBLIC_KEY: &str = concat!(
+///     "v=DKIM1; k=rsa; p=MIGfMA0GCSqGSIb3DQEBAQUAA4GNADCBiQKBgQC92lgcSv8+lxg/KZ+Mk2TIxd8e1sCtdGf6Lsd7vI0rrhjb+afuZTOH78",
+///     "Pxn6DKj4Qd6mPR0DO2sQcPZMFW6pyoMdfvbSDQZHqaX0rCuaV//WKLzA0halfg9B2p6/cVd6A3i0blRVJnSLNISDzozi5ReKVdeBpJC/T3VlJCcDeAgwIDAQAB",
+/// );
+///
+/// // A 512 bits key, too short to be trusted
+/// const SHORT_PUBLIC_KEY: &str = concat!(
+///     "v=DKIM1; k=rsa; p=MFwwDQYJKoZIhvcNAQEBBQADSwAwSAJBAKXWm4kbqu1WbnhmwuuOMRnr6CaFP5HpBVL5LEQpCYUiU68Dmlw5b2GBkhU9vfw9yhus",
+///     "MxKFrgHeBFD/9HDfgzMCAwEAAQ==",
+/// );
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server publishing the keys of the `test` and `short` selectors, failing for the `broken` one
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let name = request.queries()[0].name().clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .set_recursion_desired(true)
+///                 .set_recursion_available(true)
+///                 .add_queries(request.queries().to_vec());
+///             let key = match name.to_string().as_str() {
+///                 "test._domainkey.nervio.us." => Some(PUBLIC_KEY),
+///                 "short._domainkey.nervio.us." => Some(SHORT_PUBLIC_KEY),
+///                 _ => None,
+///             };
+///             match key {
+///                 Some(key) => {
+///                     let txt = TXT::new(vec![key.to_string()]);
+///                     response.add_answer(Record::from_rdata(name, 300, RData::TXT(txt)));
+///                 }
+///                 None if name.to_string().starts_with("broken.") => {
+///                     response.set_response_code(ResponseCode::ServFail);
+///                 }
+///                 None => {
+///                     response.set_response_code(ResponseCode::NXDomain);
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///     let verify = |raw: String| {
+///         let resolver = resolver.clone();
+///         async move {
+///             let mail = Mail::<Vec<u8>>::from_bytes(raw.into_bytes()).unwrap();
+///             verify_with_resolver(resolver, &mail).await
+///         }
+///     };
+///
+///     let result = verify(SIGNED_MAIL.to_string()).await.unwrap();
+///     assert_eq!(result, DKIMVerification::Pass { domain: "nervio.us".to_string(), selector: "test".to_string() });
+///
+///     // The whitespace changes of the relays are tolerated by relaxed/relaxed
+///     let rewritten = SIGNED_MAIL.replace("Subject: DKIM   test\r\n\tmessage", "Subject:  DKIM test message ");
+///     let rewritten = rewritten.replace("Hello  World \r\n", "Hello World\r\n");
+///     assert!(matches!(verify(rewritten).await.unwrap(), DKIMVerification::Pass { .. }));
+///
+///     // Modified body and modified header
+///     let result = verify(SIGNED_MAIL.replace("Hello", "Bye")).await.unwrap();
+///     assert_eq!(result, DKIMVerification::Fail("Body hash mismatch".to_string()));
+///     let result = verify(SIGNED_MAIL.replace("DKIM   test", "Win a prize")).await.unwrap();
+///     assert_eq!(result, DKIMVerification::Fail("Signature mismatch".to_string()));
+///
+///     // A selector without key
+///     let result = verify(SIGNED_MAIL.replace("s=test", "s=missing")).await.unwrap();
+///     assert!(matches!(result, DKIMVerification::PermError(_)));
+///
+///     // A failing DNS server is only a temporary error
+///     let result = verify(SIGNED_MAIL.replace("s=test", "s=broken")).await.unwrap();
+///     assert!(matches!(result, DKIMVerification::TempError(_)));
+///
+///     // Unsupported and malformed signatures
+///     let result = verify(SIGNED_MAIL.replace("c=relaxed/relaxed", "c=simple/relaxed")).await.unwrap();
+///     assert!(matches!(result, DKIMVerification::PermError(_)));
+///     let result = verify(SIGNED_MAIL.replace("a=rsa-sha256", "a=ed25519-sha256")).await.unwrap();
+///     assert!(matches!(result, DKIMVerification::PermError(_)));
+///
+///     // Weak signatures (RFC 8301)
+///     let result = verify(SIGNED_MAIL.replace("a=rsa-sha256", "a=rsa-sha1")).await.unwrap();
+///     assert!(matches!(result, DKIMVerification::PermError(err) if err.contains("rsa-sha1")));
+///     let result = verify(SIGNED_MAIL.replace("s=test", "s=short")).await.unwrap();
+///     assert_eq!(result, DKIMVerification::PermError("DKIM key shorter than 1024 bits".to_string()));
+///
+///     // Not signed
+///     let unsigned = &SIGNED_MAIL[SIGNED_MAIL.find("From:").unwrap()..];
+///     assert!(verify(unsigned.to_string()).await.is_err());
+/// }
+/// ```
+pub async fn verify_with_resolver(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    mail: &Mail<Vec<u8>>,
+) -> Result<DKIMVerification, SMTPError> {
+    let signatures = mail.get_all(&EmailHeaders::DKIMSignature);
+    if signatures.is_empty() {
+        return Err(Error::DKIMError("The mail isn't signed".to_string(), None));
+    }
+
+    let mut first_result = None;
+    for signature in signatures {
+        let result = verify_signature(dns_resolver.clone(), mail, signature).await;
+        if let DKIMVerification::Pass { .. } = result {
+            return Ok(result);
+        }
+        log::trace!("[🔏] DKIM signature not verified: {:?}", result);
+        first_result.get_or_insert(result);
+    }
+
+    Ok(first_result.unwrap_or(DKIMVerification::PermError("No DKIM signature".to_string())))
+}
+
+/// # verify_signature
+///
+/// Verify a single DKIM-Signature of the mail
+async fn verify_signature(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    mail: &Mail<Vec<u8>>,
+    signature_header: &str,
+) -> DKIMVerification {
+    let dkim_header = match DKIMHeader::from_string(signature_header) {
+        Ok(dkim_header) => dkim_header,
+        Err(err) => return DKIMVerification::PermError(err.to_string()),
+    };
+
+    if dkim_header.header_canonicalization == Canonicalization::Simple {
+        return DKIMVerification::PermError("Simple header canonicalization isn't supported".to_string());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default();
+    if dkim_header.expiration.is_some_and(|expiration| expiration < now) {
+        return DKIMVerification::Fail("Signature expired".to_string());
+    }
+
+    // The body hash is checked first, it doesn't need the DNS
+    let body_hash = compute_body_hash(
+        &mail.body,
+        dkim_header.body_canonicalization,
+        dkim_header.algorithm,
+        dkim_header.body_length,
+    );
+    if body_hash != dkim_header.body_hash {
+        return DKIMVerification::Fail("Body hash mismatch".to_string());
+    }
+
+    let record = match DKIMRecord::get_dns_dkim_record(dns_resolver, &dkim_header).await {
+        Ok(record) => record,
+        Err(Error::DNSError(err, Some(source))) => {
+            let not_found = source
+                .downcast_ref::<trust_dns_resolver::error::ResolveError>()
+                .is_some_and(|err| {
+                    // A SERVFAIL is also reported as no records, only NXDOMAIN and NOERROR mean the key doesn't exist
+                    matches!(
+                        err.kind(),
+                        ResolveErrorKind::NoRecordsFound {
+                            response_code: ResponseCode::NXDomain | ResponseCode::NoError,
+                            ..
+                        }
+                    )
+                });
+            return match not_found {
+                true => DKIMVerification::PermError("DKIM record not found".to_string()),
+                false => DKIMVerification::TempError(err),
+            };
+        }
+        Err(err) => return DKIMVerification::PermError(err.to_string()),
+    };
+
+    if !record.key_type.eq_ignore_ascii_case("rsa") {
+        return DKIMVerification::PermError("Unsupported DKIM key type".to_string());
+    }
+    if record.public_key.is_empty() {
+        return DKIMVerification::PermError("DKIM key revoked".to_string());
+    }
+
+    let public_key = match record.decode_public_key() {
+        Ok(public_key) => public_key,
+        Err(err) => return DKIMVerification::PermError(err.to_string()),
+    };
+    // Keys shorter than 1024 bits can be factored, their signatures are not valid (RFC 8301 3.2)
+    if public_key.bits() < 1024 {
+        return DKIMVerification::PermError("DKIM key shorter than 1024 bits".to_string());
+    }
+    let signature = match BASE64_STANDARD.decode(&dkim_header.signature) {
+        Ok(signature) => signature,
+        Err(_) => return DKIMVerification::PermError("Invalid DKIM signature encoding".to_string()),
+    };
+
+    let signed_data = signed_headers(mail, &dkim_header.headers, signature_header);
+    let verified = Verifier::new(dkim_header.algorithm.message_digest(), &public_key)
+        .and_then(|mut verifier| {
+            verifier.update(&signed_data)?;
+            verifier.verify(&signature)
+        })
+        .unwrap_or(false);
+
+    match verified {
+        true => DKIMVerification::Pass {
+            domain: dkim_header.domain,
+            selector: dkim_header.selector,
+        },
+        false => DKIMVerification::Fail("Signature mismatch".to_string()),
+    }
+}
+
+/// # parse_tags
+///
+/// Parse a `tag=value; tag=value` list, the values keep their inner whitespace
+fn parse_tags(value: &str) -> Result<HashMap<String, String>, Error> {
+    let mut tags = HashMap::new();
+    for tag in value.split(';').map(str::trim).filter(|tag| !tag.is_empty()) {
+        let (name, value) = tag
+            .split_once('=')
+            .ok_or(Error::DKIMError(format!("Invalid DKIM tag {}", tag), None))?;
+        tags.insert(name.trim().to_string(), value.trim().to_string());
+    }
+    Ok(tags)
+}
+
+/// # signed_headers
+///
+/// Build the data covered by the signature with the relaxed canonicalization: the signed headers in order,
+/// the last occurrence first when a name is repeated, and the DKIM-Signature itself without the `b=` value.
+fn signed_headers(mail: &Mail<Vec<u8>>, names: &[String], signature_header: &str) -> Vec<u8> {
+    let mut used: HashMap<String, usize> = HashMap::new();
+    let mut data = Vec::new();
+    for name in names {
+        let name = name.to_lowercase();
+        let values = mail
+            .headers
+            .iter()
+            .filter(|field| field.name.to_string().eq_ignore_ascii_case(&name))
+            .map(|field| &field.value)
+            .collect::<Vec<&String>>();
+
+        // A name signed more times than it appears signs its absence
+        let used = used.entry(name.clone()).or_default();
+        if let Some(value) = values.len().checked_sub(*used + 1).map(|index| &values[index]) {
+            data.extend_from_slice(relaxed_header(&name, value).as_bytes());
+            data.extend_from_slice(b"\r\n");
+        }
+        *used += 1;
+    }
+
+    let without_signature = signature_header
+        .split(';')
+        .map(|tag| match tag.split_once('=') {
+            Some((name, _)) if name.trim() == "b" => format!("{}=", name),
+            _ => tag.to_string(),
+        })
+        .collect::<Vec<String>>()
+        .join(";");
+    data.extend_from_slice(relaxed_header("dkim-signature", &without_signature).as_bytes());
+    data
+}
+
+/// # relaxed_header
+///
+/// Canonicalize a header with the relaxed algorithm (RFC 6376 Section 3.4.2), without the line break
+fn relaxed_header(name: &str, value: &str) -> String {
+    let value = value
+        .split([' ', '\t', '\r', '\n'])
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    format!("{}:{}", name.trim().to_lowercase(), value)
+}
+
+/// # compute_body_hash
+///
+/// Compute the base64 body hash (the `bh=` tag) of a body, limited to the first `length` bytes of the canonicalized body
+/// if given (the `l=` tag). Useful to find out why a signature fails with a body hash mismatch.
+///
+/// ## Example
+///
+/// The body of RFC 6376 Section 3.4.5, the trailing empty lines are ignored by both canonicalizations.
+///
+/// ```rust
+/// use neo_email::utilities::dkim::{compute_body_hash, Canonicalization, HashAlgo};
+///
+/// let body = b" C \r\nD \t E\r\n\r\n\r\n";
+///
+/// // Simple hashes " C \r\nD \t E\r\n"
+/// let bh = compute_body_hash(body, Canonicalization::Simple, HashAlgo::Sha256, None);
+/// assert_eq!(bh, "NOeivbQlDH9TmNKJUw7D53wZfsk8YMZ/hTuVVwTgi8s=");
+/// let bh = compute_body_hash(body, Canonicalization::Simple, HashAlgo::Sha1, None);
+/// assert_eq!(bh, "CSbuGGcoeYJFyw+cZO2DPFHmfCo=");
+///
+/// // Relaxed hashes " C\r\nD E\r\n"
+/// let bh = compute_body_hash(body, Canonicalization::Relaxed, HashAlgo::Sha256, None);
+/// assert_eq!(bh, "unak6JHq0wL+Q1HP7dW1tjBx9FLA6DffoZ0qrLwbbpo=");
+///
+/// // With l=5 only " C\r\nD" is hashed
+/// let bh = compute_body_hash(body, Canonicalization::Relaxed, HashAlgo::Sha256, Some(5));
+/// assert_eq!(bh, "lXbMuizzz61KKfSrf4wavTUrzVz/yI6aAWKyhRpIQiM=");
+///
+/// // An empty body is a single CRLF with simple and nothing with relaxed
+/// assert_eq!(
+///     compute_body_hash(b"", Canonicalization::Simple, HashAlgo::Sha256, None),
+///     "frcCV1k9oG9oKj3dpUqdJg1PxRT2RSN/XKdLCPjaYaY="
+/// );
+/// assert_eq!(
+///     compute_body_hash(b"\r\n", Canonicalization::Relaxed, HashAlgo::Sha256, None),
+///     "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="
+/// );
+/// ```
+pub fn compute_body_hash(
+    body: &[u8],
+    canonicalization: Canonicalization,
+    algorithm: HashAlgo,
+    length: Option<usize>,
+) -> String {
+    let body = canonicalize_body(body, canonicalization);
+    let length = length.unwrap_or(body.len()).min(body.len());
+    BASE64_STANDARD.encode(algorithm.digest(&body[..length]))
+}
+
+/// # canonicalize_body
+///
+/// Canonicalize the body (RFC 6376 Sections 3.4.3 and 3.4.4), the lines end with CRLF and the empty lines at the end are removed
+fn canonicalize_body(body: &[u8], canonicalization: Canonicalization) -> Vec<u8> {
+    let mut lines = body
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .collect::<Vec<&[u8]>>();
+    // The split leaves an empty segment after the last line ending
+    if body.ends_with(b"\n") {
+        lines.pop();
+    }
+
+    let mut lines = lines
+        .into_iter()
+        .map(|line| match canonicalization {
+            Canonicalization::Simple => line.to_vec(),
+            Canonicalization::Relaxed => {
+                let mut relaxed = Vec::with_capacity(line.len());
+                for word in line.split(|&b| b == b' ' || b == b'\t').filter(|word| !word.is_empty()) {
+                    if !relaxed.is_empty() || line.starts_with(b" ") || line.starts_with(b"\t") {
+                        relaxed.push(b' ');
+                    }
+                    relaxed.extend_from_slice(word);
+                }
+                relaxed
+            }
+        })
+        .collect::<Vec<Vec<u8>>>();
+
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+
+    let mut canonical = Vec::with_capacity(body.len());
+    for line in lines {
+        canonical.extend_from_slice(&line);
+        canonical.extend_from_slice(b"\r\n");
+    }
 
-    pub fn to_string(&self) -> String {
-        format!(
-            "v={}; a={}; d={}; s={}; h={}; bh={}; b={}",
-            self.version,
-            self.algorithm,
-            self.domain,
-            self.selector,
-            self.headers.join(":"),
-            self.body_hash,
-            self.signature
-        )
+    // An empty body is a single line break in the simple canonicalization
+    if canonical.is_empty() && canonicalization == Canonicalization::Simple {
+        canonical.extend_from_slice(b"\r\n");
     }
+    canonical
 }
-*/
\ No newline at end of file
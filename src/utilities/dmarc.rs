@@ -1,4 +1,7 @@
-use crate::{connection::SMTPConnection, errors::Error, mail::EmailAddress};
+use crate::{
+    connection::SMTPConnection, errors::Error, mail::EmailAddress,
+    utilities::psl::organizational_domain,
+};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use trust_dns_resolver::TokioAsyncResolver;
@@ -6,7 +9,7 @@ use trust_dns_resolver::TokioAsyncResolver;
 /// # DMARC Policy
 ///
 /// Represents the policy to apply in the DMARC record
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DMARCPolicy {
     /// # None
     /// 
@@ -25,7 +28,7 @@ pub enum DMARCPolicy {
 /// # DMARCDKIMAlignment
 /// 
 /// Represents the DKIM alignment
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DMARCDKIMAlignment {
     /// # Relaxed
     /// 
@@ -40,7 +43,7 @@ pub enum DMARCDKIMAlignment {
 /// # DMARCSPFAlignment
 /// 
 /// Represents the SPF alignment
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DMARCSPFAlignment {
     /// # Relaxed
     /// 
@@ -52,6 +55,41 @@ pub enum DMARCSPFAlignment {
     Strict,
 }
 
+/// # DMARCAlignmentMode
+/// 
+/// Represents how strict the comparison between the `From` header domain and an authenticated domain is (RFC 7489 Section 3.1)
+/// 
+/// Both `DMARCDKIMAlignment` and `DMARCSPFAlignment` can be converted into it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DMARCAlignmentMode {
+    /// # Relaxed
+    /// 
+    /// The organizational domains must match
+    Relaxed,
+    /// # Strict
+    /// 
+    /// The domains must match exactly
+    Strict,
+}
+
+impl From<DMARCDKIMAlignment> for DMARCAlignmentMode {
+    fn from(alignment: DMARCDKIMAlignment) -> Self {
+        match alignment {
+            DMARCDKIMAlignment::Relaxed => DMARCAlignmentMode::Relaxed,
+            DMARCDKIMAlignment::Strict => DMARCAlignmentMode::Strict,
+        }
+    }
+}
+
+impl From<DMARCSPFAlignment> for DMARCAlignmentMode {
+    fn from(alignment: DMARCSPFAlignment) -> Self {
+        match alignment {
+            DMARCSPFAlignment::Relaxed => DMARCAlignmentMode::Relaxed,
+            DMARCSPFAlignment::Strict => DMARCAlignmentMode::Strict,
+        }
+    }
+}
+
 /// # DMARCForensicReport
 /// 
 /// Represents the forensic report to send
@@ -78,7 +116,7 @@ pub enum DMARCForensicReport {
 /// # DMARCRecord
 ///
 /// Represents a DMARC record
-/// Example `v=dmarc1; p=none; rua=mailto:
+/// Example `v=DMARC1; p=none; rua=mailto:dmarc@nervio.us`
 #[derive(Debug, Clone)]
 pub struct DMARCRecord {
     /// # version
@@ -122,40 +160,48 @@ pub struct DMARCRecord {
     pub report_interval: Option<u32>, // The report interval
 }
 
-/// # DKIMRecord
+/// # DMARCRecord
 ///
-/// DKIMRecord implementation
+/// DMARCRecord implementation
 impl DMARCRecord {
     /// # new
     ///
-    /// Creates a new DMARCRecord
-    pub fn new(
-        version: String,
-        policy: DMARCPolicy,
-        aggregate_report_email: Option<EmailAddress>,
-        forensic_report_email: Option<EmailAddress>,
-        dkim_alignment: Option<DMARCDKIMAlignment>,
-        spf_alignment: Option<DMARCSPFAlignment>,
-        report_format: Option<String>,
-        percentage: Option<u8>,
-        report_interval: Option<u32>,
-    ) -> Self {
+    /// Creates a new DMARCRecord with the given policy, the optional tags are left unset
+    pub fn new(version: String, policy: DMARCPolicy) -> Self {
         DMARCRecord {
             version,
             policy,
-            aggregate_report_email,
-            forensic_report_email,
-            dkim_alignment,
-            spf_alignment,
-            report_format,
-            percentage,
-            report_interval,
+            aggregate_report_email: None,
+            forensic_report_email: None,
+            dkim_alignment: None,
+            spf_alignment: None,
+            report_format: None,
+            percentage: None,
+            report_interval: None,
         }
     }
 
     /// # from_string
     ///
     /// Parse a DNS DMARC record to a DMARCRecord struct
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::utilities::dmarc::{DMARCDKIMAlignment, DMARCPolicy, DMARCRecord, DMARCSPFAlignment};
+    ///
+    /// let record = DMARCRecord::from_string("v=DMARC1; p=reject; rua=mailto:x@y.com; adkim=s; aspf=r; pct=50").unwrap();
+    /// assert_eq!(record.version, "DMARC1");
+    /// assert_eq!(record.policy, DMARCPolicy::Reject);
+    /// assert_eq!(record.aggregate_report_email.unwrap().to_string(), "x@y.com");
+    /// assert!(record.forensic_report_email.is_none());
+    /// assert_eq!(record.dkim_alignment, Some(DMARCDKIMAlignment::Strict));
+    /// assert_eq!(record.spf_alignment, Some(DMARCSPFAlignment::Relaxed));
+    /// assert_eq!(record.percentage, Some(50));
+    ///
+    /// assert!(DMARCRecord::from_string("v=DMARC1; p=reject; pct=150").is_err());
+    /// assert!(DMARCRecord::from_string("v=DKIM1; p=reject").is_err());
+    /// ```
     pub fn from_string(record: &str) -> Result<Self, Error> {
         // Split the record by spaces
         let record = record.split(";").collect::<Vec<&str>>();
@@ -163,12 +209,12 @@ impl DMARCRecord {
         let record = record.iter().map(|s| s.trim()).collect::<Vec<&str>>();
         // Check if the record has at least 2 elements
         if record.len() < 2 {
-            return Err(Error::DKIMError("Invalid DMARC record".to_string()));
+            return Err(Error::DMARCError("Invalid DMARC record".to_string()));
         }
 
-        // Check if the version is v=dkim1
+        // Check if the version is v=DMARC1
         if record[0] != "v=dmarc1" && record[0] != "v=DMARC1" {
-            return Err(Error::DKIMError("Invalid DKIM version".to_string()));
+            return Err(Error::DMARCError("Invalid DMARC version".to_string()));
         }
 
         let mut version = String::new();
@@ -190,23 +236,21 @@ impl DMARCRecord {
                     "none" => DMARCPolicy::None,
                     "quarantine" => DMARCPolicy::Quarantine,
                     "reject" => DMARCPolicy::Reject,
-                    _ => return Err(Error::DKIMError("Invalid DMARC policy".to_string())),
+                    _ => return Err(Error::DMARCError("Invalid DMARC policy".to_string())),
                 };
             } else if record.starts_with("rua=") {
                 // Get the mailto:email part
                 let mailto = record.replace("rua=", "");
                 // Check if the email starts with mailto:
                 if !mailto.starts_with("mailto:") {
-                    return Err(Error::DKIMError(
-                        "Invalid DMARC aggregate report email".to_string(),
-                    ));
+                    return Err(Error::DMARCError("Invalid DMARC aggregate report email".to_string()));
                 }
 
                 // Get the email
                 let email = mailto.split(":").collect::<Vec<&str>>()[1];
                 // Check if the email is valid
                 let email = EmailAddress::from_string(email).map_err(|_| {
-                    Error::DKIMError("Invalid DMARC aggregate report email".to_string())
+                    Error::DMARCError("Invalid DMARC aggregate report email".to_string())
                 })?;
 
                 // Set the email
@@ -216,15 +260,13 @@ impl DMARCRecord {
                 let mailto = record.replace("ruf=", "");
                 // Check if the email starts with mailto:
                 if !mailto.starts_with("mailto:") {
-                    return Err(Error::DKIMError(
-                        "Invalid DMARC forensic report email".to_string(),
-                    ));
+                    return Err(Error::DMARCError("Invalid DMARC forensic report email".to_string()));
                 }
                 // Get the email
                 let email = mailto.split(":").collect::<Vec<&str>>()[1];
                 // Check if the email is valid
                 let email = EmailAddress::from_string(email).map_err(|_| {
-                    Error::DKIMError("Invalid DMARC aggregate report email".to_string())
+                    Error::DMARCError("Invalid DMARC forensic report email".to_string())
                 })?;
                 // Set the email
                 forensic_report_email = Some(email);
@@ -234,9 +276,7 @@ impl DMARCRecord {
                     "r" => Some(DMARCDKIMAlignment::Relaxed),
                     "s" => Some(DMARCDKIMAlignment::Strict),
                     _ => {
-                        return Err(Error::DKIMError(
-                            "Invalid DMARC DKIM alignment".to_string(),
-                        ))
+                        return Err(Error::DMARCError("Invalid DMARC DKIM alignment".to_string()))
                     }
                 };
             } else if record.starts_with("aspf=") {
@@ -245,9 +285,7 @@ impl DMARCRecord {
                     "r" => Some(DMARCSPFAlignment::Relaxed),
                     "s" => Some(DMARCSPFAlignment::Strict),
                     _ => {
-                        return Err(Error::DKIMError(
-                            "Invalid DMARC SPF alignment".to_string(),
-                        ))
+                        return Err(Error::DMARCError("Invalid DMARC SPF alignment".to_string()))
                     }
                 };
             } else if record.starts_with("rf=") {
@@ -257,20 +295,24 @@ impl DMARCRecord {
                     record
                         .replace("pct=", "")
                         .parse::<u8>()
-                        .map_err(|_| Error::DKIMError("Invalid DMARC percentage".to_string()))?,
+                        .ok()
+                        .filter(|percentage| *percentage <= 100)
+                        .ok_or(Error::DMARCError("Invalid DMARC percentage".to_string()))?,
                 );
             } else if record.starts_with("ri=") {
                 report_interval = Some(
                     record
                         .replace("ri=", "")
                         .parse::<u32>()
-                        .map_err(|_| Error::DKIMError("Invalid DMARC report interval".to_string()))?,
+                        .map_err(|_| {
+                            Error::DMARCError("Invalid DMARC report interval".to_string())
+                        })?,
                 );
             }
         }
 
-        // Return the DKIM record
-        Ok(DMARCRecord::new(
+        // Return the DMARC record
+        Ok(DMARCRecord {
             version,
             policy,
             aggregate_report_email,
@@ -280,7 +322,7 @@ impl DMARCRecord {
             report_format,
             percentage,
             report_interval,
-        ))
+        })
     }
 
     /// # get_dns_dmarc_record
@@ -296,7 +338,9 @@ impl DMARCRecord {
         let txt_records = dns_resolver_guarded
             .txt_lookup(format!("{}.", for_domain).as_str())
             .await
-            .map_err(|_| Error::DNSError("Failed to get DMARC record".to_string()))?;
+            .map_err(|err| {
+                Error::DNSError("Failed to get DMARC record".to_string(), Some(Box::new(err)))
+            })?;
 
         // Find the DMARC record for DMARC policy
         let dmarc_record = txt_records.iter().find(|record| {
@@ -304,7 +348,7 @@ impl DMARCRecord {
         });
 
         if dmarc_record.is_none() {
-            return Err(Error::DKIMError("DMARC record not found".to_string()));
+            return Err(Error::DMARCError("DMARC record not found".to_string()));
         }
 
         let dmarc_record = dmarc_record.unwrap().to_string();
@@ -335,4 +379,65 @@ pub async fn get_dmarc<B>(
     let conn = conn.lock().await;
     let record = DMARCRecord::get_dns_dmarc_record(conn.dns_resolver.clone(), for_domain).await?;
     Ok(record)
-}
\ No newline at end of file
+}
+
+/// # check_alignment
+///
+/// Check if the `From` header domain is aligned with an authenticated domain.
+///
+/// `header_domain` is the domain of the `From` header, see `Mail::from_domain`
+/// `authenticated_domain` is the SPF authenticated domain (the MAIL FROM domain) or the DKIM `d=` domain
+/// `mode` is the alignment mode, usually taken from `aspf=` or `adkim=` in the DMARC record
+///
+/// Identical domains are always aligned. In relaxed mode the organizational domains are compared, a suffix missing
+/// from the Public Suffix List follows its implicit `*` rule.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::dmarc::{check_alignment, DMARCAlignmentMode};
+///
+/// assert!(check_alignment("sub.example.com", "example.com", DMARCAlignmentMode::Relaxed));
+/// assert!(!check_alignment("sub.example.com", "example.com", DMARCAlignmentMode::Strict));
+/// assert!(check_alignment("Example.com", "example.com.", DMARCAlignmentMode::Strict));
+/// assert!(!check_alignment("bbc.co.uk", "evil.co.uk", DMARCAlignmentMode::Relaxed));
+///
+/// // Without organizational domain, like a public suffix or a single label, only the same domain is aligned
+/// assert!(check_alignment("localhost", "localhost", DMARCAlignmentMode::Relaxed));
+/// assert!(!check_alignment("localhost", "example.com", DMARCAlignmentMode::Relaxed));
+///
+/// // Unlisted suffixes, like internal names
+/// assert!(check_alignment("mail.corp.internal", "corp.internal", DMARCAlignmentMode::Relaxed));
+/// assert!(!check_alignment("corp.internal", "other.internal", DMARCAlignmentMode::Relaxed));
+/// ```
+pub fn check_alignment(
+    header_domain: &str,
+    authenticated_domain: &str,
+    mode: impl Into<DMARCAlignmentMode>,
+) -> bool {
+    let header_domain = header_domain.trim().trim_end_matches('.').to_lowercase();
+    let authenticated_domain = authenticated_domain
+        .trim()
+        .trim_end_matches('.')
+        .to_lowercase();
+
+    if header_domain.is_empty() || authenticated_domain.is_empty() {
+        return false;
+    }
+    if header_domain == authenticated_domain {
+        return true;
+    }
+
+    match mode.into() {
+        DMARCAlignmentMode::Strict => false,
+        DMARCAlignmentMode::Relaxed => {
+            match (
+                organizational_domain(&header_domain),
+                organizational_domain(&authenticated_domain),
+            ) {
+                (Some(header_org), Some(authenticated_org)) => header_org == authenticated_org,
+                _ => false,
+            }
+        }
+    }
+}
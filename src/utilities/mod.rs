@@ -1,8 +1,8 @@
 /// # DKIM
 /// 
-/// This module contains the DomainKeys Identified Mail. (Not implemented yet)
-//#[cfg(feature = "dkim-experimental")]
-//pub mod dkim;
+/// This module contains the DomainKeys Identified Mail verification.
+#[cfg(feature = "dkim-experimental")]
+pub mod dkim;
 
 /// # SPF
 /// 
@@ -14,4 +14,16 @@ pub mod spf;
 /// 
 /// This module contains the Domain-based Message Authentication, Reporting and Conformance.
 #[cfg(feature = "dmarc-experimental")]
-pub mod dmarc;
\ No newline at end of file
+pub mod dmarc;
+
+/// # PSL
+/// 
+/// This module contains the Public Suffix List helpers.
+#[cfg(feature = "psl-experimental")]
+pub mod psl;
+
+/// # ClamAV
+/// 
+/// This module contains the antivirus scanner trait and the clamd client.
+#[cfg(feature = "clamav-experimental")]
+pub mod clamav;
@@ -0,0 +1,57 @@
+use publicsuffix::{List, Psl};
+use std::sync::OnceLock;
+
+/// Embedded copy of the Public Suffix List (https://publicsuffix.org/list/).
+const PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+static LIST: OnceLock<List> = OnceLock::new();
+
+/// # Public Suffix List
+///
+/// Get the parsed Public Suffix List, it is parsed once on first use.
+fn list() -> &'static List {
+    LIST.get_or_init(|| {
+        log::trace!("[🌐] Parsing embedded Public Suffix List");
+        PUBLIC_SUFFIX_LIST
+            .parse()
+            .expect("The embedded Public Suffix List must be valid")
+    })
+}
+
+/// # organizational_domain
+///
+/// Get the organizational domain of a host, this is the public suffix plus one label
+/// (e.g. `www.bbc.co.uk` -> `bbc.co.uk`). Wildcard and exception rules of the list are honored.
+/// A suffix that isn't in the list gets the implicit `*` rule, its last label is the public suffix
+/// (e.g. `mail.corp.internal` -> `corp.internal`), as the PSL algorithm and DMARC (RFC 7489 3.2) require.
+///
+/// Returns `None` if the host is empty or is itself a public suffix.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::psl::organizational_domain;
+///
+/// assert_eq!(organizational_domain("www.bbc.co.uk"), Some("bbc.co.uk".to_string()));
+/// assert_eq!(organizational_domain("Mail.Example.COM."), Some("example.com".to_string()));
+///
+/// // `*.kawasaki.jp` is a wildcard rule but `!city.kawasaki.jp` is an exception
+/// assert_eq!(organizational_domain("www.city.kawasaki.jp"), Some("city.kawasaki.jp".to_string()));
+/// assert_eq!(organizational_domain("www.foo.kawasaki.jp"), Some("www.foo.kawasaki.jp".to_string()));
+///
+/// // Unlisted suffixes follow the default rule
+/// assert_eq!(organizational_domain("mail.corp.internal"), Some("corp.internal".to_string()));
+///
+/// assert_eq!(organizational_domain("co.uk"), None);
+/// assert_eq!(organizational_domain("internal"), None);
+/// ```
+pub fn organizational_domain(host: &str) -> Option<String> {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+    if host.is_empty() {
+        return None;
+    }
+
+    // The list applies the implicit `*` rule to the unknown suffixes
+    let domain = list().domain(host.as_bytes())?;
+    String::from_utf8(domain.as_bytes().to_vec()).ok()
+}
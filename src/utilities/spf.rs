@@ -1,7 +1,14 @@
-use crate::{connection::SMTPConnection, errors::Error};
-use std::{net::IpAddr, sync::Arc};
+use crate::{auth_stats::AuthResult, connection::SMTPConnection, errors::Error};
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Arc,
+};
 use tokio::sync::Mutex;
-use trust_dns_resolver::{proto::rr::RecordType, TokioAsyncResolver};
+use trust_dns_resolver::{
+    error::{ResolveError, ResolveErrorKind},
+    proto::{op::ResponseCode, rr::RecordType},
+    TokioAsyncResolver,
+};
 
 /// # SPFRecordAll
 ///
@@ -10,7 +17,8 @@ use trust_dns_resolver::{proto::rr::RecordType, TokioAsyncResolver};
 /// - Aggresive: -all means that all IPs that are not listed in the SPF record are not allowed to send emails
 /// - Passive: ~all means that all IPs that are not listed in the SPF record are allowed to send emails but marked as spam
 /// - Permissive: +all means that all IPs that are not listed in the SPF record are allowed to send emails
-#[derive(Debug, Clone)]
+/// - Neutral: ?all means that the domain doesn't say if the IPs that are not listed are allowed, also used if the record has no `all`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SPFRecordAll {
     /// -all means that all IPs that are not listed in the SPF record are not allowed to send emails
     Aggresive,
@@ -18,48 +26,312 @@ pub enum SPFRecordAll {
     Passive,
     /// +all means that all IPs that are not listed in the SPF record are allowed to send emails
     Permissive,
+    /// ?all means that nothing is said about the IPs that are not listed in the SPF record
+    Neutral,
+}
+
+impl From<SPFQualifier> for SPFRecordAll {
+    fn from(qualifier: SPFQualifier) -> Self {
+        match qualifier {
+            SPFQualifier::Pass => SPFRecordAll::Permissive,
+            SPFQualifier::Fail => SPFRecordAll::Aggresive,
+            SPFQualifier::SoftFail => SPFRecordAll::Passive,
+            SPFQualifier::Neutral => SPFRecordAll::Neutral,
+        }
+    }
+}
+
+/// # SPFQualifier
+///
+/// Represents the prefix of a mechanism, the result when the IP matches it (RFC 7208 4.6.2)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPFQualifier {
+    /// `+`, the default when there is no prefix
+    Pass,
+    /// `-`
+    Fail,
+    /// `~`
+    SoftFail,
+    /// `?`
+    Neutral,
+}
+
+impl SPFQualifier {
+    /// # from_term
+    ///
+    /// Split the qualifier of a mechanism, `-ip4:192.0.2.0/24` gives `Fail` and `ip4:192.0.2.0/24`
+    ///
+    /// ## Example
+    ///
+    /// ```rust
+    /// use neo_email::utilities::spf::SPFQualifier;
+    ///
+    /// assert_eq!(SPFQualifier::from_term("-ip4:192.0.2.0/24"), (SPFQualifier::Fail, "ip4:192.0.2.0/24"));
+    /// assert_eq!(SPFQualifier::from_term("~all"), (SPFQualifier::SoftFail, "all"));
+    /// assert_eq!(SPFQualifier::from_term("mx"), (SPFQualifier::Pass, "mx"));
+    /// ```
+    pub fn from_term(term: &str) -> (Self, &str) {
+        let qualifier = match term.chars().next() {
+            Some('+') => SPFQualifier::Pass,
+            Some('-') => SPFQualifier::Fail,
+            Some('~') => SPFQualifier::SoftFail,
+            Some('?') => SPFQualifier::Neutral,
+            _ => return (SPFQualifier::Pass, term),
+        };
+        (qualifier, &term[1..])
+    }
+
+    /// # result
+    ///
+    /// The result of the check when the IP matches a mechanism with this qualifier
+    pub fn result(&self) -> SPFResult {
+        match self {
+            SPFQualifier::Pass => SPFResult::Pass,
+            SPFQualifier::Fail => SPFResult::Fail,
+            SPFQualifier::SoftFail => SPFResult::SoftFail,
+            SPFQualifier::Neutral => SPFResult::Neutral,
+        }
+    }
+}
+
+/// # SPFResult
+///
+/// Represents the result of a SPF check (RFC 7208 2.6), it can be used to build the `Received-SPF` header
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::spf::SPFResult;
+///
+/// assert_eq!(format!("Received-SPF: {}", SPFResult::SoftFail), "Received-SPF: softfail");
+/// assert!(SPFResult::Pass.is_pass());
+/// assert!(!SPFResult::Neutral.is_pass());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SPFResult {
+    /// The IP is allowed to send emails on behalf of the domain
+    Pass,
+    /// The IP is not allowed to send emails on behalf of the domain (`-all`)
+    Fail,
+    /// The IP is probably not allowed, the email should be accepted but marked (`~all`)
+    SoftFail,
+    /// The domain doesn't say if the IP is allowed (`?all`)
+    Neutral,
+    /// The domain has no SPF record
+    None,
+    /// The SPF record couldn't be retrieved because of a DNS error, the check may succeed later
+    TempError,
+    /// The SPF record is malformed or exceeds the limits
+    PermError,
+}
+
+impl From<SPFResult> for AuthResult {
+    fn from(result: SPFResult) -> Self {
+        match result {
+            SPFResult::Pass => AuthResult::Pass,
+            SPFResult::Fail => AuthResult::Fail,
+            SPFResult::SoftFail => AuthResult::SoftFail,
+            SPFResult::Neutral => AuthResult::Neutral,
+            SPFResult::None => AuthResult::None,
+            SPFResult::TempError => AuthResult::TempError,
+            SPFResult::PermError => AuthResult::PermError,
+        }
+    }
+}
+
+impl SPFResult {
+    /// # is_pass
+    ///
+    /// Returns if the IP is allowed to send emails on behalf of the domain
+    pub fn is_pass(&self) -> bool {
+        *self == SPFResult::Pass
+    }
+
+    /// # from_error
+    ///
+    /// The result of an error found while evaluating a SPF record, DNS errors are temporary and the rest permanent
+    fn from_error(err: &Error) -> Self {
+        match err {
+            Error::DNSError(..) => SPFResult::TempError,
+            _ => SPFResult::PermError,
+        }
+    }
+}
+
+impl std::fmt::Display for SPFResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let result = match self {
+            SPFResult::Pass => "pass",
+            SPFResult::Fail => "fail",
+            SPFResult::SoftFail => "softfail",
+            SPFResult::Neutral => "neutral",
+            SPFResult::None => "none",
+            SPFResult::TempError => "temperror",
+            SPFResult::PermError => "permerror",
+        };
+        write!(f, "{}", result)
+    }
 }
 
 /// # SPFRecord
 ///
 /// Represents an SPF record
 /// Example of a raw TXT SPF Record `v=spf1 ip4:192.0.2.0 ip4:192.0.2.1 include:examplesender.email -all`
+///
+/// ## Example
+///
+/// The `a` and `mx` mechanisms allow the IPs of the domain and of its mail servers.
+/// The mechanisms are evaluated in the order of the record, and an `include` only matches when the included record passes.
+///
+/// ```rust
+/// use neo_email::utilities::spf::{sender_policy_framework_for_ip, SPFLimits, SPFMechanism, SPFQualifier, SPFRecord, SPFResult};
+/// use std::net::IpAddr;
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType};
+/// use trust_dns_resolver::proto::rr::rdata::{A, AAAA, MX, TXT};
+/// use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// fn answer(name: &str, record_type: RecordType) -> Option<RData> {
+///     let mx = |host: &str| RData::MX(MX::new(10, Name::from_ascii(host).unwrap()));
+///     match (name, record_type) {
+///         ("nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 a mx -all".to_string()]))),
+///         ("nervio.us.", RecordType::A) => Some(RData::A(A::new(192, 0, 2, 10))),
+///         ("nervio.us.", RecordType::MX) => Some(mx("mail.nervio.us.")),
+///         ("mail.nervio.us.", RecordType::A) => Some(RData::A(A::new(192, 0, 2, 20))),
+///         ("mail.nervio.us.", RecordType::AAAA) => Some(RData::AAAA(AAAA::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x20))),
+///         ("cidr.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 a:nervio.us/24 -all".to_string()]))),
+///         ("order.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 -a ip4:192.0.2.1 -all".to_string()]))),
+///         ("order.nervio.us.", RecordType::A) => Some(RData::A(A::new(192, 0, 2, 1))),
+///         ("deny.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 -ip4:192.0.2.1 ip4:192.0.2.0/24 -all".to_string()]))),
+///         ("include.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 include:deny.nervio.us ~all".to_string()]))),
+///         ("not-include.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 -include:deny.nervio.us ?all".to_string()]))),
+///         ("exists.nervio.us.", RecordType::TXT) => Some(RData::TXT(TXT::new(vec!["v=spf1 ~exists:nervio.us +all".to_string()]))),
+///         _ => None,
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     let record = SPFRecord::from_string("v=spf1 a a:nervio.us/24 mx:nervio.us -all").unwrap();
+///     assert_eq!(
+///         record.mechanisms,
+///         vec![
+///             (SPFQualifier::Pass, SPFMechanism::A("".to_string())),
+///             (SPFQualifier::Pass, SPFMechanism::A("nervio.us/24".to_string())),
+///             (SPFQualifier::Pass, SPFMechanism::Mx("nervio.us".to_string())),
+///         ]
+///     );
+///
+///     // A DNS server answering the records above
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let query = request.queries()[0].clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .add_queries(request.queries().to_vec());
+///             if let Some(rdata) = answer(&query.name().to_string(), query.query_type()) {
+///                 response.add_answer(Record::from_rdata(query.name().clone(), 300, rdata));
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///     let check = |ip: &str, domain: &'static str| {
+///         let ip: IpAddr = ip.parse().unwrap();
+///         sender_policy_framework_for_ip(resolver.clone(), ip, domain, SPFLimits::default())
+///     };
+///
+///     // The A record of the domain, and the A and AAAA records of its MX host
+///     let (result, _, pattern) = check("192.0.2.10", "nervio.us").await.unwrap();
+///     assert_eq!((result, pattern), (SPFResult::Pass, Some("192.0.2.10".to_string())));
+///     let (result, _, pattern) = check("192.0.2.20", "nervio.us").await.unwrap();
+///     assert_eq!((result, pattern), (SPFResult::Pass, Some("192.0.2.20".to_string())));
+///     assert_eq!(check("2001:db8::20", "nervio.us").await.unwrap().0, SPFResult::Pass);
+///     assert_eq!(check("192.0.2.30", "nervio.us").await.unwrap().0, SPFResult::Fail);
+///
+///     // With a CIDR suffix the whole network of the host is allowed
+///     let (result, _, pattern) = check("192.0.2.30", "cidr.nervio.us").await.unwrap();
+///     assert_eq!((result, pattern), (SPFResult::Pass, Some("192.0.2.10/24".to_string())));
+///
+///     // The first matching mechanism wins, `-a` is before `ip4`
+///     assert_eq!(check("192.0.2.1", "order.nervio.us").await.unwrap().0, SPFResult::Fail);
+///
+///     // The fail of an included record is not a match, the next mechanisms and `all` apply
+///     let (result, record, _) = check("192.0.2.1", "include.nervio.us").await.unwrap();
+///     assert_eq!(result, SPFResult::SoftFail);
+///     assert_eq!(record.unwrap().included.len(), 1);
+///     let (result, _, pattern) = check("192.0.2.2", "include.nervio.us").await.unwrap();
+///     assert_eq!((result, pattern), (SPFResult::Pass, Some("192.0.2.0/24".to_string())));
+///
+///     // The qualifier of `include` and `exists` gives the result when they match
+///     assert_eq!(check("192.0.2.2", "not-include.nervio.us").await.unwrap().0, SPFResult::Fail);
+///     assert_eq!(check("192.0.2.1", "not-include.nervio.us").await.unwrap().0, SPFResult::Neutral);
+///     assert_eq!(check("192.0.2.1", "exists.nervio.us").await.unwrap().0, SPFResult::SoftFail);
+///
+///     // Each mechanism is a DNS lookup
+///     let limits = SPFLimits { max_dns_lookups: 1, ..SPFLimits::default() };
+///     let ip: IpAddr = "192.0.2.30".parse().unwrap();
+///     let (result, _, _) = sender_policy_framework_for_ip(resolver.clone(), ip, "nervio.us", limits).await.unwrap();
+///     assert_eq!(result, SPFResult::PermError);
+/// }
+/// ```
 #[derive(Debug, Clone)]
 pub struct SPFRecord {
     /// # Version
     ///
     /// Always should be v=spf1
     pub version: String, // Always should be v=spf1
-    /// # IPv4
-    ///
-    /// List of allowed IPs
-    pub ipv4: Vec<String>, // List of allowed IPs
-    /// # IPv6
+    /// # Mechanisms
     ///
-    /// List of allowed IPs
-    ///
-    /// List of allowed IPs
-    pub ipv6: Vec<String>, // List of allowed IPs
+    /// The mechanisms before `all` with their qualifier, in the order of the record, the first one that matches gives the result (RFC 7208 4.6.2)
+    pub mechanisms: Vec<(SPFQualifier, SPFMechanism)>,
     /// # All
     ///
     /// Policy to apply
     pub all: SPFRecordAll, // Policy to apply
-    /// # Root Include
-    ///
-    /// List of to include SPF records (only contain the IP-Domains where the SPF record is located)
-    pub root_include: Vec<String>, // List of to include SPF records
     /// # Included
     ///
-    /// Included SPF records from other domains
+    /// The SPF records of the `include` mechanisms evaluated by the last check, in order
     pub included: Box<Vec<SPFRecord>>, // Included SPF records
     /// # Redirect
     ///
-    /// Set the SPF Policy on behalf of another domain
+    /// Set the SPF Policy on behalf of another domain when no mechanism matches, ignored in records with `all` (RFC 7208 6.1)
     pub redirect: Option<String>, // Redirect to another domain
-    /// # Exists
-    /// 
-    /// Check if the SPF record exists
-    pub exists: Option<String>,
+}
+
+/// # SPFMechanism
+///
+/// Represents a mechanism of a SPF record (RFC 7208 5), without its qualifier
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SPFMechanism {
+    /// `ip4:192.0.2.0/24`, an IP or CIDR range
+    Ip4(String),
+    /// `ip6:2001:db8::/32`, an IP or CIDR range
+    Ip6(String),
+    /// `a:nervio.us/24//64`, the domain whose A/AAAA records match with an optional CIDR suffix.
+    /// A bare `a` is the domain of the record, filled in when the record is fetched from the DNS
+    A(String),
+    /// `mx:nervio.us`, the domain whose MX hosts match, with the same format as `a`
+    Mx(String),
+    /// `include:_spf.nervio.us`, matches when the record of the domain passes
+    Include(String),
+    /// `exists:%{i}._spf.%{d}`, matches when the expanded domain has an A record
+    Exists(String),
 }
 
 /// # SPFRecord
@@ -71,23 +343,17 @@ impl SPFRecord {
     /// Creates a new SPFRecord
     pub fn new(
         version: String,
-        ipv4: Vec<String>,
-        ipv6: Vec<String>,
+        mechanisms: Vec<(SPFQualifier, SPFMechanism)>,
         all: SPFRecordAll,
-        root_include: Vec<String>,
         included: Box<Vec<SPFRecord>>,
         redirect: Option<String>,
-        exists: Option<String>,
     ) -> Self {
         SPFRecord {
             version,
-            ipv4,
-            ipv6,
+            mechanisms,
             all,
-            root_include,
             included,
             redirect,
-            exists,
         }
     }
 
@@ -101,59 +367,65 @@ impl SPFRecord {
         let spf_record = spf_record.split_whitespace().collect::<Vec<&str>>();
         // Check if the record is valid (have enough information)
         if spf_record.len() < 2 {
-            return Err(Error::SPFError("Invalid SPF record".to_string()));
+            return Err(Error::SPFError("Invalid SPF record".to_string(), None));
         }
 
         // Extract the version (should be v=spf1)
-        let version = spf_record[0].to_string().split("=").collect::<Vec<&str>>()[1].to_string();
+        let version = spf_record[0].split("=").collect::<Vec<&str>>()[1].to_string();
         if version != "spf1" {
-            return Err(Error::SPFError("Invalid SPF version".to_string()));
+            return Err(Error::SPFError("Invalid SPF version".to_string(), None));
         }
 
-        let mut ip4 = Vec::new();
-        let mut ip6 = Vec::new();
-        let mut all = SPFRecordAll::Passive;
-        let mut include = Vec::new();
+        let mut mechanisms = Vec::new();
+        // Without `all` the result of a non matching IP is neutral (RFC 7208 4.7)
+        let mut all = None;
         let mut redirect = None;
-        let mut exists = None;
 
         // Iterate over the record to find parts
-        for i in 1..spf_record.len() {
-            // Get the record part
-            let record = spf_record[i];
+        for record in spf_record.iter().skip(1) {
             // Convert the record to lowercase
             let record = record.to_lowercase();
+            let (qualifier, record) = SPFQualifier::from_term(&record);
 
             // Check the record
-            if record.starts_with("ip4:") {
-                ip4.push(record.replace("ip4:", ""));
-            } else if record.starts_with("ip6:") {
-                ip6.push(record.replace("ip6:", ""));
-            } else if record.starts_with("-all") {
-                all = SPFRecordAll::Aggresive;
-            } else if record.starts_with("~all") {
-                all = SPFRecordAll::Passive;
-            } else if record.starts_with("+all") {
-                all = SPFRecordAll::Permissive;
-            } else if record.starts_with("include:") {
-                include.push(record.replace("include:", ""));
-            } else if record.starts_with("redirect=") {
-                redirect = Some(record.replace("redirect=", ""));
-            } else if record.starts_with("exists:") {
-                exists = Some(record.replace("exists:", ""));
+            let mechanism = if let Some(ip) = record.strip_prefix("ip4:") {
+                SPFMechanism::Ip4(ip.to_string())
+            } else if let Some(ip) = record.strip_prefix("ip6:") {
+                SPFMechanism::Ip6(ip.to_string())
+            } else if record == "all" {
+                all = all.or(Some(qualifier.into()));
+                continue;
+            } else if let Some(domain) = record.strip_prefix("include:") {
+                SPFMechanism::Include(domain.to_string())
+            } else if let Some(domain) = record.strip_prefix("redirect=") {
+                redirect = Some(domain.to_string());
+                continue;
+            } else if let Some(domain) = record.strip_prefix("exists:") {
+                SPFMechanism::Exists(domain.to_string())
+            } else if let Some(domain_spec) = mechanism_domain_spec(record, "a") {
+                SPFMechanism::A(domain_spec)
+            } else if let Some(domain_spec) = mechanism_domain_spec(record, "mx") {
+                SPFMechanism::Mx(domain_spec)
+            } else {
+                continue;
+            };
+
+            // `all` always matches, the mechanisms after it are never reached
+            if all.is_none() {
+                mechanisms.push((qualifier, mechanism));
             }
         }
 
+        // `all` always matches, so the redirect is never applied (RFC 7208 6.1)
+        let redirect = if all.is_some() { None } else { redirect };
+
         // Return the SPFRecord
         Ok(SPFRecord::new(
             version,
-            ip4,
-            ip6,
-            all,
-            include,
-            Box::new(vec![]),
+            mechanisms,
+            all.unwrap_or(SPFRecordAll::Neutral),
+            Box::default(),
             redirect,
-            exists,
         ))
     }
 
@@ -170,49 +442,359 @@ impl SPFRecord {
     ) -> Result<Self, Error> {
         // Check if the number of remaining redirects is 0, and return an error
         if remaining_redirects == 0 {
-            return Err(Error::DNSError("Max redirects reached".to_string()));
+            return Err(Error::DNSError("Max redirects reached".to_string(), None));
         }
 
+        let parsed_spf_record = Self::fetch(dns_resolver.clone(), domain)
+            .await?
+            .ok_or(Error::SPFError("SPF record not found".to_string(), None))?;
+
+        // Some SMTP can delegate its SPF to another domain, for example gmail.com delegated to _spf.google.com
+        if let Some(redirect) = parsed_spf_record.redirect {
+            // Box the future
+            return Box::pin(Self::get_dns_spf_record(
+                remaining_redirects - 1,
+                dns_resolver.clone(),
+                redirect.as_str(),
+            ))
+            .await;
+        }
+
+        // Return the SPF record
+        Ok(parsed_spf_record)
+    }
+
+    /// # fetch
+    ///
+    /// Get the SPF record of the domain from the DNS, without following its redirect, None if the domain has no SPF record
+    async fn fetch(dns_resolver: Arc<Mutex<TokioAsyncResolver>>, domain: &str) -> Result<Option<Self>, Error> {
         // Lock the DNS resolver
         let dns_resolver_guarded = dns_resolver.lock().await;
         // Get the SPF record from the DNS
-        let spf_record = dns_resolver_guarded
-            .txt_lookup(format!("{}.", domain).as_str())
-            .await
-            .map_err(|_| Error::DNSError("Failed to get SPF record".to_string()))?;
+        let spf_record = match dns_resolver_guarded.txt_lookup(format!("{}.", domain).as_str()).await {
+            Ok(records) => records,
+            // The domain doesn't exist or doesn't have TXT records
+            Err(err) if is_missing(&err) => return Ok(None),
+            Err(err) => {
+                return Err(Error::DNSError(
+                    "Failed to get SPF record".to_string(),
+                    Some(Box::new(err)),
+                ))
+            }
+        };
 
         // Find the SPF record for SPF policy
         let spf_record = spf_record
             .iter()
             .find(|record| record.to_string().starts_with("v=spf1"));
 
-        // Check if the SPF record was found
-        let spf_record = match spf_record {
-            Some(record) => record.to_string(),
-            None => return Err(Error::SPFError("SPF record not found".to_string())),
+        // Parse the SPF record if it was found
+        let mut record = match spf_record {
+            Some(record) => Self::from_string(record.to_string().as_str())?,
+            None => return Ok(None),
         };
 
-        // Parse the SPF record
-        let parsed_spf_record = match Self::from_string(spf_record.as_str()) {
-            Ok(record) => record,
-            Err(e) => return Err(e),
+        // The bare `a` and `mx` mechanisms refer to the domain of the record
+        for (_, mechanism) in record.mechanisms.iter_mut() {
+            let (SPFMechanism::A(domain_spec) | SPFMechanism::Mx(domain_spec)) = mechanism else {
+                continue;
+            };
+            if domain_spec.is_empty() || domain_spec.starts_with('/') {
+                domain_spec.insert_str(0, domain);
+            }
+        }
+        Ok(Some(record))
+    }
+}
+
+/// # SPFMacroContext
+///
+/// The values the macros of `include:`, `exists:` and `redirect=` expand to (RFC 7208 7), like `exists:%{i}._spf.%{d}`
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::spf::SPFMacroContext;
+///
+/// let context = SPFMacroContext::new(
+///     "192.0.2.3".parse().unwrap(),
+///     Some("strong-bad@email.example.com"),
+///     "email.example.com",
+///     Some("mx.example.org"),
+/// );
+///
+/// assert_eq!(context.expand("%{ir}.%{v}._spf.example.com").unwrap(), "3.2.0.192.in-addr._spf.example.com");
+/// assert_eq!(context.expand("%{l}.%{o}").unwrap(), "strong-bad.email.example.com");
+/// assert_eq!(context.expand("%{d2}").unwrap(), "example.com");
+/// assert_eq!(context.expand("%{lr-}.%{h}").unwrap(), "bad.strong.mx.example.org");
+/// assert_eq!(context.expand("%{s}%%").unwrap(), "strong-bad@email.example.com%");
+///
+/// // IPv6 addresses expand to nibbles
+/// let context = SPFMacroContext::new("2001:db8::cb01".parse().unwrap(), None, "example.com", None);
+/// assert_eq!(
+///     context.expand("%{i}.%{v}").unwrap(),
+///     "2.0.0.1.0.d.b.8.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.c.b.0.1.ip6"
+/// );
+/// // Without sender, the postmaster of the HELO identity or the domain
+/// assert_eq!(context.expand("%{s}").unwrap(), "postmaster@example.com");
+///
+/// // Unsupported and malformed macros
+/// assert!(context.expand("%{p}").is_err());
+/// assert!(context.expand("%{d0}").is_err());
+/// assert!(context.expand("%{d").is_err());
+/// assert!(context.expand("%x").is_err());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SPFMacroContext {
+    /// # IP
+    ///
+    /// The IP of the client, `%{i}` and `%{v}`
+    pub ip: IpAddr,
+    /// # Sender
+    ///
+    /// The MAIL FROM address, `%{s}`, `%{l}` for its local part and `%{o}` for its domain
+    pub sender: String,
+    /// # Domain
+    ///
+    /// The domain whose record is evaluated, `%{d}`
+    pub domain: String,
+    /// # HELO
+    ///
+    /// The HELO/EHLO identity of the client, `%{h}`
+    pub helo: String,
+}
+
+impl SPFMacroContext {
+    /// # new
+    ///
+    /// Create the context of a check, a missing sender is the postmaster of the HELO identity (RFC 7208 2.4),
+    /// and a missing HELO identity is the checked domain
+    pub fn new(ip: IpAddr, sender: Option<&str>, domain: &str, helo: Option<&str>) -> Self {
+        let helo = helo.filter(|helo| !helo.is_empty()).unwrap_or(domain).to_string();
+        let sender = match sender.filter(|sender| !sender.is_empty()) {
+            Some(sender) if sender.contains('@') => sender.to_string(),
+            Some(sender) => format!("postmaster@{}", sender),
+            None => format!("postmaster@{}", helo),
         };
+        SPFMacroContext {
+            ip,
+            sender,
+            domain: domain.to_string(),
+            helo,
+        }
+    }
 
-        // Some SMTP can delegate its SPF to another domain, for example gmail.com delegated to _spf.google.com
-        if let Some(redirect) = parsed_spf_record.redirect {
-            // Drop the DNS resolver for the next iteration
-            drop(dns_resolver_guarded);
-            // Box the future
-            return Box::pin(Self::get_dns_spf_record(
-                remaining_redirects - 1,
-                dns_resolver.clone(),
-                redirect.as_str(),
-            ))
-            .await;
+    /// # expand
+    ///
+    /// Expand the macros of a domain spec, an unsupported or malformed macro is a permerror
+    pub fn expand(&self, domain_spec: &str) -> Result<String, Error> {
+        let malformed = || Error::SPFError(format!("Malformed macro in {} (permerror)", domain_spec), None);
+
+        let mut expanded = String::with_capacity(domain_spec.len());
+        let mut chars = domain_spec.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                expanded.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('%') => expanded.push('%'),
+                Some('_') => expanded.push(' '),
+                Some('-') => expanded.push_str("%20"),
+                Some('{') => {
+                    let mut spec = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => spec.push(c),
+                            None => return Err(malformed()),
+                        }
+                    }
+                    expanded.push_str(&self.expand_macro(&spec).ok_or_else(malformed)?);
+                }
+                _ => return Err(malformed()),
+            }
         }
 
-        // Return the SPF record
-        Ok(parsed_spf_record)
+        Ok(expanded)
+    }
+
+    /// # expand_macro
+    ///
+    /// Expand the content of `%{...}`, a letter followed by the number of labels to keep, `r` to reverse them and the delimiters
+    fn expand_macro(&self, spec: &str) -> Option<String> {
+        let letter = spec.chars().next()?;
+        let transformers = &spec[letter.len_utf8()..];
+        let digits = transformers.len() - transformers.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let (keep, transformers) = transformers.split_at(digits);
+        let (reverse, delimiters) = match transformers.strip_prefix(['r', 'R']) {
+            Some(delimiters) => (true, delimiters),
+            None => (false, transformers),
+        };
+        if !delimiters.chars().all(|c| ".-+,/_=".contains(c)) {
+            return None;
+        }
+
+        let value = match letter.to_ascii_lowercase() {
+            's' => self.sender.clone(),
+            'l' => self.sender.rsplit_once('@')?.0.to_string(),
+            'o' => self.sender.rsplit_once('@')?.1.to_string(),
+            'd' => self.domain.clone(),
+            'h' => self.helo.clone(),
+            'i' => match self.ip {
+                IpAddr::V4(ip) => ip.to_string(),
+                IpAddr::V6(ip) => ip
+                    .octets()
+                    .iter()
+                    .map(|octet| format!("{:x}.{:x}", octet >> 4, octet & 0xf))
+                    .collect::<Vec<String>>()
+                    .join("."),
+            },
+            'v' => match self.ip {
+                IpAddr::V4(_) => "in-addr".to_string(),
+                IpAddr::V6(_) => "ip6".to_string(),
+            },
+            // `p` needs a validated PTR lookup, and `c`, `r`, `t` are only allowed in explanations
+            _ => return None,
+        };
+
+        let delimiters = if delimiters.is_empty() { "." } else { delimiters };
+        let mut labels = value.split(|c| delimiters.contains(c)).collect::<Vec<&str>>();
+        if reverse {
+            labels.reverse();
+        }
+        if !keep.is_empty() {
+            let keep = keep.parse::<usize>().ok().filter(|keep| *keep > 0)?;
+            labels = labels.split_off(labels.len().saturating_sub(keep));
+        }
+        let value = labels.join(".");
+
+        // An uppercase letter URL escapes the value
+        if letter.is_ascii_uppercase() {
+            return Some(
+                value
+                    .bytes()
+                    .map(|byte| match byte {
+                        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (byte as char).to_string(),
+                        _ => format!("%{:02X}", byte),
+                    })
+                    .collect(),
+            );
+        }
+        Some(value)
+    }
+}
+
+/// # SPFLimits
+///
+/// The limits applied while evaluating a SPF record, exceeding any of them is a permerror (RFC 7208 4.6.4)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SPFLimits {
+    /// # Max Redirects
+    ///
+    /// The maximum number of `redirect=` modifiers followed
+    pub max_redirects: u8,
+    /// # Max Includes
+    ///
+    /// The maximum number of `include:` mechanisms in a record
+    pub max_includes: u8,
+    /// # Max DNS Lookups
+    ///
+    /// The maximum number of DNS lookups caused by `include:`, `exists:` and `redirect=`, the lookup of the first record is not counted
+    pub max_dns_lookups: u8,
+}
+
+impl Default for SPFLimits {
+    fn default() -> Self {
+        SPFLimits {
+            max_redirects: 10,
+            max_includes: 10,
+            max_dns_lookups: 10,
+        }
+    }
+}
+
+impl SPFLimits {
+    /// # count_dns_lookup
+    ///
+    /// Count a DNS lookup, failing when the budget is exhausted
+    fn count_dns_lookup(&self, lookups: &mut u8) -> Result<(), Error> {
+        *lookups = lookups.saturating_add(1);
+        if *lookups > self.max_dns_lookups {
+            return Err(Error::SPFError(
+                "Too many DNS lookups (permerror)".to_string(),
+                None,
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// # prewarm_spf_records
+///
+/// Fetch the SPF records of the given domains, so they are cached by the DNS resolver before the first message of these senders.
+/// Failures are logged and ignored, a domain that can't be resolved will be looked up again on use.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::spf::{prewarm_spf_records, SPFMechanism, SPFQualifier, SPFRecord};
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType};
+/// use trust_dns_resolver::proto::rr::{rdata::TXT, RData, Record};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering every query with a SPF record, counting the queries
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     let queries = Arc::new(AtomicUsize::new(0));
+///     let counter = queries.clone();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             counter.fetch_add(1, Ordering::SeqCst);
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let name = request.queries()[0].name().clone();
+///             let txt = TXT::new(vec!["v=spf1 ip4:192.0.2.1 -all".to_string()]);
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .set_recursion_desired(true)
+///                 .set_recursion_available(true)
+///                 .add_queries(request.queries().to_vec())
+///                 .add_answer(Record::from_rdata(name, 300, RData::TXT(txt)));
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     prewarm_spf_records(resolver.clone(), &["nervio.us".to_string()]).await;
+///     assert_eq!(queries.load(Ordering::SeqCst), 1);
+///
+///     // The first use is served from the cache
+///     let record = SPFRecord::get_dns_spf_record(10, resolver, "nervio.us").await.unwrap();
+///     assert_eq!(record.mechanisms, vec![(SPFQualifier::Pass, SPFMechanism::Ip4("192.0.2.1".to_string()))]);
+///     assert_eq!(queries.load(Ordering::SeqCst), 1);
+/// }
+/// ```
+pub async fn prewarm_spf_records(dns_resolver: Arc<Mutex<TokioAsyncResolver>>, domains: &[String]) {
+    for domain in domains {
+        match SPFRecord::get_dns_spf_record(10, dns_resolver.clone(), domain).await {
+            Ok(_) => log::trace!("[🌐] SPF record of {} prewarmed", domain),
+            Err(err) => log::warn!("[🌐] Failed to prewarm SPF record of {}: {}", domain, err),
+        }
     }
 }
 
@@ -222,132 +804,714 @@ impl SPFRecord {
 /// 
 /// `conn` is the SMTP connection
 /// `domain` is the domain to check the SPF record
-/// `policy` is the policy to apply
-/// `max_depth_redirect` is the maximum depth of redirects that the SPF record can have
+/// `max_depth_redirect` is the maximum depth of redirects that the SPF record can have (the record itself counts as one)
 /// `max_include` is the maximum number of included SPF records
 ///
-/// Returns a tuple with the result of the SPF check, the SPF record and the matched allowed IP pattern
+/// The DNS lookups budget is the one of the connection, use `check_spf` to apply the limits configured in the server
+///
+/// Returns a tuple with the result of the SPF check, the SPF record (None if it couldn't be retrieved) and the matched allowed IP pattern,
+/// it's only an error if the IP of the connection is unknown
 pub async fn sender_policy_framework<B>(
     conn: Arc<Mutex<SMTPConnection<B>>>,
     domain: &str,
-    policy: SPFRecordAll,
     max_depth_redirect: u8,
     max_include: u8,
-) -> Result<(bool, SPFRecord, Option<String>), Error> {
+) -> Result<(SPFResult, Option<SPFRecord>, Option<String>), Error> {
+    let limits = SPFLimits {
+        max_redirects: max_depth_redirect.saturating_sub(1),
+        max_includes: max_include,
+        max_dns_lookups: conn.lock().await.spf_limits.max_dns_lookups,
+    };
+    sender_policy_framework_with_limits(conn, domain, limits).await
+}
+
+/// # check_spf
+///
+/// Check if the sender is allowed to send emails on behalf of the domain, with the SPF limits of the connection (set with `SMTPServer::set_spf_limits`)
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::utilities::spf::{check_spf, SPFLimits, SPFResult};
+/// use std::sync::Arc;
+/// use tokio::io::BufStream;
+/// use tokio::net::{TcpListener, TcpStream, UdpSocket};
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+/// use trust_dns_resolver::proto::rr::{rdata::TXT, RData, Record};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// fn spf_record(name: &str) -> Option<&'static str> {
+///     match name {
+///         "allowed.test." => Some("v=spf1 include:a.test include:b.test include:c.test -all"),
+///         "a.test." => Some("v=spf1 ip4:192.0.2.1 -all"),
+///         "b.test." => Some("v=spf1 ip4:192.0.2.2 -all"),
+///         "c.test." => Some("v=spf1 ip4:127.0.0.1 -all"),
+///         "redirect.test." => Some("v=spf1 redirect=redirect1.test"),
+///         "redirect1.test." => Some("v=spf1 redirect=redirect2.test"),
+///         "redirect2.test." => Some("v=spf1 ip4:127.0.0.1 -all"),
+///         _ => None,
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering the SPF records above
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let name = request.queries()[0].name().clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .set_recursion_desired(true)
+///                 .set_recursion_available(true)
+///                 .add_queries(request.queries().to_vec());
+///             match spf_record(&name.to_string()) {
+///                 Some(spf) => {
+///                     let txt = TXT::new(vec![spf.to_string()]);
+///                     response.add_answer(Record::from_rdata(name, 300, RData::TXT(txt)));
+///                 }
+///                 None => {
+///                     response.set_response_code(ResponseCode::NXDomain);
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     // A connection from 127.0.0.1
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+///     let (socket, _) = listener.accept().await.unwrap();
+///     let conn = Arc::new(Mutex::new(SMTPConnection::<()> {
+///         tcp_buff_socket: Some(Arc::new(Mutex::new(BufStream::new(socket)))),
+///         ..SMTPConnection::new(resolver)
+///     }));
+///
+///     let check = |limits: SPFLimits, domain: &'static str| {
+///         let conn = conn.clone();
+///         async move {
+///             conn.lock().await.spf_limits = limits;
+///             check_spf(conn, domain).await.unwrap()
+///         }
+///     };
+///
+///     // Within the default limits
+///     let (result, record, pattern) = check(SPFLimits::default(), "allowed.test").await;
+///     assert_eq!(result, SPFResult::Pass);
+///     assert_eq!(record.unwrap().included.len(), 3);
+///     assert_eq!(pattern, Some("127.0.0.1".to_string()));
+///     assert_eq!(check(SPFLimits::default(), "redirect.test").await.0, SPFResult::Pass);
+///
+///     // Each limit exceeded
+///     let limits = SPFLimits { max_includes: 2, ..SPFLimits::default() };
+///     assert_eq!(check(limits, "allowed.test").await.0, SPFResult::PermError);
+///
+///     let limits = SPFLimits { max_dns_lookups: 2, ..SPFLimits::default() };
+///     assert_eq!(check(limits, "allowed.test").await.0, SPFResult::PermError);
+///
+///     let limits = SPFLimits { max_redirects: 1, ..SPFLimits::default() };
+///     let (result, record, _) = check(limits, "redirect.test").await;
+///     assert_eq!(result, SPFResult::PermError);
+///     // The record is the one of the checked domain, the redirects are followed while evaluating it
+///     assert_eq!(record.unwrap().redirect, Some("redirect1.test".to_string()));
+/// }
+/// ```
+pub async fn check_spf<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    domain: &str,
+) -> Result<(SPFResult, Option<SPFRecord>, Option<String>), Error> {
+    let limits = conn.lock().await.spf_limits;
+    sender_policy_framework_with_limits(conn, domain, limits).await
+}
+
+/// # sender_policy_framework_with_limits
+///
+/// Check if the sender is allowed to send emails on behalf of the domain within the given limits.
+/// The result is counted in the auth stats of the connection, shared by the connections of a server (`ServerHandle::auth_stats`).
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::auth_stats::{AuthResult, AuthStats};
+/// use neo_email::connection::SMTPConnection;
+/// use neo_email::utilities::spf::{sender_policy_framework_with_limits, SPFLimits, SPFResult};
+/// use std::sync::Arc;
+/// use tokio::io::BufStream;
+/// use tokio::net::{TcpListener, TcpStream, UdpSocket};
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+/// use trust_dns_resolver::proto::rr::{rdata::TXT, RData, Record};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// fn spf_record(name: &str) -> Result<Option<&'static str>, ResponseCode> {
+///     match name {
+///         "pass.test." => Ok(Some("v=spf1 ip4:127.0.0.1 -all")),
+///         "fail.test." => Ok(Some("v=spf1 ip4:192.0.2.1 -all")),
+///         "softfail.test." => Ok(Some("v=spf1 ip4:192.0.2.1 ~all")),
+///         "permerror.test." => Ok(Some("v=spf1 redirect=permerror.test")),
+///         "temperror.test." => Err(ResponseCode::ServFail),
+///         _ => Ok(None),
+///     }
+/// }
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering the SPF records above, failing for temperror.test
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let name = request.queries()[0].name().clone();
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .set_recursion_desired(true)
+///                 .set_recursion_available(true)
+///                 .add_queries(request.queries().to_vec());
+///             match spf_record(&name.to_string()) {
+///                 Ok(Some(spf)) => {
+///                     let txt = TXT::new(vec![spf.to_string()]);
+///                     response.add_answer(Record::from_rdata(name, 300, RData::TXT(txt)));
+///                 }
+///                 Ok(None) => {
+///                     response.set_response_code(ResponseCode::NXDomain);
+///                 }
+///                 Err(code) => {
+///                     response.set_response_code(code);
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let mut opts = ResolverOpts::default();
+///     opts.attempts = 1;
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, opts)));
+///
+///     // A connection from 127.0.0.1, sharing the counters like the connections of a server
+///     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+///     let _client = TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+///     let (socket, _) = listener.accept().await.unwrap();
+///     let stats = Arc::new(AuthStats::default());
+///     let conn = Arc::new(Mutex::new(SMTPConnection::<()> {
+///         tcp_buff_socket: Some(Arc::new(Mutex::new(BufStream::new(socket)))),
+///         auth_stats: stats.clone(),
+///         ..SMTPConnection::new(resolver)
+///     }));
+///
+///     let domains = ["pass.test", "pass.test", "fail.test", "softfail.test", "permerror.test", "temperror.test", "none.test"];
+///     for domain in domains {
+///         sender_policy_framework_with_limits(conn.clone(), domain, SPFLimits::default()).await.unwrap();
+///     }
+///
+///     assert_eq!(stats.spf.get(AuthResult::Pass), 2);
+///     assert_eq!(stats.spf.get(AuthResult::Fail), 1);
+///     assert_eq!(stats.spf.get(AuthResult::SoftFail), 1);
+///     assert_eq!(stats.spf.get(AuthResult::PermError), 1);
+///     assert_eq!(stats.spf.get(AuthResult::TempError), 1);
+///     assert_eq!(stats.spf.get(AuthResult::None), 1);
+///     assert_eq!(stats.spf.get(AuthResult::Neutral), 0);
+///     assert_eq!(stats.spf.total(), domains.len() as u64);
+///     assert_eq!(stats.dkim.total(), 0);
+/// }
+/// ```
+pub async fn sender_policy_framework_with_limits<B>(
+    conn: Arc<Mutex<SMTPConnection<B>>>,
+    domain: &str,
+    limits: SPFLimits,
+) -> Result<(SPFResult, Option<SPFRecord>, Option<String>), Error> {
     // Lock the connection
     let conn = conn.lock().await;
     // Get the IP address of the sender
     let origin_ip = match conn.get_peer_addr().await {
         Ok(ip) => ip,
-        Err(_) => return Err(Error::SPFError("Failed to get IP address".to_string())),
+        Err(err) => {
+            return Err(Error::SPFError(
+                "Failed to get IP address".to_string(),
+                Some(Box::new(err)),
+            ))
+        }
     };
+    let dns_resolver = conn.dns_resolver.clone();
+    let sender = conn
+        .envelope
+        .as_ref()
+        .and_then(|envelope| envelope.sender.as_ref())
+        .map(|sender| format!("{}@{}", sender.username, sender.domain));
+    let context = SPFMacroContext::new(origin_ip.ip(), sender.as_deref(), domain, conn.helo.as_deref());
+    let auth_stats = conn.auth_stats.clone();
+    drop(conn);
 
-    // Get the SPF record from the DNS with a max depth of `max_depth_redirect` and a max number of included records of `max_include`
-    let mut record =
-        match SPFRecord::get_dns_spf_record(max_depth_redirect, conn.dns_resolver.clone(), domain)
-            .await
-        {
-            Ok(record) => record,
-            Err(_) => return Err(Error::SPFError("Failed to get SPF record".to_string())),
-        };
+    let outcome = sender_policy_framework_with_context(dns_resolver, context, limits).await;
+    if let Ok((result, _, _)) = &outcome {
+        auth_stats.spf.record((*result).into());
+    }
+    outcome
+}
 
-    // If exists mechanism is present, check if the record exists
-    match &record.exists {
-        Some(domain_to_query) => {
-            // Append the dot to the domain for a better query
-            let domain_to_query = format!("{}.", domain_to_query);
-            // Lock the DNS resolver
-            let dns_resolver_guarded = conn.dns_resolver.lock().await;
-            // Check if the domain has a valid record
-            let mut record_exists = false;
-
-            // Check if the domain has an A or AAAA record
-            // If the domain has an A or AAAA record, then the domain exists
-            if origin_ip.is_ipv4() {
-                // Get the A record
-                let lookup = dns_resolver_guarded
-                    .lookup(domain_to_query.as_str(), RecordType::A)
-                    .await
-                    .map_err(|_| Error::DNSError("Failed to get A record".to_string()))?;
-                // Check if the domain has an A record
-                let a_record_exists = lookup.records().iter().find(|record| {
-                    record.record_type() == RecordType::A
-                });
-                // If the domain has an A record, then the domain exists
-                if a_record_exists.is_some() {
-                    record_exists = true;
-                }
-            } else {
-                // Get the AAAA record
-                let lookup = dns_resolver_guarded
-                    .lookup(domain_to_query.as_str(), RecordType::AAAA)
-                    .await
-                    .map_err(|_| Error::DNSError("Failed to get AAAA record".to_string()))?;
-                // Check if the domain has an AAAA record
-                let aaaa_record_exists = lookup.records().iter().find(|record| {
-                    record.record_type() == RecordType::AAAA
-                });
-                // If the domain has an AAAA record, then the domain exists
-                if aaaa_record_exists.is_some() {
-                    record_exists = true;
-                }
+/// # sender_policy_framework_for_ip
+///
+/// Check if the given IP is allowed to send emails on behalf of the domain within the given limits,
+/// without a connection, for example to check the IP of a `Received` header or in tests.
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::spf::{sender_policy_framework_for_ip, SPFLimits, SPFResult};
+/// use std::net::IpAddr;
+/// use std::sync::Arc;
+/// use tokio::net::UdpSocket;
+/// use tokio::sync::Mutex;
+/// use trust_dns_resolver::config::{NameServerConfig, Protocol, ResolverConfig, ResolverOpts};
+/// use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+/// use trust_dns_resolver::proto::rr::{rdata::{A, TXT}, RData, Record};
+/// use trust_dns_resolver::proto::serialize::binary::{BinDecodable, BinEncodable};
+/// use trust_dns_resolver::TokioAsyncResolver;
+///
+/// #[tokio::main]
+/// async fn main() {
+///     // A DNS server answering a malformed SPF record for broken.nervio.us, a record for each `all` policy,
+///     // a missing domain, a failure, and the same valid record for any other domain
+///     let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+///     let dns_addr = socket.local_addr().unwrap();
+///     tokio::spawn(async move {
+///         let mut buf = [0; 512];
+///         loop {
+///             let (n, peer) = socket.recv_from(&mut buf).await.unwrap();
+///             let request = Message::from_bytes(&buf[..n]).unwrap();
+///             let name = request.queries()[0].name().clone();
+///             let spf = match name.to_string().as_str() {
+///                 "broken.nervio.us." => Ok("v=spf1 ip4:10.0.0 ip4:abc.def ip6:zz::1 -all"),
+///                 "soft.nervio.us." => Ok("v=spf1 ip4:130.211.0.0/22 ~all"),
+///                 "neutral.nervio.us." => Ok("v=spf1 ip4:130.211.0.0/22 ?all"),
+///                 "open.nervio.us." => Ok("v=spf1 +all"),
+///                 "deny.nervio.us." => Ok("v=spf1 -ip4:130.211.0.0/22 ~ip4:10.0.0.0/8 +all"),
+///                 "macro.nervio.us." => Ok("v=spf1 exists:%{ir}.%{v}._spf.%{d} -all"),
+///                 "bad-macro.nervio.us." => Ok("v=spf1 exists:%{p}._spf.%{d} -all"),
+///                 "redirect.nervio.us." => Ok("v=spf1 -ip4:130.211.0.155 redirect=open.nervio.us"),
+///                 "redirect-all.nervio.us." => Ok("v=spf1 -all redirect=open.nervio.us"),
+///                 "missing.nervio.us." => Err(ResponseCode::NXDomain),
+///                 "failing.nervio.us." => Err(ResponseCode::Refused),
+///                 _ => Ok("v=spf1 ip4:130.211.0.0/22 -all"),
+///             };
+///             let mut response = Message::new();
+///             response
+///                 .set_id(request.id())
+///                 .set_message_type(MessageType::Response)
+///                 .add_queries(request.queries().to_vec());
+///             match spf {
+///                 // The only IP listed by the `exists` of macro.nervio.us
+///                 Ok(_) if name.to_string() == "1.0.0.10.in-addr._spf.macro.nervio.us." => {
+///                     response.add_answer(Record::from_rdata(name, 300, RData::A(A::new(127, 0, 0, 2))));
+///                 }
+///                 Ok(spf) => {
+///                     let txt = TXT::new(vec![spf.to_string()]);
+///                     response.add_answer(Record::from_rdata(name, 300, RData::TXT(txt)));
+///                 }
+///                 Err(code) => {
+///                     response.set_response_code(code);
+///                 }
+///             }
+///             socket.send_to(&response.to_bytes().unwrap(), peer).await.unwrap();
+///         }
+///     });
+///
+///     let mut config = ResolverConfig::new();
+///     config.add_name_server(NameServerConfig::new(dns_addr, Protocol::Udp));
+///     let resolver = Arc::new(Mutex::new(TokioAsyncResolver::tokio(config, ResolverOpts::default())));
+///
+///     let check_domain = |ip: &str, domain: &'static str| {
+///         let ip: IpAddr = ip.parse().unwrap();
+///         sender_policy_framework_for_ip(resolver.clone(), ip, domain, SPFLimits::default())
+///     };
+///     let check = |ip: &str| check_domain(ip, "nervio.us");
+///
+///     let (result, _, pattern) = check("130.211.0.155").await.unwrap();
+///     assert_eq!(result, SPFResult::Pass);
+///     assert_eq!(pattern, Some("130.211.0.0/22".to_string()));
+///
+///     let (result, _, pattern) = check("130.211.4.1").await.unwrap();
+///     assert_eq!(result, SPFResult::Fail);
+///     assert_eq!(pattern, None);
+///
+///     // The malformed IPs don't match anything
+///     let (result, _, pattern) = check_domain("10.0.0.1", "broken.nervio.us").await.unwrap();
+///     assert_eq!(result, SPFResult::Fail);
+///     assert_eq!(pattern, None);
+///
+///     // A non matching IP gets the result of the `all` policy
+///     assert_eq!(check_domain("10.0.0.1", "soft.nervio.us").await.unwrap().0, SPFResult::SoftFail);
+///     assert_eq!(check_domain("10.0.0.1", "neutral.nervio.us").await.unwrap().0, SPFResult::Neutral);
+///     assert_eq!(check_domain("10.0.0.1", "open.nervio.us").await.unwrap().0, SPFResult::Pass);
+///
+///     // A matching mechanism gets the result of its qualifier, before the `all` policy
+///     let (result, _, pattern) = check_domain("130.211.0.155", "deny.nervio.us").await.unwrap();
+///     assert_eq!(result, SPFResult::Fail);
+///     assert_eq!(pattern, Some("130.211.0.0/22".to_string()));
+///     assert_eq!(check_domain("10.0.0.1", "deny.nervio.us").await.unwrap().0, SPFResult::SoftFail);
+///     assert_eq!(check_domain("192.0.2.1", "deny.nervio.us").await.unwrap().0, SPFResult::Pass);
+///
+///     // The redirect only applies when no mechanism matches, and never with `all`
+///     assert_eq!(check_domain("130.211.0.155", "redirect.nervio.us").await.unwrap().0, SPFResult::Fail);
+///     assert_eq!(check_domain("130.211.0.156", "redirect.nervio.us").await.unwrap().0, SPFResult::Pass);
+///     assert_eq!(check_domain("130.211.0.156", "redirect-all.nervio.us").await.unwrap().0, SPFResult::Fail);
+///
+///     // The macros of `exists` expand before the query
+///     assert_eq!(check_domain("10.0.0.1", "macro.nervio.us").await.unwrap().0, SPFResult::Pass);
+///     assert_eq!(check_domain("10.0.0.2", "macro.nervio.us").await.unwrap().0, SPFResult::Fail);
+///     assert_eq!(check_domain("10.0.0.1", "bad-macro.nervio.us").await.unwrap().0, SPFResult::PermError);
+///
+///     // Without record, or with a DNS failure
+///     let (result, record, _) = check_domain("10.0.0.1", "missing.nervio.us").await.unwrap();
+///     assert_eq!(result, SPFResult::None);
+///     assert!(record.is_none());
+///     assert_eq!(check_domain("10.0.0.1", "failing.nervio.us").await.unwrap().0, SPFResult::TempError);
+/// }
+/// ```
+pub async fn sender_policy_framework_for_ip(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    origin_ip: IpAddr,
+    domain: &str,
+    limits: SPFLimits,
+) -> Result<(SPFResult, Option<SPFRecord>, Option<String>), Error> {
+    let context = SPFMacroContext::new(origin_ip, None, domain, None);
+    sender_policy_framework_with_context(dns_resolver, context, limits).await
+}
+
+/// # sender_policy_framework_with_context
+///
+/// Check if the IP of the context is allowed to send emails on behalf of its domain within the given limits,
+/// with the sender and HELO identity the macros of the record expand to
+pub async fn sender_policy_framework_with_context(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    context: SPFMacroContext,
+    limits: SPFLimits,
+) -> Result<(SPFResult, Option<SPFRecord>, Option<String>), Error> {
+    // DNS lookups done by the mechanisms and modifiers, compared against `limits.max_dns_lookups`
+    let mut lookups = 0;
+    let domain = context.domain.clone();
+
+    // Get the SPF record from the DNS, its redirect is only followed if no mechanism matches
+    let mut record = match SPFRecord::fetch(dns_resolver.clone(), domain.as_str()).await {
+        Ok(Some(record)) => record,
+        Ok(None) => return Ok((SPFResult::None, None, None)),
+        Err(err) => {
+            log::trace!("[🌐] Failed to get SPF record of {}: {}", domain, err);
+            return Ok((SPFResult::from_error(&err), None, None));
+        }
+    };
+
+    match evaluate_record(dns_resolver, &context, &mut record, limits, &mut lookups, 0).await {
+        Ok((result, matched_allowed_ip_pattern)) => Ok((result, Some(record), matched_allowed_ip_pattern)),
+        Err(err) => {
+            log::trace!("[🌐] Failed to evaluate SPF record of {}: {}", domain, err);
+            Ok((SPFResult::from_error(&err), Some(record), None))
+        }
+    }
+}
+
+/// # evaluate_record
+///
+/// Match the IP against the mechanisms of the record in order, the first one that matches gives the result of its qualifier.
+/// Without match the result is the one of the redirect target, or the `all` policy. An `include` matches when the included
+/// record passes (RFC 7208 5.2). `redirects` is the number of redirects followed to reach the record
+async fn evaluate_record(
+    dns_resolver: Arc<Mutex<TokioAsyncResolver>>,
+    context: &SPFMacroContext,
+    record: &mut SPFRecord,
+    limits: SPFLimits,
+    lookups: &mut u8,
+    redirects: u8,
+) -> Result<(SPFResult, Option<String>), Error> {
+    // Records with more than `max_includes` includes are not evaluated
+    let includes = record
+        .mechanisms
+        .iter()
+        .filter(|(_, mechanism)| matches!(mechanism, SPFMechanism::Include(_)))
+        .count();
+    if includes > limits.max_includes as usize {
+        return Err(Error::SPFError(
+            "Too many includes (permerror)".to_string(),
+            None,
+        ));
+    }
+
+    let origin_ip = context.ip;
+    record.included.clear();
+
+    for (qualifier, mechanism) in record.mechanisms.clone() {
+        // The matched IP pattern, if any, of a matching mechanism
+        let matched: Option<Option<String>> = match mechanism {
+            SPFMechanism::Ip4(pattern) => matching_ip_pattern(origin_ip, &[pattern], &[]).map(Some),
+            SPFMechanism::Ip6(pattern) => matching_ip_pattern(origin_ip, &[], &[pattern]).map(Some),
+            SPFMechanism::A(ref domain_spec) | SPFMechanism::Mx(ref domain_spec) => {
+                let mx = matches!(mechanism, SPFMechanism::Mx(_));
+                limits.count_dns_lookup(lookups)?;
+                let patterns = mechanism_ip_patterns(&dns_resolver, domain_spec, mx, origin_ip).await?;
+                let (ipv4, ipv6) = match origin_ip {
+                    IpAddr::V4(_) => (patterns, Vec::new()),
+                    IpAddr::V6(_) => (Vec::new(), patterns),
+                };
+                matching_ip_pattern(origin_ip, &ipv4, &ipv6).map(Some)
+            }
+            SPFMechanism::Include(domain) => {
+                limits.count_dns_lookup(lookups)?;
+                let included_context = SPFMacroContext {
+                    domain: context.expand(domain.as_str())?,
+                    ..context.clone()
+                };
+                // An included domain without SPF record is a permerror (RFC 7208 5.2)
+                let mut included_record = SPFRecord::fetch(dns_resolver.clone(), included_context.domain.as_str())
+                    .await?
+                    .ok_or(Error::SPFError(
+                        "Included domain without SPF record (permerror)".to_string(),
+                        None,
+                    ))?;
+                let (result, pattern) = Box::pin(evaluate_record(
+                    dns_resolver.clone(),
+                    &included_context,
+                    &mut included_record,
+                    limits,
+                    lookups,
+                    0,
+                ))
+                .await?;
+                record.included.push(included_record);
+                // Only a pass of the included record is a match, its fail, softfail or neutral are not
+                (result == SPFResult::Pass).then_some(pattern)
             }
-            // If the domain does not exist, then return an error
-            if !record_exists {
-                return Err(Error::SPFError("IP not allowed".to_string()));
+            SPFMechanism::Exists(domain_spec) => {
+                limits.count_dns_lookup(lookups)?;
+                let domain_to_query = context.expand(domain_spec.as_str())?;
+                domain_exists(&dns_resolver, &domain_to_query, origin_ip)
+                    .await?
+                    .then_some(None)
             }
+        };
+
+        if let Some(matched_ip_pattern) = matched {
+            return Ok((qualifier.result(), matched_ip_pattern));
+        }
+    }
+
+    // The IP didn't match, the redirect target decides, with the macros expanding to its domain (RFC 7208 6.1)
+    if let Some(redirect) = record.redirect.clone() {
+        if redirects >= limits.max_redirects {
+            return Err(Error::SPFError(
+                "Too many redirects (permerror)".to_string(),
+                None,
+            ));
         }
-        None => {}
+        limits.count_dns_lookup(lookups)?;
+        let redirect_context = SPFMacroContext {
+            domain: context.expand(redirect.as_str())?,
+            ..context.clone()
+        };
+        // Unlike the checked domain, a redirect target must have a record
+        let mut redirect_record = SPFRecord::fetch(dns_resolver.clone(), redirect_context.domain.as_str())
+            .await?
+            .ok_or(Error::SPFError(
+                "Redirect to a domain without SPF record (permerror)".to_string(),
+                None,
+            ))?;
+        return Box::pin(evaluate_record(
+            dns_resolver,
+            &redirect_context,
+            &mut redirect_record,
+            limits,
+            lookups,
+            redirects + 1,
+        ))
+        .await;
+    }
+
+    // Apply the policy of the record
+    let result = match record.all {
+        SPFRecordAll::Aggresive => SPFResult::Fail,
+        SPFRecordAll::Passive => SPFResult::SoftFail,
+        SPFRecordAll::Neutral => SPFResult::Neutral,
+        SPFRecordAll::Permissive => SPFResult::Pass,
+    };
+    Ok((result, None))
+}
+
+/// # mechanism_domain_spec
+///
+/// Get the domain spec of an `a` or `mx` mechanism (`a`, `a:nervio.us`, `a/24`, `mx:nervio.us/24//64`), None if it's another mechanism
+fn mechanism_domain_spec(term: &str, mechanism: &str) -> Option<String> {
+    let domain_spec = term.strip_prefix(mechanism)?;
+    match domain_spec.strip_prefix(':') {
+        Some(domain_spec) => Some(domain_spec.to_string()),
+        None if domain_spec.is_empty() || domain_spec.starts_with('/') => Some(domain_spec.to_string()),
+        None => None,
     }
+}
 
-    // Check if record require including other SPF records, and include it
-    // For now this included_records cant include other, but allow redirects
-    if record.root_include.len() > 0 {
-        // Include only `max_include` records
-        let mut i = max_include;
-        // Include the SPF records
-        for include in &record.root_include {
-            // If the max_include is 0, then break the loop
-            if i == 0 {
-                break;
+/// # mechanism_ip_patterns
+///
+/// Resolve the IPs of the hosts of an `a` or `mx` mechanism (the domain or its MX hosts), in the family of the origin IP,
+/// as `ip4`/`ip6` patterns with the CIDR suffix of the mechanism
+async fn mechanism_ip_patterns(
+    dns_resolver: &Arc<Mutex<TokioAsyncResolver>>,
+    domain_spec: &str,
+    mx: bool,
+    origin_ip: IpAddr,
+) -> Result<Vec<String>, Error> {
+    // `domain/cidr4//cidr6`
+    let (domain_spec, cidr6) = match domain_spec.split_once("//") {
+        Some((domain_spec, cidr6)) => (domain_spec, Some(cidr6)),
+        None => (domain_spec, None),
+    };
+    let (domain, cidr4) = match domain_spec.split_once('/') {
+        Some((domain, cidr4)) => (domain, Some(cidr4)),
+        None => (domain_spec, None),
+    };
+    let (record_type, cidr) = match origin_ip {
+        IpAddr::V4(_) => (RecordType::A, cidr4),
+        IpAddr::V6(_) => (RecordType::AAAA, cidr6),
+    };
+
+    let dns_resolver = dns_resolver.lock().await;
+    let hosts = match mx {
+        true => match dns_resolver.mx_lookup(format!("{}.", domain).as_str()).await {
+            Ok(lookup) => lookup.iter().map(|mx| mx.exchange().to_string()).collect::<Vec<String>>(),
+            Err(err) if is_missing(&err) => Vec::new(),
+            Err(err) => {
+                return Err(Error::DNSError(
+                    "Failed to get MX record".to_string(),
+                    Some(Box::new(err)),
+                ))
             }
-            // For now this included_records cant include other, but allow redirect
-            let included_record = match SPFRecord::get_dns_spf_record(
-                max_depth_redirect,
-                conn.dns_resolver.clone(),
-                include.as_str(),
-            )
-            .await
-            {
-                Ok(record) => record,
-                Err(_) => {
-                    return Err(Error::SPFError(
-                        "Failed to get included SPF record".to_string(),
-                    ))
-                }
-            };
-            // Add the included record to the SPF record
-            record.included.push(included_record);
-            // Decrement the counter
-            i -= 1;
+        },
+        false => vec![format!("{}.", domain)],
+    };
+
+    // Looking up the hosts of more than 10 MX records is a permerror (RFC 7208 4.6.4)
+    if hosts.len() > 10 {
+        return Err(Error::SPFError("Too many MX records (permerror)".to_string(), None));
+    }
+
+    let mut patterns = Vec::new();
+    for host in hosts {
+        let lookup = match dns_resolver.lookup(host.as_str(), record_type).await {
+            Ok(lookup) => lookup,
+            Err(err) if is_missing(&err) => continue,
+            Err(err) => {
+                return Err(Error::DNSError(
+                    format!("Failed to get {} record", record_type),
+                    Some(Box::new(err)),
+                ))
+            }
+        };
+        for ip in lookup.iter().filter_map(|data| data.ip_addr()) {
+            patterns.push(match cidr {
+                Some(cidr) => format!("{}/{}", ip, cidr),
+                None => ip.to_string(),
+            });
         }
     }
 
-    // Extend the ipv4 list with the included records
-    let mut total_ipv4 = record.ipv4.clone();
-    let mut total_ipv6 = record.ipv6.clone();
-    for included_record in record.included.iter() {
-        // Extend the ipv4 list with the included records
-        total_ipv4.extend(included_record.ipv4.clone());
-        // Extend the ipv6 list with the included records
-        total_ipv6.extend(included_record.ipv6.clone());
+    Ok(patterns)
+}
+
+/// # domain_exists
+///
+/// Check if the domain has an A record, or an AAAA record if the IP is IPv6
+async fn domain_exists(
+    dns_resolver: &Arc<Mutex<TokioAsyncResolver>>,
+    domain: &str,
+    origin_ip: IpAddr,
+) -> Result<bool, Error> {
+    let record_type = match origin_ip {
+        IpAddr::V4(_) => RecordType::A,
+        IpAddr::V6(_) => RecordType::AAAA,
+    };
+
+    // Append the dot to the domain for a better query
+    let domain_to_query = format!("{}.", domain);
+    let dns_resolver = dns_resolver.lock().await;
+    match dns_resolver.lookup(domain_to_query.as_str(), record_type).await {
+        Ok(lookup) => Ok(lookup
+            .records()
+            .iter()
+            .any(|record| record.record_type() == record_type)),
+        Err(err) if is_missing(&err) => Ok(false),
+        Err(err) => Err(Error::DNSError(
+            format!("Failed to get {} record", record_type),
+            Some(Box::new(err)),
+        )),
     }
+}
+
+/// # is_missing
+///
+/// Check if the DNS answered that the name or its records don't exist, other negative answers like REFUSED are failures
+fn is_missing(err: &ResolveError) -> bool {
+    matches!(
+        err.kind(),
+        ResolveErrorKind::NoRecordsFound {
+            response_code: ResponseCode::NXDomain | ResponseCode::NoError,
+            ..
+        }
+    )
+}
 
-    // Check if the IP is in the list of allowed IPs
+/// # matching_ip_pattern
+///
+/// Returns the first `ip4`/`ip6` pattern (an IP or a CIDR range) that matches the IP, None if the IP isn't allowed
+///
+/// ## Example
+///
+/// ```rust
+/// use neo_email::utilities::spf::matching_ip_pattern;
+/// use std::net::IpAddr;
+///
+/// let ipv4 = vec!["192.0.2.1".to_string(), "130.211.0.0/22".to_string()];
+/// let ipv6 = vec!["2001:db8:0:0:0:0:0:0/32".to_string()];
+///
+/// let ip: IpAddr = "130.211.0.155".parse().unwrap();
+/// assert_eq!(matching_ip_pattern(ip, &ipv4, &ipv6), Some("130.211.0.0/22".to_string()));
+///
+/// let ip: IpAddr = "130.211.4.1".parse().unwrap();
+/// assert_eq!(matching_ip_pattern(ip, &ipv4, &ipv6), None);
+///
+/// let ip: IpAddr = "2001:db8:0:0:0:0:0:1".parse().unwrap();
+/// assert_eq!(matching_ip_pattern(ip, &ipv4, &ipv6), Some("2001:db8:0:0:0:0:0:0/32".to_string()));
+///
+/// // Malformed patterns are skipped
+/// let ipv4 = vec!["10.0.0".to_string(), "abc.def".to_string(), "10.0.0.1/".to_string(), "10.0.0.1".to_string()];
+/// let ipv6 = vec!["zz::1/64".to_string(), "2001:db8::1".to_string()];
+/// assert_eq!(matching_ip_pattern("10.0.0.1".parse().unwrap(), &ipv4, &ipv6), Some("10.0.0.1".to_string()));
+/// assert_eq!(matching_ip_pattern("10.0.0.2".parse().unwrap(), &ipv4, &ipv6), None);
+/// assert_eq!(matching_ip_pattern("2001:db8::1".parse().unwrap(), &ipv4, &ipv6), Some("2001:db8::1".to_string()));
+/// ```
+pub fn matching_ip_pattern(origin_ip: IpAddr, ipv4: &[String], ipv6: &[String]) -> Option<String> {
     let mut matched_allowed_ip_pattern: Option<String> = None;
 
     if origin_ip.is_ipv4() {
-        for ipv4 in total_ipv4.iter() {
+        for ipv4 in ipv4.iter() {
             // Split the IP/CIDR
             let parts = ipv4.split("/").collect::<Vec<&str>>();
 
@@ -361,11 +1525,11 @@ pub async fn sender_policy_framework<B>(
                 continue;
             };
 
-            // Convert the IP to a number
-            let ip_num = allowed_ip
-                .split('.')
-                .map(|s| s.parse::<u32>().unwrap())
-                .fold(0, |acc, part| (acc << 8) + part);
+            // Convert the IP to a number, skipping malformed records like `10.0.0`
+            let ip_num = match allowed_ip.parse::<Ipv4Addr>() {
+                Ok(ip) => u32::from(ip),
+                Err(_) => continue,
+            };
 
             // Create the mask
             let cdir_num = match cdir.parse::<u8>() {
@@ -377,13 +1541,11 @@ pub async fn sender_policy_framework<B>(
                 continue;
             }
 
-            // Create the mask
-            let mask = (0xffffffff as u32) << (32 - cdir_num);
+            // Create the mask, /0 matches any IP
+            let mask = 0xffffffff_u32.checked_shl(32 - cdir_num as u32).unwrap_or(0);
 
             // Apply the mask
             let ip_num = ip_num & mask;
-            // Get the IP from the peer IP
-            let origin_ip = origin_ip.ip();
 
             // Example
             // allowed ip: 130.211.0.0/22 from an allowed Gmail google server
@@ -405,7 +1567,7 @@ pub async fn sender_policy_framework<B>(
             }
         }
     } else {
-        for ipv6 in total_ipv6.iter() {
+        for ipv6 in ipv6.iter() {
             // Split the IP/CIDR
             let parts = ipv6.split("/").collect::<Vec<&str>>();
 
@@ -429,11 +1591,11 @@ pub async fn sender_policy_framework<B>(
                 continue;
             }
 
-            // Parse the allowed IP into segments
-            let allowed_ip_segments: Vec<u16> = allowed_ip
-                .split(':')
-                .map(|s| u16::from_str_radix(s, 16).unwrap_or(0))
-                .collect();
+            // Parse the allowed IP into segments, skipping malformed records
+            let allowed_ip_segments: Vec<u16> = match allowed_ip.parse::<Ipv6Addr>() {
+                Ok(ip) => ip.segments().to_vec(),
+                Err(_) => continue,
+            };
 
             // Compute the mask for the given CIDR
             let mask: Vec<u16> = (0..8)
@@ -441,7 +1603,8 @@ pub async fn sender_policy_framework<B>(
                     if i < (cidr_num / 16) {
                         0xffff
                     } else if i == (cidr_num / 16) {
-                        0xffff << (16 - (cidr_num % 16))
+                        // The leading bits of the segment, none on a segment boundary (e.g. /32)
+                        !(0xffff_u16 >> (cidr_num % 16))
                     } else {
                         0
                     }
@@ -456,7 +1619,7 @@ pub async fn sender_policy_framework<B>(
                 .collect();
 
             // Apply the mask to the sender's IP segments
-            if let IpAddr::V6(ipv6_addr) = origin_ip.ip() {
+            if let IpAddr::V6(ipv6_addr) = origin_ip {
                 let peer_ip_segments: Vec<u16> = ipv6_addr.segments().to_vec();
                 let masked_peer_ip: Vec<u16> = peer_ip_segments
                     .iter()
@@ -473,17 +1636,5 @@ pub async fn sender_policy_framework<B>(
         }
     }
 
-    // Check the policy based on the result
-    match (policy, matched_allowed_ip_pattern.as_ref()) {
-        // If the policy is Aggresive and the IP is on the list then return true
-        (SPFRecordAll::Aggresive, Some(_)) => Ok((true, record, matched_allowed_ip_pattern)),
-        // If the policy is Aggresive and the IP is not on the list then return an error
-        (SPFRecordAll::Aggresive, None) => Err(Error::SPFError("IP not allowed".to_string())),
-        // If the policy is Passive and the IP is on the list then return true
-        (SPFRecordAll::Passive, Some(_)) => Ok((true, record, matched_allowed_ip_pattern)),
-        // If the policy is Passive and the IP is not on the list then return false
-        (SPFRecordAll::Passive, None) => Ok((false, record, matched_allowed_ip_pattern)),
-        // If the policy is Permissive then return true
-        (SPFRecordAll::Permissive, _) => Ok((true, record, matched_allowed_ip_pattern)),
-    }
+    matched_allowed_ip_pattern
 }